@@ -0,0 +1,158 @@
+use rayon::prelude::*;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Reason why a file was not processed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The file does not exist or is not a file
+    Access,
+    /// The file cannot be read
+    Read,
+    /// The extension of the file is not a supported one
+    Format(String),
+    /// The version string of the file is not a supported one
+    Version(String),
+    /// The file is corrupted, with the reason given by the reader
+    Parse(String),
+    /// The output file cannot be written
+    Write(String),
+    /// The processing of the song failed (unknown track, merge or rescale error, ...)
+    Process(String),
+}
+impl Failure {
+    /// Name of the kind of the failure, to count them
+    fn kind(&self) -> &'static str {
+        match self {
+            Failure::Access => "file not found",
+            Failure::Read => "read error",
+            Failure::Format(_) => "unsupported format",
+            Failure::Version(_) => "unsupported version",
+            Failure::Parse(_) => "parse error",
+            Failure::Write(_) => "write error",
+            Failure::Process(_) => "processing error",
+        }
+    }
+}
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Access => write!(f, "Unable to access file"),
+            Failure::Read => write!(f, "Unable to read file contents"),
+            Failure::Format(ext) if ext == "GPX" => write!(f, "Guitar pro file (new version) is not supported yet"), //new Guitar Pro files
+            Failure::Format(ext) => write!(f, "Unable to process a {} file (GP1 and GP2 files are not supported)", ext),
+            Failure::Version(v) => write!(f, "Unsupported file version ({})", v),
+            Failure::Parse(reason) => write!(f, "Unable to parse file ({})", reason),
+            Failure::Write(message) | Failure::Process(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Counts of the processed files of a batch
+#[derive(Default)]
+struct Summary {
+    total: usize,
+    done: usize,
+    failed: usize,
+    /// The progress is written on the error output
+    progress: bool,
+    /// Number of failures of each kind
+    kinds: BTreeMap<&'static str, usize>,
+    /// Number of files and of failed files of each detected version string
+    versions: BTreeMap<String, (usize, usize)>,
+}
+impl Summary {
+    fn draw_progress(&self) { eprint!("\r{}/{} files, {} failed", self.done, self.total, self.failed); }
+    fn clear_progress(&self) { if self.progress {eprint!("\r\x1b[K");} }
+}
+
+/// Summary of the current batch, it also serializes the messages written on the error output
+static SUMMARY: Mutex<Option<Summary>> = Mutex::new(None);
+
+thread_local! {
+    /// Version string of the file processed by the thread
+    static VERSION: RefCell<Option<String>> = const {RefCell::new(None)};
+    /// A failure was reported for the file processed by the thread
+    static FAILED: Cell<bool> = const {Cell::new(false)};
+    /// The panics of the thread are caught by `catch()` and not printed
+    static CATCHING: Cell<bool> = const {Cell::new(false)};
+}
+
+/// Set the number of threads processing the files, all the CPU cores are used by default.
+pub fn set_jobs(jobs: usize) {
+    if rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global().is_err() {eprintln!("Unable to start {} threads", jobs);}
+}
+
+/// Print the panics on the error output, except the ones caught by `catch()`.
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| if !CATCHING.with(Cell::get) {default(info)}));
+}
+
+/// Run a function of the library that panics on invalid data, the panic message is returned instead of being printed.
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    CATCHING.with(|c| c.set(true));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(false));
+    result.map_err(|e| e.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| e.downcast_ref::<String>().cloned()).unwrap_or_default())
+}
+
+/// Write a message on the error output, above the progress line.
+pub fn warn(message: &str) {
+    let summary = SUMMARY.lock().unwrap();
+    match summary.as_ref() {
+        Some(s) if s.progress => {s.clear_progress(); eprintln!("{}", message); s.draw_progress();},
+        _ => eprintln!("{}", message),
+    }
+}
+
+/// Report the failure of the file processed by the thread, the file is counted as failed.
+pub fn fail(path: &Path, failure: Failure) {
+    warn(&format!("{}: {}", failure, path.display()));
+    FAILED.with(|f| f.set(true));
+    if let Some(s) = SUMMARY.lock().unwrap().as_mut() {*s.kinds.entry(failure.kind()).or_default() += 1;}
+}
+
+/// Record the version string of the file processed by the thread.
+pub fn detected_version(version: &str) { VERSION.with(|v| *v.borrow_mut() = Some(String::from(version))); }
+
+/// Process files in parallel, the progress is written on the error output when it is a terminal. The results are in the order
+/// of the files, `None` when the job failed (the failures are reported with `fail()`).
+pub fn run<T: Send>(files: &[(PathBuf, PathBuf)], job: impl Fn(&Path, &Path) -> Option<T> + Sync) -> Vec<Option<T>> {
+    let progress = files.len() > 1 && std::io::stderr().is_terminal();
+    *SUMMARY.lock().unwrap() = Some(Summary {total: files.len(), progress, ..Default::default()});
+    let results = files.par_iter().map(|(path, relative)| {
+        FAILED.with(|f| f.set(false));
+        VERSION.with(|v| v.borrow_mut().take());
+        let result = job(path, relative);
+        let failed = FAILED.with(Cell::get) || result.is_none();
+        let version = VERSION.with(|v| v.borrow_mut().take());
+        if let Some(s) = SUMMARY.lock().unwrap().as_mut() {
+            s.done += 1;
+            if failed {s.failed += 1;}
+            if let Some(v) = version {let c = s.versions.entry(v).or_default(); c.0 += 1; if failed {c.1 += 1;}}
+            //redraw about 1000 times at most
+            if s.progress && (s.done % (s.total / 1000).max(1) == 0 || s.done == s.total) {s.draw_progress();}
+        }
+        result
+    }).collect();
+    if let Some(s) = SUMMARY.lock().unwrap().as_ref() {s.clear_progress();}
+    results
+}
+
+/// End the batch started by `run()`, the summary is written on the error output when several files were processed.
+/// Returns `true` if a file failed.
+pub fn finish() -> bool {
+    let s = match SUMMARY.lock().unwrap().take() {Some(s) => s, None => return false};
+    if s.total > 1 {
+        eprintln!("{} files processed, {} failed", s.total, s.failed);
+        if !s.kinds.is_empty() {eprintln!("Failures:");}
+        for (kind, count) in &s.kinds {eprintln!("  {}: {}", kind, count);}
+        if !s.versions.is_empty() {eprintln!("Versions:");}
+        for (version, (count, failed)) in &s.versions {eprintln!("  {}: {} files, {} failed", version, count, failed);}
+    }
+    s.failed > 0
+}
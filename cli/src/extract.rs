@@ -0,0 +1,192 @@
+use fraction::ToPrimitive;
+use scorelib::{gp::Song, enums::NoteType, key_signature::KeySignature, pitch::Pitch, sections::Section, melody::MelodyHit, validation::RhythmIssue, diff::Difference, progression::json_string};
+
+/// Maximum number of measures of the repeated parts
+pub const REPEAT_MAX_LENGTH: usize = 4;
+
+/// Information that can be extracted from a song
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extract {
+    /// Song information (title, artist, album, ...)
+    Info,
+    /// Track summaries (name, instrument, tuning, capo, ...)
+    Tracks,
+    /// Lyric lines with their starting measure
+    Lyrics,
+    /// Measures played several times in a row in all the tracks
+    Repeats,
+    /// Notes with their string, fret and spelled pitch
+    Notes,
+}
+
+impl Extract {
+    /// Check if the records only need the information and the tracks of the songs, read without the measures.
+    pub fn header_only(self) -> bool { matches!(self, Extract::Info | Extract::Tracks) }
+}
+
+/// Output format of the extracted data
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format { Json, Csv }
+
+/// A value of an extracted record
+pub enum Value { Text(String), Number(i64), Decimal(f64), Null }
+impl Value {
+    fn json(&self) -> String {
+        match self {
+            Value::Text(s) => json_string(s),
+            Value::Number(n) => n.to_string(),
+            Value::Decimal(d) => format!("{:.3}", d),
+            Value::Null => String::from("null"),
+        }
+    }
+    fn csv(&self) -> String {
+        match self {
+            Value::Text(s) => csv_field(s),
+            Value::Number(n) => n.to_string(),
+            Value::Decimal(d) => format!("{:.3}", d),
+            Value::Null => String::new(),
+        }
+    }
+}
+impl From<&str> for Value { fn from(s: &str) -> Self { Value::Text(String::from(s)) }}
+impl From<String> for Value { fn from(s: String) -> Self { Value::Text(s) }}
+impl From<i64> for Value { fn from(n: i64) -> Self { Value::Number(n) }}
+impl From<f64> for Value { fn from(d: f64) -> Self { Value::Decimal(d) }}
+
+/// Extracted rows of a file, every row has the same columns
+pub type Record = Vec<(&'static str, Value)>;
+
+/// Extract the records of a song.
+/// - `file`: path of the file, added as first column of each record
+/// - `track`: only keep the tracks matching a number (starting at 1) or a part of the name or instrument
+pub fn records(song: &Song, file: &str, what: Extract, track: Option<&str>) -> Vec<Record> {
+    match what {
+        Extract::Info => vec![info(song, file)],
+        Extract::Tracks => tracks(song, file, track),
+        Extract::Lyrics => lyrics(song, file),
+        Extract::Repeats => repeats(song, file),
+        Extract::Notes => notes(song, file, track),
+    }
+}
+
+fn info(song: &Song, file: &str) -> Record {
+    vec![("file", file.into()), ("version", song.version.data.as_str().into()),
+         ("title", song.name.as_str().into()), ("subtitle", song.subtitle.as_str().into()), ("artist", song.artist.as_str().into()),
+         ("album", song.album.as_str().into()), ("words", song.words.as_str().into()), ("music", song.author.as_str().into()),
+         ("date", song.date.as_str().into()), ("copyright", song.copyright.as_str().into()), ("writer", song.writer.as_str().into()),
+         ("transcriber", song.transcriber.as_str().into()), ("instructions", song.instructions.as_str().into()),
+         ("comments", song.comments.as_str().into()), ("notice", song.notice.join("\n").into()),
+         ("tempo", i64::from(song.tempo).into()), ("key", song.key.to_string().into()),
+         ("tracks", song.tracks.len().to_i64().unwrap().into()), ("measures", song.measure_headers.len().to_i64().unwrap().into())]
+}
+
+fn tracks(song: &Song, file: &str, track: Option<&str>) -> Vec<Record> {
+    let mut result = Vec::with_capacity(song.tracks.len());
+    for (i, t) in song.tracks.iter().enumerate() {
+        let instrument = if t.percussion_track {String::from("Percussion")}
+                         else {song.channels.get(t.channel_index).map(|c| c.get_instrument_name()).unwrap_or_default()};
+        if let Some(filter) = track {
+            let matches = match filter.parse::<usize>() {
+                Ok(n) => n == i + 1,
+                Err(_) => t.name.to_lowercase().contains(&filter.to_lowercase()) || instrument.to_lowercase().contains(&filter.to_lowercase()),
+            };
+            if !matches {continue;}
+        }
+        //strings are stored from the highest to the lowest one, the tuning is written from the lowest one
+        let tuning = if t.percussion_track {String::new()} else {t.strings.iter().rev().map(|s| note_name(s.1)).collect::<Vec<String>>().join(" ")};
+        result.push(vec![("file", file.into()), ("number", (i + 1).to_i64().unwrap().into()), ("name", t.name.as_str().into()),
+                         ("instrument", instrument.into()), ("strings", t.strings.len().to_i64().unwrap().into()), ("tuning", tuning.into()),
+                         ("capo", i64::from(t.offset).into()), ("fret_count", i64::from(t.fret_count).into()),
+                         ("color", format!("#{:06X}", t.color & 0xffffff).into())]);
+    }
+    result
+}
+
+fn lyrics(song: &Song, file: &str) -> Vec<Record> {
+    let mut result = Vec::with_capacity(song.lyrics.lines.len());
+    for l in &song.lyrics.lines {
+        if l.2.trim().is_empty() {continue;}
+        let start = song.measure_headers.get(usize::from(l.1).max(1) - 1).map(|h| Value::Number(h.start)).unwrap_or(Value::Null);
+        result.push(vec![("file", file.into()), ("track", i64::from(song.lyrics.track_choice).into()), ("line", i64::from(l.0 + 1).into()),
+                         ("measure", i64::from(l.1).into()), ("start", start), ("text", l.2.as_str().into())]);
+    }
+    result
+}
+
+fn repeats(song: &Song, file: &str) -> Vec<Record> {
+    song.find_repeated_groups(REPEAT_MAX_LENGTH).iter().map(|g| vec![("file", file.into()), ("measure", (g.start + 1).to_i64().unwrap().into()),
+        ("length", g.length.to_i64().unwrap().into()), ("count", g.count.to_i64().unwrap().into()), ("ending", g.ending.to_i64().unwrap().into()),
+        ("saved_measures", g.saved_measures().to_i64().unwrap().into())]).collect()
+}
+
+fn notes(song: &Song, file: &str, track: Option<&str>) -> Vec<Record> {
+    let mut result = Vec::new();
+    for c in song.notes().filter(|c| c.note.kind != NoteType::Rest) {
+        let (t, b) = (c.beat.track_index, &c.beat);
+        if track.is_some_and(|f| f.parse::<usize>().map(|n| n != t + 1).unwrap_or(!b.track.name.to_lowercase().contains(&f.to_lowercase()))) {continue;}
+        let pitch = c.pitch();
+        result.push(vec![("file", file.into()), ("track", (t + 1).to_i64().unwrap().into()), ("measure", (b.measure_index + 1).to_i64().unwrap().into()),
+            ("voice", (b.voice_index + 1).to_i64().unwrap().into()), ("beat", (b.beat_index + 1).to_i64().unwrap().into()), ("tick", b.tick.into()),
+            ("string", i64::from(c.note.string).into()), ("fret", i64::from(c.note.value).into()),
+            ("pitch", pitch.as_ref().map(|p| Value::Number(i64::from(p.midi))).unwrap_or(Value::Null)),
+            ("name", pitch.map(|p| Value::Text(p.name())).unwrap_or(Value::Null))]);
+    }
+    result
+}
+
+/// Records of the sections found in a song
+pub fn sections(file: &str, sections: &[Section]) -> Vec<Record> {
+    sections.iter().map(|s| vec![("file", file.into()), ("measure", (s.start + 1).to_i64().unwrap().into()), ("length", s.length.to_i64().unwrap().into()),
+        ("label", s.label.as_str().into()), ("similarity", s.similarity.into())]).collect()
+}
+
+/// Records of the rhythm issues of a song
+pub fn rhythm_issues(file: &str, issues: &[RhythmIssue]) -> Vec<Record> {
+    let index = |i: Option<usize>| i.map(|i| Value::Number((i + 1).to_i64().unwrap())).unwrap_or(Value::Null);
+    issues.iter().map(|i| vec![("file", file.into()), ("track", (i.track + 1).to_i64().unwrap().into()), ("measure", (i.measure + 1).to_i64().unwrap().into()),
+        ("voice", (i.voice + 1).to_i64().unwrap().into()), ("beat", index(i.beat)), ("note", index(i.note)), ("issue", i.to_string().into())]).collect()
+}
+
+/// Records of the differences of two songs, the indexes start at 1
+pub fn differences(differences: &[Difference]) -> Vec<Record> {
+    let index = |i: Option<usize>| i.map(|i| Value::Number((i + 1).to_i64().unwrap())).unwrap_or(Value::Null);
+    let text = |t: &Option<String>| t.as_ref().map(|t| Value::Text(t.clone())).unwrap_or(Value::Null);
+    differences.iter().map(|d| vec![("old_measure", index(d.old_measure)), ("new_measure", index(d.new_measure)), ("track", index(d.track)),
+        ("voice", index(d.voice)), ("position", d.position.map(Value::Number).unwrap_or(Value::Null)), ("field", d.field.as_str().into()),
+        ("old", text(&d.old)), ("new", text(&d.new))]).collect()
+}
+
+/// Records of the matches of a melody search
+pub fn melody_hits(hits: &[(String, MelodyHit)]) -> Vec<Record> {
+    hits.iter().map(|(file, h)| vec![("file", file.as_str().into()), ("track", (h.track + 1).to_i64().unwrap().into()), ("voice", (h.voice + 1).to_i64().unwrap().into()),
+        ("measure", (h.measure + 1).to_i64().unwrap().into()), ("beat", (h.beat + 1).to_i64().unwrap().into()),
+        ("end_measure", (h.end_measure + 1).to_i64().unwrap().into()), ("transposition", i64::from(h.transposition).into())]).collect()
+}
+
+/// Format records as a JSON array of objects.
+pub fn to_json(records: &[Record]) -> String {
+    let rows: Vec<String> = records.iter().map(|r| format!("{{{}}}", r.iter().map(|(k, v)| format!("{}:{}", json_string(k), v.json())).collect::<Vec<String>>().join(","))).collect();
+    format!("[{}]\n", rows.join(",\n"))
+}
+
+/// Format records as CSV with a header line. Nothing is written when there is no record.
+pub fn to_csv(records: &[Record]) -> String {
+    let mut s = String::new();
+    if let Some(first) = records.first() {
+        s.push_str(&first.iter().map(|(k, _)| *k).collect::<Vec<&str>>().join(","));
+        s.push('\n');
+    }
+    for r in records {
+        s.push_str(&r.iter().map(|(_, v)| v.csv()).collect::<Vec<String>>().join(","));
+        s.push('\n');
+    }
+    s
+}
+
+/// Get the scientific name of a MIDI note (60 is `C4`)
+fn note_name(value: i8) -> String { Pitch::spell(i16::from(value), &KeySignature::default()).name() }
+
+/// Quote a CSV field when it contains a separator, a quote or a new line
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {format!("\"{}\"", s.replace('"', "\"\""))} else {String::from(s)}
+}
@@ -1,57 +1,473 @@
-use clap::Parser;
-use fraction::ToPrimitive;
-use scorelib::gp;
-use std::path::Path;
+use clap::{Parser, Subcommand};
+use scorelib::{gp, encoding::TextEncoding, rescale::TimeScale, melody::{MelodyIndex, MelodyPattern}};
+use batch::Failure;
+use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 use std::fs;
-use std::io::Read;
+use std::sync::OnceLock;
 
-const GUITAR_FILE_MAX_SIZE:usize = 16777216; //16 MB, it should be enough
+mod batch;
+mod extract;
+
+const SUPPORTED_EXTENSIONS: [&str; 3] = ["GP3", "GP4", "GP5"];
+
+/// Encoding of the strings of the input files, detected if it is not set
+static INPUT_ENCODING: OnceLock<TextEncoding> = OnceLock::new();
+/// Encoding of the strings of the written files, the one of the input file if it is not set
+static OUTPUT_ENCODING: OnceLock<TextEncoding> = OnceLock::new();
+
+/// Formats that can be written
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat { Gp3, Gp4, Gp5 }
+impl OutputFormat {
+    fn from_extension(ext: &str) -> Option<OutputFormat> {
+        match ext {"GP3" => Some(OutputFormat::Gp3), "GP4" => Some(OutputFormat::Gp4), "GP5" => Some(OutputFormat::Gp5), _ => None}
+    }
+    /// Format of a song read from a file, e.g. the standard input which has no extension
+    fn from_version(number: (u8,u8,u8)) -> OutputFormat {
+        match number.0 {3 => OutputFormat::Gp3, 4 => OutputFormat::Gp4, _ => OutputFormat::Gp5}
+    }
+    fn extension(self) -> &'static str {
+        match self {OutputFormat::Gp3 => "gp3", OutputFormat::Gp4 => "gp4", OutputFormat::Gp5 => "gp5"}
+    }
+    /// Version written by the latest Guitar Pro release using this format
+    fn version(self) -> (u8,u8,u8) {
+        match self {OutputFormat::Gp3 => (3,0,0), OutputFormat::Gp4 => (4,0,6), OutputFormat::Gp5 => (5,1,0)}
+    }
+}
+
+/// Rescaling of the note values or time signatures
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Scale {
+    /// Double the note values and the tempo (4/4 becomes 4/2)
+    Double,
+    /// Halve the note values and the tempo (4/4 becomes 4/8)
+    Half,
+    /// Twice as many beats of half the value (4/4 becomes 8/8)
+    Split,
+    /// Half as many beats of twice the value (4/4 becomes 2/2)
+    Merge,
+}
+impl Scale {
+    fn time_scale(self) -> TimeScale {
+        match self {Scale::Double => TimeScale::Double, Scale::Half => TimeScale::Half, Scale::Split => TimeScale::SplitBeats, Scale::Merge => TimeScale::MergeBeats}
+    }
+}
+
+/// Operation on the repeats
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RepeatOperation {
+    /// Replace the measures played several times in a row by repeat signs and alternative endings
+    Compress,
+    /// Write the measures in the order they are played and remove the repeat signs
+    Expand,
+}
+
+/// Formats of the differences of two songs
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DiffFormat {
+    /// One difference per line
+    Text,
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[clap(author="slundi", version, about="Perform operation on music files", long_about = None)]
 struct Args {
-    /// Action
-    #[clap()] action: String,
-    
-    /// Input file
-    #[clap(short='i', long, help="Input file path")] input: String,
+    #[clap(subcommand)] command: Command,
+    #[clap(short='j', long, global=true, help="Number of files processed in parallel, the number of CPU cores by default")] jobs: Option<usize>,
+    #[clap(short='e', long, global=true, help="Encoding of the strings of the input files (utf-8, windows-1252, windows-1251, shift_jis, gbk, ...), detected by default")] encoding: Option<TextEncoding>,
+    #[clap(long, global=true, help="Encoding of the strings of the written files, the encoding of the input file by default")] output_encoding: Option<TextEncoding>,
+}
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Extract song information, tracks or lyrics
+    Extract {
+        /// Data to extract
+        #[clap(value_enum)] what: extract::Extract,
+        #[clap(short='f', long, value_enum, default_value="json", help="Output format")] format: extract::Format,
+        #[clap(short='t', long, help="Only extract the tracks matching a number (starting at 1), a name or an instrument")] track: Option<String>,
+        #[clap(short='i', long, required=true, num_args=1.., help="Input files, directories or glob patterns (e.g. `tabs/**/*.gp5`)")] input: Vec<String>,
+        #[clap(short='o', long, help="Output file path, the standard output is used if missing")] output: Option<String>,
+    },
+    /// Convert files to another Guitar Pro version
+    Convert {
+        #[clap(short='i', long, required=true, num_args=1.., help="Input files, directories or glob patterns (e.g. `tabs/**/*.gp5`)")] input: Vec<String>,
+        #[clap(short='o', long, help="Output file path, or output directory when several files are converted")] output: String,
+        #[clap(short='f', long, value_enum, help="Output format, guessed from the output file extension if missing")] format: Option<OutputFormat>,
+    },
+    /// Check the rhythm and the notes of files
+    Validate {
+        #[clap(short='i', long, required=true, num_args=1.., help="Input files, directories or glob patterns (e.g. `tabs/**/*.gp5`)")] input: Vec<String>,
+        #[clap(short='f', long, value_enum, default_value="json", help="Output format")] format: extract::Format,
+        #[clap(short='o', long, help="Output file path, the standard output is used if missing")] output: Option<String>,
+    },
+    /// Compare two versions of a song
+    Diff {
+        #[clap(short='i', long, required=true, num_args=2, help="Old and new files")] input: Vec<String>,
+        #[clap(short='f', long, value_enum, default_value="text", help="Output format")] format: DiffFormat,
+        #[clap(short='o', long, help="Output file path, the standard output is used if missing")] output: Option<String>,
+    },
+    /// Write one file per track
+    Split {
+        #[clap(short='i', long, help="Input file")] input: String,
+        #[clap(short='o', long, help="Output directory")] output: String,
+        #[clap(short='t', long, value_delimiter=',', help="Numbers of the tracks to extract (starting at 1), all the tracks if missing")] tracks: Vec<usize>,
+    },
+    /// Merge tracks of several files into one file
+    Merge {
+        #[clap(short='i', long, required=true, num_args=1.., help="Input files, followed by the numbers of the tracks to take (all the tracks if missing), e.g. `bass.gp5:1` or `guitars.gp5:2,3`")] input: Vec<String>,
+        #[clap(short='o', long, help="Output file path")] output: String,
+    },
+    /// Merge two edited versions of a file with their common ancestor, the conflicts are written on the error output
+    MergeEdits {
+        #[clap(short='b', long, help="Common ancestor")] base: String,
+        #[clap(long, help="Our version, kept when both versions changed the same part")] ours: String,
+        #[clap(long, help="Their version")] theirs: String,
+        #[clap(short='o', long, help="Output file path")] output: String,
+        #[clap(short='f', long, value_enum, help="Output format, guessed from the output file extension or from our file if missing")] format: Option<OutputFormat>,
+    },
+    /// Rewrite a file with other note values or time signatures, the music sounds the same
+    Rescale {
+        #[clap(value_enum)] scale: Scale,
+        #[clap(short='i', long, help="Input file")] input: String,
+        #[clap(short='o', long, help="Output file path")] output: String,
+    },
+    /// Find a sequence of notes in files
+    Find {
+        #[clap(short='n', long, help="Notes to find, e.g. `DADDC` anywhere, `|DADDC|` in a measure, `|A|E|CC|` in consecutive measures, `D:8A:8` with durations or `+2 -1` intervals")] notes: String,
+        #[clap(short='T', long, help="Also find the transposed notes")] transpose: bool,
+        #[clap(short='t', long, value_delimiter=',', help="Numbers of the tracks to search (starting at 1), all the tracks but percussion ones if missing")] tracks: Vec<usize>,
+        #[clap(short='i', long, required=true, num_args=1.., help="Input files, directories or glob patterns (e.g. `tabs/**/*.gp5`)")] input: Vec<String>,
+        #[clap(short='f', long, value_enum, default_value="json", help="Output format")] format: extract::Format,
+        #[clap(short='o', long, help="Output file path, the standard output is used if missing")] output: Option<String>,
+    },
+    /// Detect similar sections (verses, choruses, ...)
+    Sections {
+        #[clap(short='i', long, required=true, num_args=1.., help="Input files, directories or glob patterns (e.g. `tabs/**/*.gp5`)")] input: Vec<String>,
+        #[clap(short='s', long, default_value_t=0.8, help="Minimum similarity of the sections sharing a label, from 0 to 1")] similarity: f64,
+        #[clap(short='l', long, default_value_t=4, help="Number of measures of the compared groups")] length: usize,
+        #[clap(short='t', long, value_delimiter=',', help="Numbers of the tracks to compare (starting at 1), all the tracks but percussion ones if missing")] tracks: Vec<usize>,
+        #[clap(short='f', long, value_enum, default_value="json", help="Output format")] format: extract::Format,
+        #[clap(short='o', long, help="Output file path, the standard output is used if missing")] output: Option<String>,
+        #[clap(short='m', long, help="Write the input file with a marker at the start of each section to this path (one input file only)")] markers: Option<String>,
+    },
+    /// Replace repetitions by repeat signs, or expand the repeats
+    Repeats {
+        #[clap(value_enum)] operation: RepeatOperation,
+        #[clap(short='i', long, help="Input file")] input: String,
+        #[clap(short='o', long, help="Output file path")] output: String,
+        #[clap(short='m', long, default_value_t=extract::REPEAT_MAX_LENGTH, help="Maximum number of measures of the repeated parts")] max_length: usize,
+    },
 }
 
 fn main() {
     let args: Args = Args::parse();
-    let f = Path::new(&args.input);
+    batch::install_panic_hook();
+    if let Some(j) = args.jobs {batch::set_jobs(j);}
+    if let Some(e) = args.encoding {INPUT_ENCODING.get_or_init(|| e);}
+    if let Some(e) = args.output_encoding {OUTPUT_ENCODING.get_or_init(|| e);}
+    let mut failed = false;
+    match args.command {
+        Command::Extract {what, format, track, input, output} => {
+            let records: Vec<extract::Record> = batch::run(&input_files(&input), |f, _| {
+                let song = load_song(f, what.header_only())?;
+                process(f, "extract the song", || extract::records(&song, &f.to_string_lossy(), what, track.as_deref()))
+            }).into_iter().flatten().flatten().collect();
+            failed |= batch::finish();
+            let s = match format {
+                extract::Format::Json => extract::to_json(&records),
+                extract::Format::Csv => extract::to_csv(&records),
+            };
+            match output {
+                Some(o) => fs::write(&o, s).unwrap_or_else(|_e| {panic!("Unable to write file: {}", o)}),
+                None => print!("{}", s),
+            }
+        },
+        Command::Convert {input, output, format} => {
+            let files = input_files(&input);
+            //several files or a directory are converted into a directory, keeping the paths relative to the input directories
+            let batch = files.len() > 1 || input.iter().any(|i| Path::new(i).is_dir());
+            let format = match format.or_else(|| if batch {None} else {OutputFormat::from_extension(&extension(Path::new(&output)))}) {
+                Some(f) => f,
+                None => {eprintln!("Unable to guess the output format, use --format"); std::process::exit(2);}
+            };
+            batch::run(&files, |f, relative| {
+                let target = if batch {Path::new(&output).join(relative).with_extension(format.extension())} else {PathBuf::from(&output)};
+                convert(f, &target, format).then_some(())
+            });
+            failed |= batch::finish();
+        },
+        Command::Validate {input, format, output} => {
+            let records: Vec<extract::Record> = batch::run(&input_files(&input), |f, _| {
+                let song = read_song(f)?;
+                process(f, "validate the song", || extract::rhythm_issues(&f.to_string_lossy(), &song.validate_rhythm()))
+            }).into_iter().flatten().flatten().collect();
+            failed |= batch::finish();
+            let s = match format {
+                extract::Format::Json => extract::to_json(&records),
+                extract::Format::Csv => extract::to_csv(&records),
+            };
+            match output {
+                Some(o) => fs::write(&o, s).unwrap_or_else(|_e| {panic!("Unable to write file: {}", o)}),
+                None => print!("{}", s),
+            }
+        },
+        Command::Diff {input, format, output} => {
+            let (old, new) = match (read_song(Path::new(&input[0])), read_song(Path::new(&input[1]))) {
+                (Some(o), Some(n)) => (o, n),
+                _ => std::process::exit(1),
+            };
+            let differences = old.diff(&new);
+            let s = match format {
+                DiffFormat::Text => differences.iter().map(|d| format!("{}\n", d)).collect(),
+                DiffFormat::Json => extract::to_json(&extract::differences(&differences)),
+            };
+            match output {
+                Some(o) => fs::write(&o, s).unwrap_or_else(|_e| {panic!("Unable to write file: {}", o)}),
+                None => print!("{}", s),
+            }
+        },
+        Command::Split {input, output, tracks} => {
+            let path = Path::new(&input);
+            match read_song(path) {
+                Some(song) => {
+                    let format = OutputFormat::from_version(song.version.number);
+                    let stem = if path == Path::new("-") {String::from("stdin")} else {path.file_stem().unwrap_or_default().to_string_lossy().to_string()};
+                    let tracks: Vec<usize> = if tracks.is_empty() {(1..=song.tracks.len()).collect()} else {tracks};
+                    for t in tracks {
+                        if t == 0 || t > song.tracks.len() {eprintln!("Unknown track {} in {}", t, input); failed = true; continue;}
+                        let name = format!("{} - {} {}.{}", stem, t, song.tracks[t - 1].name.replace(['/', '\\'], "-"), format.extension());
+                        if !write_song(&song.extract_tracks(&[t - 1]), &Path::new(&output).join(name), format) {failed = true;}
+                    }
+                },
+                None => failed = true,
+            }
+        },
+        Command::Merge {input, output} => {
+            let format = match OutputFormat::from_extension(&extension(Path::new(&output))) {
+                Some(f) => f,
+                None => {eprintln!("Unable to guess the output format from {}", output); std::process::exit(2);}
+            };
+            let mut song: Option<gp::Song> = None;
+            for i in &input {
+                //the track list is after the last colon
+                let (path, tracks) = match i.rsplit_once(':') {
+                    Some((p, t)) if !t.is_empty() && t.chars().all(|c| c.is_ascii_digit() || c == ',') => (p, t.split(',').filter_map(|n| n.parse::<usize>().ok()).collect()),
+                    _ => (i.as_str(), Vec::new()),
+                };
+                let other = match read_song(Path::new(path)) {Some(s) => s, None => {failed = true; continue;}};
+                let tracks: Vec<usize> = if tracks.is_empty() {(0..other.tracks.len()).collect()}
+                                         else {tracks.into_iter().filter(|t| *t > 0 && *t <= other.tracks.len()).map(|t| t - 1).collect()};
+                let mut s = song.take().unwrap_or_else(|| {let mut s = other.clone(); s.tracks.clear(); s});
+                if let Err(e) = s.merge_tracks(&other, &tracks) {eprintln!("Unable to merge tracks of {} ({})", path, e); std::process::exit(1);}
+                song = Some(s);
+            }
+            if let Some(s) = song {if !write_song(&s, Path::new(&output), format) {failed = true;}}
+        },
+        Command::MergeEdits {base, ours, theirs, output, format} => {
+            let format = match format.or_else(|| OutputFormat::from_extension(&extension(Path::new(&output)))).or_else(|| OutputFormat::from_extension(&extension(Path::new(&ours)))) {
+                Some(f) => f,
+                None => {eprintln!("Unable to guess the output format, use --format"); std::process::exit(2);}
+            };
+            let (base, ours, theirs) = match (read_song(Path::new(&base)), read_song(Path::new(&ours)), read_song(Path::new(&theirs))) {
+                (Some(b), Some(o), Some(t)) => (b, o, t),
+                _ => std::process::exit(2),
+            };
+            let merged = match batch::catch(|| base.merge_edits(&ours, &theirs)) {
+                Ok(m) => m,
+                Err(e) => {eprintln!("Unable to merge the songs ({})", e); std::process::exit(2);}
+            };
+            for c in &merged.conflicts {eprintln!("conflict: {}", c);}
+            if !write_song(&merged.song, Path::new(&output), format) {std::process::exit(2);}
+            failed = !merged.conflicts.is_empty();
+        },
+        Command::Rescale {scale, input, output} => {
+            let format = match OutputFormat::from_extension(&extension(Path::new(&output))) {
+                Some(f) => f,
+                None => {eprintln!("Unable to guess the output format from {}", output); std::process::exit(2);}
+            };
+            match read_song(Path::new(&input)) {
+                Some(mut song) => {
+                    if let Err(e) = song.rescale_time(scale.time_scale()) {
+                        eprintln!("Unable to rescale {} ({})", input, e);
+                        std::process::exit(1);
+                    }
+                    if !write_song(&song, Path::new(&output), format) {failed = true;}
+                },
+                None => failed = true,
+            }
+        },
+        Command::Find {notes, transpose, tracks, input, format, output} => {
+            let pattern = match MelodyPattern::parse(&notes, transpose) {
+                Some(p) => p,
+                None => {eprintln!("Invalid notes: {}", notes); std::process::exit(2);}
+            };
+            let tracks: Vec<usize> = tracks.into_iter().filter(|t| *t > 0).map(|t| t - 1).collect();
+            let songs = batch::run(&input_files(&input), |f, _| {
+                let song = read_song(f)?;
+                let tracks: Vec<usize> = tracks.iter().copied().filter(|t| *t < song.tracks.len()).collect();
+                Some((f.to_string_lossy().to_string(), song.melodies(&tracks)))
+            });
+            failed |= batch::finish();
+            let index = MelodyIndex {songs: songs.into_iter().flatten().collect()};
+            let records = extract::melody_hits(&index.search(&pattern));
+            let s = match format {
+                extract::Format::Json => extract::to_json(&records),
+                extract::Format::Csv => extract::to_csv(&records),
+            };
+            match output {
+                Some(o) => fs::write(&o, s).unwrap_or_else(|_e| {panic!("Unable to write file: {}", o)}),
+                None => print!("{}", s),
+            }
+        },
+        Command::Sections {input, similarity, length, tracks, format, output, markers} => {
+            let files = input_files(&input);
+            if markers.is_some() && files.len() != 1 {eprintln!("Markers can only be written for one input file"); std::process::exit(2);}
+            let markers = markers.map(|m| match OutputFormat::from_extension(&extension(Path::new(&m))) {
+                Some(f) => (m, f),
+                None => {eprintln!("Unable to guess the output format from {}", m); std::process::exit(2);}
+            });
+            let tracks: Vec<usize> = tracks.into_iter().filter(|t| *t > 0).map(|t| t - 1).collect();
+            let records: Vec<extract::Record> = batch::run(&files, |f, _| {
+                let mut song = read_song(f)?;
+                if tracks.iter().any(|t| *t >= song.tracks.len()) {batch::fail(f, Failure::Process(String::from("Unknown track"))); return None;}
+                let sections = song.detect_sections(length, similarity, &tracks);
+                if let Some((m, format)) = &markers {
+                    song.mark_sections(&sections);
+                    write_song(&song, Path::new(m), *format);
+                }
+                Some(extract::sections(&f.to_string_lossy(), &sections))
+            }).into_iter().flatten().flatten().collect();
+            failed |= batch::finish();
+            let s = match format {
+                extract::Format::Json => extract::to_json(&records),
+                extract::Format::Csv => extract::to_csv(&records),
+            };
+            match output {
+                Some(o) => fs::write(&o, s).unwrap_or_else(|_e| {panic!("Unable to write file: {}", o)}),
+                None => print!("{}", s),
+            }
+        },
+        Command::Repeats {operation, input, output, max_length} => {
+            let format = match OutputFormat::from_extension(&extension(Path::new(&output))) {
+                Some(f) => f,
+                None => {eprintln!("Unable to guess the output format from {}", output); std::process::exit(2);}
+            };
+            match read_song(Path::new(&input)) {
+                Some(mut song) => {
+                    match operation {
+                        RepeatOperation::Compress => {
+                            let count = song.compress_repeats(max_length);
+                            eprintln!("{}: {} repeats added", output, count);
+                        },
+                        RepeatOperation::Expand => song.expand_repeats(),
+                    }
+                    if !write_song(&song, Path::new(&output), format) {failed = true;}
+                },
+                None => failed = true,
+            }
+        },
+    }
+    if failed {std::process::exit(1);}
+}
+
+/// List the files to process with their path relative to the input: files are kept as is, directories are walked recursively to
+/// find the supported files (sorted by path). Glob patterns are expanded, the paths of the matches are relative to the directory
+/// before the first wildcard.
+fn input_files(input: &[String]) -> Vec<(PathBuf, PathBuf)> {
+    let mut files = Vec::new();
+    for i in input {
+        let p = Path::new(i);
+        if !p.exists() && i.contains(['*', '?', '[']) {
+            let base: PathBuf = p.components().take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '['])).collect();
+            let paths = match glob::glob(i) {Ok(paths) => paths, Err(e) => {eprintln!("Invalid pattern {}: {}", i, e); continue;}};
+            for m in paths.flatten() {
+                if m.is_dir() {
+                    let mut found = Vec::new();
+                    walk(&m, &mut found);
+                    found.sort();
+                    files.extend(found.into_iter().map(|f| {let r = f.strip_prefix(&base).unwrap_or(&f).to_path_buf(); (f, r)}));
+                } else {let r = m.strip_prefix(&base).unwrap_or(&m).to_path_buf(); files.push((m, r));}
+            }
+        } else if p.is_dir() {
+            let mut found = Vec::new();
+            walk(p, &mut found);
+            found.sort();
+            files.extend(found.into_iter().map(|f| {let r = f.strip_prefix(p).unwrap_or(&f).to_path_buf(); (f, r)}));
+        } else {files.push((p.to_path_buf(), PathBuf::from(p.file_name().unwrap_or_default())));}
+    }
+    files
+}
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {Ok(e) => e, Err(_) => {eprintln!("Unable to read directory: {}", dir.display()); return;}};
+    for e in entries.flatten() {
+        let p = e.path();
+        if p.is_dir() {walk(&p, files);}
+        else if SUPPORTED_EXTENSIONS.contains(&extension(&p).as_str()) {files.push(p);}
+    }
+}
+fn extension(path: &Path) -> String { path.extension().and_then(OsStr::to_str).unwrap_or_default().to_uppercase() }
+
+/// Read a song, errors are reported with `batch::fail()` and `None` is returned. The files are parsed while they are read, `-`
+/// reads the standard input (any Guitar Pro 3, 4 or 5 file).
+fn read_song(path: &Path) -> Option<gp::Song> { load_song(path, false) }
+
+/// Read a song, or only its information and tracks without the measures if `header_only` is set (see `read_song()`).
+fn load_song(path: &Path, header_only: bool) -> Option<gp::Song> {
+    let song = if path == Path::new("-") {parse_song(std::io::stdin().lock(), header_only)} else {open_song(path, header_only)};
+    song.map_err(|e| batch::fail(path, e)).ok()
+}
+/// Process a song with `batch::catch()`, a panic is reported with `batch::fail()` and `None` is returned.
+fn process<T>(path: &Path, what: &str, job: impl FnOnce() -> T) -> Option<T> {
+    batch::catch(job).map_err(|e| batch::fail(path, Failure::Process(format!("Unable to {} ({})", what, e)))).ok()
+}
+fn open_song(path: &Path, header_only: bool) -> Result<gp::Song, Failure> {
     //check if path OK, file exists and is file
-    if !f.exists() || !f.is_file() {panic!("Unable to access file: {}", &args.input);}
+    if !path.exists() || !path.is_file() {return Err(Failure::Access);}
     //check file format
-    let ext = f.extension().and_then(OsStr::to_str).unwrap_or_else(||{panic!("Cannont get input file extension");}).to_uppercase();
-    let size: usize = fs::metadata(&args.input).unwrap_or_else(|_e|{panic!("Unable to get file size")}).len().to_usize().unwrap();
-    if size > GUITAR_FILE_MAX_SIZE {panic!("File is too big (bigger than 16 MB)");}
-    let f = fs::OpenOptions::new().read(true).open(&args.input).unwrap_or_else(|_error| {
-        /*if error.kind() == fs::ErrorKind::NotFound {panic!("File {} was not found", &file);}
-        else if error.kind() == fs::ErrorKind::PermissionDenieds {panic!("File {} is unreadable, check permissions", &file);}
-        else {panic!("Unknown error while opening {}", &file);}*/
-        panic!("Unknown error while opening {}", args.input);
-    });
-    let mut data: Vec<u8> = Vec::with_capacity(size);
-    f.take(u64::from_ne_bytes(size.to_ne_bytes())).read_to_end(&mut data).unwrap_or_else(|_error|{panic!("Unable to read file contents");});
-    let mut song: gp::Song = gp::Song::default();
-    match ext.as_str() {
-        "GP3" => song.read_gp3(&data),
-        "GP4" => song.read_gp4(&data),
-        "GP5" => song.read_gp5(&data),
-        "GPX" => println!("Guitar pro file (new version) is not supported yet"), //new Guitar Pro files
-        _ => panic!("Unable to process a {} file (GP1 and GP2 files are not supported)", ext),
-    }
-    println!("Artist: \"{}\"", song.artist);
-    println!("Title:  \"{}\"", song.name);
-    println!("Album:  \"{}\"", song.album);
-    println!("Author: \"{}\"", song.author);
-    println!("Date:   \"{}\"", song.date);
-    println!("Copyright:   \"{}\"", song.copyright);
-    println!("Writer:      \"{}\"", song.writer);
-    println!("Transcriber: \"{}\"", song.transcriber);
-    println!("Comments:    \"{}\"", song.comments);
-    let _out = song.write((3,0,0), Some(false));
+    let ext = extension(path);
+    if !SUPPORTED_EXTENSIONS.contains(&ext.as_str()) {return Err(Failure::Format(ext));}
+    let file = fs::File::open(path).map_err(|_| Failure::Read)?;
+    parse_song(file, header_only)
+}
+
+/// Parse a song from a stream, its version string is recorded with `batch::detected_version()`. The song is read with the input
+/// encoding and will be written with the output encoding.
+fn parse_song<R: std::io::Read>(reader: R, header_only: bool) -> Result<gp::Song, Failure> {
+    let mut song = gp::Song {encoding: INPUT_ENCODING.get().copied().unwrap_or_default(), ..Default::default()};
+    //the readers panic on corrupted files
+    match batch::catch(|| if header_only {song.read_header_only(reader);} else {song.read_from(reader);}) {
+        Ok(()) => {
+            batch::detected_version(&song.version.data);
+            if let Some(e) = OUTPUT_ENCODING.get() {song.encoding = *e;}
+            Ok(song)
+        },
+        Err(reason) if reason.starts_with("Unsupported file version") => Err(Failure::Version(song.version.data)),
+        Err(reason) => {if !song.version.data.is_empty() {batch::detected_version(&song.version.data);} Err(Failure::Parse(reason))},
+    }
+}
+
+/// Convert a file, the lost data are written on the error output. Returns `false` on failure.
+fn convert(input: &Path, output: &Path, format: OutputFormat) -> bool {
+    match read_song(input) {
+        Some(song) => write_song(&song, output, format),
+        None => false,
+    }
+}
+
+/// Write a song, the lost data are written on the error output. Returns `false` on failure.
+fn write_song(song: &gp::Song, output: &Path, format: OutputFormat) -> bool {
+    let version = format.version();
+    for w in song.conversion_warnings(version) {batch::warn(&format!("{}: warning: {}", output.display(), w));}
+    let data = match batch::catch(|| song.write(version, None)) {
+        Ok(d) => d,
+        Err(_) => {batch::fail(output, Failure::Write(String::from("Unable to write file"))); return false;}
+    };
+    if let Some(dir) = output.parent() {
+        if !dir.as_os_str().is_empty() && fs::create_dir_all(dir).is_err() {batch::fail(dir, Failure::Write(String::from("Unable to create directory"))); return false;}
+    }
+    if fs::write(output, data).is_err() {batch::fail(output, Failure::Write(String::from("Unable to write file"))); return false;}
+    true
 }
@@ -0,0 +1,29 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use scorelib::{gp::Song, summary::SongSummary};
+
+/// Guitar Pro 3, 4 and 5 files of the `test` directory that can be read
+fn fixtures() -> Vec<Vec<u8>> {
+    let dir = format!("{}/../test", env!("CARGO_MANIFEST_DIR"));
+    let mut paths: Vec<_> = std::fs::read_dir(dir).unwrap().flatten().map(|e| e.path())
+        .filter(|p| p.extension().map(|e| ["gp3", "gp4", "gp5"].contains(&e.to_string_lossy().to_lowercase().as_str())).unwrap_or(false)).collect();
+    paths.sort();
+    paths.into_iter().map(|p| std::fs::read(p).unwrap())
+        .filter(|d| std::panic::catch_unwind(|| Song::default().read_from(&d[..])).is_ok()).collect()
+}
+
+fn read(c: &mut Criterion) {
+    std::panic::set_hook(Box::new(|_| {}));
+    let files = fixtures();
+    let _ = std::panic::take_hook();
+    c.bench_function("full parse", |b| b.iter(|| for f in &files {
+        let mut song = Song::default();
+        song.read_from(black_box(&f[..]));
+        black_box(song);
+    }));
+    c.bench_function("header only", |b| b.iter(|| for f in &files {black_box(SongSummary::read(black_box(&f[..])));}));
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
@@ -4,6 +4,7 @@ use crate::{mix_table::*, effects::*, chord::*, key_signature::*, note::*, io::*
 
 /// Parameters of beat display
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BeatDisplay {
     break_beam: bool,
     force_beam: bool,
@@ -18,6 +19,7 @@ impl Default for BeatDisplay { fn default() -> Self { BeatDisplay { break_beam:f
 
 /// A stroke effect for beats.
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BeatStroke {
     pub direction: BeatStrokeDirection,
     pub value: u16,
@@ -33,6 +35,7 @@ impl BeatStroke {
 
 /// A voice contains multiple beats
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Voice {
     //pub measure: Measure, //circular depth?
     pub measure_index: i16,
@@ -43,6 +46,7 @@ impl Default for Voice {fn default() -> Self { Voice { measure_index: 0, /*measu
 
 /// This class contains all beat effects
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BeatEffects {
     pub stroke: BeatStroke,
     pub has_rasgueado: bool,
@@ -84,6 +88,7 @@ impl BeatEffects {
 
 /// A beat contains multiple notes
 #[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Beat {
     pub notes: Vec<Note>,
     pub duration: Duration,
@@ -117,6 +122,18 @@ impl Beat {
     }
 }
 
+/// Rest beats filling a measure starting at `measure_start` from `start` to `end`, in ticks from the start of the measure.
+pub(crate) fn rest_beats(measure_start: i64, start: i64, end: i64) -> Vec<Beat> {
+    let mut beats = Vec::new();
+    let mut position = measure_start + start;
+    for d in durations_for(end - start) {
+        let length = i64::from(d.time());
+        beats.push(Beat {start: Some(position), duration: d, status: BeatStatus::Rest, ..Default::default()});
+        position += length;
+    }
+    beats
+}
+
 impl Song {
     /// Read beat. The first byte is the beat flags. It lists the data present in the current beat:
     /// - *0x01*: dotted notes- *0x02*: presence of a chord diagram
@@ -134,9 +151,9 @@ impl Song {
     /// - Text: `int-byte-size-string`.
     /// - Beat effects. See `BeatEffects::read()`.
     /// - Mix table change effect. See `MixTableChange::read()`.
-    pub(crate) fn read_beat(&mut self, data: &[u8], seek: &mut usize, voice: &mut Voice, start: i64, track_index: usize) -> i64 {
-        let flags = read_byte(data, seek);
-        //println!("read_beat(),    flags: {} \t seek: {}", flags, *seek);
+    pub(crate) fn read_beat(&mut self, data: &mut Input, voice: &mut Voice, start: i64, track_index: usize) -> i64 {
+        let flags = read_byte(data);
+        //println!("read_beat(),    flags: {} \t position: {}", flags, data._position());
         //get a beat
         let mut b = 0;
         let mut new_beat = true;
@@ -150,22 +167,22 @@ impl Song {
             b = voice.beats.len() - 1;
         }
         
-        if (flags & 0x40) == 0x40 { voice.beats[b].status = get_beat_status(read_byte(data, seek));} //else { voice.beats[b].status = BeatStatus::Normal;}
-        let duration = read_duration(data, seek, flags);
+        if (flags & 0x40) == 0x40 { voice.beats[b].status = get_beat_status(read_byte(data));} //else { voice.beats[b].status = BeatStatus::Normal;}
+        let duration = read_duration(data, flags);
         let mut note_effect = NoteEffect::default();
-        if (flags & 0x02) == 0x02 {voice.beats[b].effect.chord = Some(self.read_chord(data, seek, self.tracks[track_index].strings.len().to_u8().unwrap()));}
-        if (flags & 0x04) == 0x04 {voice.beats[b].text = read_int_byte_size_string(data, seek);}
+        if (flags & 0x02) == 0x02 {voice.beats[b].effect.chord = Some(self.read_chord(data, self.tracks[track_index].strings.len().to_u8().unwrap()));}
+        if (flags & 0x04) == 0x04 {voice.beats[b].text = read_int_byte_size_string(data);}
         if (flags & 0x08) == 0x08 {
             let chord = voice.beats[b].effect.chord.clone();
-            if   self.version.number.0 == 3 {voice.beats[b].effect = self.read_beat_effects_v3(data, seek, &mut note_effect); }
-            else                            {voice.beats[b].effect = self.read_beat_effects_v4(data, seek);}
+            if   self.version.number.0 == 3 {voice.beats[b].effect = self.read_beat_effects_v3(data, &mut note_effect); }
+            else                            {voice.beats[b].effect = self.read_beat_effects_v4(data);}
             voice.beats[b].effect.chord = chord;
         }
         if (flags & 0x10) == 0x10 {
-            let mtc = self.read_mix_table_change(data, seek);
+            let mtc = self.read_mix_table_change(data);
             voice.beats[b].effect.mix_table_change = Some(mtc);
         }
-        self.read_notes(data, seek, track_index, &mut voice.beats[b], &duration, note_effect);
+        self.read_notes(data, track_index, &mut voice.beats[b], &duration, note_effect);
         if voice.beats[b].status == BeatStatus::Empty {0} else {duration.time().to_i64().unwrap()}
     }
     /// Read beat. First, beat is read is in Guitar Pro 3 `guitarpro.gp3.readBeat`. Then it is followed by set of flags stored in `short`.
@@ -183,13 +200,13 @@ impl Song {
     /// - *0x1000*: break secondary tuplet
     /// - *0x2000*: force tuplet bracket
     /// - Break secondary beams: `byte`. Appears if flag at *0x0800* is set. Signifies how much beams should be broken.
-    pub(crate) fn read_beat_v5(&mut self, data: &[u8], seek: &mut usize, voice: &mut Voice, start: &mut i64, track_index: usize) -> i64 {
-        let duration = self.read_beat(data, seek, voice, *start, track_index);
+    pub(crate) fn read_beat_v5(&mut self, data: &mut Input, voice: &mut Voice, start: &mut i64, track_index: usize) -> i64 {
+        let duration = self.read_beat(data, voice, *start, track_index);
         //get the beat used in read_beat()
         let b = voice.beats.len() - 1;
 
-        let flags2 = read_short(data, seek);
-        //println!("read_beat_v5(), flags2: {} \t seek: {}", flags2, *seek);
+        let flags2 = read_short(data);
+        //println!("read_beat_v5(), flags2: {} \t position: {}", flags2, data._position());
         if (flags2 & 0x0010) == 0x0010 {voice.beats[b].octave = Octave::Ottava;}
         if (flags2 & 0x0020) == 0x0020 {voice.beats[b].octave = Octave::OttavaBassa;}
         if (flags2 & 0x0040) == 0x0040 {voice.beats[b].octave = Octave::Quindicesima;}
@@ -203,7 +220,7 @@ impl Song {
         if (flags2 & 0x0008) == 0x0008 {voice.beats[b].display.beam_direction = VoiceDirection::Up;}
         if (flags2 & 0x0200) == 0x0200 {voice.beats[b].display.tuplet_bracket = TupletBracket::Start;}
         if (flags2 & 0x0400) == 0x0400 {voice.beats[b].display.tuplet_bracket = TupletBracket::End;}
-        if (flags2 & 0x0800) == 0x0800 {voice.beats[b].display.break_secondary = read_byte(data, seek);}
+        if (flags2 & 0x0800) == 0x0800 {voice.beats[b].display.break_secondary = read_byte(data);}
 
         duration
     }
@@ -221,18 +238,18 @@ impl Song {
     /// - *2*: slap
     /// - *3*: pop
     /// - Beat stroke direction. See `BeatStroke::read()`
-    fn read_beat_effects_v3(&self, data: &[u8], seek: &mut usize, note_effect: &mut NoteEffect) -> BeatEffects {
+    fn read_beat_effects_v3(&self, data: &mut Input, note_effect: &mut NoteEffect) -> BeatEffects {
         //println!("read_beat_effects()");
         let mut be = BeatEffects::default();
-        let flags = read_byte(data, seek);
+        let flags = read_byte(data);
         note_effect.vibrato = (flags & 0x01) == 0x01 || note_effect.vibrato;
         be.vibrato = (flags & 0x02) == 0x02 || be.vibrato;
         be.fade_in = (flags & 0x10) == 0x10;
         if (flags & 0x20) == 0x20 {
-            be.slap_effect = get_slap_effect(read_byte(data, seek));
-            if be.slap_effect == SlapEffect::None {be.tremolo_bar = Some(self.read_tremolo_bar(data, seek));} else {read_int(data, seek);}
+            be.slap_effect = get_slap_effect(read_byte(data));
+            if be.slap_effect == SlapEffect::None {be.tremolo_bar = Some(self.read_tremolo_bar(data));} else {read_int(data);}
         }
-        if (flags & 0x40) == 0x40 {be.stroke = self.read_beat_stroke(data, seek);}
+        if (flags & 0x40) == 0x40 {be.stroke = self.read_beat_stroke(data);}
         //In GP3 harmonics apply to the whole beat, not the individual notes. Here we set the noteEffect for all the notes in the beat.
         if (flags & 0x04) == 0x04 {note_effect.harmonic = Some(HarmonicEffect::default());}
         if (flags & 0x08) == 0x08 {note_effect.harmonic = Some(HarmonicEffect {kind: HarmonicType::Artificial, ..Default::default()});}
@@ -263,27 +280,27 @@ impl Song {
     /// - Tremolo bar. See `readTremoloBar`.
     /// - Beat stroke. See `readBeatStroke`.
     /// - Pick stroke: `signed-byte`. For value mapping see `BeatStrokeDirection`.
-    fn read_beat_effects_v4(&self, data: &[u8], seek: &mut usize) -> BeatEffects {
+    fn read_beat_effects_v4(&self, data: &mut Input) -> BeatEffects {
         let mut be = BeatEffects::default();
-        let flags1 = read_signed_byte(data, seek);
-        let flags2 = read_signed_byte(data, seek);
+        let flags1 = read_signed_byte(data);
+        let flags2 = read_signed_byte(data);
         be.vibrato = (flags1 & 0x02) == 0x02 || be.vibrato;
         be.fade_in = (flags1 & 0x10) == 0x10;
-        if (flags1 & 0x20) == 0x20 {be.slap_effect = get_slap_effect(read_signed_byte(data, seek).to_u8().unwrap());}
-        if (flags2 & 0x04) == 0x04 {be.tremolo_bar = self.read_bend_effect(data, seek);}
-        if (flags1 & 0x40) == 0x40 {be.stroke = self.read_beat_stroke(data, seek);}
+        if (flags1 & 0x20) == 0x20 {be.slap_effect = get_slap_effect(read_signed_byte(data).to_u8().unwrap());}
+        if (flags2 & 0x04) == 0x04 {be.tremolo_bar = self.read_bend_effect(data);}
+        if (flags1 & 0x40) == 0x40 {be.stroke = self.read_beat_stroke(data);}
         be.has_rasgueado = (flags2 &0x01) == 0x01;
-        if (flags2 & 0x02) == 0x02 {be.pick_stroke = get_beat_stroke_direction(read_signed_byte(data, seek));}
+        if (flags2 & 0x02) == 0x02 {be.pick_stroke = get_beat_stroke_direction(read_signed_byte(data));}
         //println!("Beat effect: {:?}", be);
         be
     }
     /// Read beat stroke. Beat stroke consists of two `Bytes <byte>` which correspond to stroke up
     /// and stroke down speed. See `BeatStrokeDirection` for value mapping.
-    fn read_beat_stroke(&self, data: &[u8], seek: &mut usize) -> BeatStroke {
+    fn read_beat_stroke(&self, data: &mut Input) -> BeatStroke {
         //println!("read_beat_stroke()");
         let mut bs = BeatStroke::default();
-        let down = read_signed_byte(data, seek);
-        let up = read_signed_byte(data, seek);
+        let down = read_signed_byte(data);
+        let up = read_signed_byte(data);
         if up > 0 {
             bs.direction = BeatStrokeDirection::Up;
             bs.value = self.stroke_value(up).to_u16().unwrap();
@@ -309,10 +326,10 @@ impl Song {
     }
     /// Read tremolo bar beat effect. The only type of tremolo bar effect Guitar Pro 3 supports is `dip <BendType::Dip>`. The value of the
     /// effect is encoded in `Int` and shows how deep tremolo bar is pressed.
-    fn read_tremolo_bar(&self, data: &[u8], seek: &mut usize) -> BendEffect {
+    fn read_tremolo_bar(&self, data: &mut Input) -> BendEffect {
         //println!("read_tremolo_bar()");
         let mut be = BendEffect{kind: BendType::Dip, ..Default::default()};
-        be.value = read_int(data, seek).to_i16().unwrap();
+        be.value = read_int(data).to_i16().unwrap();
         be.points.push(BendPoint{ position: 0, value: 0, ..Default::default() });
         be.points.push(BendPoint{ position: BEND_EFFECT_MAX_POSITION / 2,
                                   value: (-f32::from(be.value) / GP_BEND_SEMITONE).round().to_i8().unwrap(),
@@ -326,9 +343,9 @@ impl Song {
         if beat.duration.dotted {flags |= 0x01;}
         if beat.effect.is_chord() {flags |= 0x02;}
         if !beat.text.is_empty() {flags |= 0x04;}
-        if beat.effect.is_default() {flags |= 0x08;}
+        if !beat.effect.is_default() || beat.has_vibrato() || beat.has_harmonic() {flags |= 0x08;}
         if let Some(mtc) = &beat.effect.mix_table_change {
-            if mtc.is_just_wah() {flags |= 0x10;}
+            if !mtc.is_just_wah() {flags |= 0x10;}
         }
         if !beat.duration.is_default_tuplet() {flags |= 0x20;}
         if beat.status != BeatStatus::Normal {flags |= 0x40;}
@@ -336,7 +353,7 @@ impl Song {
         if (flags & 0x40) == 0x40 {write_byte(data, from_beat_status(&beat.status));}
         beat.duration.write_duration(data, flags);
         if (flags & 0x02) == 0x02 {self.write_chord(data, beat);}
-        if (flags & 0x04) == 0x04 {write_int_byte_size_string(data, &beat.text);}
+        if (flags & 0x04) == 0x04 {write_int_byte_size_string(data, &beat.text, self.encoding);}
         if (flags & 0x08) == 0x08 {self.write_beat_effect_v3(data, beat);}
         if (flags & 0x10) == 0x10 {self.write_mix_table_change(data, &beat.effect.mix_table_change, &(3,0,0));}
         self.write_notes(data, beat, &Vec::new(), &(3,0,0));
@@ -347,9 +364,9 @@ impl Song {
         if beat.duration.dotted {flags |= 0x01;}
         if beat.effect.is_chord() {flags |= 0x02;}
         if !beat.text.is_empty() {flags |= 0x04;}
-        if beat.effect.is_default() {flags |= 0x08;}
+        if !beat.effect.is_default() {flags |= 0x08;}
         if let Some(mtc) = &beat.effect.mix_table_change {
-            if mtc.is_just_wah() || version.0 > 4 {flags |= 0x10;}
+            if !mtc.is_just_wah() || version.0 > 4 {flags |= 0x10;}
         }
         if !beat.duration.is_default_tuplet() {flags |= 0x20;}
         if beat.status != BeatStatus::Normal {flags |= 0x40;}
@@ -357,7 +374,7 @@ impl Song {
         if (flags & 0x40) == 0x40 {write_byte(data, from_beat_status(&beat.status));}
         beat.duration.write_duration(data, flags);
         if (flags & 0x02) == 0x02 {self.write_chord_v4(data, beat);}
-        if (flags & 0x04) == 0x04 {write_int_byte_size_string(data, &beat.text);}
+        if (flags & 0x04) == 0x04 {write_int_byte_size_string(data, &beat.text, self.encoding);}
         if (flags & 0x08) == 0x08 {self.write_beat_effect_v4(data, beat, version);}
         if (flags & 0x10) == 0x10 {self.write_mix_table_change(data, &beat.effect.mix_table_change, version);}
         self.write_notes(data, beat, strings, version);
@@ -384,7 +401,7 @@ impl Song {
     fn write_beat_effect_v3(&self, data: &mut  Vec<u8>, beat: &Beat) {
         let mut flags1: u8 = 0;
         if beat.has_vibrato()  {flags1 |= 0x01;}
-        if beat.effect.vibrato {flags1 |= 0x01;}
+        if beat.effect.vibrato {flags1 |= 0x02;}
         if beat.has_harmonic() {
             for n in 0..beat.notes.len() {
                 if let Some(h) = &beat.notes[n].effect.harmonic {
@@ -406,7 +423,7 @@ impl Song {
 
     fn write_beat_effect_v4(&self, data: &mut  Vec<u8>, beat: &Beat, version: &(u8,u8,u8)) {
         let mut flags1: i8 = 0;
-        if beat.has_vibrato()  {flags1 |= 0x01;}
+        if beat.effect.vibrato {flags1 |= 0x02;}
         if beat.effect.fade_in {flags1 |= 0x10;}
         if beat.effect.is_slap_effect() {flags1 |= 0x20;}
         if beat.effect.stroke.direction != BeatStrokeDirection::None && beat.effect.stroke.value != 0 {flags1 |= 0x40;}
@@ -420,7 +437,7 @@ impl Song {
 
         if (flags1 & 0x20) == 0x20 {write_signed_byte(data, from_slap_effect(&beat.effect.slap_effect).to_i8().unwrap());}
         if (flags2 & 0x04) == 0x04 {self.write_bend(data, &beat.effect.tremolo_bar);} //write tremolo bar
-        if (flags2 & 0x40) == 0x40 {self.write_beat_stroke(data, &beat.effect.stroke, version);}
+        if (flags1 & 0x40) == 0x40 {self.write_beat_stroke(data, &beat.effect.stroke, version);}
         if (flags2 & 0x02) == 0x02 {write_signed_byte(data, from_beat_stroke_direction(&beat.effect.pick_stroke));}
     }
 
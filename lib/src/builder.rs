@@ -0,0 +1,257 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, headers::*, track::*, measure::*, beat::*, note::*, key_signature::*, midi::*, mix_table::*, enums::*};
+
+/// Number of voices of the measures written in Guitar Pro 5 files
+const VOICE_COUNT: usize = 2;
+/// Number of lyric lines of a song
+const LYRIC_LINE_COUNT: u8 = 5;
+
+/// Reason why a `SongBuilder` cannot build its song. Tracks, measures and beats are counted from 1.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum BuildError {
+    /// The time signature cannot be written
+    TimeSignature {numerator: i8, denominator: u16},
+    /// The tracks set different time signatures in the measure
+    TimeSignatureClash {measure: usize},
+    /// The tuplet of a beat cannot be written
+    Tuplet {track: usize, measure: usize, beat: usize, enters: u8, times: u8},
+    /// A note is on a string that its track does not have
+    String {track: usize, measure: usize, beat: usize, string: i8},
+    /// A voice is longer than its measure, the lengths are in ticks
+    OverfullMeasure {track: usize, measure: usize, length: i64, expected: i64},
+    /// Every MIDI channel is used, none is left for the track
+    NoFreeChannel {track: String},
+}
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::TimeSignature {numerator, denominator} => write!(f, "invalid time signature {}/{}", numerator, denominator),
+            BuildError::TimeSignatureClash {measure} => write!(f, "the tracks have different time signatures in measure {}", measure),
+            BuildError::Tuplet {track, measure, beat, enters, times} => write!(f, "unsupported {}:{} tuplet (track {}, measure {}, beat {})", enters, times, track, measure, beat),
+            BuildError::String {track, measure, beat, string} => write!(f, "no string {} on track {} (measure {}, beat {})", string, track, measure, beat),
+            BuildError::OverfullMeasure {track, measure, length, expected} => write!(f, "overfull measure {} on track {}: {} ticks instead of {}", measure, track, length, expected),
+            BuildError::NoFreeChannel {track} => write!(f, "no free MIDI channel left for the track {}", track),
+        }
+    }
+}
+impl std::error::Error for BuildError {}
+
+/// Build a song that can be written in any file format. The measure headers, the numbers, indexes and starts of the measures and the
+/// beats, the MIDI channels and the lyric lines are computed by `build()`.
+#[derive(Debug,Clone)]
+pub struct SongBuilder {
+    song: Song,
+    time_signature: (i8, u16),
+    tracks: Vec<TrackBuilder>,
+}
+
+/// Build a track of a `SongBuilder`
+#[derive(Debug,Clone)]
+pub struct TrackBuilder {
+    track: Track,
+    instrument: i32,
+    measures: Vec<MeasureBuilder>,
+}
+
+/// Build a measure of a `TrackBuilder`. The settings of the measure header (time signature, key signature, tempo, marker and repeats)
+/// are shared by the tracks: the first track setting them wins.
+#[derive(Debug,Clone,Default)]
+pub struct MeasureBuilder {
+    time_signature: Option<(i8, u16)>,
+    key_signature: Option<KeySignature>,
+    tempo: Option<i32>,
+    marker: Option<String>,
+    repeat_open: bool,
+    repeat_close: Option<i8>,
+    voice: usize,
+    voices: [Vec<Beat>; VOICE_COUNT],
+}
+
+impl Default for SongBuilder { fn default() -> Self { SongBuilder::new() }}
+impl SongBuilder {
+    pub fn new() -> SongBuilder {
+        let mut song = Song {version: Version {data: String::from("FICHIER GUITAR PRO v5.10"), number: (5,1,0), clipboard: false}, ..Default::default()};
+        for i in 0..LYRIC_LINE_COUNT {song.lyrics.lines.push((i, 1, String::new()));}
+        SongBuilder {song, time_signature: (4, 4), tracks: Vec::new()}
+    }
+    pub fn title(mut self, title: &str) -> Self { self.song.name = String::from(title); self }
+    pub fn artist(mut self, artist: &str) -> Self { self.song.artist = String::from(artist); self }
+    pub fn album(mut self, album: &str) -> Self { self.song.album = String::from(album); self }
+    /// Set the tempo in quarter notes per minute.
+    pub fn tempo(mut self, tempo: i16) -> Self { self.song.tempo = tempo; self }
+    /// Set the key of the song, also used by the measures without key signature.
+    pub fn key(mut self, key: KeySignature) -> Self { self.song.key = key; self }
+    /// Set the time signature of the measures without time signature, 4/4 by default.
+    pub fn time_signature(mut self, numerator: i8, denominator: u16) -> Self { self.time_signature = (numerator, denominator); self }
+    pub fn track(mut self, track: TrackBuilder) -> Self { self.tracks.push(track); self }
+
+    /// Build the song:
+    /// * every track gets as many measures as the longest track, the missing measures and the end of the underfull voices are filled with rests,
+    /// * a measure header is created for every measure with the time signature and key signature of the previous one if it does not set them,
+    /// * every track gets its MIDI channel and effect channel in any of the 4 MIDI ports, percussion tracks use the percussion channel.
+    ///
+    /// Returns an error if a time signature cannot be written or differs between the tracks, a voice is longer than its measure,
+    /// a beat has a tuplet that cannot be written, a note is not on a string of its track or the tracks need more than the 60 MIDI
+    /// channels available besides the percussion ones (a track uses a channel and an effect channel).
+    pub fn build(self) -> Result<Song, BuildError> {
+        let mut song = self.song;
+        for i in 0..CHANNEL_COUNT {song.channels.push(MidiChannel::default().with_channels(i, i));}
+        let default_time_signature = time_signature(self.time_signature)?;
+        //measure headers
+        let count = self.tracks.iter().map(|t| t.measures.len()).max().unwrap_or(0);
+        let mut start = DURATION_QUARTER_TIME;
+        for h in 0..count {
+            let previous = song.measure_headers.last();
+            let mut header = MeasureHeader {number: (h + 1).to_u16().unwrap(), start,
+                                            time_signature: previous.map(|p| p.time_signature.clone()).unwrap_or(default_time_signature.clone()),
+                                            key_signature: previous.map(|p| p.key_signature.clone()).unwrap_or(song.key.clone()), ..Default::default()};
+            let measures: Vec<&MeasureBuilder> = self.tracks.iter().filter_map(|t| t.measures.get(h)).collect();
+            if let Some(ts) = measures.iter().find_map(|m| m.time_signature) {
+                if measures.iter().filter_map(|m| m.time_signature).any(|t| t != ts) {return Err(BuildError::TimeSignatureClash {measure: h + 1});}
+                header.time_signature = time_signature(ts)?;
+            }
+            if let Some(k) = measures.iter().find_map(|m| m.key_signature.as_ref()) {header.key_signature = k.clone();}
+            if let Some(t) = measures.iter().find_map(|m| m.tempo) {header.tempo = t;}
+            if let Some(title) = measures.iter().find_map(|m| m.marker.as_ref()) {header.marker = Some(Marker {title: title.clone(), ..Default::default()});}
+            header.repeat_open = measures.iter().any(|m| m.repeat_open);
+            if let Some(c) = measures.iter().find_map(|m| m.repeat_close) {header.repeat_close = c;}
+            start += header.length();
+            song.measure_headers.push(header);
+        }
+        //tracks and channels
+        let mut used: Vec<usize> = Vec::with_capacity(32);
+        for (t, builder) in self.tracks.into_iter().enumerate() {
+            let mut track = builder.track;
+            track.number = t.to_i32().unwrap();
+            track.channel_index = if track.percussion_track {
+                let p = DEFAULT_PERCUSSION_CHANNEL.to_usize().unwrap();
+                song.channels[p].set_instrument(0);
+                p
+            } else {
+                let main = free_channel(&used).ok_or_else(|| BuildError::NoFreeChannel {track: track.name.clone()})?;
+                used.push(main);
+                let effect = free_channel(&used).unwrap_or(main);
+                used.push(effect);
+                song.channels[main] = MidiChannel::default().with_channels(main, effect);
+                song.channels[main].set_instrument(builder.instrument);
+                main
+            };
+            track.port = (track.channel_index / 16 + 1).to_u8().unwrap();
+            for (h, header) in song.measure_headers.iter().enumerate() {
+                let m = builder.measures.get(h).cloned().unwrap_or_default();
+                track.measures.push(m.build(&track, t, h, header)?);
+            }
+            song.tracks.push(track);
+        }
+        //tempo changes are written as mix table changes
+        if let Some(track) = song.tracks.first_mut() {
+            for (h, header) in song.measure_headers.iter().enumerate().filter(|(_, h)| h.tempo > 0) {
+                let beat = &mut track.measures[h].voices[0].beats[0];
+                let mut mtc = beat.effect.mix_table_change.take().unwrap_or_default();
+                mtc.tempo = Some(MixTableItem {value: header.tempo.clamp(1, 255).to_u8().unwrap(), ..Default::default()});
+                mtc.hide_tempo = false;
+                beat.effect.mix_table_change = Some(mtc);
+            }
+        }
+        Ok(song)
+    }
+}
+
+impl TrackBuilder {
+    /// Create a 6 strings guitar track in standard tuning, played with the MIDI instrument 25 (acoustic guitar, steel).
+    pub fn new(name: &str) -> TrackBuilder { TrackBuilder {track: Track {name: String::from(name), ..Default::default()}, instrument: 25, measures: Vec::new()} }
+    /// Set the tuning: MIDI pitches of the open strings, from the first (highest) string.
+    pub fn strings(mut self, pitches: &[i8]) -> Self {
+        self.track.strings = pitches.iter().enumerate().map(|(i, p)| ((i + 1).to_i8().unwrap(), *p)).collect();
+        self
+    }
+    /// Set the General MIDI program of the track.
+    pub fn instrument(mut self, program: i32) -> Self { self.instrument = program; self }
+    /// Make the track a drum track: the notes are MIDI drum sounds (e.g. `36` for the bass drum) on any of its 6 strings.
+    pub fn percussion(mut self) -> Self {
+        self.track.percussion_track = true;
+        self.track.strings = (1..=6).map(|s| (s, 0)).collect();
+        self
+    }
+    pub fn capo(mut self, fret: i32) -> Self { self.track.offset = fret; self }
+    pub fn fret_count(mut self, count: u8) -> Self { self.track.fret_count = count; self }
+    pub fn color(mut self, color: i32) -> Self { self.track.color = color; self }
+    pub fn measure(mut self, measure: MeasureBuilder) -> Self { self.measures.push(measure); self }
+}
+
+impl MeasureBuilder {
+    pub fn new() -> MeasureBuilder { MeasureBuilder::default() }
+    /// Change the time signature from this measure, e.g. `(6, 8)`.
+    pub fn time_signature(mut self, numerator: i8, denominator: u16) -> Self { self.time_signature = Some((numerator, denominator)); self }
+    /// Change the key signature from this measure.
+    pub fn key_signature(mut self, key: KeySignature) -> Self { self.key_signature = Some(key); self }
+    /// Change the tempo (in quarter notes per minute) at the start of this measure.
+    pub fn tempo(mut self, tempo: i32) -> Self { self.tempo = Some(tempo); self }
+    pub fn marker(mut self, title: &str) -> Self { self.marker = Some(String::from(title)); self }
+    pub fn repeat_open(mut self) -> Self { self.repeat_open = true; self }
+    /// Close a repeat at the end of this measure, the part is played `repeats + 1` times.
+    pub fn repeat_close(mut self, repeats: i8) -> Self { self.repeat_close = Some(repeats); self }
+    /// Add the next beats to a voice (`0` or `1`), the first one by default.
+    pub fn voice(mut self, voice: usize) -> Self {
+        if voice >= VOICE_COUNT {panic!("A measure has only {} voices", VOICE_COUNT);}
+        self.voice = voice;
+        self
+    }
+    /// Add a beat to the current voice. Its start is computed when the song is built. The measures without beats are whole measure rests.
+    pub fn beat(mut self, beat: Beat) -> Self { self.voices[self.voice].push(beat); self }
+    /// Add a beat playing a note on a string (`1` is the highest string) and a fret.
+    pub fn note(self, string: i8, fret: i16, duration: Duration) -> Self { self.notes(&[(string, fret)], duration) }
+    /// Add a beat playing notes given as `(string, fret)`, sorted by string like in the files.
+    pub fn notes(self, notes: &[(i8, i16)], duration: Duration) -> Self {
+        let mut beat = Beat {duration, ..Default::default()};
+        for (string, fret) in notes {
+            let mut note = Note::default();
+            (note.value, note.string, note.kind) = (*fret, *string, NoteType::Normal);
+            beat.notes.push(note);
+        }
+        beat.notes.sort_by_key(|n| n.string);
+        self.beat(beat)
+    }
+    pub fn rest(self, duration: Duration) -> Self { self.beat(Beat {duration, status: BeatStatus::Rest, ..Default::default()}) }
+
+    /// Create the measure of a track, checking its beats.
+    fn build(self, track: &Track, track_index: usize, header_index: usize, header: &MeasureHeader) -> Result<Measure, BuildError> {
+        let mut measure = Measure {number: header_index + 1, start: header.start, track_index, header_index, has_double_bar: header.double_bar,
+                                   key_signature: header.key_signature.clone(), time_signature: header.time_signature.clone(), ..Default::default()};
+        let expected = header.length();
+        for (v, beats) in self.voices.into_iter().enumerate() {
+            //the voices after the first one are not used without beats
+            if v > 0 && beats.is_empty() {
+                let beat = Beat {status: BeatStatus::Empty, start: Some(header.start), ..Default::default()};
+                measure.voices.push(Voice {measure_index: header_index.to_i16().unwrap(), beats: vec![beat], ..Default::default()});
+                continue;
+            }
+            let mut voice = Voice {measure_index: header_index.to_i16().unwrap(), beats, ..Default::default()};
+            let mut position = 0i64;
+            for (b, beat) in voice.beats.iter_mut().enumerate() {
+                if !beat.duration.is_supported() {
+                    return Err(BuildError::Tuplet {track: track_index + 1, measure: header_index + 1, beat: b + 1,
+                                                   enters: beat.duration.tuplet_enters, times: beat.duration.tuplet_times});
+                }
+                for n in beat.notes.iter().filter(|n| n.kind != NoteType::Rest) {
+                    if n.string < 1 || n.string.to_usize().unwrap() > track.strings.len() {
+                        return Err(BuildError::String {track: track_index + 1, measure: header_index + 1, beat: b + 1, string: n.string});
+                    }
+                }
+                beat.start = Some(header.start + position);
+                position += i64::from(beat.duration.time());
+            }
+            if position > expected {return Err(BuildError::OverfullMeasure {track: track_index + 1, measure: header_index + 1, length: position, expected});}
+            voice.beats.extend(rest_beats(header.start, position, expected));
+            measure.voices.push(voice);
+        }
+        Ok(measure)
+    }
+}
+
+/// Time signature with the default beams, as long as it can be written
+fn time_signature((numerator, denominator): (i8, u16)) -> Result<TimeSignature, BuildError> {
+    if numerator < 1 || !denominator.is_power_of_two() || denominator > 32 {return Err(BuildError::TimeSignature {numerator, denominator});}
+    Ok(TimeSignature {numerator, denominator: Duration {value: denominator, ..Default::default()}, ..Default::default()})
+}
@@ -0,0 +1,325 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, track::*, beat::*, chord::*, enums::*};
+
+/// Penalty of a chord without third (power chords and suspended chords excepted).
+const NO_THIRD_COST: i32 = 3;
+/// Penalty of a chord without fifth.
+const NO_FIFTH_COST: i32 = 1;
+/// Penalty of every extension or alteration.
+const EXTENSION_COST: i32 = 1;
+/// Penalty of a chord whose root is not the lowest note.
+const INVERSION_COST: i32 = 2;
+
+/// A chord found at some point of the song
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct ChordChange {
+    /// Index of the measure
+    pub measure: usize,
+    /// Start of the chord in ticks
+    pub start: i64,
+    pub chord: Chord,
+}
+
+/// The degrees found above a root.
+#[derive(Debug,Clone,Default)]
+struct Degrees {
+    kind: Option<ChordType>,
+    fifth: Option<ChordAlteration>,
+    seventh: bool,
+    ninth: Option<ChordAlteration>,
+    eleventh: Option<ChordAlteration>,
+    thirteenth: bool,
+    diminished_seventh: bool,
+    cost: i32,
+}
+
+/// Build a pitch class from its value (0 is C), spelled with sharps or flats.
+pub(crate) fn pitch_class(value: i8, sharp: bool) -> PitchClass {
+    let value = value.rem_euclid(12);
+    let mut p = if sharp || !SHARP_NOTES[value as usize].ends_with('#') {PitchClass::from(value, None, None)}
+                else {PitchClass::from((value + 1) % 12, Some(-1), None)};
+    p.value = value;
+    p.note = p.to_string();
+    p
+}
+
+/// Analyse the intervals (as a 12 bits mask) above a root and find the chord quality, extensions and alterations.
+/// Returns `None` when some interval cannot be explained.
+fn degrees(intervals: u16) -> Option<Degrees> {
+    let has = |i: u16| (intervals & (1 << i)) != 0;
+    let mut d = Degrees::default();
+    let mut used: u16 = 1;
+    let major = has(4);
+    let minor = has(3) && !major;
+    //fifth
+    let perfect = has(7);
+    if perfect {used |= 1 << 7;}
+    else if minor && has(6) {d.fifth = Some(ChordAlteration::Diminished); used |= 1 << 6;}
+    else if major && has(8) {d.fifth = Some(ChordAlteration::Augmented); used |= 1 << 8;}
+    else {d.cost += NO_FIFTH_COST;}
+    //sevenths
+    if has(10) {d.seventh = true; used |= 1 << 10;}
+    else if has(11) && !(minor && has(10)) {d.seventh = true; used |= 1 << 11;}
+    //third
+    if major {
+        used |= 1 << 4;
+        d.kind = Some(if d.fifth == Some(ChordAlteration::Augmented) && !d.seventh {ChordType::Augmented}
+                      else if has(10) {ChordType::Seventh} else if has(11) {ChordType::MajorSeventh}
+                      else if has(9) {ChordType::Sixth} else {ChordType::Major});
+        if d.kind == Some(ChordType::Augmented) {d.fifth = None;}
+        if has(3) {d.ninth = Some(ChordAlteration::Augmented); used |= 1 << 3;}
+    } else if minor {
+        used |= 1 << 3;
+        d.kind = Some(if d.fifth == Some(ChordAlteration::Diminished) && !d.seventh {ChordType::Diminished}
+                      else if has(10) {ChordType::MinorSeventh} else if has(11) {ChordType::MinorMajor}
+                      else if has(9) {ChordType::MinorSixth} else {ChordType::Minor});
+        if d.kind == Some(ChordType::Diminished) {
+            d.fifth = None;
+            if has(9) {d.diminished_seventh = true; used |= 1 << 9;}
+        }
+    } else if has(5) && perfect {
+        used |= 1 << 5;
+        d.kind = Some(if has(10) {ChordType::SeventhSuspendedFourth} else {ChordType::SuspendedFourth});
+    } else if has(2) && perfect {
+        used |= 1 << 2;
+        d.kind = Some(if has(10) {ChordType::SeventhSuspendedSecond} else {ChordType::SuspendedSecond});
+    } else if perfect && intervals == (1 | 1 << 7) {
+        d.kind = Some(ChordType::Power);
+    } else {
+        d.cost += NO_THIRD_COST;
+        d.kind = Some(if has(10) {ChordType::Seventh} else {ChordType::Major});
+    }
+    //sixths are part of the sixth chords
+    if matches!(d.kind, Some(ChordType::Sixth) | Some(ChordType::MinorSixth)) {used |= 1 << 9;}
+    //extensions
+    if (used & (1 << 2)) == 0 && has(2) {d.ninth = Some(ChordAlteration::Perfect); used |= 1 << 2;}
+    if has(1) {d.ninth = Some(ChordAlteration::Diminished); used |= 1 << 1;}
+    if (used & (1 << 5)) == 0 && has(5) {d.eleventh = Some(ChordAlteration::Perfect); used |= 1 << 5;}
+    if (used & (1 << 6)) == 0 && has(6) && perfect {d.eleventh = Some(ChordAlteration::Augmented); used |= 1 << 6;}
+    if (used & (1 << 9)) == 0 && has(9) && d.seventh {d.thirteenth = true; used |= 1 << 9;}
+    if (used & (1 << 8)) == 0 && has(8) && d.seventh {d.fifth = Some(ChordAlteration::Augmented); used |= 1 << 8;}
+    if used != intervals {return None;}
+    d.cost += EXTENSION_COST * (i32::from(d.ninth.is_some()) + i32::from(d.eleventh.is_some()) + i32::from(d.thirteenth) + i32::from(d.fifth.is_some()));
+    Some(d)
+}
+
+/// Name the chord formed by MIDI pitches. The lowest pitch is the bass. Returns `None` if there are less than 2 different pitch classes
+/// or if they cannot be named. When several roots fit, the chord with the fewest omissions, extensions and alterations wins.
+/// - `pitches`: the MIDI pitches of the notes
+/// - `sharp`: spell the notes with sharps (`true`) or with flats (`false`)
+pub fn detect_chord(pitches: &[i16], sharp: bool) -> Option<Chord> {
+    let bass = pitches.iter().min()?.rem_euclid(12).to_i8().unwrap();
+    let mut set: u16 = 0;
+    for p in pitches {set |= 1 << p.rem_euclid(12);}
+    if set.count_ones() < 2 {return None;}
+    let mut best: Option<(i32, i8, Degrees)> = None;
+    for root in 0..12i8 {
+        if (set & (1 << root)) == 0 {continue;}
+        let intervals = ((set >> root) | (set << (12 - root))) & 0x0fff;
+        if let Some(d) = degrees(intervals) {
+            let cost = d.cost + if root != bass {INVERSION_COST} else {0};
+            if best.as_ref().map(|b| cost < b.0).unwrap_or(true) {best = Some((cost, root, d));}
+        }
+    }
+    let (_, root, d) = best?;
+    let extension = if d.thirteenth {ChordExtension::Thirteenth}
+                    else if d.eleventh == Some(ChordAlteration::Perfect) {ChordExtension::Eleventh}
+                    else if d.ninth == Some(ChordAlteration::Perfect) {ChordExtension::Ninth}
+                    else {ChordExtension::None};
+    let mut chord = Chord {sharp: Some(sharp), root: Some(pitch_class(root, sharp)), kind: d.kind.clone(), extension: Some(extension.clone()),
+                           bass: if bass != root {Some(pitch_class(bass, sharp))} else {None},
+                           add: Some(!d.seventh && extension != ChordExtension::None),
+                           fifth: d.fifth.clone(), ninth: d.ninth.clone(), eleventh: d.eleventh.clone(),
+                           new_format: Some(true), show: Some(true), ..Default::default()};
+    chord.name = chord_name(&chord, &d);
+    Some(chord)
+}
+
+/// Get the suffix of a chord type in chord names, e.g. *m7* for `ChordType::MinorSeventh`.
+pub(crate) fn chord_type_suffix(kind: &ChordType) -> &'static str {
+    match kind {
+        ChordType::Major => "", ChordType::Seventh => "7", ChordType::MajorSeventh => "maj7", ChordType::Sixth => "6",
+        ChordType::Minor => "m", ChordType::MinorSeventh => "m7", ChordType::MinorMajor => "m(maj7)", ChordType::MinorSixth => "m6",
+        ChordType::SuspendedSecond => "sus2", ChordType::SuspendedFourth => "sus4",
+        ChordType::SeventhSuspendedSecond => "7sus2", ChordType::SeventhSuspendedFourth => "7sus4",
+        ChordType::Diminished => "dim", ChordType::Augmented => "aug", ChordType::Power => "5",
+        ChordType::Unknown(_) => "",
+    }
+}
+
+/// Build the name of the chord, e.g. *Am7b5* or *C9/E*.
+fn chord_name(chord: &Chord, d: &Degrees) -> String {
+    let mut suffix = String::from(chord_type_suffix(chord.kind.as_ref().unwrap()));
+    if d.diminished_seventh {suffix.push('7');}
+    let extension = match chord.extension {
+        Some(ChordExtension::Ninth) => "9", Some(ChordExtension::Eleventh) => "11", Some(ChordExtension::Thirteenth) => "13", _ => "",
+    };
+    if d.seventh {
+        if !extension.is_empty() {suffix = suffix.replacen('7', extension, 1);}
+    } else if !extension.is_empty() {
+        suffix.push_str("add");
+        suffix.push_str(extension);
+    }
+    if chord.fifth == Some(ChordAlteration::Diminished) {suffix.push_str("b5");}
+    if chord.fifth == Some(ChordAlteration::Augmented) {suffix.push_str("#5");}
+    if chord.ninth == Some(ChordAlteration::Diminished) {suffix.push_str("b9");}
+    if chord.ninth == Some(ChordAlteration::Augmented) {suffix.push_str("#9");}
+    if chord.eleventh == Some(ChordAlteration::Augmented) {suffix.push_str("#11");}
+    //name is limited to 22 characters in the files
+    let mut name = format!("{}{}", chord.root.as_ref().unwrap(), suffix);
+    if let Some(b) = &chord.bass {name.push_str(&format!("/{}", b));}
+    name.truncate(22);
+    name
+}
+
+impl Track {
+    /// Get the MIDI pitches of the notes sounding at `start` (in ticks) in all the voices of a measure. Rests, dead notes and beats without
+    /// start (see `Song::update_beat_starts()`) are ignored.
+    pub fn pitches_at(&self, measure: usize, start: i64) -> Vec<i16> {
+        let mut pitches: Vec<i16> = Vec::new();
+        if self.percussion_track {return pitches;}
+        for v in &self.measures[measure].voices {
+            for b in v.beats.iter().filter(|b| b.status == BeatStatus::Normal) {
+                let s = match b.start {Some(s) => s, None => continue};
+                if s > start || s + i64::from(b.duration.time()) <= start {continue;}
+                for n in b.notes.iter().filter(|n| n.kind == NoteType::Normal || n.kind == NoteType::Tie) {
+                    if let Some(p) = self.fingering_pitch(n) {if !pitches.contains(&p) {pitches.push(p);}}
+                }
+            }
+        }
+        pitches
+    }
+
+    /// Name the chord played on a beat, including the notes of the other voices sounding at the same time.
+    /// The chord diagram is filled with the frets of the notes of the beat.
+    pub fn detect_beat_chord(&self, measure: usize, voice: usize, beat: usize) -> Option<Chord> {
+        let b = &self.measures[measure].voices[voice].beats[beat];
+        let sharp = self.measures[measure].key_signature.key >= 0;
+        let mut chord = detect_chord(&self.pitches_at(measure, b.start?), sharp)?;
+        self.fill_chord_diagram(&mut chord, b);
+        Some(chord)
+    }
+
+    /// Fill the strings of a chord diagram with the frets of the notes of a beat.
+    fn fill_chord_diagram(&self, chord: &mut Chord, beat: &Beat) {
+        chord.length = self.strings.len().to_u8().unwrap();
+        chord.strings = vec![-1; self.strings.len()];
+        for n in beat.notes.iter().filter(|n| n.kind != NoteType::Rest && n.string > 0) {
+            let index = (n.string - 1).to_usize().unwrap();
+            if index < chord.strings.len() {chord.strings[index] = if n.kind == NoteType::Dead {-1} else {n.value.to_i8().unwrap()};}
+        }
+        chord.first_fret = Some(chord.strings.iter().filter(|f| **f > 0).min().copied().unwrap_or(1).to_u8().unwrap());
+    }
+
+    /// Fill the missing chord annotations of the track. A beat is annotated when its chord differs from the chord previously
+    /// found or annotated in the track, so that sustained or arpeggiated chords are named once. Existing chords are kept.
+    /// Returns the number of chords added.
+    pub fn detect_chords(&mut self) -> usize {
+        let mut count = 0;
+        let mut last = String::new();
+        for m in 0..self.measures.len() {
+            for v in 0..self.measures[m].voices.len() {
+                for b in 0..self.measures[m].voices[v].beats.len() {
+                    if let Some(c) = &self.measures[m].voices[v].beats[b].effect.chord {
+                        last = c.name.clone();
+                        continue;
+                    }
+                    if let Some(c) = self.detect_beat_chord(m, v, b) {
+                        if c.name == last {continue;}
+                        last = c.name.clone();
+                        self.measures[m].voices[v].beats[b].effect.chord = Some(c);
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+}
+
+impl Song {
+    /// Get the chord progression played by several tracks together: at every beat start of the given tracks, the notes sounding in
+    /// all these tracks are merged and named. Only the changes of chord are returned.
+    /// - `tracks`: indexes of the tracks to analyse. If empty, all the tracks which are not percussion tracks are used.
+    pub fn chord_progression(&self, tracks: &[usize]) -> Vec<ChordChange> {
+        let tracks: Vec<usize> = if tracks.is_empty() {(0..self.tracks.len()).filter(|t| !self.tracks[*t].percussion_track).collect()} else {tracks.to_vec()};
+        let mut changes: Vec<ChordChange> = Vec::new();
+        for m in 0..self.measure_headers.len() {
+            let mut starts: Vec<i64> = tracks.iter().filter(|t| m < self.tracks[**t].measures.len())
+                                             .flat_map(|t| self.tracks[*t].measures[m].voices.iter().flat_map(|v| v.beats.iter().filter_map(|b| b.start)))
+                                             .collect();
+            starts.sort_unstable();
+            starts.dedup();
+            let sharp = self.measure_headers[m].key_signature.key >= 0;
+            for start in starts {
+                let pitches: Vec<i16> = tracks.iter().filter(|t| m < self.tracks[**t].measures.len()).flat_map(|t| self.tracks[*t].pitches_at(m, start)).collect();
+                if let Some(chord) = detect_chord(&pitches, sharp) {
+                    if changes.last().map(|c| c.chord.name != chord.name).unwrap_or(true) {changes.push(ChordChange {measure: m, start, chord});}
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{chord_detection::*, note::*, measure::*, key_signature::*};
+
+    fn name(pitches: &[i16]) -> String { detect_chord(pitches, true).map(|c| c.name).unwrap_or_default() }
+
+    #[test]
+    fn test_triads() {
+        assert_eq!(name(&[48, 52, 55]), "C");
+        assert_eq!(name(&[45, 52, 57, 60, 64]), "Am");
+        assert_eq!(name(&[40, 47]), "E5");
+        assert_eq!(name(&[50, 57, 62, 67]), "Dsus4");
+        assert_eq!(name(&[47, 50, 53]), "Bdim");
+        assert_eq!(name(&[48, 52, 56]), "Caug");
+        assert_eq!(name(&[52, 55, 60]), "C/E");
+        assert_eq!(name(&[60]), "");
+    }
+
+    #[test]
+    fn test_extensions() {
+        assert_eq!(name(&[43, 47, 50, 53]), "G7");
+        assert_eq!(name(&[48, 52, 55, 59, 62]), "Cmaj9");
+        assert_eq!(name(&[45, 48, 51, 55]), "Am7b5");
+        assert_eq!(name(&[48, 52, 55, 62]), "Cadd9");
+        assert_eq!(name(&[40, 44, 47, 50, 55]), "E7#9");
+        let c = detect_chord(&[46, 50, 53], false).unwrap();
+        assert_eq!(c.name, "Bb");
+        assert_eq!(c.root.unwrap().value, 10);
+        assert_eq!(c.kind, Some(ChordType::Major));
+    }
+
+    #[test]
+    fn test_detect_track_chords() {
+        let mut track = Track::default();
+        let mut measure = Measure {key_signature: KeySignature::default(), ..Default::default()};
+        let mut voice = Voice::default();
+        //E major strummed twice, then an open A minor
+        for (i, frets) in [[0, 0, 1, 2, 2, 0], [0, 0, 1, 2, 2, 0], [0, 1, 2, 2, 0, -1]].iter().enumerate() {
+            let mut beat = Beat {start: Some(960 + 960 * i as i64), ..Default::default()};
+            for (s, f) in frets.iter().enumerate() {
+                if *f < 0 {continue;}
+                let mut note = Note::default();
+                note.string = (s + 1) as i8;
+                note.value = *f;
+                note.kind = NoteType::Normal;
+                beat.notes.push(note);
+            }
+            voice.beats.push(beat);
+        }
+        measure.voices.push(voice);
+        track.measures.push(measure);
+        assert_eq!(track.detect_chords(), 2);
+        let beats = &track.measures[0].voices[0].beats;
+        assert_eq!(beats[0].effect.chord.as_ref().unwrap().name, "E");
+        assert_eq!(beats[0].effect.chord.as_ref().unwrap().strings, vec![0, 0, 1, 2, 2, 0]);
+        assert!(beats[1].effect.chord.is_none());
+        assert_eq!(beats[2].effect.chord.as_ref().unwrap().name, "Am");
+        assert_eq!(beats[2].effect.chord.as_ref().unwrap().strings[5], -1);
+    }
+}
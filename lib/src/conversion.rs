@@ -0,0 +1,130 @@
+use crate::{gp::*, beat::*, note::*, enums::*};
+
+/// A kind of data lost when a song is written in an older file format
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConversionWarning {
+    /// Lowest major version of the file format supporting the data
+    pub since: u8,
+    /// Number of occurrences in the song
+    pub count: usize,
+    /// Description of the lost data
+    pub description: String,
+}
+impl std::fmt::Display for ConversionWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} (requires Guitar Pro {} or later)", self.count, self.description, self.since)
+    }
+}
+
+/// Counters of the data lost by a conversion
+#[derive(Default)]
+struct Losses {
+    second_voice_beats: usize,
+    rse_tracks: usize,
+    directions: usize,
+    triplet_feels: usize,
+    line_breaks: usize,
+    octaves: usize,
+    beat_displays: usize,
+    rse_mix_table_changes: usize,
+    duration_percents: usize,
+    swap_accidentals: usize,
+    multiple_slides: usize,
+    lyrics: usize,
+    staccatos: usize,
+    palm_mutes: usize,
+    tremolo_pickings: usize,
+    trills: usize,
+    harmonics: usize,
+    slides: usize,
+    fingerings: usize,
+    accentuations: usize,
+    rasgueados: usize,
+    pick_strokes: usize,
+    tremolo_bars: usize,
+}
+
+impl Song {
+    /// Get the data of the song that cannot be written in a file of the given `version`, grouped by kind. An empty list means that
+    /// no musical data is lost by the conversion.
+    pub fn conversion_warnings(&self, version: (u8,u8,u8)) -> Vec<ConversionWarning> {
+        let mut l = Losses::default();
+        if version.0 < 5 {
+            l.rse_tracks = self.tracks.iter().filter(|t| t.use_rse).count();
+            l.directions = self.measure_headers.iter().filter(|h| h.direction.is_some()).count();
+            l.triplet_feels = self.measure_headers.iter().filter(|h| h.triplet_feel != self.triplet_feel).count();
+        }
+        if version.0 < 4 {l.lyrics = self.lyrics.lines.iter().filter(|l| !l.2.trim().is_empty()).count();}
+        for t in &self.tracks {
+            for m in &t.measures {
+                if version.0 < 5 && m.line_break != LineBreak::None {l.line_breaks += 1;}
+                for (v, voice) in m.voices.iter().enumerate() {
+                    if version.0 < 5 && v > 0 {l.second_voice_beats += voice.beats.iter().filter(|b| b.status != BeatStatus::Empty).count(); continue;}
+                    for b in &voice.beats {self.beat_losses(b, version, &mut l);}
+                }
+            }
+        }
+        let mut warnings = Vec::new();
+        for (since, count, description) in [(5, l.second_voice_beats, "beats of the second voice"),
+                                            (5, l.rse_tracks, "tracks using RSE"),
+                                            (5, l.directions, "measures with a direction (coda, segno, ...)"),
+                                            (5, l.triplet_feels, "measures with their own triplet feel"),
+                                            (5, l.line_breaks, "measures with a line break"),
+                                            (5, l.octaves, "beats with an octave sign"),
+                                            (5, l.beat_displays, "beats with beam or bracket display settings"),
+                                            (5, l.rse_mix_table_changes, "mix table changes with RSE or wah settings"),
+                                            (5, l.duration_percents, "notes with a duration percent"),
+                                            (5, l.swap_accidentals, "notes with swapped accidentals"),
+                                            (5, l.multiple_slides, "notes with several slides"),
+                                            (4, l.lyrics, "lyric lines"),
+                                            (4, l.staccatos, "staccato notes"),
+                                            (4, l.palm_mutes, "palm muted notes"),
+                                            (4, l.tremolo_pickings, "notes with tremolo picking"),
+                                            (4, l.trills, "notes with a trill"),
+                                            (4, l.harmonics, "tapped, pinch or semi harmonics"),
+                                            (4, l.slides, "notes with a slide into or out of the note"),
+                                            (4, l.fingerings, "notes with fingering"),
+                                            (4, l.accentuations, "accentuated notes"),
+                                            (4, l.rasgueados, "beats with rasgueado"),
+                                            (4, l.pick_strokes, "beats with a pick stroke"),
+                                            (4, l.tremolo_bars, "tremolo bars other than a dip")] {
+            if count > 0 {warnings.push(ConversionWarning {since, count, description: String::from(description)});}
+        }
+        warnings
+    }
+
+    fn beat_losses(&self, beat: &Beat, version: (u8,u8,u8), l: &mut Losses) {
+        if version.0 < 5 {
+            if beat.octave != Octave::None {l.octaves += 1;}
+            if beat.display != BeatDisplay::default() {l.beat_displays += 1;}
+            if let Some(mtc) = &beat.effect.mix_table_change {if mtc.use_rse || mtc.wah.is_some() {l.rse_mix_table_changes += 1;}}
+        }
+        if version.0 < 4 {
+            if beat.effect.has_rasgueado {l.rasgueados += 1;}
+            if beat.effect.has_pick_stroke() {l.pick_strokes += 1;}
+            //Guitar Pro 3 only stores the value of a dip
+            if let Some(t) = &beat.effect.tremolo_bar {if t.kind != BendType::Dip || t.points.len() != 3 {l.tremolo_bars += 1;}}
+        }
+        for n in &beat.notes {note_losses(n, version, l);}
+    }
+}
+
+fn note_losses(note: &Note, version: (u8,u8,u8), l: &mut Losses) {
+    let e = &note.effect;
+    if version.0 < 5 {
+        if (note.duration_percent - 1.0).abs() > 1e-3 {l.duration_percents += 1;}
+        if note.swap_accidentals {l.swap_accidentals += 1;}
+        if e.slides.len() > 1 {l.multiple_slides += 1;}
+    }
+    if version.0 < 4 {
+        if e.staccato {l.staccatos += 1;}
+        if e.palm_mute {l.palm_mutes += 1;}
+        if e.tremolo_picking.is_some() {l.tremolo_pickings += 1;}
+        if e.trill.is_some() {l.trills += 1;}
+        if let Some(h) = &e.harmonic {if h.kind != HarmonicType::Natural && h.kind != HarmonicType::Artificial {l.harmonics += 1;}}
+        if e.slides.iter().any(|s| *s != SlideType::ShiftSlideTo && *s != SlideType::LegatoSlideTo) {l.slides += 1;}
+        if e.left_hand_finger != Fingering::Open || e.right_hand_finger != Fingering::Open {l.fingerings += 1;}
+        if e.accentuated_note {l.accentuations += 1;}
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::{gp::*, headers::*, track::*, beat::*, note::*, key_signature::*, enums::*};
+
+/// A difference between an old and a new version of a song
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Difference {
+    /// Index of the track, for the differences of the tracks and of the beats
+    pub track: Option<usize>,
+    /// Index of the measure in the old song, `None` for a measure added to the new song
+    pub old_measure: Option<usize>,
+    /// Index of the measure in the new song, `None` for a measure removed from the old song
+    pub new_measure: Option<usize>,
+    pub voice: Option<usize>,
+    /// Position of the beat from the start of its measure, in ticks
+    pub position: Option<i64>,
+    /// Name of the compared value, e.g. `title`, `tuning`, `time signature` or `notes`
+    pub field: String,
+    /// Value in the old song, `None` when it was added
+    pub old: Option<String>,
+    /// Value in the new song, `None` when it was removed
+    pub new: Option<String>,
+}
+impl Difference {
+    fn new(field: &str, old: Option<String>, new: Option<String>) -> Difference {
+        Difference {track: None, old_measure: None, new_measure: None, voice: None, position: None, field: String::from(field), old, new}
+    }
+}
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut location = Vec::new();
+        match (self.old_measure, self.new_measure) {
+            (Some(o), Some(n)) if o == n => location.push(format!("measure {}", o + 1)),
+            (Some(o), Some(n)) => location.push(format!("measure {} (new {})", o + 1, n + 1)),
+            (Some(o), None) => location.push(format!("measure {}", o + 1)),
+            (None, Some(n)) => location.push(format!("new measure {}", n + 1)),
+            (None, None) => (),
+        }
+        if let Some(t) = self.track {location.push(format!("track {}", t + 1));}
+        if let Some(v) = self.voice {location.push(format!("voice {}", v + 1));}
+        if let Some(p) = self.position {location.push(format!("beat at tick {}", p));}
+        let location = if location.is_empty() {String::from("song")} else {location.join(", ")};
+        match (&self.old, &self.new) {
+            (Some(o), Some(n)) => write!(f, "{}: {}: {} -> {}", location, self.field, o, n),
+            (Some(o), None) => write!(f, "{}: {} removed: {}", location, self.field, o),
+            (None, Some(n)) => write!(f, "{}: {} added: {}", location, self.field, n),
+            (None, None) => write!(f, "{}: {}", location, self.field),
+        }
+    }
+}
+
+impl Song {
+    /// Compare the song (the old version) with a new version: information, tracks (name, instrument, tuning, capo, frets), measure
+    /// headers (time signature, tempo, key, repeats, markers, ...) and the beats of each voice of the tracks of both songs.
+    /// The measures are aligned first (see `align_measures()`) so that an inserted or deleted measure does not change the next ones.
+    /// The beats of a voice are compared by position in the measure.
+    pub fn diff(&self, new: &Song) -> Vec<Difference> {
+        let mut differences = Vec::new();
+        let info = [("title", &self.name, &new.name), ("subtitle", &self.subtitle, &new.subtitle), ("artist", &self.artist, &new.artist),
+                    ("album", &self.album, &new.album), ("words", &self.words, &new.words), ("music", &self.author, &new.author),
+                    ("copyright", &self.copyright, &new.copyright), ("writer", &self.writer, &new.writer), ("transcriber", &self.transcriber, &new.transcriber),
+                    ("instructions", &self.instructions, &new.instructions), ("comments", &self.comments, &new.comments)];
+        for (field, a, b) in info {if a != b {differences.push(Difference::new(field, Some(a.clone()), Some(b.clone())));}}
+        let values = [("notice", self.notice.join("\n"), new.notice.join("\n")), ("tempo", self.tempo.to_string(), new.tempo.to_string()),
+                      ("key", self.key.to_string(), new.key.to_string()), ("lyrics", self.lyrics.to_string(), new.lyrics.to_string())];
+        for (field, a, b) in values {if a != b {differences.push(Difference::new(field, Some(a), Some(b)));}}
+        //tracks
+        for t in 0..self.tracks.len().max(new.tracks.len()) {
+            let track = |field: &str, old: Option<String>, new: Option<String>| Difference {track: Some(t), ..Difference::new(field, old, new)};
+            match (self.tracks.get(t), new.tracks.get(t)) {
+                (Some(a), Some(b)) => {
+                    for ((field, x), (_, y)) in self.track_fields(a).into_iter().zip(new.track_fields(b)) {
+                        if x != y {differences.push(track(field, Some(x), Some(y)));}
+                    }
+                },
+                (Some(a), None) => differences.push(track("track", Some(a.name.clone()), None)),
+                (None, Some(b)) => differences.push(track("track", None, Some(b.name.clone()))),
+                (None, None) => (),
+            }
+        }
+        //measures
+        let tracks = self.tracks.len().min(new.tracks.len());
+        for (o, n) in self.align_measures(new) {
+            let measure = |field: &str, old: Option<String>, new: Option<String>| Difference {old_measure: o, new_measure: n, ..Difference::new(field, old, new)};
+            match (o, n) {
+                (Some(i), Some(j)) => {
+                    for ((field, x), (_, y)) in header_fields(&self.measure_headers[i]).into_iter().zip(header_fields(&new.measure_headers[j])) {
+                        if x != y {differences.push(measure(field, Some(x), Some(y)));}
+                    }
+                    for t in 0..tracks {
+                        let (a, b) = (self.tracks[t].measures.get(i), new.tracks[t].measures.get(j));
+                        let voices = a.map(|m| m.voices.len()).unwrap_or(0).max(b.map(|m| m.voices.len()).unwrap_or(0));
+                        for v in 0..voices {
+                            let x = beat_positions(a.and_then(|m| m.voices.get(v)));
+                            let y = beat_positions(b.and_then(|m| m.voices.get(v)));
+                            let beat = |position: i64, field: &str, old: Option<String>, new: Option<String>| Difference {track: Some(t), voice: Some(v), position: Some(position), ..measure(field, old, new)};
+                            let (mut p, mut q) = (0, 0);
+                            while p < x.len() || q < y.len() {
+                                if q == y.len() || (p < x.len() && x[p].1 < y[q].1) {differences.push(beat(x[p].1, "beat", Some(beat_text(x[p].2)), None)); p += 1;}
+                                else if p == x.len() || y[q].1 < x[p].1 {differences.push(beat(y[q].1, "beat", None, Some(beat_text(y[q].2)))); q += 1;}
+                                else {
+                                    for (field, old, new) in beat_changes(x[p].2, y[q].2) {differences.push(beat(x[p].1, &field, Some(old), Some(new)));}
+                                    p += 1;
+                                    q += 1;
+                                }
+                            }
+                        }
+                    }
+                },
+                (Some(i), None) => differences.push(measure("measure", Some(measure_text(self, i)), None)),
+                (None, Some(j)) => differences.push(measure("measure", None, Some(measure_text(new, j)))),
+                (None, None) => (),
+            }
+        }
+        differences
+    }
+
+    /// Align the measures of the song with the measures of a new version: the identical measures (same header and same beats in the
+    /// tracks of both songs) are matched in order with a longest common subsequence, the measures between them are paired as changed
+    /// measures and the extra ones are removed (no new index) or added (no old index).
+    pub fn align_measures(&self, new: &Song) -> Vec<(Option<usize>, Option<usize>)> {
+        let tracks = self.tracks.len().min(new.tracks.len());
+        let (a, b) = (self.measure_fingerprints(tracks), new.measure_fingerprints(tracks));
+        //lengths of the common subsequences of the ends of the measure lists
+        let mut lengths = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {lengths[i][j] = if a[i] == b[j] {lengths[i + 1][j + 1] + 1} else {lengths[i + 1][j].max(lengths[i][j + 1])};}
+        }
+        let mut pairs = Vec::with_capacity(a.len().max(b.len()));
+        let (mut removed, mut added) = (Vec::new(), Vec::new());
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                pair_gap(&mut pairs, &mut removed, &mut added);
+                pairs.push((Some(i), Some(j)));
+                i += 1;
+                j += 1;
+            } else if j == b.len() || (i < a.len() && lengths[i + 1][j] > lengths[i][j + 1]) {removed.push(i); i += 1;}
+            else {added.push(j); j += 1;}
+        }
+        pair_gap(&mut pairs, &mut removed, &mut added);
+        pairs
+    }
+
+    /// Hash the header and the beats of the first `tracks` tracks of each measure
+    pub(crate) fn measure_fingerprints(&self, tracks: usize) -> Vec<u64> {
+        self.measure_headers.iter().enumerate().map(|(m, h)| {
+            let mut hasher = DefaultHasher::new();
+            header_fields(h).hash(&mut hasher);
+            for t in &self.tracks[..tracks] {
+                for v in t.measures.get(m).map(|m| m.voices.as_slice()).unwrap_or_default() {
+                    for b in v.beats.iter().filter(|b| b.status != BeatStatus::Empty) {beat_fingerprint(b).hash(&mut hasher);}
+                }
+            }
+            hasher.finish()
+        }).collect()
+    }
+
+    /// Compared values of a track
+    pub(crate) fn track_fields(&self, track: &Track) -> [(&'static str, String); 5] {
+        let instrument = if track.percussion_track {String::from("Percussion")}
+                         else {self.channels.get(track.channel_index).map(|c| c.get_instrument_name()).unwrap_or_default()};
+        //the strings are stored from the highest one, the tuning is written from the lowest one
+        let tuning = track.strings.iter().rev().map(|s| s.1.to_string()).collect::<Vec<String>>().join(" ");
+        [("name", track.name.clone()), ("instrument", instrument), ("tuning", tuning), ("capo", track.offset.to_string()), ("fret count", track.fret_count.to_string())]
+    }
+}
+
+/// Add the measures found between 2 identical measures: the first ones are paired as changed measures, the other ones are removed or added
+fn pair_gap(pairs: &mut Vec<(Option<usize>, Option<usize>)>, removed: &mut Vec<usize>, added: &mut Vec<usize>) {
+    for k in 0..removed.len().max(added.len()) {pairs.push((removed.get(k).copied(), added.get(k).copied()));}
+    removed.clear();
+    added.clear();
+}
+
+/// Compared values of a measure header
+pub(crate) fn header_fields(header: &MeasureHeader) -> [(&'static str, String); 10] {
+    let none = || String::from("none");
+    [("time signature", format!("{}/{}", header.time_signature.numerator, header.time_signature.denominator.value)),
+     ("tempo", header.tempo.to_string()), ("key signature", header.key_signature.to_string()), ("triplet feel", format!("{:?}", header.triplet_feel)),
+     ("repeat open", header.repeat_open.to_string()),
+     ("repeat close", if header.repeat_close < 0 {none()} else {header.repeat_close.to_string()}),
+     ("alternative endings", if header.repeat_alternative == 0 {none()} else {(0..8).filter(|b| header.repeat_alternative & (1 << b) != 0).map(|b| (b + 1).to_string()).collect::<Vec<String>>().join(",")}),
+     ("marker", header.marker.as_ref().map(|m| m.title.clone()).unwrap_or_else(none)),
+     ("direction", header.direction.as_ref().map(|d| format!("{:?}", d)).unwrap_or_else(none)),
+     ("double bar", header.double_bar.to_string())]
+}
+
+/// Summary of an added or removed measure
+pub(crate) fn measure_text(song: &Song, measure: usize) -> String {
+    let h = &song.measure_headers[measure];
+    format!("{}/{}, {} beats", h.time_signature.numerator, h.time_signature.denominator.value,
+            song.tracks.iter().filter_map(|t| t.measures.get(measure)).flat_map(|m| &m.voices).flat_map(|v| &v.beats).filter(|b| b.status != BeatStatus::Empty).count())
+}
+
+/// Beats of a voice with their index and their position from the start of the measure, the empty beats are skipped
+pub(crate) fn beat_positions(voice: Option<&Voice>) -> Vec<(usize, i64, &Beat)> {
+    let mut position = 0;
+    let mut beats = Vec::new();
+    for (i, b) in voice.map(|v| v.beats.as_slice()).unwrap_or_default().iter().enumerate() {
+        if b.status != BeatStatus::Empty {beats.push((i, position, b));}
+        position += i64::from(b.duration.time());
+    }
+    beats
+}
+
+/// Differences of 2 beats at the same position as (field, old value, new value)
+fn beat_changes(a: &Beat, b: &Beat) -> Vec<(String, String, String)> {
+    let mut changes = Vec::new();
+    let mut compare = |field: String, x: String, y: String| if x != y {changes.push((field, x, y));};
+    compare(String::from("duration"), duration_text(&a.duration), duration_text(&b.duration));
+    let (notes_a, notes_b) = (notes_text(a), notes_text(b));
+    if notes_a == notes_b {
+        for (x, y) in a.notes.iter().zip(&b.notes) {
+            let (old, new) = effects_text(note_effect_names(&x.effect), note_effect_names(&y.effect), x.effect != y.effect);
+            compare(format!("effects of string {}", x.string), old, new);
+            compare(format!("velocity of string {}", x.string), x.velocity.to_string(), y.velocity.to_string());
+        }
+    } else {compare(String::from("notes"), notes_a, notes_b);}
+    let (old, new) = effects_text(beat_effect_names(&a.effect), beat_effect_names(&b.effect), a.effect != b.effect);
+    compare(String::from("beat effects"), old, new);
+    compare(String::from("text"), a.text.clone(), b.text.clone());
+    compare(String::from("octave"), format!("{:?}", a.octave), format!("{:?}", b.octave));
+    changes
+}
+
+/// Everything compared in a beat (its start excepted)
+pub(crate) fn beat_fingerprint(b: &Beat) -> String { format!("{:?}{:?}{:?}{:?}{:?}{}", b.notes, b.duration, b.effect, b.octave, b.display, b.text) }
+
+/// Summary of an added or removed beat: its duration and its notes
+pub(crate) fn beat_text(beat: &Beat) -> String { format!("{} {}", duration_text(&beat.duration), notes_text(beat)) }
+
+/// Note value of a duration, e.g. `1/8.` or `1/8 (3:2)`
+fn duration_text(duration: &Duration) -> String {
+    let dots = if duration.double_dotted {".."} else if duration.dotted {"."} else {""};
+    let tuplet = if duration.tuplet_enters != duration.tuplet_times {format!(" ({}:{})", duration.tuplet_enters, duration.tuplet_times)} else {String::new()};
+    format!("1/{}{}{}", duration.value, dots, tuplet)
+}
+
+/// Notes of a beat as `string:fret`, the tied notes are written `string:(fret)` and the dead notes `string:x`
+fn notes_text(beat: &Beat) -> String {
+    let notes: Vec<String> = beat.notes.iter().filter(|n| n.kind != NoteType::Rest).map(|n| match n.kind {
+        NoteType::Tie => format!("{}:({})", n.string, n.value),
+        NoteType::Dead => format!("{}:x", n.string),
+        _ => format!("{}:{}", n.string, n.value),
+    }).collect();
+    if notes.is_empty() {String::from("rest")} else {notes.join(" ")}
+}
+
+fn note_effect_names(e: &NoteEffect) -> Vec<String> {
+    let flags = [(e.accentuated_note, "accent"), (e.heavy_accentuated_note, "heavy accent"), (e.ghost_note, "ghost note"), (e.hammer, "hammer-on/pull-off"),
+                 (e.let_ring, "let ring"), (e.palm_mute, "palm mute"), (e.staccato, "staccato"), (e.vibrato, "vibrato"), (e.bend.is_some(), "bend"),
+                 (e.grace.is_some(), "grace note"), (e.harmonic.is_some(), "harmonic"), (e.tremolo_picking.is_some(), "tremolo picking"),
+                 (e.trill.is_some(), "trill"), (!e.slides.is_empty(), "slide"),
+                 (e.left_hand_finger != Fingering::Open || e.right_hand_finger != Fingering::Open, "fingering")];
+    flags.iter().filter(|f| f.0).map(|f| String::from(f.1)).collect()
+}
+
+fn beat_effect_names(e: &BeatEffects) -> Vec<String> {
+    let d = BeatEffects::default();
+    let flags = [(e.stroke != d.stroke, "stroke"), (e.has_rasgueado, "rasgueado"), (e.pick_stroke != d.pick_stroke, "pick stroke"), (e.fade_in, "fade in"),
+                 (e.tremolo_bar.is_some(), "tremolo bar"), (e.slap_effect != d.slap_effect, "slap"), (e.vibrato, "vibrato")];
+    let mut names: Vec<String> = flags.iter().filter(|f| f.0).map(|f| String::from(f.1)).collect();
+    if let Some(c) = &e.chord {names.push(format!("chord {}", c.name));}
+    if let Some(m) = &e.mix_table_change {names.push(match &m.tempo {Some(t) => format!("tempo {}", t.value), None => String::from("mix table change")});}
+    names
+}
+
+/// Old and new names of the effects. The new ones are marked as changed when the effects differ with the same names (e.g. other bend points).
+fn effects_text(old: Vec<String>, new: Vec<String>, changed: bool) -> (String, String) {
+    let text = |names: &Vec<String>| if names.is_empty() {String::from("none")} else {names.join(", ")};
+    if changed && old == new {(text(&old), format!("{} (changed)", text(&new)))} else {(text(&old), text(&new))}
+}
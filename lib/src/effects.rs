@@ -4,6 +4,7 @@ use crate::{io::*, gp::*, chord::*, key_signature::*, enums::*};
 
 /// A single point within the BendEffect
 #[derive(Debug,Clone,PartialEq, Eq, Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BendPoint {
     pub position: u8,
     pub value: i8,
@@ -25,6 +26,7 @@ pub const GP_BEND_POSITION: f32 = 60.0;
 pub const GP_BEND_SEMITONE_LENGTH: f32 = 1.0;
 /// This effect is used to describe string bends and tremolo bars
 #[derive(Debug,Clone, PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BendEffect {
     pub kind: BendType,
     pub value: i16,
@@ -59,6 +61,7 @@ pub(crate) fn pack_velocity(velocity: i16) -> i8 { ((velocity + VELOCITY_INCREME
 
 /// A grace note effect
 #[derive(Debug,Clone, PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GraceEffect {
     pub duration: u8,
     pub fret: i8,
@@ -76,6 +79,7 @@ impl GraceEffect {
 
 /// A harmonic note effect
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HarmonicEffect {
     pub kind: HarmonicType,
     //artificial harmonic
@@ -88,6 +92,7 @@ impl Default for HarmonicEffect { fn default() -> Self {HarmonicEffect { kind: H
 
 /// A tremolo picking effect.
 #[derive(Debug,Clone,PartialEq,Eq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TremoloPickingEffect {pub duration: Duration,}
 //impl Default for TremoloPickingEffect { fn default() -> Self {TremoloPickingEffect { duration: Duration::default() }}}
 /// Convert tremolo picking speed to actual duration. Values are:
@@ -97,14 +102,15 @@ pub struct TremoloPickingEffect {pub duration: Duration,}
 fn from_tremolo_value(value: i8) -> u8 {
     match value {
         1 => DURATION_EIGHTH,
-        3 => DURATION_SIXTEENTH,
-        2 => DURATION_THIRTY_SECOND,
+        2 => DURATION_SIXTEENTH,
+        3 => DURATION_THIRTY_SECOND,
         _ => panic!("Cannot get tremolo value")
     }
 }
 
 /// A trill effect.
 #[derive(Debug,Clone,PartialEq,Eq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrillEffect {
     pub fret: i8,
     pub duration: Duration,
@@ -120,14 +126,14 @@ impl Song {
     ///   * Position: `int`. Shows where point is set along *x*-axis.
     ///   * Value: `int`. Shows where point is set along *y*-axis.
     ///   * Vibrato: `bool`.
-    pub(crate) fn read_bend_effect(&self, data: &[u8], seek: &mut usize) -> Option<BendEffect> {
-        let mut be = BendEffect{kind: get_bend_type(read_signed_byte(data, seek)), ..Default::default()};
-        be.value = read_int(data, seek).to_i16().unwrap();
-        let count: u8 = read_int(data, seek).to_u8().unwrap();
+    pub(crate) fn read_bend_effect(&self, data: &mut Input) -> Option<BendEffect> {
+        let mut be = BendEffect{kind: get_bend_type(read_signed_byte(data)), ..Default::default()};
+        be.value = read_int(data).to_i16().unwrap();
+        let count: u8 = read_int(data).to_u8().unwrap();
         for _ in 0..count {
-            let mut bp = BendPoint{position: (f32::from(read_int(data, seek).to_i16().unwrap()) * f32::from(BEND_EFFECT_MAX_POSITION) / GP_BEND_POSITION).round().to_u8().unwrap(), ..Default::default()};
-            bp.value = (f32::from(read_int(data, seek).to_i16().unwrap()) * f32::from(be.semitone_length) / GP_BEND_SEMITONE).round().to_i8().unwrap();
-            bp.vibrato = read_bool(data, seek);
+            let mut bp = BendPoint{position: (f32::from(read_int(data).to_i16().unwrap()) * f32::from(BEND_EFFECT_MAX_POSITION) / GP_BEND_POSITION).round().to_u8().unwrap(), ..Default::default()};
+            bp.value = (f32::from(read_int(data).to_i16().unwrap()) * f32::from(be.semitone_length) / GP_BEND_SEMITONE).round().to_i8().unwrap();
+            bp.vibrato = read_bool(data);
             be.points.push(bp);
         }
         //println!("read_bend_effect(): {:?}", be);
@@ -147,14 +153,14 @@ impl Song {
     ///   * 8: fff
     /// - Transition: `byte`. This variable determines the transition type used to make the grace note: `0: None`, `1: Slide`, `2: Bend`, `3: Hammer` (defined in `GraceEffectTransition`).
     /// - Duration: `byte`. Determines the grace note duration, coded this way: `3: Sixteenth note`, `2: Twenty-fourth note`, `1: Thirty-second note`.
-    pub(crate) fn read_grace_effect(&self, data: &[u8], seek: &mut usize) -> GraceEffect {
+    pub(crate) fn read_grace_effect(&self, data: &mut Input) -> GraceEffect {
         //println!("read_grace_effect()");
-        let mut g = GraceEffect{fret: read_signed_byte(data, seek), ..Default::default()};
-        g.velocity = unpack_velocity(read_byte(data, seek).to_i16().unwrap());
-        g.duration = 1 << (7 - read_byte(data, seek));
-        //g.duration = 1 << (7 - read_byte(data, seek));
+        let mut g = GraceEffect{fret: read_signed_byte(data), ..Default::default()};
+        g.velocity = unpack_velocity(read_byte(data).to_i16().unwrap());
+        g.duration = 1 << (7 - read_byte(data));
+        //g.duration = 1 << (7 - read_byte(data));
         g.is_dead = g.fret == -1;
-        g.transition = get_grace_effect_transition(read_signed_byte(data, seek));
+        g.transition = get_grace_effect_transition(read_signed_byte(data));
         g
     }
 
@@ -169,25 +175,25 @@ impl Song {
     /// - Flags: `byte`.
     ///   - *0x01*: grace note is muted (dead)
     ///   - *0x02*: grace note is on beat
-    pub(crate) fn read_grace_effect_v5(&self, data: &[u8], seek: &mut usize) -> GraceEffect {
-        let mut g = GraceEffect{fret: read_byte(data, seek).to_i8().unwrap(), ..Default::default()};
-        g.velocity = unpack_velocity(read_byte(data, seek).to_i16().unwrap());
-        g.transition = get_grace_effect_transition(read_byte(data, seek).to_i8().unwrap());
-        g.duration = 1 << (7 - read_byte(data, seek));
-        let flags = read_byte(data, seek);
+    pub(crate) fn read_grace_effect_v5(&self, data: &mut Input) -> GraceEffect {
+        let mut g = GraceEffect{fret: read_byte(data).to_i8().unwrap(), ..Default::default()};
+        g.velocity = unpack_velocity(read_byte(data).to_i16().unwrap());
+        g.transition = get_grace_effect_transition(read_byte(data).to_i8().unwrap());
+        g.duration = 1 << (7 - read_byte(data));
+        let flags = read_byte(data);
         g.is_dead = (flags &0x01) == 0x01;
         g.is_on_beat = (flags &0x02) == 0x02;
         g
     }
 
     /// Read tremolo picking. Tremolo constists of picking speed encoded in `signed-byte`. For value mapping refer to `from_tremolo_value()`.
-    pub(crate) fn read_tremolo_picking(&self, data: &[u8], seek: &mut usize) -> TremoloPickingEffect {
+    pub(crate) fn read_tremolo_picking(&self, data: &mut Input) -> TremoloPickingEffect {
         let mut tp = TremoloPickingEffect::default();
-        tp.duration.value = from_tremolo_value(read_signed_byte(data, seek)).to_u16().unwrap();
+        tp.duration.value = from_tremolo_value(read_signed_byte(data)).to_u16().unwrap();
         tp
     }
     ///// Read slides. Slide is encoded in `signed-byte`. See `SlideType` for value mapping.
-    //pub(crate) fn read_slides(&self, data: &[u8], seek: &mut usize) -> SlideType { get_slide_type(read_signed_byte(data, seek)) }
+    //pub(crate) fn read_slides(&self, data: &mut Input) -> SlideType { get_slide_type(read_signed_byte(data)) }
 
     /// Read slides. First `byte` stores slide types:
     /// - *0x01*: shift slide
@@ -196,8 +202,8 @@ impl Song {
     /// - *0x08*: slide out upwards
     /// - *0x10*: slide into from below
     /// - *0x20*: slide into from above
-    pub(crate) fn read_slides_v5(&self, data: &[u8], seek: &mut usize) -> Vec<SlideType> {
-        let t = read_byte(data, seek);
+    pub(crate) fn read_slides_v5(&self, data: &mut Input) -> Vec<SlideType> {
+        let t = read_byte(data);
         let mut v: Vec<SlideType> = Vec::with_capacity(6);
         if (t & 0x01) == 0x01 {v.push(SlideType::ShiftSlideTo);}
         if (t & 0x02) == 0x02 {v.push(SlideType::LegatoSlideTo);}
@@ -215,15 +221,15 @@ impl Song {
     /// - *15*: artificial harmonic on (*n + 5*)th fret
     /// - *17*: artificial harmonic on (*n + 7*)th fret
     /// - *22*: artificial harmonic on (*n + 12*)th fret
-    pub(crate) fn read_harmonic(&self, data: &[u8], seek: &mut usize, note: &crate::note::Note) -> HarmonicEffect {
+    pub(crate) fn read_harmonic(&self, data: &mut Input, note: &crate::note::Note) -> HarmonicEffect {
         let mut he = HarmonicEffect::default();
-        match read_signed_byte(data, seek) {
+        match read_signed_byte(data) {
             1 => he.kind = HarmonicType::Natural,
             3 => he.kind = HarmonicType::Tapped,
             4 => he.kind = HarmonicType::Pinch,
             5 => he.kind = HarmonicType::Semi,
             15 => {
-                he.pitch = Some(PitchClass::from(((note.value +7 ) % 12).to_i8().unwrap(), None, None));
+                he.pitch = Some(PitchClass::from((note.real_value(&self.tracks[self.current_track.expect("Current track not defined")].strings) + 7) % 12, None, None));
                 he.octave = Some(Octave::Ottava);
                 he.kind = HarmonicType::Artificial;
             },
@@ -256,23 +262,23 @@ impl Song {
     /// 
     /// If harmonic type is tapped:
     /// - Fret: `byte`.
-    pub(crate) fn read_harmonic_v5(&mut self, data: &[u8], seek: &mut usize) -> HarmonicEffect {
+    pub(crate) fn read_harmonic_v5(&mut self, data: &mut Input) -> HarmonicEffect {
         let mut he = HarmonicEffect::default();
-        match read_signed_byte(data, seek) {
+        match read_signed_byte(data) {
             1 => he.kind = HarmonicType::Natural,
             2 => {
                 // C = 0, D = 2, E = 4, F = 5...
                 // b = -1, # = 1
                 // loco = 0, 8va = 1, 15ma = 2
                 he.kind = HarmonicType::Artificial;
-                let semitone = read_byte(data, seek).to_i8().unwrap();
-                let accidental = read_signed_byte(data, seek);
+                let semitone = read_byte(data).to_i8().unwrap();
+                let accidental = read_signed_byte(data);
                 he.pitch = Some(PitchClass::from(semitone, Some(accidental), None));
-                he.octave = Some(get_octave(read_byte(data, seek)));
+                he.octave = Some(get_octave(read_byte(data)));
             },
             3 => {
                 he.kind = HarmonicType::Tapped;
-                he.fret = Some(read_byte(data, seek).to_i8().unwrap());
+                he.fret = Some(read_byte(data).to_i8().unwrap());
             },
             4 => he.kind = HarmonicType::Pinch,
             5 => he.kind = HarmonicType::Semi,
@@ -283,9 +289,9 @@ impl Song {
     /// Read trill.
     /// - Fret: `signed-byte`.
     /// - Period: `signed-byte`. See `from_trill_period`.
-    pub(crate) fn read_trill(&self, data: &[u8], seek: &mut usize) -> TrillEffect {
-        let mut t = TrillEffect{fret: read_signed_byte(data, seek), ..Default::default()};
-        t.duration.value = Self::from_trill_period(read_signed_byte(data, seek));
+    pub(crate) fn read_trill(&self, data: &mut Input) -> TrillEffect {
+        let mut t = TrillEffect{fret: read_signed_byte(data), ..Default::default()};
+        t.duration.value = Self::from_trill_period(read_signed_byte(data));
         t
     }
     fn from_trill_period(period: i8) -> u16 {
@@ -330,14 +336,14 @@ impl Song {
     pub(crate) fn write_harmonic(&self, data: &mut Vec<u8>, note: &crate::note::Note, strings: &[(i8,i8)]) {
         if let Some(h) = &note.effect.harmonic {
             let mut byte = from_harmonic_type(&h.kind);
-            if h.kind != HarmonicType::Artificial {
+            if h.kind == HarmonicType::Artificial {
+                byte = 22;
                 if h.pitch.is_some() && h.octave.is_some() {
                     let p = h.pitch.clone().unwrap();
                     let o = h.octave.clone().unwrap();
                     if      p.value == ((note.real_value(strings) +7) % 12) && o == Octave::Ottava {byte = 15;}
                     else if p.value == (note.real_value(strings) % 12) && o == Octave::Quindicesima {byte = 17;}
-                    else if p.value == (note.real_value(strings) % 12) && o == Octave::Ottava {byte = 22;}
-                } else {byte = 22;}
+                }
             }
             write_signed_byte(data, byte);
         }
@@ -345,9 +351,9 @@ impl Song {
     pub(crate) fn write_harmonic_v5(&self, data: &mut Vec<u8>, note: &crate::note::Note, strings: &[(i8,i8)]) {
         if let Some(h) = &note.effect.harmonic {
             write_signed_byte(data, from_harmonic_type(&h.kind));
-            if h.kind == HarmonicType::Artificial && (h.pitch.is_none() || h.octave.is_none()) {
-                let p = PitchClass::from(note.real_value(strings) % 12, None, None);
-                let o = Octave::Ottava;
+            if h.kind == HarmonicType::Artificial {
+                let p = h.pitch.clone().unwrap_or_else(|| PitchClass::from(note.real_value(strings) % 12, None, None));
+                let o = h.octave.clone().unwrap_or(Octave::Ottava);
                 write_byte(data, p.just.to_u8().unwrap());
                 write_signed_byte(data, p.accidental);
                 write_byte(data, from_octave(&o));
@@ -0,0 +1,130 @@
+use encoding_rs::*;
+
+/// Encoding of the strings of a Guitar Pro file. The files store the strings in the code page of the system which wrote them,
+/// without saying which one it is.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextEncoding {
+    /// The encoding is detected from the strings when reading (see `detect()`). The strings are written in Windows-1252, or in
+    /// UTF-8 if they cannot be represented in Windows-1252.
+    #[default]
+    Auto,
+    Utf8,
+    /// Western European code page, used by most files
+    Windows1252,
+    /// Central European code page
+    Windows1250,
+    /// Cyrillic code page
+    Windows1251,
+    /// Japanese
+    ShiftJis,
+    /// Simplified Chinese
+    Gbk,
+    /// Traditional Chinese
+    Big5,
+    /// Korean
+    EucKr,
+}
+
+/// The encodings which can be selected
+pub const TEXT_ENCODINGS: [TextEncoding; 9] = [TextEncoding::Auto, TextEncoding::Utf8, TextEncoding::Windows1252, TextEncoding::Windows1250,
+                                               TextEncoding::Windows1251, TextEncoding::ShiftJis, TextEncoding::Gbk, TextEncoding::Big5, TextEncoding::EucKr];
+/// Number of letters in a row needed to detect a cyrillic word, see `TextEncoding::detect()`
+pub const MIN_CYRILLIC_LETTERS: usize = 3;
+
+impl TextEncoding {
+    fn encoding(self) -> &'static Encoding {
+        match self {
+            TextEncoding::Auto | TextEncoding::Windows1252 => WINDOWS_1252,
+            TextEncoding::Utf8 => UTF_8,
+            TextEncoding::Windows1250 => WINDOWS_1250,
+            TextEncoding::Windows1251 => WINDOWS_1251,
+            TextEncoding::ShiftJis => SHIFT_JIS,
+            TextEncoding::Gbk => GBK,
+            TextEncoding::Big5 => BIG5,
+            TextEncoding::EucKr => EUC_KR,
+        }
+    }
+
+    /// Name of the encoding, `auto` for `TextEncoding::Auto`
+    pub fn name(self) -> &'static str { if self == TextEncoding::Auto {"auto"} else {self.encoding().name()} }
+
+    /// Get the encoding from its name or one of its labels (`utf-8`, `cp1252`, `windows-1251`, `sjis`, `gbk`, ...).
+    pub fn from_label(label: &str) -> Option<TextEncoding> {
+        let label = label.trim();
+        if label.eq_ignore_ascii_case("auto") {return Some(TextEncoding::Auto);}
+        let encoding = Encoding::for_label(label.as_bytes())?;
+        TEXT_ENCODINGS.into_iter().skip(1).find(|e| e.encoding() == encoding)
+    }
+
+    /// Check if the bytes are valid in this encoding
+    pub fn can_decode(self, bytes: &[u8]) -> bool { self.encoding().decode_without_bom_handling_and_without_replacement(bytes).is_some() }
+
+    /// Check if the string can be written in this encoding
+    pub fn can_encode(self, value: &str) -> bool { self == TextEncoding::Auto || !self.encoding().encode(value).2 }
+
+    /// Decode a string, the invalid bytes are replaced by `�`. The encoding is detected with `detect()` for `TextEncoding::Auto`.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let encoding = if self == TextEncoding::Auto {TextEncoding::detect(bytes).unwrap_or(TextEncoding::Windows1252)} else {self};
+        encoding.encoding().decode_without_bom_handling(bytes).0.to_string()
+    }
+
+    /// Encode a string. The characters which cannot be represented are written as HTML numeric character references (`&#1234;`),
+    /// except with `TextEncoding::Auto` which writes these strings in UTF-8.
+    pub fn encode(self, value: &str) -> Vec<u8> {
+        let (cow, _encoding_used, had_errors) = self.encoding().encode(value);
+        if had_errors && self == TextEncoding::Auto {value.as_bytes().to_vec()} else {cow.to_vec()}
+    }
+
+    /// Guess the encoding of a string, `None` if it is plain ASCII (it is the same in every encoding). The heuristics are, in order:
+    /// - valid UTF-8 is UTF-8, as accented Windows-1252 letters do not make valid UTF-8 sequences;
+    /// - Shift-JIS if the string contains kana, or mostly first level kanji;
+    /// - GBK if the string contains mostly common hanzi or Chinese punctuation, both bytes above 0xa0 (cyrillic lowercase letters
+    ///   make rare hanzi, an accented western letter followed by an ASCII one is not a common hanzi);
+    /// - Windows-1251 if at least a third of the letters are in the upper half of the code page, with `MIN_CYRILLIC_LETTERS`
+    ///   of them in a row: cyrillic words are made of these letters while western words only contain some accented letters,
+    ///   seldom next to each other (a ratio alone is not enough, short strings such as "Été" are mostly accented letters);
+    /// - Windows-1252 otherwise.
+    pub fn detect(bytes: &[u8]) -> Option<TextEncoding> {
+        if bytes.is_ascii() {return None;}
+        if std::str::from_utf8(bytes).is_ok() {return Some(TextEncoding::Utf8);}
+        if let Some(text) = SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes) {
+            let kana = text.chars().any(|c| ('\u{3041}'..='\u{30ff}').contains(&c));
+            let half_width_kana = text.chars().any(|c| ('\u{ff61}'..='\u{ff9f}').contains(&c));
+            if !half_width_kana && (kana || mostly(&double_bytes(bytes, |b| (0xa1..=0xdf).contains(&b)), |c| (0x81..=0x98).contains(&c.0))) {
+                return Some(TextEncoding::ShiftJis);
+            }
+        }
+        if GBK.decode_without_bom_handling_and_without_replacement(bytes).is_some()
+           && mostly(&double_bytes(bytes, |_| false), |c| ((0xa1..=0xa3).contains(&c.0) || (0xb0..=0xd7).contains(&c.0)) && c.1 >= 0xa1) {
+            return Some(TextEncoding::Gbk);
+        }
+        let upper_letters = bytes.iter().filter(|b| **b >= 0xc0).count();
+        let ascii_letters = bytes.iter().filter(|b| b.is_ascii_alphabetic()).count();
+        let word = bytes.split(|b| *b < 0xc0).map(|w| w.len()).max().unwrap_or(0);
+        Some(if upper_letters * 2 >= ascii_letters && word >= MIN_CYRILLIC_LETTERS {TextEncoding::Windows1251} else {TextEncoding::Windows1252})
+    }
+}
+
+/// Lead and trail bytes of the characters of a double byte encoding, `single` tells which bytes above 0x7f are characters by themselves
+fn double_bytes(bytes: &[u8], single: impl Fn(u8) -> bool) -> Vec<(u8, u8)> {
+    let mut chars = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] < 0x80 || single(bytes[i]) {i += 1;}
+        else {chars.push((bytes[i], bytes.get(i + 1).copied().unwrap_or(0))); i += 2;}
+    }
+    chars
+}
+/// Check if more than half of the characters match
+fn mostly(chars: &[(u8, u8)], matches: impl Fn(&(u8, u8)) -> bool) -> bool { chars.iter().filter(|c| matches(c)).count() * 2 > chars.len() }
+
+impl std::fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "{}", self.name()) }
+}
+impl std::str::FromStr for TextEncoding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TextEncoding::from_label(s).ok_or_else(|| format!("unknown encoding {} (supported: {})", s, TEXT_ENCODINGS.map(|e| e.name()).join(", ")))
+    }
+}
@@ -2,6 +2,7 @@
 /// An enumeration of different triplet feels.
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TripletFeel { None, Eighth, Sixteenth }
 pub(crate) fn get_triplet_feel(value: i8) -> TripletFeel {
     match value {
@@ -23,10 +24,12 @@ pub(crate) fn from_triplet_feel(value: &TripletFeel) -> u8 {
 #[allow(dead_code)]
 #[repr(u8)]
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MeasureClef { Treble, Bass, Tenor, Alto }
 /// A line break directive: `NONE: no line break`, `BREAK: break line`, `Protect the line from breaking`.
 #[repr(u8)]
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LineBreak { None, Break, Protect }
 pub(crate) fn get_line_break(value: u8) -> LineBreak {
     match value {
@@ -46,6 +49,7 @@ pub(crate) fn from_line_break(value: &LineBreak) -> u8 {
 /// An enumeration of all supported slide types.
 #[repr(i8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SlideType {
     IntoFromAbove = -2, //-2
     IntoFromBelow = -1, //-1
@@ -82,6 +86,7 @@ pub(crate) fn from_slide_type(value: &SlideType) -> i8 {
 /// An enumeration of all supported slide types.
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NoteType {
     Rest, //0
     Normal, Tie, Dead,
@@ -108,6 +113,7 @@ pub(crate) fn from_note_type(value: &NoteType) -> u8 {
 
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BeatStatus {Empty, Normal, Rest}
 pub(crate) fn get_beat_status(value: u8) -> BeatStatus {
     match value {
@@ -127,11 +133,13 @@ pub(crate) fn from_beat_status(value: &BeatStatus) -> u8 {
 
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TupletBracket {None, Start, End}
 
 /// Octave signs
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Octave { None, Ottava, Quindicesima, OttavaBassa, QuindicesimaBassa }
 pub(crate) fn get_octave(value: u8) -> Octave {
     match value {
@@ -156,6 +164,7 @@ pub(crate) fn from_octave(value: &Octave) -> u8 {
 /// All beat stroke directions
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BeatStrokeDirection { None, Up, Down }
 pub(crate) fn get_beat_stroke_direction(value: i8) -> BeatStrokeDirection {
     match value {
@@ -175,6 +184,7 @@ pub(crate) fn from_beat_stroke_direction(value: &BeatStrokeDirection) -> i8 {
 /// Characteristic of articulation
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SlapEffect { None, Tapping, Slapping, Popping }
 pub(crate) fn get_slap_effect(value: u8) -> SlapEffect {
     match value {
@@ -198,11 +208,13 @@ pub(crate) fn from_slap_effect(value: &SlapEffect) -> u8 {
 /// Voice directions indicating the direction of beams
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VoiceDirection { None, Up, Down }
 
 /// Type of the chord.
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChordType {
     /// Major chord.
     Major,
@@ -281,6 +293,7 @@ pub(crate) fn from_chord_type(value: &ChordType) -> u8 {
 /// Tonality of the chord
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChordAlteration {
     /// Perfect.
     Perfect,
@@ -308,6 +321,7 @@ pub(crate) fn from_chord_alteration(value: &ChordAlteration) -> u8 {
 /// Extension type of the chord
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChordExtension {
     None,
     /// Ninth chord.
@@ -340,6 +354,7 @@ pub(crate) fn from_chord_extension(value: &ChordExtension) -> u8 {
 /// Left and right hand fingering used in tabs and chord diagram editor.
 #[repr(i8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Fingering {
     /// Open or muted.
     Open = -1, //-1?
@@ -383,6 +398,7 @@ pub(crate) fn from_fingering(value: &Fingering) -> i8 {
 /// All Bend presets
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BendType {
     /// No Preset.
     None,
@@ -450,6 +466,7 @@ pub(crate) fn from_bend_type(value: &BendType) -> i8 {
 /// All transition types for grace notes.
 #[repr(i8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GraceEffectTransition {
     ///No transition
     None = 0,
@@ -480,6 +497,7 @@ pub(crate) fn from_grace_effect_transition(value: &GraceEffectTransition) -> i8
 
 #[repr(u8)]
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HarmonicType {
     Natural = 1, //1
     Artificial,
@@ -500,6 +518,7 @@ pub(crate) fn from_harmonic_type(value: &HarmonicType) -> i8 {
 /// Values of auto-accentuation on the beat found in track RSE settings
 #[repr(u8)]
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Accentuation { None, VerySoft, Soft, Medium, Strong, VeryStrong }
 pub(crate) fn get_accentuation(value: u8) -> Accentuation {
     match value {
@@ -525,7 +544,8 @@ pub(crate) fn from_accentuation(value: &Accentuation) -> u8 {
 
 /// A navigation sign like *Coda* (𝄌: U+1D10C) or *Segno* (𝄋 or 𝄉: U+1D10B or U+1D109).
 #[repr(u8)]
-#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+#[derive(Debug,Clone,PartialEq,Eq,Hash,PartialOrd,Ord)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DirectionSign {
     Coda, DoubleCoda,
     Segno, SegnoSegno,
@@ -0,0 +1,298 @@
+use fraction::ToPrimitive;
+
+use crate::{beat::*, enums::*, note::*, track::*};
+
+/// Maximum number of positions kept for a single beat.
+const MAX_CANDIDATES: usize = 128;
+/// Widest stretch (in frets) the left hand is expected to cover without shifting.
+const MAX_STRETCH: i16 = 4;
+/// Cost of moving the hand by one fret between two beats.
+const SHIFT_COST: i32 = 4;
+/// Cost of each fret of stretch inside a beat.
+const STRETCH_COST: i32 = 2;
+/// Cost of each fret the hand is away from the nut, so that low positions are preferred.
+const HEIGHT_COST: i32 = 1;
+/// Cost of each fret between the hand position and the one implied by a `left_hand_finger` hint.
+const HINT_COST: i32 = 6;
+
+/// A way of playing all the notes of a beat: the string and fret of each note and the hand position it implies.
+#[derive(Debug,Clone)]
+struct Position {
+    /// `(note index, string number, fret)` for every placed note.
+    frets: Vec<(usize, i8, i16)>,
+    /// Lowest fretted fret, `None` if only open strings are played.
+    hand: Option<i16>,
+    /// Cost of the position itself, regardless of the previous beat.
+    cost: i32,
+}
+impl Position {
+    fn transition_cost(&self, previous: &Position) -> i32 {
+        match (previous.hand, self.hand) {
+            (Some(a), Some(b)) => SHIFT_COST * i32::from((a - b).abs()),
+            _ => 0,
+        }
+    }
+}
+
+impl Track {
+    /// Get the MIDI pitch of a note played on this track, capo included.
+    ///
+    /// Notes that are not bound to a string (`string` is 0, e.g. imported from MIDI) carry their MIDI pitch in `value`.
+    pub(crate) fn fingering_pitch(&self, note: &Note) -> Option<i16> {
+        if note.string == 0 {return Some(note.value);}
+        let s = self.strings.iter().find(|s| s.0 == note.string)?;
+        Some(i16::from(s.1) + self.offset.to_i16().unwrap() + note.value)
+    }
+
+    /// Get the fret (relative to the capo) where `pitch` is played on the string `string`, if it is reachable.
+    pub(crate) fn fret_for_pitch(&self, string: (i8, i8), pitch: i16) -> Option<i16> {
+        let fret = pitch - i16::from(string.1) - self.offset.to_i16().unwrap();
+        if fret >= 0 && fret <= i16::from(self.fret_count) - self.offset.to_i16().unwrap() {Some(fret)} else {None}
+    }
+
+    /// Assign a string and a fret to every note of the track. Each voice is solved across all the measures, so that
+    /// hand movements between the end of a measure and the start of the next one are taken into account.
+    ///
+    /// Returns the number of notes that cannot be played with the tuning, fret count and capo of the track. They are left untouched.
+    pub fn assign_fingering(&mut self) -> usize {
+        if self.percussion_track {return 0;}
+        let voice_count = self.measures.iter().map(|m| m.voices.len()).max().unwrap_or(0);
+        let mut unplaced = 0;
+        for v in 0..voice_count {
+            let beats: Vec<&Beat> = self.measures.iter().filter(|m| v < m.voices.len()).flat_map(|m| m.voices[v].beats.iter()).collect();
+            let pitches: Vec<Vec<Option<i16>>> = beats.iter().map(|b| b.notes.iter().map(|n| self.fingering_pitch(n)).collect()).collect();
+            let (positions, count) = self.solve(&beats);
+            unplaced += count;
+            let mut b = 0;
+            let mut previous: Vec<(i16, i8, i16)> = Vec::new();
+            for m in self.measures.iter_mut().filter(|m| v < m.voices.len()) {
+                for beat in m.voices[v].beats.iter_mut() {
+                    previous = apply_position(beat, &pitches[b], positions[b].as_ref(), &previous);
+                    b += 1;
+                }
+            }
+        }
+        unplaced
+    }
+
+    /// Assign a string and a fret to every note of a single voice.
+    ///
+    /// Returns the number of notes that cannot be played with the tuning, fret count and capo of the track. They are left untouched.
+    pub fn assign_voice_fingering(&self, voice: &mut Voice) -> usize {
+        if self.percussion_track {return 0;}
+        let (positions, unplaced) = self.solve(&voice.beats.iter().collect::<Vec<&Beat>>());
+        let mut previous: Vec<(i16, i8, i16)> = Vec::new();
+        for (b, beat) in voice.beats.iter_mut().enumerate() {
+            let pitches: Vec<Option<i16>> = beat.notes.iter().map(|n| self.fingering_pitch(n)).collect();
+            previous = apply_position(beat, &pitches, positions[b].as_ref(), &previous);
+        }
+        unplaced
+    }
+
+    /// Find the cheapest sequence of positions for the beats (dynamic programming over the positions of each beat).
+    fn solve(&self, beats: &[&Beat]) -> (Vec<Option<Position>>, usize) {
+        let mut unplaced = 0;
+        let mut candidates: Vec<Vec<Position>> = Vec::with_capacity(beats.len());
+        for beat in beats {
+            let (c, u) = self.positions(beat);
+            unplaced += u;
+            candidates.push(c);
+        }
+        // costs[b][c] is the cost of the best path ending with the candidate `c` of the beat `b`, back[b][c] the candidate used on the previous played beat
+        let mut costs: Vec<Vec<i32>> = Vec::with_capacity(beats.len());
+        let mut back: Vec<Vec<usize>> = Vec::with_capacity(beats.len());
+        let mut last: Option<usize> = None;
+        let mut links: Vec<Option<usize>> = vec![None; beats.len()];
+        for b in 0..candidates.len() {
+            let mut cost = Vec::with_capacity(candidates[b].len());
+            let mut from = Vec::with_capacity(candidates[b].len());
+            for c in &candidates[b] {
+                let mut best = (c.cost, 0usize);
+                if let Some(l) = last {
+                    best = (i32::MAX, 0);
+                    for (p, previous) in candidates[l].iter().enumerate() {
+                        let total = costs[l][p] + c.transition_cost(previous) + c.cost;
+                        if total < best.0 {best = (total, p);}
+                    }
+                }
+                cost.push(best.0);
+                from.push(best.1);
+            }
+            if !candidates[b].is_empty() {
+                links[b] = last;
+                last = Some(b);
+            }
+            costs.push(cost);
+            back.push(from);
+        }
+        //walk the best path backward
+        let mut result: Vec<Option<Position>> = vec![None; beats.len()];
+        if let Some(l) = last {
+            let mut c = (0..costs[l].len()).min_by_key(|&c| costs[l][c]).unwrap();
+            let mut b = Some(l);
+            while let Some(i) = b {
+                result[i] = Some(candidates[i][c].clone());
+                c = back[i][c];
+                b = links[i];
+            }
+        }
+        (result, unplaced)
+    }
+
+    /// Enumerate the ways of playing the notes of a beat. Rests, tied notes and notes that cannot be played are ignored.
+    fn positions(&self, beat: &Beat) -> (Vec<Position>, usize) {
+        let mut unplaced = 0;
+        let mut options: Vec<(usize, Vec<(i8, i16)>)> = Vec::with_capacity(beat.notes.len());
+        for (i, n) in beat.notes.iter().enumerate() {
+            if n.kind == NoteType::Rest || n.kind == NoteType::Tie {continue;}
+            let pitch = match self.fingering_pitch(n) {Some(p) => p, None => {unplaced += 1; continue;}};
+            let frets: Vec<(i8, i16)> = self.strings.iter().filter_map(|s| self.fret_for_pitch(*s, pitch).map(|f| (s.0, f))).collect();
+            if frets.is_empty() {unplaced += 1;} else {options.push((i, frets));}
+        }
+        if options.is_empty() {return (Vec::new(), unplaced);}
+        //place the most constrained notes first
+        options.sort_by_key(|o| o.1.len());
+        let mut result: Vec<Position> = Vec::new();
+        let mut current: Vec<(usize, i8, i16)> = Vec::with_capacity(options.len());
+        self.combine(beat, &options, &mut current, &mut result, true);
+        if result.is_empty() {self.combine(beat, &options, &mut current, &mut result, false);}
+        if result.is_empty() {
+            //more notes than strings: keep the notes that fit
+            let mut used: Vec<i8> = Vec::new();
+            let mut frets = Vec::new();
+            for (i, o) in &options {
+                if let Some(f) = o.iter().find(|f| !used.contains(&f.0)) {used.push(f.0); frets.push((*i, f.0, f.1));}
+                else {unplaced += 1;}
+            }
+            result.push(self.position(beat, frets));
+        }
+        result.sort_by_key(|p| p.cost);
+        result.truncate(MAX_CANDIDATES);
+        (result, unplaced)
+    }
+
+    fn combine(&self, beat: &Beat, options: &[(usize, Vec<(i8, i16)>)], current: &mut Vec<(usize, i8, i16)>, result: &mut Vec<Position>, within_stretch: bool) {
+        if result.len() >= MAX_CANDIDATES * 4 {return;}
+        if current.len() == options.len() {
+            result.push(self.position(beat, current.clone()));
+            return;
+        }
+        let (note, frets) = &options[current.len()];
+        for &(string, fret) in frets {
+            if current.iter().any(|c| c.1 == string) {continue;}
+            if within_stretch && fret > 0 && current.iter().any(|c| c.2 > 0 && (c.2 - fret).abs() > MAX_STRETCH) {continue;}
+            current.push((*note, string, fret));
+            self.combine(beat, options, current, result, within_stretch);
+            current.pop();
+        }
+    }
+
+    fn position(&self, beat: &Beat, frets: Vec<(usize, i8, i16)>) -> Position {
+        let fretted: Vec<i16> = frets.iter().map(|f| f.2).filter(|&f| f > 0).collect();
+        let hand = fretted.iter().min().copied();
+        let span = match hand {Some(h) => fretted.iter().max().unwrap() - h, None => 0};
+        let mut cost = STRETCH_COST * i32::from(span) + HEIGHT_COST * i32::from(hand.unwrap_or(0));
+        if span > MAX_STRETCH {cost += STRETCH_COST * 4 * i32::from(span - MAX_STRETCH);}
+        for f in &frets {
+            let finger = match beat.notes[f.0].effect.left_hand_finger {
+                Fingering::Index => 1, Fingering::Middle => 2, Fingering::Annular => 3, Fingering::Little => 4,
+                _ => continue,
+            };
+            //the index finger is on the hand position, the other fingers on the following frets
+            let expected = f.2 - finger + 1;
+            cost += HINT_COST * i32::from((expected - hand.unwrap_or(expected)).abs());
+            if f.2 == 0 {cost += HINT_COST * 2;}
+        }
+        Position {frets, hand, cost}
+    }
+}
+
+/// Write the position in the notes of the beat. Tied notes take the string and the fret of the note they are tied to.
+///
+/// Returns the `(pitch, string, fret)` of the notes of the beat, used to resolve the ties of the next beat.
+fn apply_position(beat: &mut Beat, pitches: &[Option<i16>], position: Option<&Position>, previous: &[(i16, i8, i16)]) -> Vec<(i16, i8, i16)> {
+    let mut played: Vec<(i16, i8, i16)> = Vec::with_capacity(beat.notes.len());
+    if let Some(p) = position {
+        for &(i, string, fret) in &p.frets {
+            beat.notes[i].string = string;
+            beat.notes[i].value = fret;
+        }
+    }
+    for (i, n) in beat.notes.iter_mut().enumerate() {
+        if n.kind == NoteType::Tie {
+            let tied = previous.iter().find(|t| Some(t.0) == pitches[i] || t.1 == n.string);
+            if let Some(t) = tied {
+                n.string = t.1;
+                n.value = t.2;
+                played.push(*t);
+            }
+        } else if n.kind != NoteType::Rest && n.string > 0 {
+            if let Some(p) = pitches[i] {played.push((p, n.string, n.value));}
+        }
+    }
+    played
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{beat::*, enums::*, measure::*, note::*, track::*};
+
+    fn note(pitch: i16) -> Note {
+        let mut n = Note::default();
+        n.value = pitch;
+        n.kind = NoteType::Normal;
+        n
+    }
+    fn beat(pitches: &[i16]) -> Beat { Beat {notes: pitches.iter().map(|p| note(*p)).collect(), ..Default::default()} }
+
+    #[test]
+    fn test_open_chord() {
+        let track = Track::default();
+        //E major chord: E2 B2 E3 G#3 B3 E4
+        let mut voice = Voice {beats: vec![beat(&[40, 47, 52, 56, 59, 64])], ..Default::default()};
+        assert_eq!(track.assign_voice_fingering(&mut voice), 0);
+        let mut frets: Vec<(i8, i16)> = voice.beats[0].notes.iter().map(|n| (n.string, n.value)).collect();
+        frets.sort();
+        assert_eq!(frets, vec![(1, 0), (2, 0), (3, 1), (4, 2), (5, 2), (6, 0)]);
+    }
+
+    #[test]
+    fn test_melody_stays_in_position() {
+        let track = Track::default();
+        //A minor pentatonic from the 5th fret: A2 C3 D3 E3 G3 A3
+        let mut voice = Voice {beats: [45, 48, 50, 52, 55, 57].iter().map(|p| beat(&[*p])).collect(), ..Default::default()};
+        track.assign_voice_fingering(&mut voice);
+        for b in &voice.beats {
+            let n = &b.notes[0];
+            assert!(n.value == 0 || (2..=8).contains(&n.value), "note on string {} fret {}", n.string, n.value);
+            assert!(n.string > 0);
+        }
+    }
+
+    #[test]
+    fn test_capo_and_unplayable() {
+        let track = Track {offset: 2, ..Default::default()};
+        let mut voice = Voice {beats: vec![beat(&[30, 42])], ..Default::default()};
+        assert_eq!(track.assign_voice_fingering(&mut voice), 1);
+        //F#2 with a capo on the 2nd fret is the open 6th string
+        assert_eq!((voice.beats[0].notes[1].string, voice.beats[0].notes[1].value), (6, 0));
+        assert_eq!(voice.beats[0].notes[0].string, 0);
+    }
+
+    #[test]
+    fn test_hint_and_ties() {
+        let mut track = Track::default();
+        let mut hinted = note(45);
+        hinted.effect.left_hand_finger = Fingering::Index;
+        let mut tied = note(45);
+        tied.kind = NoteType::Tie;
+        let mut m = Measure::default();
+        m.voices.push(Voice {beats: vec![Beat {notes: vec![hinted], ..Default::default()}, Beat {notes: vec![tied], ..Default::default()}], ..Default::default()});
+        track.measures.push(m);
+        assert_eq!(track.assign_fingering(), 0);
+        let beats = &track.measures[0].voices[0].beats;
+        //A2 played with the index finger is not the open 5th string
+        assert_eq!((beats[0].notes[0].string, beats[0].notes[0].value), (6, 5));
+        assert_eq!((beats[1].notes[0].string, beats[1].notes[0].value), (beats[0].notes[0].string, beats[0].notes[0].value));
+    }
+}
@@ -5,6 +5,7 @@ use fraction::ToPrimitive;
 use crate::{io::*, gp::*, key_signature::*, enums::*};
 
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Version {
     pub data: String,
     pub number: (u8, u8, u8),
@@ -12,6 +13,7 @@ pub struct Version {
 }
 
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Clipboard {
     pub start_measure: i32,
     pub stop_measure: i32,
@@ -26,6 +28,7 @@ impl Default for Clipboard {
 }
 
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MeasureHeader {
     pub number: u16,
 	pub start: i64,
@@ -58,12 +61,13 @@ impl Default for MeasureHeader {
     }}
 }
 impl MeasureHeader {
-    pub(crate) fn length(&self) -> i64 {self.time_signature.numerator.to_i64().unwrap() * self.time_signature.denominator.time().to_i64().unwrap()}
+    pub fn length(&self) -> i64 {self.time_signature.numerator.to_i64().unwrap() * self.time_signature.denominator.time().to_i64().unwrap()}
     pub(crate) fn _end(&self) -> i64 {self.start + self.length()}
 }
 
 /// A marker annotation for beats.
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Marker {
     pub title: String,
     pub color: i32,
@@ -72,14 +76,15 @@ impl Default for Marker {fn default() -> Self { Marker {title: "Section".to_owne
 /// Read a marker. The markers are written in two steps:
 /// - first is written an integer equal to the marker's name length + 1
 /// - then a string containing the marker's name. Finally the marker's color is written.
-fn read_marker(data: &[u8], seek: &mut usize) -> Marker {
-    let mut marker = Marker{title: read_int_size_string(data, seek), ..Default::default()};
-    marker.color = read_color(data, seek);
+fn read_marker(data: &mut Input) -> Marker {
+    let mut marker = Marker{title: read_int_byte_size_string(data), ..Default::default()};
+    marker.color = read_color(data);
     marker
 }
 
 /// This class can store the information about a group of measures which are repeated.
 #[derive(Debug,Clone,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RepeatGroup {
     /// List of measure header indexes.
     pub measure_headers: Vec<usize>,
@@ -110,43 +115,45 @@ impl Song {
         self.measure_headers.push(header);
     }
 
-    pub(crate) fn read_clipboard(&mut self, data: &[u8], seek: &mut usize) -> Option<Clipboard> {
+    pub(crate) fn read_clipboard(&mut self, data: &mut Input) -> Option<Clipboard> {
         if !self.version.clipboard {return None;}
-        let mut c = Clipboard{start_measure: read_int(data, seek), ..Default::default()};
-        c.stop_measure = read_int(data, seek);
-        c.start_track = read_int(data, seek);
-        c.stop_track = read_int(data, seek);
+        let mut c = Clipboard{start_measure: read_int(data), ..Default::default()};
+        c.stop_measure = read_int(data);
+        c.start_track = read_int(data);
+        c.stop_track = read_int(data);
         if self.version.number.0 == 5 {
-            c.start_beat = read_int(data, seek);
-            c.stop_beat = read_int(data, seek);
-            c.sub_bar_copy = read_int(data, seek) != 0;
+            c.start_beat = read_int(data);
+            c.stop_beat = read_int(data);
+            c.sub_bar_copy = read_int(data) != 0;
         }
-        println!("read_clipboard(): {:?}", c);
+        //println!("read_clipboard(): {:?}", c);
         Some(c)
     }
 
     /// Read measure headers. The *measures* are written one after another, their number have been specified previously.
     /// * `measure_count`: number of measures to expect.
-    pub(crate) fn read_measure_headers(&mut self, data: &[u8], seek: &mut usize, measure_count: usize) {
+    pub(crate) fn read_measure_headers(&mut self, data: &mut Input, measure_count: usize) {
         //println!("read_measure_headers()");
         let mut previous: Option<MeasureHeader> = None;
         for i in 1..measure_count + 1  {
-            let r: (MeasureHeader, u8) = self.read_measure_header(data, seek, i, previous);
+            let r: (MeasureHeader, u8) = self.read_measure_header(data, i, previous);
             previous = Some(r.0.clone());
             self.measure_headers.push(r.0); //TODO: use add_measure_header
         }
     }
 
-    pub(crate) fn read_measure_headers_v5(&mut self, data: &[u8], seek: &mut usize, measure_count: usize, directions: &(HashMap<DirectionSign, i16>, HashMap<DirectionSign, i16>)) {
+    pub(crate) fn read_measure_headers_v5(&mut self, data: &mut Input, measure_count: usize, directions: &(HashMap<DirectionSign, i16>, HashMap<DirectionSign, i16>)) {
         //println!("read_measure_headers_v5()");
         let mut previous: Option<MeasureHeader> = None;
         for i in 1..measure_count + 1  {
-            let r: (MeasureHeader, u8) = self.read_measure_header_v5(data, seek, i, previous);
+            let r: (MeasureHeader, u8) = self.read_measure_header_v5(data, i, previous);
             previous = Some(r.0.clone());
             self.measure_headers.push(r.0); //TODO: use add_measure_header
         }
-        for s in &directions.0 { if s.1 > &-1 {self.measure_headers[s.1.to_usize().unwrap() - 1].direction = Some(s.0.clone());} }
-        for s in &directions.1 { if s.1 > &-1 {self.measure_headers[s.1.to_usize().unwrap() - 1].direction = Some(s.0.clone());} }
+        //a measure header only keeps one sign: sort them to always keep the same one
+        let mut signs: Vec<(&DirectionSign, &i16)> = directions.0.iter().chain(directions.1.iter()).collect();
+        signs.sort();
+        for s in signs { if s.1 > &-1 {self.measure_headers[s.1.to_usize().unwrap() - 1].direction = Some(s.0.clone());} }
     }
 
     /// Read measure header. The first byte is the measure's flags. It lists the data given in the current measure.
@@ -157,7 +164,7 @@ impl Song {
     ///
     /// Each of these elements is present only if the corresponding bit is a 1. The different elements are written (if they are present) from lowest to highest bit.  
     /// Exceptions are made for the double bar and the beginning of repeat whose sole presence is enough, complementary data is not necessary.
-
+    ///
     /// * **Numerator of the (key) signature**: `byte`. Numerator of the (key) signature of the piece
     /// * **Denominator of the (key) signature**: `byte`. Denominator of the (key) signature of the piece
     /// * **End of repeat**: `byte`. Number of repeats until the previous Beginning of repeat. Nombre de renvoi jusqu'au début de renvoi précédent.
@@ -166,27 +173,27 @@ impl Song {
     /// 1) First is written an `integer` equal to the marker's name length + 1
     /// 2) a string containing the marker's name. Finally the marker's color is written.
     /// * **Tonality of the measure**: `byte`. This value encodes a key (signature) change on the current piece. It is encoded as: `0: C`, `1: G (#)`, `2: D (##)`, `-1: F (b)`, ...
-    pub(crate) fn read_measure_header(&mut self, data: &[u8], seek: &mut usize, number: usize, previous: Option<MeasureHeader>) -> (MeasureHeader, u8) {
-        let flag = read_byte(data, seek);
+    pub(crate) fn read_measure_header(&mut self, data: &mut Input, number: usize, previous: Option<MeasureHeader>) -> (MeasureHeader, u8) {
+        let flag = read_byte(data);
         //println!("read_measure_header(), flags: {} \t N: {} \t Measure header count: {}", flag, number, self.measure_headers.len());
         let mut mh = MeasureHeader{number: number.to_u16().unwrap(), ..Default::default()};
         mh.start  = 0;
         mh.triplet_feel = self.triplet_feel.clone(); //TODO: use ref & lifetime
         //we need a previous header for the next 2 flags
         //Numerator of the (key) signature
-        if (flag & 0x01 )== 0x01 {mh.time_signature.numerator = read_signed_byte(data, seek);}
+        if (flag & 0x01 )== 0x01 {mh.time_signature.numerator = read_signed_byte(data);}
         else if number > 1 {mh.time_signature.numerator = previous.clone().unwrap().time_signature.numerator;}
         //Denominator of the (key) signature
-        if (flag & 0x02) == 0x02 {mh.time_signature.denominator.value = read_signed_byte(data, seek).to_u16().unwrap();}
+        if (flag & 0x02) == 0x02 {mh.time_signature.denominator.value = read_signed_byte(data).to_u16().unwrap();}
         else if number > 1 {mh.time_signature.denominator = previous.clone().unwrap().time_signature.denominator;}
 
         mh.repeat_open = (flag & 0x04) == 0x04; //Beginning of repeat
-        if (flag & 0x08) == 0x08 {mh.repeat_close = read_signed_byte(data, seek);} //End of repeat
-        if (flag & 0x10) == 0x10 {mh.repeat_alternative = if self.version.number.0 == 5 {self.read_repeat_alternative_v5(data, seek)} else {self.read_repeat_alternative(data, seek)};} //Number of alternate ending
-        if (flag & 0x20) == 0x20 {mh.marker = Some(read_marker(data, seek));} //Presence of a marker
+        if (flag & 0x08) == 0x08 {mh.repeat_close = read_signed_byte(data);} //End of repeat
+        if (flag & 0x10) == 0x10 {mh.repeat_alternative = if self.version.number.0 == 5 {self.read_repeat_alternative_v5(data)} else {self.read_repeat_alternative(data)};} //Number of alternate ending
+        if (flag & 0x20) == 0x20 {mh.marker = Some(read_marker(data));} //Presence of a marker
         if (flag & 0x40) == 0x40 { //Tonality of the measure 
-            mh.key_signature.key      = read_signed_byte(data, seek);
-            mh.key_signature.is_minor = read_signed_byte(data, seek) != 0;
+            mh.key_signature.key      = read_signed_byte(data);
+            mh.key_signature.is_minor = read_signed_byte(data) != 0;
         } else if mh.number > 1 {mh.key_signature = previous.unwrap().key_signature;}
         mh.double_bar = (flag & 0x80) == 0x80; //presence of a double bar
         (mh, flag)
@@ -197,25 +204,25 @@ impl Song {
     /// - Time signature beams: 4 `Bytes <byte>`. Appears If time signature was set, i.e. flags *0x01* and *0x02* are both set.
     /// - Blank `byte` if flag at *0x10* is set.
     /// - Triplet feel: `byte`. See `TripletFeel`.
-    pub(crate) fn read_measure_header_v5(&mut self, data: &[u8], seek: &mut usize, number: usize, previous: Option<MeasureHeader>) -> (MeasureHeader,u8) {
-        if previous.is_some() { *seek += 1; } //always
-        let r = self.read_measure_header(data, seek, number, previous.clone());
+    pub(crate) fn read_measure_header_v5(&mut self, data: &mut Input, number: usize, previous: Option<MeasureHeader>) -> (MeasureHeader,u8) {
+        if previous.is_some() { data.skip(1); } //always
+        let r = self.read_measure_header(data, number, previous.clone());
         let mut mh = r.0;
         let flags = r.1;
         //println!("read_measure_header_v5(), flags: {}", flags);
         if mh.repeat_close > -1 {mh.repeat_close -= 1;}
         if (flags & 0x03) == 0x03 {
-            for i in 0..4 {mh.time_signature.beams[i] = read_byte(data, seek);}
+            for i in 0..4 {mh.time_signature.beams[i] = read_byte(data);}
         } else {mh.time_signature.beams = previous.unwrap().time_signature.beams;};
-        if (flags & 0x10) == 0 { *seek += 1; } //always 0
-        mh.triplet_feel = get_triplet_feel(read_byte(data, seek).to_i8().unwrap());
+        if (flags & 0x10) == 0 { data.skip(1); } //always 0
+        mh.triplet_feel = get_triplet_feel(read_byte(data).to_i8().unwrap());
         //println!("################################### {:?}", mh.triplet_feel);
         (mh, flags)
     }
 
-    fn read_repeat_alternative(&mut self, data: &[u8], seek: &mut usize) -> u8 {
+    fn read_repeat_alternative(&mut self, data: &mut Input) -> u8 {
         //println!("read_repeat_alternative()");
-        let value = read_byte(data, seek).to_u16().unwrap();
+        let value = read_byte(data).to_u16().unwrap();
         let mut existing_alternative = 0u16;
         for i in (0..self.measure_headers.len()).rev() {
             if self.measure_headers[i].repeat_open {break;}
@@ -225,7 +232,7 @@ impl Song {
         //println!("read_repeat_alternative(), return: {}", ((1 << value) - 1) ^ existing_alternative);
         (((1 << value) - 1) ^ existing_alternative).to_u8().unwrap()
     }
-    fn read_repeat_alternative_v5(&mut self, data: &[u8], seek: &mut usize) -> u8 {read_byte(data, seek)}
+    fn read_repeat_alternative_v5(&mut self, data: &mut Input) -> u8 {read_byte(data)}
 
     /// Read directions.  Directions is a list of 19 `ShortInts <short>` each pointing at the number of measure.
     /// 
@@ -249,30 +256,30 @@ impl Song {
     /// - Da Segno Segno al Fine
     /// - Da Coda
     /// - Da Double Coda
-    pub(crate) fn read_directions(&self, data: &[u8], seek: &mut usize) -> (HashMap<DirectionSign, i16>, HashMap<DirectionSign, i16>) {
+    pub(crate) fn read_directions(&self, data: &mut Input) -> (HashMap<DirectionSign, i16>, HashMap<DirectionSign, i16>) {
         let mut signs: HashMap<DirectionSign, i16> = HashMap::with_capacity(4);
         let mut from_signs: HashMap<DirectionSign, i16> = HashMap::with_capacity(15);
         //signs
-        signs.insert(DirectionSign::Coda, read_short(data, seek));
-        signs.insert(DirectionSign::DoubleCoda, read_short(data, seek));
-        signs.insert(DirectionSign::Segno, read_short(data, seek));
-        signs.insert(DirectionSign::SegnoSegno, read_short(data, seek));
-        signs.insert(DirectionSign::Fine, read_short(data, seek));
+        signs.insert(DirectionSign::Coda, read_short(data));
+        signs.insert(DirectionSign::DoubleCoda, read_short(data));
+        signs.insert(DirectionSign::Segno, read_short(data));
+        signs.insert(DirectionSign::SegnoSegno, read_short(data));
+        signs.insert(DirectionSign::Fine, read_short(data));
         //from signs
-        from_signs.insert(DirectionSign::DaCapo, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaCapoAlCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaCapoAlDoubleCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaCapoAlFine, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegno, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoAlCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoAlDoubleCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoAlFine, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoSegno, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoSegnoAlCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoSegnoAlDoubleCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaSegnoSegnoAlFine, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaCoda, read_short(data, seek));
-        from_signs.insert(DirectionSign::DaDoubleCoda, read_short(data, seek));
+        from_signs.insert(DirectionSign::DaCapo, read_short(data));
+        from_signs.insert(DirectionSign::DaCapoAlCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaCapoAlDoubleCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaCapoAlFine, read_short(data));
+        from_signs.insert(DirectionSign::DaSegno, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoAlCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoAlDoubleCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoAlFine, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoSegno, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoSegnoAlCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoSegnoAlDoubleCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaSegnoSegnoAlFine, read_short(data));
+        from_signs.insert(DirectionSign::DaCoda, read_short(data));
+        from_signs.insert(DirectionSign::DaDoubleCoda, read_short(data));
         (signs, from_signs)
     }
 
@@ -286,85 +293,82 @@ impl Song {
     }
 
     fn write_measure_header(&self, data: &mut Vec<u8>, header: usize, previous: Option<usize>, version: &(u8,u8,u8)) {
+        let mh = &self.measure_headers[header];
         //pack measure header flags
         let mut flags: u8 = 0x00;
         if let Some(p) = previous {
-            if self.measure_headers[header].time_signature.numerator != self.measure_headers[p].time_signature.numerator {flags |= 0x01;}
-            if self.measure_headers[header].time_signature.denominator.value != self.measure_headers[p].time_signature.denominator.value {flags |= 0x02;}
+            let p = &self.measure_headers[p];
+            if mh.time_signature.numerator != p.time_signature.numerator {flags |= 0x01;}
+            if mh.time_signature.denominator.value != p.time_signature.denominator.value {flags |= 0x02;}
+            //beams are only written with a complete time signature
+            if version.0 >= 5 && (flags != 0 || mh.time_signature.beams != p.time_signature.beams) {flags |= 0x03;}
+            if mh.key_signature != p.key_signature {flags |= 0x40;}
         } else {
-            flags |= 0x01;
-            flags |= 0x02;
-            if self.measure_headers[header].repeat_open {flags |= 0x04;}
-            if self.measure_headers[header].repeat_close > -1 {flags |= 0x08;}
-            if self.measure_headers[header].repeat_alternative > 0 {flags |= 0x10;}
-            if self.measure_headers[header].marker.is_some() {flags |= 0x20;}
-        }
-        if version.0 >= 4 {
-            if previous.is_none() {flags |= 0x40;}
-            else if let Some(p) = previous {if self.measure_headers[header].key_signature ==  self.measure_headers[p].key_signature {flags |= 0x40;}}
-            if self.measure_headers[header].double_bar {flags |= 0x80;}
-        }
-        if version.0 >= 5 {
-            if let Some(p) = previous {
-                if self.measure_headers[header].time_signature != self.measure_headers[p].time_signature {flags |= 0x03;}
-                write_placeholder_default(data, 1);
-            }
+            flags |= 0x03;
+            if version.0 >= 4 || mh.key_signature != KeySignature::default() {flags |= 0x40;}
         }
+        if mh.repeat_open {flags |= 0x04;}
+        if mh.repeat_close > -1 {flags |= 0x08;}
+        if mh.repeat_alternative > 0 {flags |= 0x10;}
+        if mh.marker.is_some() {flags |= 0x20;}
+        if mh.double_bar {flags |= 0x80;}
         //end pack
         //write measure header values
+        if version.0 >= 5 && previous.is_some() {write_placeholder_default(data, 1);}
         write_byte(data, flags);
-        if (flags & 0x01) == 0x01 {write_signed_byte(data, self.measure_headers[header].time_signature.numerator);}
-        if (flags & 0x02) == 0x02 {write_signed_byte(data, self.measure_headers[header].time_signature.denominator.value.to_i8().unwrap());}
-        if (flags & 0x08) == 0x08 {write_signed_byte(data, if version.0 < 5 {self.measure_headers[header].repeat_close} else {self.measure_headers[header].repeat_close + 1});}
+        if (flags & 0x01) == 0x01 {write_signed_byte(data, mh.time_signature.numerator);}
+        if (flags & 0x02) == 0x02 {write_signed_byte(data, mh.time_signature.denominator.value.to_i8().unwrap());}
+        if (flags & 0x08) == 0x08 {write_signed_byte(data, if version.0 < 5 {mh.repeat_close} else {mh.repeat_close + 1});}
         if (flags & 0x10) == 0x10 { //write repeat alternative
-            if version.0 ==5 {write_byte(data, self.measure_headers[header].repeat_alternative);}
+            if version.0 == 5 {write_byte(data, mh.repeat_alternative);}
             else {
-                let mut first_one = false;
-                let mut ra:u8 = 0;
-                for i in 0u8..9-self.measure_headers[header].repeat_alternative.leading_zeros().to_u8().unwrap() {
-                    ra = i;
-                    if (self.measure_headers[header].repeat_alternative & 1 << i) > 0 {first_one = true;}
-                    else if first_one {break;}
+                //the previous versions only store the number of the last alternative, the other ones are the ones not used before
+                let mut existing_alternative = 0u16;
+                for i in (0..header).rev() {
+                    if self.measure_headers[i].repeat_open {break;}
+                    existing_alternative |= self.measure_headers[i].repeat_alternative.to_u16().unwrap();
                 }
-                write_byte(data, ra);
+                let alternatives = existing_alternative | mh.repeat_alternative.to_u16().unwrap();
+                write_byte(data, (16 - alternatives.leading_zeros()).to_u8().unwrap());
             }
         }
         if (flags & 0x20) == 0x20 { //write marker
-            if let Some(marker) = &self.measure_headers[header].marker {
-                write_int_byte_size_string(data, &marker.title);
+            if let Some(marker) = &mh.marker {
+                write_int_byte_size_string(data, &marker.title, self.encoding);
                 write_color(data, marker.color);
             }
         }
-        if version.0 >= 4 {
-            write_signed_byte(data, self.measure_headers[header].key_signature.key);
-            write_signed_byte(data, i8::from(self.measure_headers[header].key_signature.is_minor));
+        if (flags & 0x40) == 0x40 {
+            write_signed_byte(data, mh.key_signature.key);
+            write_signed_byte(data, i8::from(mh.key_signature.is_minor));
         }
         if version.0 >= 5 {
             if (flags & 0x03) == 0x03 {
-                for i in 0..self.measure_headers[header].time_signature.beams.len() {write_byte(data, self.measure_headers[header].time_signature.beams[i]);}
+                for i in 0..mh.time_signature.beams.len() {write_byte(data, mh.time_signature.beams[i]);}
             }
-            if (flags & 0x10) == 0x10 {write_placeholder_default(data, 1);}
-            write_byte(data, from_triplet_feel(&self.measure_headers[header].triplet_feel));
+            if (flags & 0x10) == 0 {write_placeholder_default(data, 1);}
+            write_byte(data, from_triplet_feel(&mh.triplet_feel));
         }
     }
 
+    /// Write the clipboard range. When the song has no clipboard, the range covers all the measures and tracks.
     pub(crate) fn write_clipboard(&self, data: &mut Vec<u8>, version: &(u8,u8,u8)) {
-        if let Some(c) = &self.clipboard {
-            write_i32(data, c.start_measure.to_i32().unwrap());
-            write_i32(data, c.stop_measure.to_i32().unwrap());
-            write_i32(data, c.start_track.to_i32().unwrap());
-            write_i32(data, c.stop_track.to_i32().unwrap());
-            if version.0 == 5 {
-                write_i32(data, c.start_beat.to_i32().unwrap());
-                write_i32(data, c.stop_beat.to_i32().unwrap());
-                write_i32(data, i32::from(c.sub_bar_copy));
-            }
+        let c = self.clipboard.clone().unwrap_or_else(|| Clipboard {stop_measure: self.measure_headers.len().max(1).to_i32().unwrap(),
+                                                                    stop_track: self.tracks.len().max(1).to_i32().unwrap(), ..Default::default()});
+        write_i32(data, c.start_measure);
+        write_i32(data, c.stop_measure);
+        write_i32(data, c.start_track);
+        write_i32(data, c.stop_track);
+        if version.0 == 5 {
+            write_i32(data, c.start_beat);
+            write_i32(data, c.stop_beat);
+            write_i32(data, i32::from(c.sub_bar_copy));
         }
     }
     pub(crate) fn write_directions(&self, data: &mut Vec<u8>) {
         let mut map: HashMap<DirectionSign, i16>= HashMap::with_capacity(19);
-        for i in 1..self.measure_headers.len() {
-            if let Some(d) = &self.measure_headers[i].direction { map.insert(d.clone(), i.to_i16().unwrap()); }
+        for i in 0..self.measure_headers.len() {
+            if let Some(d) = &self.measure_headers[i].direction { map.insert(d.clone(), (i + 1).to_i16().unwrap()); }
         }
         let order: Vec<DirectionSign> = vec![DirectionSign::Coda, DirectionSign::DoubleCoda, DirectionSign::Segno, DirectionSign::SegnoSegno, DirectionSign::Fine,
                                              DirectionSign::DaCapo,
@@ -1,119 +1,126 @@
 use fraction::ToPrimitive;
-use encoding_rs::*;
+use crate::encoding::TextEncoding;
+use crate::gp::GUITAR_FILE_MAX_SIZE;
+use std::io::{BufRead, Read};
 
 //reading functions
 
+/// Bytes of a file read one value after another, from a slice in memory or from a stream
+pub(crate) struct Input<'a> {
+    reader: Box<dyn BufRead + 'a>,
+    /// Number of bytes read
+    position: usize,
+    /// Encoding of the strings, they are detected with `TextEncoding::Auto`
+    encoding: TextEncoding,
+    /// Encoding detected from the strings read with `TextEncoding::Auto`
+    detected: Option<TextEncoding>,
+}
+impl<'a> Input<'a> {
+    pub(crate) fn new(reader: impl BufRead + 'a, encoding: TextEncoding) -> Input<'a> {
+        Input {reader: Box::new(reader), position: 0, encoding, detected: None}
+    }
+    /// Encoding of the strings: the detected one with `TextEncoding::Auto`, or `TextEncoding::Auto` if every string was plain ASCII
+    pub(crate) fn encoding(&self) -> TextEncoding { if self.encoding == TextEncoding::Auto {self.detected.unwrap_or_default()} else {self.encoding} }
+    /// Decode a string. With `TextEncoding::Auto`, the first detected encoding is kept for the following strings while they are valid
+    /// in it: short strings are easy to mistake for another encoding.
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        if self.encoding != TextEncoding::Auto {return self.encoding.decode(bytes);}
+        if !bytes.is_ascii() && !self.detected.is_some_and(|e| e.can_decode(bytes)) {self.detected = TextEncoding::detect(bytes);}
+        self.detected.unwrap_or(TextEncoding::Windows1252).decode(bytes)
+    }
+    /// Number of bytes read since the start of the file
+    pub(crate) fn _position(&self) -> usize { self.position }
+    /// Check if every byte has been read
+    pub(crate) fn is_at_end(&mut self) -> bool { self.reader.fill_buf().map(|b| b.is_empty()).unwrap_or(true) }
+    /// Panics if `count` more bytes go beyond `GUITAR_FILE_MAX_SIZE`: the lengths read in a corrupted file or an endless stream
+    /// must not allocate or read more than a file can hold.
+    fn check_size(&self, count: usize) {
+        if count > GUITAR_FILE_MAX_SIZE - self.position {panic!("File is too big (bigger than 16 MB)");}
+    }
+    /// Read `N` bytes, panics at the end of the file
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        self.check_size(N);
+        let mut bytes = [0u8; N];
+        if self.reader.read_exact(&mut bytes).is_err() {panic!("End of file reached");}
+        self.position += N;
+        bytes
+    }
+    /// Read `count` bytes, panics at the end of the file
+    fn bytes(&mut self, count: usize) -> Vec<u8> {
+        self.check_size(count);
+        let mut bytes = vec![0u8; count];
+        if self.reader.read_exact(&mut bytes).is_err() {panic!("End of file reached");}
+        self.position += count;
+        bytes
+    }
+    /// Skip `count` bytes, or the remaining bytes at the end of the file
+    pub(crate) fn skip(&mut self, count: usize) {
+        self.check_size(count);
+        let skipped = std::io::copy(&mut self.reader.by_ref().take(count.to_u64().unwrap()), &mut std::io::sink()).unwrap_or(0);
+        self.position += skipped.to_usize().unwrap();
+    }
+}
+
 /// Read a byte and increase the cursor position by 1
-/// * `data` - array of bytes
-/// * `seek` - start position to read
+/// * `data` - input to read
 /// * returns the read byte as u8
-pub(crate) fn read_byte(data: &[u8], seek: &mut usize ) -> u8 {
-    if data.len() < *seek {panic!("End of filee reached");}
-    let b = data[*seek];
-    *seek += 1;
-    b
-}
+pub(crate) fn read_byte(data: &mut Input) -> u8 { data.array::<1>()[0] }
 
 /// Read a signed byte and increase the cursor position by 1
-/// * `data` - array of bytes
-/// * `seek` - start position to read
+/// * `data` - input to read
 /// * returns the read byte as u8
-pub(crate) fn read_signed_byte(data: &[u8], seek: &mut usize ) -> i8 {
-    if data.len() < *seek {panic!("End of file reached");}
-    let b = data[*seek] as i8;
-    *seek += 1;
-    b
-}
+pub(crate) fn read_signed_byte(data: &mut Input) -> i8 { i8::from_le_bytes(data.array::<1>()) }
 
 /// Read a boolean and increase the cursor position by 1
-/// * `data` - array of bytes
-/// * `seek` - start position to read
+/// * `data` - input to read
 /// * returns boolean value
-pub(crate) fn read_bool(data: &[u8], seek: &mut usize ) -> bool {
-    if data.len() < *seek {panic!("End of file reached");}
-    let b = data[*seek];
-    *seek += 1;
-    b != 0
-}
+pub(crate) fn read_bool(data: &mut Input) -> bool { data.array::<1>()[0] != 0 }
 
 /// Read a short and increase the cursor position by 2 (2 little-endian bytes)
-/// * `data` - array of bytes
-/// * `seek` - start position to read
+/// * `data` - input to read
 /// * returns the short value
-pub(crate) fn read_short(data: &[u8], seek: &mut usize ) -> i16 {
-    if data.len() < *seek + 2 {panic!("End of file reached");}
-    let n = i16::from_le_bytes([data[*seek], data[*seek+1]]);
-    *seek += 2;
-    n
-}
+pub(crate) fn read_short(data: &mut Input) -> i16 { i16::from_le_bytes(data.array::<2>()) }
 
 /// Read an integer and increase the cursor position by 4 (4 little-endian bytes)
-/// * `data` - array of bytes
-/// * `seek` - start position to read
+/// * `data` - input to read
 /// * returns the integer value
-pub(crate) fn read_int(data: &[u8], seek: &mut usize ) -> i32 {
-    if data.len() < *seek + 4 {panic!("End of file reached");}
-    let n = i32::from_le_bytes([data[*seek], data[*seek+1], data[*seek+2], data[*seek+3]]);
-    *seek += 4;
-    n
-}
-
-/*/// Read a float and increase the cursor position by 4 (4 little-endian bytes)
-/// * `data` - array of bytes
-/// * `seek` - start position to read
-/// * returns the float value
-pub(crate) fn read_float(data: &[u8], seek: &mut usize ) -> f32 {
-    let n = f32::from_le_bytes([data[*seek], data[*seek+1], data[*seek+2], data[*seek+3]]);
-    *seek += 4;
-    n
-}*/
+pub(crate) fn read_int(data: &mut Input) -> i32 { i32::from_le_bytes(data.array::<4>()) }
 
 /// Read a double and increase the cursor position by 8 (8 little-endian bytes)
-/// * `data` - array of bytes
-/// * `seek` - start position to read
+/// * `data` - input to read
 /// * returns the float value
-pub(crate) fn read_double(data: &[u8], seek: &mut usize ) -> f64 {
-    let n = f64::from_le_bytes([data[*seek], data[*seek+1], data[*seek+2], data[*seek+3], data[*seek+4], data[*seek+5], data[*seek+6], data[*seek+7]]);
-    *seek += 8;
-    n
-}
+pub(crate) fn read_double(data: &mut Input) -> f64 { f64::from_le_bytes(data.array::<8>()) }
 
 /// Read length of the string stored in 1 integer and followed by character bytes.
-pub(crate) fn read_int_size_string(data: &[u8], seek: &mut usize) -> String {
-    let size = read_int(data, seek).to_usize().unwrap();
-    read_string(data, seek, size, None)
+pub(crate) fn read_int_size_string(data: &mut Input) -> String {
+    let size = read_int(data).to_usize().unwrap();
+    read_string(data, size, None)
 }
 
 /// Read length of the string increased by 1 and stored in 1 integer followed by length of the string in 1 byte and finally followed by character bytes.
-pub(crate) fn read_int_byte_size_string(data: &[u8], seek: &mut usize) -> String {
-    let s = (read_int(data, seek) - 1).to_usize().unwrap();
-    read_byte_size_string(data, seek, s)
+pub(crate) fn read_int_byte_size_string(data: &mut Input) -> String {
+    let s = (read_int(data) - 1).to_usize().unwrap();
+    read_byte_size_string(data, s)
 }
 
 /// Read length of the string stored in 1 byte and followed by character bytes.
 /// * `size`: string length that we should attempt to read.
-pub(crate) fn read_byte_size_string(data: &[u8], seek: &mut usize, size: usize) -> String {
+pub(crate) fn read_byte_size_string(data: &mut Input, size: usize) -> String {
     //println!("read_int_byte_size_string(), size={}", size);
-    let length = read_byte(data, seek).to_usize().unwrap();
-    read_string(data, seek, size, Some(length))
+    let length = read_byte(data).to_usize().unwrap();
+    read_string(data, size, Some(length))
 }
 
 /// Read a string
 /// * `size`:   real string length
 /// * `length`: optionnal provided length (in case of blank chars after the string)
-fn read_string(data: &[u8], seek: &mut usize, size: usize, length: Option<usize>) -> String {
+fn read_string(data: &mut Input, size: usize, length: Option<usize>) -> String {
     //println!("read_string(), size={} \t length={:?}", size, length);
     let length = length.unwrap_or(size);
-    //let count = if size > 0 {size} else {length};
-    let (cow, _encoding_used, had_errors) = WINDOWS_1252.decode(&data[*seek..*seek+length]);
-    if had_errors {
-        let parse = std::str::from_utf8(&data[*seek..*seek+length]);
-        if parse.is_err() {panic!("Unable to read string");}
-        *seek += size;
-        return parse.unwrap().to_string();
-    }
-    *seek += size;
-    cow.to_string()
+    let bytes = data.bytes(length);
+    //the string is followed by blank chars up to `size`
+    data.skip(size.saturating_sub(length));
+    data.decode(&bytes)
 }
 
 pub const VERSIONS: [((u8,u8,u8), bool, &str); 10] = [((3, 0, 0), false, "FICHIER GUITAR PRO v3.00"),
@@ -130,11 +137,10 @@ pub const VERSIONS: [((u8,u8,u8), bool, &str); 10] = [((3, 0, 0), false, "FICHIE
                                                       ((5, 2, 0), true,  "CLIPBOARD GP 5.2")];
 
 /// Read the file version. It is on the first 31 bytes (1st byte is the real length, the following 30 bytes contain the version string) of the file.
-/// * `data` - array of bytes
-/// * `seek` - cursor that will be incremented
+/// * `data` - input to read
 /// * returns version
-pub(crate) fn read_version_string(data: &[u8], seek: &mut usize) -> crate::headers::Version {
-    let mut v = crate::headers::Version {data: read_byte_size_string(data, seek, 30), number: (5,2,0), clipboard: false};
+pub(crate) fn read_version_string(data: &mut Input) -> crate::headers::Version {
+    let mut v = crate::headers::Version {data: read_byte_size_string(data, 30), number: (5,2,0), clipboard: false};
     //println!("Version {} {}", n, s);
     //get the version
     for x in VERSIONS {
@@ -149,11 +155,11 @@ pub(crate) fn read_version_string(data: &[u8], seek: &mut usize) -> crate::heade
 }
 
 /// Read a color. Colors are used by `Marker` and `Track`. They consist of 3 consecutive bytes and one blank byte.
-pub(crate) fn read_color(data: &[u8], seek: &mut usize) -> i32 {
-    let r = read_byte(data, seek).to_i32().unwrap();
-    let g = read_byte(data, seek).to_i32().unwrap();
-    let b = read_byte(data, seek).to_i32().unwrap();
-    *seek += 1;
+pub(crate) fn read_color(data: &mut Input) -> i32 {
+    let r = read_byte(data).to_i32().unwrap();
+    let g = read_byte(data).to_i32().unwrap();
+    let b = read_byte(data).to_i32().unwrap();
+    data.skip(1);
     r * 65536 + g * 256 + b
 }
 
@@ -179,27 +185,48 @@ pub(crate) fn write_color(data: &mut Vec<u8>, value: i32) {
     write_byte(data, b);
     write_placeholder_default(data, 1);
 }
-pub(crate) fn write_byte_size_string(data: &mut Vec<u8>, value: &str) {
-    write_byte(data, value.chars().count().to_u8().unwrap());
-    data.extend(value.as_bytes());
+/// The lengths of the strings are the lengths of the encoded bytes, see `TextEncoding::encode()`. The length of a byte-size-string
+/// is stored in a byte: longer strings are truncated to 255 bytes.
+pub(crate) fn write_byte_size_string(data: &mut Vec<u8>, value: &str, encoding: TextEncoding) {
+    let bytes = encode_truncated(value, u8::MAX.into(), encoding);
+    write_byte(data, bytes.len().to_u8().unwrap());
+    data.extend(bytes);
+}
+/// Write a byte-size-string in a field of `size` bytes: the string is truncated if it is too long, otherwise the field is padded.
+pub(crate) fn write_padded_byte_size_string(data: &mut Vec<u8>, value: &str, size: usize, encoding: TextEncoding) {
+    let bytes = encode_truncated(value, size, encoding);
+    let padding = size - bytes.len();
+    write_byte(data, bytes.len().to_u8().unwrap());
+    data.extend(bytes);
+    write_placeholder_default(data, padding);
+}
+/// Encode a string in at most `size` bytes, without cutting a character in the middle.
+fn encode_truncated(value: &str, size: usize, encoding: TextEncoding) -> Vec<u8> {
+    let mut value = value;
+    let mut bytes = encoding.encode(value);
+    while bytes.len() > size {
+        value = &value[..value.char_indices().last().map(|c| c.0).unwrap_or(0)];
+        bytes = encoding.encode(value);
+    }
+    bytes
 }
-pub(crate) fn write_int_size_string(data: &mut Vec<u8>, value: &str) {
-    let count = value.chars().count();
-    write_i32(data, count.to_i32().unwrap()+1);
-    data.extend(value.as_bytes());
+pub(crate) fn write_int_size_string(data: &mut Vec<u8>, value: &str, encoding: TextEncoding) {
+    let bytes = encoding.encode(value);
+    write_i32(data, bytes.len().to_i32().unwrap());
+    data.extend(bytes);
 }
 
-pub(crate) fn write_int_byte_size_string(data: &mut Vec<u8>, value: &str) {
-    let count = value.chars().count();
-    write_i32(data, count.to_i32().unwrap()+1); //write_i32( (value.getBytes(charset).length + 1) );
-    write_byte(data, count.to_u8().unwrap());
-    data.extend(value.as_bytes());
+pub(crate) fn write_int_byte_size_string(data: &mut Vec<u8>, value: &str, encoding: TextEncoding) {
+    let bytes = encode_truncated(value, u8::MAX.into(), encoding);
+    write_i32(data, bytes.len().to_i32().unwrap()+1); //write_i32( (value.getBytes(charset).length + 1) );
+    write_byte(data, bytes.len().to_u8().unwrap());
+    data.extend(bytes);
 }
 
-pub(crate) fn write_version(data: &mut Vec<u8>, version: (u8,u8,u8)) {
+pub(crate) fn write_version(data: &mut Vec<u8>, version: (u8,u8,u8), clipboard: bool) {
     for v in VERSIONS {
-        if version == v.0 {
-            write_byte_size_string(data, v.2);
+        if version == v.0 && clipboard == v.1 {
+            write_byte_size_string(data, v.2, TextEncoding::Auto);
             write_placeholder_default(data, 30 - v.2.len());
             break;
         }
@@ -216,28 +243,37 @@ mod test {
                                  0x20,0x47,0x55,0x49,0x54,0x41,0x52,0x20,
                                  0x50,0x52,0x4f,0x20,0x76,0x33,0x2e,0x30,
                                  0x30];
-        let mut seek = 0usize;
-        assert_eq!(read_byte_size_string(&data, &mut seek, 30), "FICHIER GUITAR PRO v3.00");
+        let mut input = Input::new(&data[..], TextEncoding::Auto);
+        assert_eq!(read_byte_size_string(&mut input, 30), "FICHIER GUITAR PRO v3.00");
     }
 
     #[test]
     fn test_read_int_size_string() {
         let data: Vec<u8> = vec![0x08,0x00,0x00,0x00,   0x25,0x41,0x52,0x54,0x49,0x53,0x54,0x25];
-        let mut seek = 0usize;
-        assert_eq!(read_int_size_string(&data, &mut seek), "%ARTIST%");
+        let mut input = Input::new(&data[..], TextEncoding::Auto);
+        assert_eq!(read_int_size_string(&mut input), "%ARTIST%");
     }
 
     #[test]
     fn test_read_int_byte_size_string() {
         let data: Vec<u8> = vec![0x09,0x00,0x00,0x00,   0x08,   0x25,0x41,0x52,0x54,0x49,0x53,0x54,0x25];
-        let mut seek = 0usize;
-        assert_eq!(read_int_byte_size_string(&data, &mut seek), "%ARTIST%");
+        let mut input = Input::new(&data[..], TextEncoding::Auto);
+        assert_eq!(read_int_byte_size_string(&mut input), "%ARTIST%");
+    }
+
+    #[test]
+    #[should_panic(expected = "File is too big")]
+    fn test_read_too_long_string() {
+        //the length is checked before allocating the string
+        let data: Vec<u8> = vec![0xff,0xff,0xff,0x7f,   0x25,0x41,0x52,0x54,0x49,0x53,0x54,0x25];
+        let mut input = Input::new(&data[..], TextEncoding::Auto);
+        read_int_size_string(&mut input);
     }
 
     #[test]
     fn test_write_byte_size_string() {
         let mut out: Vec<u8> = Vec::with_capacity(32);
-        write_byte_size_string(&mut out, "FICHIER GUITAR PRO v3.00");
+        write_byte_size_string(&mut out, "FICHIER GUITAR PRO v3.00", TextEncoding::Auto);
         let expected_result: Vec<u8> = vec![0x18,0x46,0x49,0x43,0x48,0x49,0x45,0x52,
                                             0x20,0x47,0x55,0x49,0x54,0x41,0x52,0x20,
                                             0x50,0x52,0x4f,0x20,0x76,0x33,0x2e,0x30,
@@ -247,14 +283,41 @@ mod test {
     #[test]
     fn test_write_int_size_string() {
         let mut out: Vec<u8> = Vec::with_capacity(16);
-        write_int_size_string(&mut out, "%ARTIST%");
-        let expected_result: Vec<u8> = vec![0x09,0x00,0x00,0x00,   0x08,0x25,0x41,0x52,0x54,0x49,0x53,0x54,0x25];
+        write_int_size_string(&mut out, "%ARTIST%", TextEncoding::Auto);
+        let expected_result: Vec<u8> = vec![0x08,0x00,0x00,0x00,   0x25,0x41,0x52,0x54,0x49,0x53,0x54,0x25];
         assert_eq!(out, expected_result);
     }
     #[test]
+    fn test_write_padded_byte_size_string() {
+        let mut out: Vec<u8> = Vec::with_capacity(8);
+        write_padded_byte_size_string(&mut out, "Bass", 6, TextEncoding::Utf8);
+        assert_eq!(out, vec![0x04,   0x42,0x61,0x73,0x73,   0x00,0x00]);
+        out.clear();
+        write_padded_byte_size_string(&mut out, "Guitare électrique", 4, TextEncoding::Utf8);
+        assert_eq!(out, vec![0x04,   0x47,0x75,0x69,0x74]);
+        //"é" is two bytes and is not cut
+        out.clear();
+        write_padded_byte_size_string(&mut out, "Été", 4, TextEncoding::Utf8);
+        assert_eq!(out, vec![0x03,   0xc3,0x89,0x74,   0x00]);
+        out.clear();
+        write_padded_byte_size_string(&mut out, "Été", 1, TextEncoding::Utf8);
+        assert_eq!(out, vec![0x00,   0x00]);
+    }
+    #[test]
+    fn test_write_long_byte_size_string() {
+        //the length is stored in a byte
+        let mut out: Vec<u8> = Vec::with_capacity(512);
+        write_byte_size_string(&mut out, &"é".repeat(200), TextEncoding::Utf8);
+        assert_eq!((out[0], out.len()), (254, 255));
+        assert_eq!(std::str::from_utf8(&out[1..]).unwrap(), "é".repeat(127));
+        out.clear();
+        write_int_byte_size_string(&mut out, &"a".repeat(300), TextEncoding::Windows1252);
+        assert_eq!((&out[0..5], out.len()), (&[0x00,0x01,0x00,0x00,   0xff][..], 260));
+    }
+    #[test]
     fn test_write_int_byte_size_string() {
         let mut out: Vec<u8> = Vec::with_capacity(16);
-        write_int_byte_size_string(&mut out, "%ARTIST%");
+        write_int_byte_size_string(&mut out, "%ARTIST%", TextEncoding::Auto);
         let expected_result: Vec<u8> = vec![0x09,0x00,0x00,0x00,   0x08,   0x25,0x41,0x52,0x54,0x49,0x53,0x54,0x25];
         assert_eq!(out, expected_result);
     }
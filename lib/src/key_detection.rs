@@ -0,0 +1,179 @@
+use std::ops::Range;
+
+use fraction::ToPrimitive;
+
+use crate::{gp::*, key_signature::*, enums::*};
+
+/// Krumhansl-Kessler profile of the major keys, from the tonic
+const MAJOR_PROFILE: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+/// Krumhansl-Kessler profile of the minor keys, from the tonic
+const MINOR_PROFILE: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+/// The result of a key estimation
+#[derive(Debug,Clone,PartialEq)]
+pub struct KeyEstimate {
+    pub key: KeySignature,
+    /// Pitch class of the tonic (0 is C)
+    pub tonic: i8,
+    /// Correlation between the pitch class histogram and the profile of the key, from -1 to 1
+    pub correlation: f64,
+    /// Difference between the correlation of the best key and the correlation of the second best key.
+    /// A value close to 0 means that the estimation is ambiguous.
+    pub confidence: f64,
+}
+
+/// The key estimated for a section of the song
+#[derive(Debug,Clone,PartialEq)]
+pub struct SectionKey {
+    /// Title of the marker starting the section, empty if the song starts without a marker
+    pub title: String,
+    /// Indexes of the measures of the section
+    pub measures: Range<usize>,
+    /// `None` if the section has no pitched note
+    pub estimate: Option<KeyEstimate>,
+}
+
+/// Get the key signature (number of sharps if positive, flats if negative) of a key. Six flats are preferred over six sharps.
+fn key_signature(tonic: i8, is_minor: bool) -> KeySignature {
+    let major_tonic = if is_minor {(tonic + 3) % 12} else {tonic};
+    let mut key = (major_tonic * 7) % 12;
+    if key > 5 {key -= 12;}
+    KeySignature {key, is_minor}
+}
+
+/// Pearson correlation between a histogram and a profile rotated to start on `tonic`.
+fn correlation(histogram: &[f64; 12], profile: &[f64; 12], tonic: usize) -> f64 {
+    let mean_h = histogram.iter().sum::<f64>() / 12.0;
+    let mean_p = profile.iter().sum::<f64>() / 12.0;
+    let (mut num, mut den_h, mut den_p) = (0f64, 0f64, 0f64);
+    for i in 0..12 {
+        let h = histogram[(i + tonic) % 12] - mean_h;
+        let p = profile[i] - mean_p;
+        num += h * p;
+        den_h += h * h;
+        den_p += p * p;
+    }
+    if den_h == 0.0 || den_p == 0.0 {0.0} else {num / (den_h * den_p).sqrt()}
+}
+
+/// Estimate the key of a pitch class histogram (index 0 is C) by correlating it with the 24 major and minor key profiles.
+/// Returns `None` if the histogram is empty.
+pub fn estimate_key(histogram: &[f64; 12]) -> Option<KeyEstimate> {
+    if histogram.iter().all(|h| *h <= 0.0) {return None;}
+    let mut scores: Vec<(f64, i8, bool)> = Vec::with_capacity(24);
+    for tonic in 0..12usize {
+        scores.push((correlation(histogram, &MAJOR_PROFILE, tonic), tonic.to_i8().unwrap(), false));
+        scores.push((correlation(histogram, &MINOR_PROFILE, tonic), tonic.to_i8().unwrap(), true));
+    }
+    scores.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    Some(KeyEstimate {key: key_signature(scores[0].1, scores[0].2), tonic: scores[0].1, correlation: scores[0].0, confidence: scores[0].0 - scores[1].0})
+}
+
+impl Song {
+    /// Compute the pitch class histogram of some measures: every pitched note adds its duration (in quarter notes) to its pitch class.
+    /// Percussion tracks, rests and dead notes are ignored.
+    pub fn pitch_class_histogram(&self, measures: Range<usize>) -> [f64; 12] {
+        let mut histogram = [0f64; 12];
+        for t in self.tracks.iter().filter(|t| !t.percussion_track) {
+            for m in t.measures.iter().skip(measures.start).take(measures.len()) {
+                for b in m.voices.iter().flat_map(|v| v.beats.iter()) {
+                    let length = f64::from(b.duration.time()) / DURATION_QUARTER_TIME.to_f64().unwrap();
+                    for n in b.notes.iter().filter(|n| n.kind == NoteType::Normal || n.kind == NoteType::Tie) {
+                        if let Some(p) = t.fingering_pitch(n) {histogram[p.rem_euclid(12).to_usize().unwrap()] += length;}
+                    }
+                }
+            }
+        }
+        histogram
+    }
+
+    /// Estimate the key of the whole song.
+    pub fn detect_key(&self) -> Option<KeyEstimate> { estimate_key(&self.pitch_class_histogram(0..self.measure_headers.len())) }
+
+    /// Estimate the key of every section of the song. A section starts on every measure with a marker and lasts until the next marker.
+    pub fn detect_section_keys(&self) -> Vec<SectionKey> {
+        let mut sections: Vec<SectionKey> = Vec::new();
+        for (i, h) in self.measure_headers.iter().enumerate() {
+            if i == 0 || h.marker.is_some() {
+                if let Some(s) = sections.last_mut() {s.measures.end = i;}
+                sections.push(SectionKey {title: h.marker.as_ref().map(|m| m.title.clone()).unwrap_or_default(), measures: i..self.measure_headers.len(), estimate: None});
+            }
+        }
+        for s in sections.iter_mut() {s.estimate = estimate_key(&self.pitch_class_histogram(s.measures.clone()));}
+        sections
+    }
+
+    /// Estimate the keys of the song and write them: the key of the whole song in `Song::key`, and the key of every section in the
+    /// key signature of its measure headers and measures. Sections without pitched notes keep the key of the previous section.
+    /// Returns the estimated sections.
+    pub fn write_detected_keys(&mut self) -> Vec<SectionKey> {
+        if let Some(e) = self.detect_key() {self.key = e.key;}
+        let sections = self.detect_section_keys();
+        let mut key = self.key.clone();
+        for s in &sections {
+            if let Some(e) = &s.estimate {key = e.key.clone();}
+            for m in s.measures.clone() {
+                self.measure_headers[m].key_signature = key.clone();
+                for t in self.tracks.iter_mut() {
+                    if m < t.measures.len() {t.measures[m].key_signature = key.clone();}
+                }
+            }
+        }
+        sections
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{key_detection::*, headers::*, track::*, measure::*, beat::*, note::*};
+
+    /// A track playing the given pitches as quarter notes, 4 per measure
+    fn song(pitches: &[i16], markers: &[(usize, &str)]) -> Song {
+        let mut song = Song::default();
+        let mut track = Track::default();
+        for (i, chunk) in pitches.chunks(4).enumerate() {
+            let mut header = MeasureHeader {number: (i + 1) as u16, ..Default::default()};
+            if let Some(m) = markers.iter().find(|m| m.0 == i) {header.marker = Some(Marker {title: m.1.to_string(), ..Default::default()});}
+            song.measure_headers.push(header);
+            let mut voice = Voice::default();
+            for p in chunk {
+                let mut note = Note::default();
+                note.value = *p;
+                note.kind = NoteType::Normal;
+                voice.beats.push(Beat {notes: vec![note], ..Default::default()});
+            }
+            track.measures.push(Measure {voices: vec![voice], ..Default::default()});
+        }
+        song.tracks.push(track);
+        song
+    }
+
+    #[test]
+    fn test_estimate_key() {
+        let s = song(&[60, 62, 64, 65, 67, 69, 71, 72, 67, 64, 60, 67], &[]);
+        let e = s.detect_key().unwrap();
+        assert_eq!((e.tonic, e.key.key, e.key.is_minor), (0, 0, false));
+        assert!(e.confidence > 0.0);
+        let s = song(&[57, 59, 60, 62, 64, 65, 68, 69, 64, 60, 57, 64], &[]);
+        let e = s.detect_key().unwrap();
+        assert_eq!((e.tonic, e.key.key, e.key.is_minor), (9, 0, true));
+        assert_eq!(e.key.to_string(), "A minor");
+        assert!(estimate_key(&[0.0; 12]).is_none());
+    }
+
+    #[test]
+    fn test_section_keys() {
+        //a verse in G major, then a chorus in E flat major
+        let mut s = song(&[55, 59, 62, 67, 66, 62, 57, 55, 55, 59, 62, 67,
+                           63, 67, 70, 75, 74, 70, 65, 63, 63, 67, 70, 75], &[(0, "Verse"), (3, "Chorus")]);
+        let sections = s.detect_section_keys();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].title, "Verse");
+        assert_eq!(sections[1].measures, 3..6);
+        assert_eq!(sections[0].estimate.as_ref().unwrap().key.key, 1);
+        assert_eq!(sections[1].estimate.as_ref().unwrap().key.key, -3);
+        s.write_detected_keys();
+        assert_eq!(s.measure_headers[2].key_signature.key, 1);
+        assert_eq!(s.tracks[0].measures[4].key_signature.key, -3);
+    }
+}
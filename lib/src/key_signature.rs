@@ -13,6 +13,7 @@ pub const DURATION_HUNDRED_TWENTY_EIGHTH: u8 = 128;
 
 /// A time signature
 #[derive(Debug,Clone, PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimeSignature {
     pub numerator: i8,
     pub denominator: Duration,
@@ -29,6 +30,7 @@ pub const KEY_SIGNATURES: [&str; 34] = ["F♭ major", "C♭ major", "G♭ major"
             "F minor", "C minor", "G minor", "D minor", "A minor", "E minor", "B minor",
             "F# minor", "C# minor", "G# minor", "D# minor", "A# minor", "E# minor"];
 #[derive(Debug,Clone,Default,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KeySignature {
     pub key: i8,
     pub is_minor: bool,
@@ -36,15 +38,16 @@ pub struct KeySignature {
 //impl Default for KeySignature { fn default() -> Self { KeySignature { key: 0, is_minor: false, }} }
 impl std::fmt::Display for KeySignature {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let index: usize = if self.is_minor {(23i8 + self.key).to_usize().unwrap()} else {(8i8 + self.key).to_usize().unwrap()};
+        let index: usize = if self.is_minor {(25i8 + self.key).to_usize().unwrap()} else {(8i8 + self.key).to_usize().unwrap()};
         write!(f, "{}", KEY_SIGNATURES[index])
     }
 }
 
 
-const SUPPORTED_TUPLETS: [(u8, u8); 10] = [(1,1), (3,2), (5,4), (6,4), (7,4), (9,8), (10,8), (11,8), (12,8), (13,8)];
+pub(crate) const SUPPORTED_TUPLETS: [(u8, u8); 10] = [(1,1), (3,2), (5,4), (6,4), (7,4), (9,8), (10,8), (11,8), (12,8), (13,8)];
 
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Duration {
     pub value:u16,
     pub dotted: bool,
@@ -64,18 +67,20 @@ impl Default for Duration {
 impl Duration {
     //fn convert_time(&self, time: u64) -> u64 { time * self.division_times as u64 / self.division_enters as u64 }
 
-    pub(crate) fn is_supported(&self) -> bool { SUPPORTED_TUPLETS.contains(&(self.tuplet_enters, self.tuplet_times))}
+    pub fn is_supported(&self) -> bool { SUPPORTED_TUPLETS.contains(&(self.tuplet_enters, self.tuplet_times))}
 
     pub(crate) fn convert_time(&self, time: u32) -> u32 {
-        let result = fraction::Fraction::new(time * self.tuplet_enters.to_u32().unwrap(), self.tuplet_times.to_u32().unwrap());
+        let result = fraction::Fraction::new(time * self.tuplet_times.to_u32().unwrap(), self.tuplet_enters.to_u32().unwrap());
         if *result.denom().unwrap() == 1 {(*result.numer().unwrap()).to_u32().unwrap()}
         else {result.trunc().to_u32().unwrap()}
     }
 
-    pub(crate) fn time(&self) -> u32 {
+    pub fn time(&self) -> u32 {
         let mut result = (f64::from(DURATION_QUARTER_TIME.to_i32().unwrap()) * 4f64 / f64::from(self.value)).trunc();
         //println!("\tDuration.time(): result: {}", result);
-        if self.dotted { result += (result/2f64).trunc(); }
+        let base = result;
+        if self.dotted { result += (base/2f64).trunc(); }
+        if self.double_dotted { result += (base/2f64).trunc() + (base/4f64).trunc(); }
         //if self.dotted { result += (result/4f64).trunc() * 3f64; }
         //println!("\tDuration.time(): result: {}", result);
         self.convert_time(result.to_u32().unwrap())
@@ -114,13 +119,13 @@ impl Duration {
 /// * *3*: thirty-second note
 /// 
 /// If flag at *0x20* is true, the tuplet is read
-pub(crate) fn read_duration(data: &[u8], seek: &mut usize, flags: u8) -> Duration {
+pub(crate) fn read_duration(data: &mut Input, flags: u8) -> Duration {
     //println!("read_duration()");
-    let mut d = Duration{value: 1 << (read_signed_byte(data, seek) + 2), ..Default::default()};
-    //let b = read_signed_byte(data, seek); println!("B: {}", b); d.value = 1 << (b + 2);
+    let mut d = Duration{value: 1 << (read_signed_byte(data) + 2), ..Default::default()};
+    //let b = read_signed_byte(data); println!("B: {}", b); d.value = 1 << (b + 2);
     d.dotted = (flags & 0x01) == 0x01;
     if (flags & 0x20) == 0x20 {
-        let i_tuplet = read_int(data, seek);
+        let i_tuplet = read_int(data);
         if i_tuplet == 3       {d.tuplet_enters = 3;  d.tuplet_times = 2;}
         else if i_tuplet == 5  {d.tuplet_enters = 5;  d.tuplet_times = 4;}
         else if i_tuplet == 6  {d.tuplet_enters = 6;  d.tuplet_times = 4;}
@@ -134,6 +139,48 @@ pub(crate) fn read_duration(data: &[u8], seek: &mut usize, flags: u8) -> Duratio
     d
 }
 
+/// All the durations that can be written, with their length in ticks, the longest first. Straight and dotted durations come before tuplets of the same length.
+/// The table is built on the first call.
+fn duration_table() -> &'static [(i64, Duration)] {
+    static TABLE: std::sync::OnceLock<Vec<(i64, Duration)>> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table: Vec<(i64, Duration)> = Vec::new();
+        for tuplet in SUPPORTED_TUPLETS {
+            for dotted in [false, true] {
+                for value in [1u16, 2, 4, 8, 16, 32, 64] {
+                    if dotted && tuplet != (1,1) {continue;}
+                    let d = Duration {value, dotted, tuplet_enters: tuplet.0, tuplet_times: tuplet.1, ..Default::default()};
+                    table.push((i64::from(d.time()), d));
+                }
+            }
+        }
+        table.sort_by(|a, b| b.0.cmp(&a.0).then(u8::from(!a.1.is_default_tuplet()).cmp(&u8::from(!b.1.is_default_tuplet()))));
+        table
+    })
+}
+
+/// Split a length in ticks into durations. Straight and dotted durations are used when the length is a multiple of a sixty-fourth note,
+/// tuplets otherwise. What remains below the shortest duration is dropped.
+pub(crate) fn durations_for(ticks: i64) -> Vec<Duration> {
+    let table = duration_table();
+    let mut result = Vec::new();
+    let mut remaining = ticks;
+    while remaining > 0 {
+        let straight = remaining % 60 == 0;
+        let d = table.iter().find(|d| d.0 == remaining)
+                     .or_else(|| table.iter().find(|d| d.0 <= remaining && d.1.is_default_tuplet() == straight))
+                     .or_else(|| table.iter().find(|d| d.0 <= remaining));
+        match d {
+            Some(d) => {
+                result.push(d.1.clone());
+                remaining -= d.0;
+            },
+            None => break,
+        }
+    }
+    result
+}
+
 /*/// A *n:m* tuplet.
 #[derive(Clone)]
 struct Tuplet {
@@ -14,6 +14,25 @@ pub mod rse;
 pub mod note;
 pub mod lyric;
 pub mod beat;
+pub mod fingering;
+pub mod smf;
+pub mod chord_detection;
+pub mod key_detection;
+pub mod progression;
+pub mod conversion;
+pub mod timing;
+pub mod validation;
+pub mod rescale;
+pub mod repeat;
+pub mod sections;
+pub mod melody;
+pub mod pitch;
+pub mod traversal;
+pub mod builder;
+pub mod summary;
+pub mod diff;
+pub mod merge;
+pub mod encoding;
 
 #[cfg(test)]
 mod test {
@@ -22,6 +41,7 @@ mod test {
     use crate::gp::Song;
 
     fn read_file(path: String) -> Vec<u8> {
+        let path = format!("{}/../{}", env!("CARGO_MANIFEST_DIR"), path);
         let f = fs::OpenOptions::new().read(true).open(&path).expect("Cannot open file");
         let size: usize = fs::metadata(&path).unwrap_or_else(|_e|{panic!("Unable to get file size")}).len().to_usize().unwrap();
         let mut data: Vec<u8> = Vec::with_capacity(size);
@@ -64,6 +84,160 @@ mod test {
         let mut song: Song = Song::default();
         song.read_gp3(&read_file(String::from("test/Duration.gp3")));
     }
+    #[test]
+    fn test_tuplet_duration() {
+        use crate::key_signature::*;
+        //a triplet of eighths lasts as long as two eighths
+        assert_eq!(Duration {value: 8, tuplet_enters: 3, tuplet_times: 2, ..Default::default()}.time(), 320);
+        assert_eq!(Duration {value: 16, tuplet_enters: 5, tuplet_times: 4, ..Default::default()}.time() * 5, 960);
+    }
+    #[test]
+    fn test_set_instrument() {
+        use crate::midi::*;
+        let mut channel = MidiChannel::default();
+        channel.set_instrument(30);
+        assert_eq!(channel.get_instrument_name(), CHANNEL_DEFAULT_NAMES[30]);
+        //percussion channels use -1 for the default drum kit
+        let mut drums = MidiChannel::default();
+        drums.channel = DEFAULT_PERCUSSION_CHANNEL;
+        drums.set_instrument(-1);
+        assert_eq!(drums.get_instrument_name(), CHANNEL_DEFAULT_NAMES[0]);
+    }
+
+    #[test]
+    fn test_gp5_track_flags() {
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/all-percussion.gp5")));
+        for t in &song.tracks {
+            assert!(t.visible && !t.banjo_track && !t.twelve_stringed_guitar_track);
+            assert!(t.percussion_track && song.channels[t.channel_index].channel == 9);
+        }
+        song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Effects.gp5")));
+        assert!(song.tracks.iter().all(|t| t.visible && !t.percussion_track && song.channels[t.channel_index].channel != 9));
+    }
+
+    #[test]
+    fn test_int_byte_size_strings() {
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/gamma_ray-heading_for_tomorrow.gp3")));
+        assert!(song.measure_headers.iter().any(|h| h.marker.as_ref().is_some_and(|m| m.title == "Comienza a cantar")));
+        song = Song::default();
+        song.read_gp4(&read_file(String::from("test/fret-diagram.gp4")));
+        assert!(song.measure_headers.iter().any(|h| h.marker.as_ref().is_some_and(|m| m.title == "Intro")));
+        song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Key.gp5")));
+        assert_eq!((song.tempo_name.as_str(), song.page_setup.title.as_str(), song.page_setup.page_number.as_str()), ("Moderate", "%TITLE%", "Page %N%/%P%"));
+    }
+
+    #[test]
+    fn test_read_chords_and_note_effects() {
+        use crate::beat::Beat;
+        fn beats(song: &Song) -> Vec<&Beat> { song.tracks.iter().flat_map(|t| &t.measures).flat_map(|m| &m.voices).flat_map(|v| &v.beats).collect() }
+        for (path, version) in [("test/Chords.gp4", 4), ("test/Chords.gp5", 5)] {
+            let mut song: Song = Song::default();
+            if version == 4 {song.read_gp4(&read_file(String::from(path)));} else {song.read_gp5(&read_file(String::from(path)));}
+            let chords: Vec<_> = beats(&song).into_iter().filter_map(|b| b.effect.chord.as_ref()).collect();
+            assert!(chords.iter().all(|c| c.strings.len() == 6));
+            assert!(chords.iter().any(|c| c.name == "E" && c.strings == vec![4, 5, 4, 2, 2, 0]));
+        }
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/heavy-accent.gp5")));
+        assert!(beats(&song).iter().flat_map(|b| &b.notes).any(|n| n.effect.heavy_accentuated_note && !n.effect.accentuated_note));
+        song = Song::default();
+        song.read_gp4(&read_file(String::from("test/sforzato.gp4")));
+        assert!(beats(&song).iter().flat_map(|b| &b.notes).any(|n| n.effect.accentuated_note && !n.effect.heavy_accentuated_note));
+        song = Song::default();
+        song.read_gp5(&read_file(String::from("test/tremolos.gp5")));
+        let mut tremolos: Vec<u16> = beats(&song).iter().flat_map(|b| &b.notes).filter_map(|n| n.effect.tremolo_picking.as_ref().map(|t| t.duration.value)).collect();
+        tremolos.sort();
+        assert_eq!(tremolos, vec![8, 32]);
+        //the artificial harmonics are 7 semitones above the real note
+        song = Song::default();
+        song.read_gp4(&read_file(String::from("test/Harmonics.gp4")));
+        assert!(beats(&song).iter().flat_map(|b| &b.notes).any(|n| n.effect.harmonic.as_ref().is_some_and(|h| h.pitch.as_ref().is_some_and(|p| p.value == 2))));
+    }
+
+    #[test]
+    fn test_read_mix_tables_and_measures() {
+        //mix table durations must not drop the changed values
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/gamma_ray-heading_for_tomorrow.gp3")));
+        let volumes: Vec<u8> = song.tracks.iter().flat_map(|t| t.measures.iter()).flat_map(|m| m.voices.iter()).flat_map(|v| v.beats.iter())
+            .filter_map(|b| b.effect.mix_table_change.as_ref().and_then(|c| c.volume.as_ref().map(|v| v.value))).collect();
+        assert!(!volumes.is_empty());
+        //the measure start is the start of its header and is not moved by the beats of its voice
+        assert!(song.tracks[0].measures.iter().all(|m| m.start == song.measure_headers[m.header_index].start));
+        //the last equalizer value is the gain
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/RSE.gp5")));
+        assert_eq!(song.master_effect.equalizer.knobs.len(), 10);
+        assert!(song.tracks.iter().all(|t| t.rse.equalizer.knobs.len() == 3));
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Directions.gp5")));
+        assert_eq!(song.measure_headers[0].direction, Some(crate::enums::DirectionSign::Coda));
+        assert_eq!(song.measure_headers[5].direction, Some(crate::enums::DirectionSign::DaCapo));
+        assert_eq!(song.measure_headers[18].direction, Some(crate::enums::DirectionSign::DaDoubleCoda));
+    }
+
+    #[test]
+    fn test_write_long_names() {
+        let mut song = Song::default();
+        song.read_gp3(&read_file(String::from("test/Chords.gp3")));
+        song.tracks[0].name = "Electric guitar ".repeat(3);
+        let mut chords = 0;
+        for m in song.tracks[0].measures.iter_mut() {for v in m.voices.iter_mut() {for b in v.beats.iter_mut() {
+            if let Some(c) = b.effect.chord.as_mut() {c.name = String::from("Chord with a name of more than 22 bytes"); chords += 1;}
+        }}}
+        assert!(chords > 0);
+        //the names are cut to the size of their field
+        let out = song.write((3,0,0), None);
+        let mut song = Song::default();
+        song.read_gp3(&out);
+        assert_eq!(song.tracks[0].name, "Electric guitar Electric guitar Electric");
+        assert!(song.tracks[0].measures.iter().flat_map(|m| m.voices.iter()).flat_map(|v| v.beats.iter())
+            .filter_map(|b| b.effect.chord.as_ref()).all(|c| c.name == "Chord with a name of m"));
+    }
+
+    #[test]
+    fn test_write_measure_headers() {
+        for (file, version) in [("test/Repeat.gp4", (4,0,0)), ("test/volta.gp5", (5,1,0)), ("test/Directions.gp5", (5,1,0)), ("test/keysig.gp4", (4,0,0))] {
+            let mut song = Song::default();
+            if version.0 == 4 {song.read_gp4(&read_file(String::from(file)));} else {song.read_gp5(&read_file(String::from(file)));}
+            let out = song.write(version, None);
+            let mut written = Song::default();
+            if version.0 == 4 {written.read_gp4(&out);} else {written.read_gp5(&out);}
+            assert_eq!(written.measure_headers.len(), song.measure_headers.len(), "{}", file);
+            assert_eq!(written.tracks.len(), song.tracks.len(), "{}", file);
+            for (w, h) in written.measure_headers.iter().zip(song.measure_headers.iter()) {
+                assert_eq!((w.repeat_open, w.repeat_close, w.repeat_alternative, w.double_bar), (h.repeat_open, h.repeat_close, h.repeat_alternative, h.double_bar), "{}", file);
+                assert_eq!((w.time_signature.numerator, w.time_signature.denominator.value), (h.time_signature.numerator, h.time_signature.denominator.value), "{}", file);
+                assert_eq!(w.key_signature, h.key_signature, "{}", file);
+                assert_eq!(w.direction, h.direction, "{}", file);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_effects() {
+        use crate::beat::Beat;
+        fn beats(song: &Song) -> Vec<&Beat> { song.tracks.iter().flat_map(|t| &t.measures).flat_map(|m| &m.voices).flat_map(|v| &v.beats).collect() }
+        for (file, version) in [("test/Effects.gp4", (4,0,0)), ("test/Chords.gp4", (4,0,0)), ("test/Effects.gp5", (5,1,0)), ("test/Wah.gp5", (5,1,0)), ("test/tremolos.gp5", (5,1,0)), ("test/Harmonics.gp5", (5,1,0))] {
+            let mut song = Song::default();
+            if version.0 == 4 {song.read_gp4(&read_file(String::from(file)));} else {song.read_gp5(&read_file(String::from(file)));}
+            let out = song.write(version, None);
+            let mut written = Song::default();
+            if version.0 == 4 {written.read_gp4(&out);} else {written.read_gp5(&out);}
+            assert_eq!((written.page_setup.words.as_str(), written.page_setup.copyright.as_str()), (song.page_setup.words.as_str(), song.page_setup.copyright.as_str()), "{}", file);
+            let (w, b) = (beats(&written), beats(&song));
+            assert_eq!(w.len(), b.len(), "{}", file);
+            for (w, b) in w.iter().zip(b.iter()) {
+                assert_eq!(format!("{:?}", w.effect), format!("{:?}", b.effect), "{}", file);
+                assert_eq!(w.notes.len(), b.notes.len(), "{}", file);
+                for (wn, n) in w.notes.iter().zip(b.notes.iter()) {assert_eq!(format!("{:?}", wn.effect), format!("{:?}", n.effect), "{}", file);}
+            }
+        }
+    }
 
     //effects
     #[test]
@@ -501,4 +675,607 @@ mod test {
         assert_eq!(out, data[0..out.len()]);
         song.read_gp3(&out);
     }
+
+    #[test]
+    fn test_write_read_round_trip() {
+        for (path, version) in [("test/Chords.gp4", (4,0,6)), ("test/Effects.gp5", (5,1,0)), ("test/Harmonics.gp5", (5,1,0)), ("test/Directions.gp5", (5,1,0)), ("test/Voices.gp5", (5,1,0))] {
+            let mut song = Song::default();
+            if version.0 == 4 {song.read_gp4(&read_file(String::from(path)));} else {song.read_gp5(&read_file(String::from(path)));}
+            let out = song.write(version, None);
+            let mut song2 = Song::default();
+            if version.0 == 4 {song2.read_gp4(&out);} else {song2.read_gp5(&out);}
+            assert_eq!(format!("{:?}", song.tracks), format!("{:?}", song2.tracks), "{}", path);
+            assert_eq!(format!("{:?}", song.measure_headers), format!("{:?}", song2.measure_headers), "{}", path);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_round_trip() {
+        for path in ["test/Chords.gp5", "test/Effects.gp5", "test/Wah.gp5", "test/Key.gp5", "test/RSE.gp5"] {
+            let mut song = Song::default();
+            song.read_gp5(&read_file(String::from(path)));
+            let json = serde_json::to_string(&song).unwrap();
+            let song2: Song = serde_json::from_str(&json).unwrap();
+            assert_eq!(song.write((5,1,0), None), song2.write((5,1,0), None), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_conversion_warnings() {
+        let mut song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Effects.gp5")));
+        assert!(song.conversion_warnings((5,1,0)).is_empty());
+        let warnings = song.conversion_warnings((3,0,0));
+        assert!(warnings.iter().any(|w| w.description == "palm muted notes" && w.since == 4));
+        assert!(warnings.iter().any(|w| w.description == "tracks using RSE" && w.since == 5));
+        assert!(song.conversion_warnings((4,0,6)).iter().all(|w| w.since == 5));
+        let mut song = Song::default();
+        song.read_gp3(&read_file(String::from("test/Effects.gp3")));
+        assert!(song.conversion_warnings((3,0,0)).is_empty());
+    }
+
+    #[test]
+    fn test_split_merge_tracks() {
+        let mut song = Song::default();
+        song.read_gp5(&read_file(String::from("test/001_Funky_Guy.gp5")));
+        let drums = song.extract_tracks(&[1]);
+        assert_eq!(drums.tracks.len(), 1);
+        assert!(drums.tracks[0].percussion_track);
+        assert_eq!(drums.measure_headers.len(), song.measure_headers.len());
+        let mut bass = song.extract_tracks(&[0]);
+        bass.merge_tracks(&drums, &[0]).unwrap();
+        assert_eq!(format!("{:?}", bass.tracks[1].measures), format!("{:?}", song.tracks[1].measures));
+        assert_eq!(bass.channels[bass.tracks[1].channel_index].channel, 9);
+        //written songs can be read back
+        let mut s = Song::default();
+        s.read_gp5(&drums.write((5,1,0), None));
+        assert_eq!(s.tracks[0].name, song.tracks[1].name);
+        //songs with different lengths
+        let mut chords = Song::default();
+        chords.read_gp5(&read_file(String::from("test/Chords.gp5")));
+        let mut effects = Song::default();
+        effects.read_gp5(&read_file(String::from("test/Effects.gp5")));
+        let (c, e) = (chords.measure_headers.len(), effects.measure_headers.len());
+        chords.merge_tracks(&effects, &[0]).unwrap();
+        assert_eq!(chords.measure_headers.len(), c.max(e));
+        assert!(chords.tracks.iter().all(|t| t.measures.len() == c.max(e)));
+        assert_ne!(chords.tracks[0].channel_index, chords.tracks[1].channel_index);
+        let mut s = Song::default();
+        s.read_gp5(&chords.write((5,1,0), None));
+        assert_eq!(s.tracks.len(), 2);
+        //the song is not changed when the time signatures differ
+        let mut other = effects.clone();
+        other.measure_headers[1].time_signature.numerator = 3;
+        let before = format!("{:?}{:?}{:?}", chords.measure_headers, chords.tracks, chords.channels);
+        assert_eq!(chords.merge_tracks(&other, &[0]), Err(crate::track::MergeError::TimeSignature {measure: 2, first: (4, 4), second: (3, 4)}));
+        assert_eq!(format!("{:?}{:?}{:?}", chords.measure_headers, chords.tracks, chords.channels), before);
+        //the channels of the other ports are used once the first port is full
+        while chords.merge_tracks(&effects, &[0]).is_ok() {}
+        assert!(chords.tracks.iter().any(|t| t.port == 4 && t.channel_index >= 48));
+        let before = format!("{:?}{:?}", chords.tracks, chords.channels);
+        assert!(matches!(chords.merge_tracks(&effects, &[0]), Err(crate::track::MergeError::NoFreeChannel {..})));
+        assert_eq!(format!("{:?}{:?}", chords.tracks, chords.channels), before);
+        let mut s = Song::default();
+        s.read_gp5(&chords.write((5,1,0), None));
+        assert!(s.tracks.iter().zip(chords.tracks.iter()).all(|(a, b)| a.channel_index == b.channel_index && a.port == b.port));
+    }
+
+    #[test]
+    fn test_measure_edition() {
+        //the measures and beats start at the ticks computed from the headers and the chords are inside their measure
+        let check = |song: &Song| {
+            assert!(song.tracks.iter().all(|t| t.measures.iter().all(|m| m.start == song.measure_headers[m.header_index].start)));
+            assert!(song.timing().iter().all(|t| song.tracks[t.track].measures[t.measure].voices[t.voice].beats[t.beat].start == Some(t.tick)));
+            for c in song.progression(&[]) {
+                let h = &song.measure_headers[c.measure];
+                assert!(c.start >= h.start && c.start < h.start + h.length());
+                assert!(c.beat >= 1.0 && c.beat < 1.0 + f64::from(h.time_signature.numerator));
+            }
+        };
+        let mut song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Chords.gp5")));
+        let progression = song.progression(&[]);
+        let count = song.measure_headers.len();
+        let copy = song.copy_measures(1..3);
+        assert_eq!(copy.measure_headers.len(), 2);
+        assert_eq!(copy.measure_headers[0].start, 960);
+        check(&copy);
+        //cut then paste at the same place
+        let cut = song.cut_measures(2..5);
+        assert_eq!(song.measure_headers.len(), count - 3);
+        assert!(song.tracks.iter().all(|t| t.measures.len() == count - 3));
+        check(&song);
+        song.insert_measures(2, &cut);
+        check(&song);
+        assert_eq!(song.progression(&[]), progression);
+        song.duplicate_measures(0..2);
+        assert_eq!(song.measure_headers.len(), count + 2);
+        assert_eq!(song.measure_headers[3].number, 4);
+        assert_eq!(song.measure_headers[2].start, song.measure_headers[1].start + song.measure_headers[1].length());
+        check(&song);
+        song.delete_measures(2..4);
+        check(&song);
+        assert_eq!(song.progression(&[]), progression);
+        //the chords of the following measures keep their beat
+        song.delete_measures(0..1);
+        check(&song);
+        let shifted: Vec<(usize, String, f64)> = progression.iter().filter(|c| c.measure > 1).map(|c| (c.measure - 1, c.name.clone(), c.beat)).collect();
+        assert!(!shifted.is_empty());
+        assert_eq!(song.progression(&[]).into_iter().filter(|c| c.measure > 0).map(|c| (c.measure, c.name, c.beat)).collect::<Vec<_>>(), shifted);
+        //clipboard files
+        let data = copy.write((5,1,0), Some(true));
+        let mut pasted = Song::default();
+        pasted.read_gp5(&data);
+        assert!(pasted.version.clipboard);
+        assert_eq!(pasted.clipboard, copy.clipboard);
+        assert_eq!(pasted.measure_headers.len(), 2);
+        //repeats cut by the range
+        let mut song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Repeat.gp5")));
+        let copy = song.copy_measures(1..3);
+        assert!(copy.measure_headers.iter().all(|h| h.repeat_close < 0 && h.repeat_alternative == 0));
+        song.delete_measures(0..1);
+        assert!(song.measure_headers[0].repeat_open);
+        assert_eq!(song.repeats()[0], (0, 0));
+    }
+    #[test]
+    fn test_timing() {
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Chords.gp5")));
+        //constant tempo: a quarter note lasts 60 / tempo seconds
+        let quarter = 60.0 / f64::from(song.tempo);
+        let end = song.measure_headers.last().map(|h| h.start + h.length()).unwrap();
+        assert!((song.length_seconds() - (end - 960).to_f64().unwrap() / 960.0 * quarter).abs() < 1e-9);
+        let timing = song.timing();
+        assert_eq!(timing.len(), song.tracks.iter().flat_map(|t| t.measures.iter().flat_map(|m| m.voices.iter().map(|v| v.beats.len()))).sum::<usize>());
+        for t in &timing {
+            assert!(t.tick >= song.measure_headers[t.measure].start && t.tick < song.measure_headers[t.measure].start + song.measure_headers[t.measure].length());
+            assert!((t.seconds - (t.tick - 960).to_f64().unwrap() / 960.0 * quarter).abs() < 1e-9);
+        }
+        assert!(timing.iter().any(|t| !t.notes.is_empty()));
+        //the beats read have absolute starts
+        assert!(timing.iter().all(|t| song.tracks[t.track].measures[t.measure].voices[t.voice].beats[t.beat].start == Some(t.tick)));
+        //beats without start are skipped by the readers until the starts are set again
+        let progression = song.chord_progression(&[]);
+        assert!(!progression.is_empty());
+        for b in song.tracks.iter_mut().flat_map(|t| t.measures.iter_mut().flat_map(|m| m.voices.iter_mut().flat_map(|v| v.beats.iter_mut()))) {b.start = None;}
+        assert!(song.chord_progression(&[]).is_empty());
+        song.update_beat_starts();
+        assert_eq!(song.chord_progression(&[]), progression);
+        assert!(timing.iter().all(|t| song.tracks[t.track].measures[t.measure].voices[t.voice].beats[t.beat].start == Some(t.tick)));
+        //tempo changes and transitions
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/tempo.gp5")));
+        let map = song.tempo_map();
+        assert!(map.changes.len() > 1);
+        assert!(map.changes.windows(2).all(|c| c[0].tick < c[1].tick));
+        let map = crate::timing::TempoMap {changes: vec![crate::timing::TempoChange {tick: 960, tempo: 120.0, transition: 0},
+                                                         crate::timing::TempoChange {tick: 1920, tempo: 60.0, transition: 960}]};
+        assert!((map.seconds(1920) - 0.5).abs() < 1e-9);
+        assert!((map.seconds(2880) - 0.5 - 2f64.ln()).abs() < 1e-9);
+        assert!((map.seconds(3840) - 1.5 - 2f64.ln()).abs() < 1e-9);
+        assert!((map.tempo_at(2400) - 90.0).abs() < 1e-9);
+    }
+    #[test]
+    fn test_rhythm_validation() {
+        use crate::validation::RhythmIssueKind;
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/Duration.gp3")));
+        assert!(song.validate_rhythm().is_empty()); //rounded tuplets
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/slur-notes-effect-mask.gp5")));
+        assert_eq!(song.validate_rhythm()[0].kind, RhythmIssueKind::Overfull {length: 4080, expected: 3840});
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/high-pitch.gp3")));
+        assert!(song.validate_rhythm().iter().all(|i| matches!(i.kind, RhythmIssueKind::FretOutOfRange {..})));
+        let strings = song.tracks[0].strings.len();
+        song.tracks[0].measures[0].voices[0].beats[0].notes[0].string = strings.to_i8().unwrap() + 1;
+        assert!(song.validate_rhythm().iter().any(|i| i.kind == RhythmIssueKind::StringOutOfRange {string: strings.to_i8().unwrap() + 1, strings}));
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/all-percussion.gp5")));
+        let issues = song.validate_rhythm();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, RhythmIssueKind::Underfull {length: 1920, expected: 3840});
+        assert_eq!(issues[0].to_string(), "track 1, measure 30, voice 1: underfull measure (1920 ticks instead of 3840)");
+        let beats = song.tracks[0].measures[29].voices[0].beats.len();
+        assert_eq!(song.fix_rhythm(), 1);
+        assert!(song.validate_rhythm().is_empty());
+        assert!(song.tracks[0].measures[29].voices[0].beats[beats..].iter().all(|b| b.status == crate::enums::BeatStatus::Rest));
+        //the rests start after the beats of the measure
+        assert_eq!(song.tracks[0].measures[29].voices[0].beats[beats].start, Some(song.measure_headers[29].start + 1920));
+        let data = song.write((5,1,0), None);
+        let mut written: Song = Song::default();
+        written.read_gp5(&data);
+        assert!(written.validate_rhythm().is_empty());
+    }
+    #[test]
+    fn test_rescale_time() {
+        use crate::rescale::TimeScale;
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/tempo.gp5")));
+        let data = song.write((5,1,0), None);
+        let length = song.length_seconds();
+        let tempo = song.tempo;
+        song.rescale_time(TimeScale::Double).unwrap();
+        assert_eq!(song.tempo, tempo * 2);
+        assert_eq!(song.measure_headers[0].time_signature.denominator.value, 2);
+        assert!((song.length_seconds() - length).abs() < 1e-6);
+        assert!(song.validate_rhythm().is_empty());
+        song.rescale_time(TimeScale::Half).unwrap();
+        assert_eq!(song.write((5,1,0), None), data);
+        song.rescale_time(TimeScale::SplitBeats).unwrap();
+        assert_eq!((song.measure_headers[0].time_signature.numerator, song.measure_headers[0].time_signature.denominator.value), (8, 8));
+        assert!((song.length_seconds() - length).abs() < 1e-6);
+        song.rescale_time(TimeScale::MergeBeats).unwrap();
+        assert_eq!(song.write((5,1,0), None), data);
+        //3 beats cannot be merged, the song is unchanged
+        song.measure_headers[0].time_signature.numerator = 3;
+        let before = format!("{:?}{:?}", song.measure_headers, song.tracks);
+        assert_eq!(song.rescale_time(TimeScale::MergeBeats), Err(crate::rescale::RescaleError::Beats {measure: 1, numerator: 3}));
+        assert_eq!(format!("{:?}{:?}", song.measure_headers, song.tracks), before);
+    }
+    #[test]
+    fn test_repeats() {
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Repeat.gp5")));
+        //8 alternative endings, the fourth and the eighth ones have their own measure
+        assert_eq!(song.playback_order()[..16], [0, 1, 0, 1, 0, 1, 0, 2, 0, 1, 0, 1, 0, 1, 0, 3]);
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/volta.gp5")));
+        assert_eq!(song.playback_order(), [0, 1, 2, 3, 4, 5, 6, 0, 1, 2, 3, 4, 5, 7]);
+        song.expand_repeats();
+        assert_eq!(song.measure_headers.len(), 14);
+        assert_eq!(song.playback_order(), (0..14).collect::<Vec<usize>>());
+        let expanded = song.write((5,1,0), None);
+        assert_eq!(song.find_repeated_groups(4), [crate::repeat::RepeatedGroup {start: 0, length: 4, count: 2, ending: 3}]);
+        assert_eq!(song.compress_repeats(4), 1);
+        assert_eq!(song.measure_headers.len(), 10);
+        assert_eq!(song.playback_order(), [0, 1, 2, 3, 4, 5, 6, 0, 1, 2, 3, 7, 8, 9]);
+        song.expand_repeats();
+        assert_eq!(song.write((5,1,0), None), expanded);
+        //the same measure played several times
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/volta.gp3")));
+        song.expand_repeats();
+        let groups = song.find_repeated_groups(4);
+        assert_eq!(groups[0], crate::repeat::RepeatedGroup {start: 6, length: 2, count: 6, ending: 0});
+        song.compress_repeats(4);
+        assert_eq!((song.measure_headers[6].repeat_open, song.measure_headers[7].repeat_close), (true, 5));
+    }
+    #[test]
+    fn test_sections() {
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/gamma_ray-heading_for_tomorrow.gp3")));
+        assert!((song.section_similarity(28, 52, 4, &[]) - 1.0).abs() < 1e-9);
+        assert!(song.section_similarity(0, 4, 4, &[]) < 0.5);
+        let sections = song.detect_sections(4, 0.8, &[]);
+        assert_eq!(sections.iter().map(|s| s.length).sum::<usize>(), song.measure_headers.len());
+        let label = |start: usize| sections.iter().find(|s| s.start == start).map(|s| s.label.clone()).unwrap();
+        assert_eq!(label(0), "A");
+        assert_eq!(label(28), label(52));
+        assert_eq!(label(76), label(88));
+        assert_ne!(label(28), label(76));
+        //everything is similar enough without threshold
+        assert_eq!(song.detect_sections(4, 0.0, &[]).len(), 1);
+        let markers = song.measure_headers.iter().filter(|h| h.marker.is_some()).count();
+        assert_eq!(song.mark_sections(&sections), sections.len());
+        assert_eq!(song.measure_headers.iter().filter(|h| h.marker.is_some()).count(), markers + sections.len());
+        assert_eq!(song.measure_headers[52].marker.as_ref().unwrap().title, label(28));
+        assert_eq!(song.measure_headers[52].marker.as_ref().unwrap().color, song.measure_headers[28].marker.as_ref().unwrap().color);
+        //any label can be used
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/gamma_ray-heading_for_tomorrow.gp3")));
+        for h in song.measure_headers.iter_mut() {h.marker = None;}
+        let named = [("intro", 0), ("Verse", 4), ("intro", 8), ("Verse 2", 12)].map(|(label, start)| crate::sections::Section {start, length: 4, label: String::from(label), similarity: 1.0});
+        assert_eq!(song.mark_sections(&named), 4);
+        let color = |m: usize| song.measure_headers[m].marker.as_ref().unwrap().color;
+        assert_eq!(color(0), color(8));
+        assert_ne!(color(0), color(4));
+        assert_ne!(color(4), color(12));
+    }
+    #[test]
+    fn test_melody_search() {
+        use crate::{melody::*, chord::SHARP_NOTES};
+        let mut song: Song = Song::default();
+        song.read_gp3(&read_file(String::from("test/gamma_ray-heading_for_tomorrow.gp3")));
+        let melodies = song.melodies(&[6]);
+        let events = &melodies[0].events;
+        let name = |p: i16| SHARP_NOTES[usize::try_from(p.rem_euclid(12)).unwrap()];
+        let first: String = events[..5].iter().map(|e| name(*e.pitches.last().unwrap())).collect();
+        let hits = song.find_melody(&MelodyPattern::parse(&first, false).unwrap(), &[6]);
+        assert_eq!((hits[0].measure, hits[0].beat), (events[0].measure, events[0].beat));
+        //transposed by a fifth
+        let transposed: String = events[..5].iter().map(|e| name(*e.pitches.last().unwrap() + 7)).collect();
+        assert!(song.find_melody(&MelodyPattern::parse(&transposed, false).unwrap(), &[6]).iter().all(|h| h.measure != events[0].measure || h.beat != events[0].beat));
+        let hits = song.find_melody(&MelodyPattern::parse(&transposed, true).unwrap(), &[6]);
+        assert!(hits.iter().any(|h| h.measure == events[0].measure && h.beat == events[0].beat && h.transposition == 5));
+        let intervals: Vec<String> = events[..5].windows(2).map(|w| format!("{:+}", w[1].pitches.last().unwrap() - w[0].pitches.last().unwrap())).collect();
+        assert!(song.find_melody(&MelodyPattern::parse(&intervals.join(" "), false).unwrap(), &[6]).iter().any(|h| h.measure == events[0].measure && h.beat == events[0].beat));
+        //a whole measure with its rhythm
+        let measure: Vec<&MelodyEvent> = events.iter().filter(|e| e.measure == events[0].measure).collect();
+        let pattern = format!("|{}|", measure.iter().map(|e| format!("{}:{}{}", name(*e.pitches.last().unwrap()), e.duration.value, if e.duration.dotted {"."} else {""})).collect::<String>());
+        let hits = song.find_melody(&MelodyPattern::parse(&pattern, false).unwrap(), &[6]);
+        assert!(hits.iter().all(|h| h.measure == h.end_measure && h.beat == 0));
+        assert_eq!(hits[0].measure, events[0].measure);
+        //the index gives the same results
+        let mut index = MelodyIndex::default();
+        index.add("gamma ray", &song, &[6]);
+        assert_eq!(index.search(&MelodyPattern::parse(&pattern, false).unwrap()).len(), hits.len());
+        assert!(MelodyPattern::parse("DAX", false).is_none());
+        assert!(MelodyPattern::parse("D:3", false).is_none());
+        assert_eq!(MelodyPattern::parse("|A|E|CC|", false).unwrap().bars, [true, true, true, false, true]);
+    }
+    #[test]
+    fn test_pitch_spelling() {
+        use crate::{pitch::*, key_signature::KeySignature, enums::Octave};
+        let key = |key: i8| KeySignature {key, is_minor: false};
+        assert_eq!(Pitch::spell(66, &key(0)).name(), "F#4");
+        assert_eq!(Pitch::spell(66, &key(-1)).name(), "Gb4");
+        assert_eq!(Pitch::spell(66, &key(0)).swapped().name(), "Gb4");
+        assert_eq!(Pitch::spell(58, &key(2)).to_string(), "A#3");
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/keysig.gp5")));
+        //C# major: F and C are written E# and B#, C♭ major: B is written C♭ in the next octave
+        assert_eq!(song.measure_headers[7].key_signature.key, 7);
+        assert_eq!((0..4).map(|b| song.note_pitch(0, 7, 0, b, 0).unwrap().name()).collect::<Vec<String>>(), ["E#3", "E3", "D3", "B#2"]);
+        let cb = song.note_pitch(0, 14, 0, 1, 0).unwrap();
+        assert_eq!((cb.midi, cb.letter, cb.accidental, cb.octave), (59, 'C', -1, 4));
+        //the ottava sign changes the written octave, not the sounding pitch
+        song.tracks[0].measures[0].voices[0].beats[0].octave = Octave::Ottava;
+        let c = song.note_pitch(0, 0, 0, 0, 0).unwrap();
+        assert_eq!((c.midi, c.written, c.name().as_str()), (48, 36, "C2"));
+        song.tracks[0].measures[0].voices[0].beats[0].notes[0].swap_accidentals = true;
+        assert_eq!(song.note_pitch(0, 0, 0, 0, 0).unwrap().name(), "C2");
+        assert!(song.note_pitch(0, 0, 0, 0, 1).is_none());
+        //capo and harmonics
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/capo-fret.gp5")));
+        assert_eq!(song.note_pitch(0, 0, 0, 0, 1).unwrap().name(), "G#3");
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Harmonics.gp5")));
+        let track = &song.tracks[0];
+        let pitches: Vec<i16> = track.measures[0].voices[0].beats.iter().map(|b| track.midi_pitch(&b.notes[0]).unwrap()).collect();
+        assert_eq!(pitches, [55, 55, 67, 62]);
+        //notes without string carry their pitch
+        let mut note = crate::note::Note::default();
+        (note.value, note.string, note.kind) = (61, 0, crate::enums::NoteType::Normal);
+        assert_eq!(track.pitch(&track.measures[0].voices[0].beats[0], &note, &key(-3)).unwrap().name(), "Db4");
+    }
+    #[test]
+    fn test_traversal() {
+        use crate::{traversal::*, enums::*, note::Note};
+        let mut song: Song = Song::default();
+        song.read_gp5(&read_file(String::from("test/Voices.gp5")));
+        let beats: usize = song.tracks.iter().flat_map(|t| t.measures.iter().flat_map(|m| m.voices.iter().map(|v| v.beats.len()))).sum();
+        assert_eq!(song.beats().count(), beats);
+        let notes: usize = song.tracks.iter().flat_map(|t| t.measures.iter().flat_map(|m| m.voices.iter().flat_map(|v| v.beats.iter().map(|b| b.notes.len())))).sum();
+        assert_eq!(song.notes().count(), notes);
+        //the ticks are the ones of the timing
+        let timing = song.timing();
+        for c in song.beats().filter(|c| c.track_index == 0 && c.beat.status != BeatStatus::Empty).take(50) {
+            assert!(timing.iter().any(|t| t.track == 0 && t.measure == c.measure_index && t.voice == c.voice_index && t.beat == c.beat_index && t.tick == c.tick));
+            assert_eq!(usize::from(c.header.number), c.measure_index + 1);
+        }
+        let n = song.tracks[song.tracks.len() - 1].notes_with_context(&song.measure_headers).find(|c| c.note.kind == NoteType::Normal).unwrap();
+        assert_eq!((n.beat.track_index, n.pitch().unwrap().midi), (song.tracks.len() - 1, n.beat.track.midi_pitch(n.note).unwrap()));
+        //a visitor moving every note one fret higher
+        struct Transpose {notes: usize, last_tick: i64}
+        impl VisitorMut for Transpose {
+            fn visit_note(&mut self, note: &mut Note, location: &Location) {
+                if note.kind != NoteType::Rest {note.value += 1; self.notes += 1; self.last_tick = location.tick;}
+            }
+        }
+        let before: Vec<i16> = song.notes().filter(|c| c.note.kind != NoteType::Rest).map(|c| c.note.value).collect();
+        let mut visitor = Transpose {notes: 0, last_tick: 0};
+        song.visit_mut(&mut visitor);
+        let after: Vec<i16> = song.notes().filter(|c| c.note.kind != NoteType::Rest).map(|c| c.note.value).collect();
+        assert_eq!(visitor.notes, before.len());
+        assert_eq!(visitor.last_tick, song.notes().filter(|c| c.note.kind != NoteType::Rest).last().unwrap().beat.tick);
+        assert!(before.iter().zip(&after).all(|(b, a)| *a == b + 1));
+    }
+    #[test]
+    fn test_builder() {
+        use crate::{builder::*, key_signature::*, enums::*};
+        let quarter = Duration::default();
+        let eighth = Duration {value: 8, ..Default::default()};
+        let triplet = Duration {value: 8, tuplet_enters: 3, tuplet_times: 2, ..Default::default()};
+        let song = SongBuilder::new().title("Exercise").tempo(90).key(KeySignature {key: 1, is_minor: false})
+            .track(TrackBuilder::new("Guitar").capo(2)
+                .measure(MeasureBuilder::new().marker("Scale").note(6, 3, quarter.clone()).note(6, 5, quarter.clone()).notes(&[(5, 2), (4, 0)], quarter.clone()).rest(quarter.clone()))
+                .measure(MeasureBuilder::new().time_signature(3, 4).tempo(120).note(5, 3, eighth.clone()).note(5, 5, eighth.clone())
+                    .voice(1).note(1, 0, triplet.clone()).note(1, 2, triplet.clone()).note(1, 3, triplet)))
+            .track(TrackBuilder::new("Bass").strings(&[43, 38, 33, 28]).instrument(33)
+                .measure(MeasureBuilder::new().note(4, 3, Duration {value: 1, ..Default::default()})))
+            .track(TrackBuilder::new("Drums").percussion()
+                .measure(MeasureBuilder::new().note(1, 36, quarter.clone()).note(2, 38, quarter.clone()).note(1, 36, quarter.clone()).note(2, 38, quarter))
+                .measure(MeasureBuilder::new().repeat_close(1)))
+            .build().unwrap();
+        assert_eq!(song.measure_headers.len(), 2);
+        assert_eq!((song.measure_headers[1].start, song.measure_headers[1].time_signature.numerator), (960 + 3840, 3));
+        assert_eq!(song.measure_headers[1].key_signature.key, 1);
+        assert!(song.tracks.iter().all(|t| t.measures.len() == 2 && t.measures.iter().enumerate().all(|(h, m)| m.header_index == h && m.number == h + 1)));
+        assert_eq!((song.tracks[0].channel_index, song.tracks[1].channel_index, song.tracks[2].channel_index), (0, 2, 9));
+        assert_ne!(song.channels[2].get_instrument_name(), song.channels[0].get_instrument_name());
+        //the underfull voices and the missing measures are filled with rests
+        assert!(song.validate_rhythm().is_empty());
+        assert_eq!(song.tracks[1].measures[1].voices[0].beats[0].status, BeatStatus::Rest);
+        assert_eq!(song.tracks[0].measures[1].voices[0].beats.last().unwrap().start, Some(960 + 3840 + 960));
+        assert_eq!(song.tempo_map().tempo_at(960 + 3840), 120.0);
+        for version in [(3,0,0), (4,0,6), (5,1,0)] {
+            let data = song.write(version, None);
+            let mut read = Song::default();
+            match version.0 {3 => read.read_gp3(&data), 4 => read.read_gp4(&data), _ => read.read_gp5(&data)}
+            assert_eq!((read.name.as_str(), read.tracks.len(), read.measure_headers.len()), ("Exercise", 3, 2));
+            assert_eq!(read.measure_headers[0].marker.as_ref().map(|m| m.title.as_str()), Some("Scale"));
+            assert_eq!(read.measure_headers[1].repeat_close, 1);
+            assert_eq!(read.tracks[0].offset, 2);
+            assert!(read.tracks[2].percussion_track);
+            let notes = |s: &Song, t: usize| s.tracks[t].measures.iter().map(|m| m.voices[0].beats.iter().map(|b| b.notes.iter().map(|n| (n.string, n.value)).collect::<Vec<_>>()).collect::<Vec<_>>()).collect::<Vec<_>>();
+            for t in 0..3 {assert_eq!(notes(&read, t), notes(&song, t));}
+            if version.0 == 5 {assert_eq!(read.tracks[0].measures[1].voices[1].beats.iter().filter(|b| !b.notes.is_empty()).count(), 3);}
+        }
+        //the songs which cannot be written are not built
+        let build = |measure: MeasureBuilder| SongBuilder::new().track(TrackBuilder::new("Guitar").measure(measure)).build().err();
+        assert_eq!(build(MeasureBuilder::new().note(1, 0, Duration {value: 1, ..Default::default()}).rest(Duration::default())),
+                   Some(BuildError::OverfullMeasure {track: 1, measure: 1, length: 4800, expected: 3840}));
+        assert_eq!(build(MeasureBuilder::new().note(1, 0, Duration {tuplet_enters: 4, tuplet_times: 3, ..Default::default()})),
+                   Some(BuildError::Tuplet {track: 1, measure: 1, beat: 1, enters: 4, times: 3}));
+        assert_eq!(build(MeasureBuilder::new().rest(Duration::default()).note(7, 0, Duration::default())), Some(BuildError::String {track: 1, measure: 1, beat: 2, string: 7}));
+        assert_eq!(build(MeasureBuilder::new().time_signature(3, 5)), Some(BuildError::TimeSignature {numerator: 3, denominator: 5}));
+        assert_eq!(SongBuilder::new().time_signature(0, 4).build().err(), Some(BuildError::TimeSignature {numerator: 0, denominator: 4}));
+        assert_eq!(SongBuilder::new().track(TrackBuilder::new("Guitar").measure(MeasureBuilder::new().time_signature(3, 4)))
+                       .track(TrackBuilder::new("Bass").measure(MeasureBuilder::new().time_signature(6, 8))).build().err(), Some(BuildError::TimeSignatureClash {measure: 1}));
+        //the tracks use the channels of every port, a track and its effects take two channels
+        let tracks = |count: usize| (1..=count).fold(SongBuilder::new(), |s, t| s.track(TrackBuilder::new(&format!("Guitar {}", t))));
+        let song = tracks(30).build().unwrap();
+        assert_eq!((song.tracks[29].channel_index, song.tracks[29].port), (62, 4));
+        assert!(song.tracks.iter().all(|t| !song.channels[t.channel_index].is_percussion_channel()));
+        assert_eq!(tracks(31).build().err(), Some(BuildError::NoFreeChannel {track: String::from("Guitar 31")}));
+    }
+    #[test]
+    fn test_read_from_stream() {
+        /// A stream giving a few bytes at a time, like a pipe
+        struct Trickle<'a> {data: &'a [u8]}
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let count = buf.len().min(self.data.len()).min(7);
+                buf[..count].copy_from_slice(&self.data[..count]);
+                self.data = &self.data[count..];
+                Ok(count)
+            }
+        }
+        for (path, version) in [("test/Chords.gp3", (3,0,0)), ("test/Chords.gp4", (4,0,6)), ("test/Effects.gp5", (5,1,0)), ("test/Voices.gp5", (5,1,0))] {
+            let data = read_file(String::from(path));
+            let mut song = Song::default();
+            match version.0 {3 => song.read_gp3(&data), 4 => song.read_gp4(&data), _ => song.read_gp5(&data)}
+            let mut streamed = Song::default();
+            streamed.read_from(Trickle {data: &data});
+            assert_eq!(streamed.version.number, song.version.number);
+            assert_eq!(streamed.write(version, None), song.write(version, None));
+            let mut file = Song::default();
+            file.read_from(fs::File::open(format!("{}/../{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap());
+            assert_eq!(file.write(version, None), song.write(version, None));
+        }
+        assert!(std::panic::catch_unwind(|| Song::default().read_from(&b"\x04MIDI file"[..])).is_err());
+    }
+    #[test]
+    fn test_read_header_only() {
+        use crate::summary::*;
+        for path in ["test/Chords.gp3", "test/Chords.gp4", "test/Effects.gp5", "test/capo-fret.gp5", "test/all-percussion.gp5"] {
+            let data = read_file(String::from(path));
+            let mut song = Song::default();
+            song.read_from(&data[..]);
+            let mut header = Song::default();
+            header.read_header_only(&data[..]);
+            assert!(header.tracks.iter().all(|t| t.measures.is_empty()));
+            assert_eq!(header.measure_headers.len(), song.measure_headers.len());
+            let summary = SongSummary::read(&data[..]);
+            assert_eq!(summary, song.summary());
+            assert_eq!((summary.tracks.len(), summary.measure_count), (song.tracks.len(), song.measure_headers.len()));
+        }
+        let summary = SongSummary::read(&read_file(String::from("test/capo-fret.gp5"))[..]);
+        assert_eq!((summary.tracks[0].offset, summary.tracks[0].strings[5].1), (1, 36));
+        assert!(SongSummary::read(&read_file(String::from("test/all-percussion.gp5"))[..]).tracks.iter().all(|t| t.percussion_track));
+    }
+
+    #[test]
+    fn test_diff() {
+        use crate::{builder::*, key_signature::*};
+        let quarter = Duration::default();
+        let half = Duration {value: 2, ..Default::default()};
+        let a = || MeasureBuilder::new().note(6, 3, quarter.clone()).note(6, 5, quarter.clone()).note(5, 2, half.clone());
+        let b = || MeasureBuilder::new().notes(&[(5, 3), (4, 2)], half.clone()).rest(half.clone());
+        let old = SongBuilder::new().title("Exercise").track(TrackBuilder::new("Guitar").measure(a()).measure(b()).measure(a())).build().unwrap();
+        let new = SongBuilder::new().title("Exercise 2")
+            .track(TrackBuilder::new("Guitar").strings(&[64, 59, 55, 50, 45, 38])
+                .measure(a()).measure(MeasureBuilder::new().marker("Bridge").note(1, 0, Duration {value: 1, ..Default::default()})).measure(b())
+                .measure(MeasureBuilder::new().note(6, 3, quarter.clone()).note(6, 7, quarter.clone()).note(5, 2, half.clone())))
+            .build().unwrap();
+        //the inserted measure does not shift the comparison of the next ones
+        assert_eq!(old.align_measures(&new), vec![(Some(0), Some(0)), (None, Some(1)), (Some(1), Some(2)), (Some(2), Some(3))]);
+        let differences = old.diff(&new);
+        assert_eq!(differences.len(), 4);
+        assert_eq!((differences[0].field.as_str(), differences[0].new.as_deref()), ("title", Some("Exercise 2")));
+        assert_eq!((differences[1].track, differences[1].field.as_str(), differences[1].new.as_deref()), (Some(0), "tuning", Some("38 45 50 55 59 64")));
+        assert_eq!((differences[2].old_measure, differences[2].new_measure, differences[2].old.is_none()), (None, Some(1), true));
+        assert_eq!((differences[3].old_measure, differences[3].new_measure, differences[3].position), (Some(2), Some(3), Some(960)));
+        assert_eq!(differences[3].to_string(), "measure 3 (new 4), track 1, voice 1, beat at tick 960: notes: 6:5 -> 6:7");
+        assert!(new.diff(&new).is_empty());
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_merge_edits() {
+        use crate::{builder::*, key_signature::*};
+        let (quarter, half) = (Duration::default(), Duration {value: 2, ..Default::default()});
+        let a = |second: i16, third: i16| MeasureBuilder::new().note(6, 3, quarter.clone()).note(6, second, quarter.clone()).note(5, third, half.clone());
+        let b = |last: Option<i16>| match last {
+            Some(f) => MeasureBuilder::new().notes(&[(5, 3), (4, 2)], half.clone()).note(1, f, half.clone()),
+            None => MeasureBuilder::new().notes(&[(5, 3), (4, 2)], half.clone()).rest(half.clone()),
+        };
+        let base = SongBuilder::new().title("Exercise").track(TrackBuilder::new("Guitar").measure(a(5, 2)).measure(b(None)).measure(a(5, 2)).measure(b(None))).build().unwrap();
+        let ours = SongBuilder::new().title("Exercise 2").track(TrackBuilder::new("Guitar").measure(a(5, 2))
+            .measure(MeasureBuilder::new().marker("Bridge").note(1, 0, Duration {value: 1, ..Default::default()})).measure(b(None)).measure(a(7, 2)).measure(b(Some(0)))).build().unwrap();
+        let theirs = SongBuilder::new().title("Exercise").artist("Me")
+            .track(TrackBuilder::new("Guitar").strings(&[64, 59, 55, 50, 45, 38]).measure(a(5, 2)).measure(b(None)).measure(a(5, 4)).measure(b(Some(3)))).build().unwrap();
+        let merged = base.merge_edits(&ours, &theirs);
+        assert_eq!((merged.song.name.as_str(), merged.song.artist.as_str(), merged.song.tracks[0].strings[5].1), ("Exercise 2", "Me", 38));
+        assert_eq!(merged.song.measure_headers.len(), 5);
+        assert_eq!(merged.song.measure_headers[1].marker.as_ref().map(|m| m.title.as_str()), Some("Bridge"));
+        //the changes of different beats of a measure are merged
+        let expected = SongBuilder::new().track(TrackBuilder::new("Guitar").measure(a(7, 4))).build().unwrap();
+        assert!(expected.tracks[0].measures[0].voices[0].beats.iter().zip(&merged.song.tracks[0].measures[3].voices[0].beats).all(|(x, y)| x.notes == y.notes));
+        //the same beat changed in both versions keeps our change
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!((merged.conflicts[0].measure, merged.conflicts[0].position, merged.conflicts[0].field.as_str()), (Some(4), Some(1920), "beat"));
+        assert_eq!(merged.song.tracks[0].measures[4].voices[0].beats[1].notes[0].value, 0);
+        assert!(merged.song.validate_rhythm().is_empty());
+        //a change made in one version only is taken as is
+        let merged = base.merge_edits(&base, &ours);
+        assert!(merged.conflicts.is_empty() && ours.diff(&merged.song).is_empty());
+    }
+
+    #[test]
+    fn test_encodings() {
+        use crate::{builder::*, encoding::*, key_signature::*};
+        let song = |title: &str, artist: &str, track: &str| SongBuilder::new().title(title).artist(artist)
+            .track(TrackBuilder::new(track).measure(MeasureBuilder::new().rest(Duration::default()))).build().unwrap();
+        //the lengths are the lengths of the encoded strings and the encoding is detected when reading the file back
+        for (encoding, title, artist, track) in [(TextEncoding::ShiftJis, "さくらさくら", "日本古謡", "ギター"),
+                                                 (TextEncoding::Windows1251, "Группа крови", "Кино", "Гитара"),
+                                                 (TextEncoding::Windows1252, "Café Müller", "Hervé", "Guitare électrique"),
+                                                 (TextEncoding::Gbk, "茉莉花", "民歌", "吉他"),
+                                                 (TextEncoding::Utf8, "Ελληνικά", "Σωκράτης", "Κιθάρα")] {
+            let mut s = song(title, artist, track);
+            s.encoding = encoding;
+            for version in [(3,0,0), (4,0,0), (5,1,0)] {
+                let mut read = Song::default();
+                read.read_from(&s.write(version, None)[..]);
+                assert_eq!((read.name.as_str(), read.artist.as_str(), read.tracks[0].name.as_str(), read.encoding), (title, artist, track, encoding));
+            }
+        }
+        //a forced encoding is used even if it is not the detected one
+        let mut s = song("Кино", "", "");
+        s.encoding = TextEncoding::Windows1251;
+        let mut read = Song {encoding: TextEncoding::Windows1252, ..Default::default()};
+        read.read_from(&s.write((5,1,0), None)[..]);
+        assert_eq!(read.name, "Êèíî");
+        //padded names are not cut in the middle of a character
+        let mut s = song("", "", &"ギ".repeat(30));
+        s.encoding = TextEncoding::ShiftJis;
+        let mut read = Song::default();
+        read.read_from(&s.write((5,1,0), None)[..]);
+        assert_eq!(read.tracks[0].name, "ギ".repeat(20));
+        //short western strings with accented letters are not cyrillic
+        for text in ["Été", "Ève", "Noël", "À l'été", "Ça été", "Señor", "Über"] {
+            assert_eq!(TextEncoding::detect(&TextEncoding::Windows1252.encode(text)), Some(TextEncoding::Windows1252), "{}", text);
+        }
+        for text in ["Кино", "Кино - Live", "Ария"] {
+            assert_eq!(TextEncoding::detect(&TextEncoding::Windows1251.encode(text)), Some(TextEncoding::Windows1251), "{}", text);
+        }
+        assert_eq!(TextEncoding::from_label("cp1251"), Some(TextEncoding::Windows1251));
+        assert_eq!("sjis".parse::<TextEncoding>(), Ok(TextEncoding::ShiftJis));
+        assert_eq!(TextEncoding::detect(b"FICHIER"), None);
+    }
 }
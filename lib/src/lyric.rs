@@ -1,6 +1,6 @@
 use fraction::ToPrimitive;
 
-use crate::io::*;
+use crate::{io::*, enums::*};
 
 pub const _MAX_LYRICS_LINE_COUNT: u8 = 5;
 
@@ -13,10 +13,48 @@ pub const _MAX_LYRICS_LINE_COUNT: u8 = 5;
 ///   * "+": merge two syllables for the same beat
 ///   * "\[lorem ipsum...\]": hidden text
 #[derive(Debug,Clone,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lyrics {
     pub track_choice: u8,
     pub lines: Vec<(u8, u16, String)>,
 }
+impl Lyrics {
+    /// Split a lyric line into syllables: hidden text is removed, spaces and hyphens separate the syllables (the hyphen is kept at
+    /// the end of the syllable it follows) and `+` joins two syllables sung on the same beat.
+    pub fn syllables(text: &str) -> Vec<String> {
+        let mut visible = String::with_capacity(text.len());
+        let mut hidden = false;
+        for c in text.chars() {
+            match c {
+                '[' => hidden = true,
+                ']' => hidden = false,
+                _ if !hidden => visible.push(c),
+                _ => {},
+            }
+        }
+        let mut syllables = Vec::new();
+        for word in visible.split_whitespace() {
+            let mut syllable = String::new();
+            for c in word.chars() {
+                syllable.push(if c == '+' {' '} else {c});
+                if c == '-' {syllables.push(std::mem::take(&mut syllable));}
+            }
+            if !syllable.is_empty() {syllables.push(syllable);}
+        }
+        syllables
+    }
+}
+
+/// A syllable of the lyrics placed on a beat
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LyricSyllable {
+    /// Index of the measure
+    pub measure: usize,
+    /// Start of the beat in ticks
+    pub start: i64,
+    pub text: String,
+}
 //impl Default for Lyrics { fn default() -> Self { Lyrics { track_choice: 0, line1: BTreeMap::new(), line2: BTreeMap::new(), line3: BTreeMap::new(), line4: BTreeMap::new(), line5: BTreeMap::new(), }}}
 impl std::fmt::Display for Lyrics {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -31,19 +69,53 @@ impl crate::gp::Song {
     ///
     /// First, read an `i32` that points to the track lyrics are bound to. Then it is followed by 5 lyric lines. Each one consists of
     /// number of starting measure encoded in`i32` and`int-size-string` holding text of the lyric line.
-    pub(crate) fn read_lyrics(&self, data: &[u8], seek: &mut usize) -> Lyrics {
-        let mut lyrics = Lyrics{track_choice: read_int(data, seek).to_u8().unwrap(), ..Default::default()};
+    pub(crate) fn read_lyrics(&self, data: &mut Input) -> Lyrics {
+        let mut lyrics = Lyrics{track_choice: read_int(data).to_u8().unwrap(), ..Default::default()};
         for i in 0..5u8 {
-            let starting_measure = read_int(data, seek).to_u16().unwrap();
-            lyrics.lines.push((i, starting_measure, read_int_size_string(data, seek)));
+            let starting_measure = read_int(data).to_u16().unwrap();
+            lyrics.lines.push((i, starting_measure, read_int_size_string(data)));
         }
         lyrics
     }
+    /// Get the index of the track the lyrics are bound to. `track_choice` is the number of the track, starting at 1.
+    pub fn lyrics_track(&self) -> usize {
+        let t = self.lyrics.track_choice.to_usize().unwrap();
+        if t > 0 && t <= self.tracks.len() {t - 1} else {0}
+    }
+    /// Place the syllables of a lyric line on the beats of the lyrics track: from the starting measure of the line, every beat of the
+    /// first voice starting a new note (rests, tied notes and beats without start are skipped) gets the next syllable.
+    /// - `line`: index of the lyric line (0 to 4)
+    pub fn lyric_syllables(&self, line: usize) -> Vec<LyricSyllable> {
+        let mut result = Vec::new();
+        if line >= self.lyrics.lines.len() || self.tracks.is_empty() {return result;}
+        let mut syllables = Lyrics::syllables(&self.lyrics.lines[line].2).into_iter();
+        let first = self.lyrics.lines[line].1.to_usize().unwrap().max(1) - 1;
+        let track = &self.tracks[self.lyrics_track()];
+        for (m, measure) in track.measures.iter().enumerate().skip(first) {
+            if measure.voices.is_empty() {continue;}
+            for b in measure.voices[0].beats.iter() {
+                if b.status != BeatStatus::Normal || b.start.is_none() || !b.notes.iter().any(|n| n.kind == NoteType::Normal || n.kind == NoteType::Dead) {continue;}
+                match syllables.next() {
+                    Some(text) => result.push(LyricSyllable {measure: m, start: b.start.unwrap(), text}),
+                    None => return result,
+                }
+            }
+        }
+        result
+    }
     pub(crate) fn write_lyrics(&self, data: &mut Vec<u8>) {
         write_i32(data, self.lyrics.track_choice.to_i32().unwrap());
         for i in 0..5 {
-            write_i32(data, self.lyrics.lines[i].1.to_i32().unwrap());
-            write_int_size_string(data, &self.lyrics.lines[i].2);
+            match self.lyrics.lines.get(i) {
+                Some(l) => {
+                    write_i32(data, l.1.to_i32().unwrap());
+                    write_int_size_string(data, &l.2, self.encoding);
+                },
+                None => {
+                    write_i32(data, 1);
+                    write_int_size_string(data, "", self.encoding);
+                }
+            }
         }
     }
 }
\ No newline at end of file
@@ -1,11 +1,12 @@
 use fraction::ToPrimitive;
 
-use crate::{beat::*, gp::*, key_signature::*, io::*, enums::*};
+use crate::{beat::*, gp::*, headers::*, key_signature::*, io::*, enums::*, track::*, lyric::*};
 
 const MAX_VOICES: usize = 2;
 
 /// A measure header contains metadata for measures over multiple tracks.
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Measure {
     pub number: usize,
     pub start: i64,
@@ -39,6 +40,14 @@ impl Default for Measure {fn default() -> Self { Measure {
     voices: Vec::with_capacity(2),
     line_break: LineBreak::None
 }}}
+impl Measure {
+    /// Create a measure of a track where every voice only has an empty beat.
+    pub(crate) fn empty(track_index: usize, header_index: usize, header: &MeasureHeader) -> Measure {
+        let voice = Voice {beats: vec![Beat {status: BeatStatus::Empty, start: Some(DURATION_QUARTER_TIME), ..Default::default()}], ..Default::default()};
+        Measure {track_index, header_index, key_signature: header.key_signature.clone(), time_signature: header.time_signature.clone(),
+                 has_double_bar: header.double_bar, voices: vec![voice; MAX_VOICES], ..Default::default()}
+    }
+}
 
 impl Song {
     /// Read measures. Measures are written in the following order:
@@ -55,15 +64,15 @@ impl Song {
     /// - measure n/track 2
     /// - ...
     /// - measure n/track m
-    pub(crate) fn read_measures(&mut self, data: &[u8], seek: &mut usize) {
+    pub(crate) fn read_measures(&mut self, data: &mut Input) {
         let mut start = DURATION_QUARTER_TIME;
         for h in 0..self.measure_headers.len() {
             self.measure_headers[h].start = start;
             for t in 0..self.tracks.len() {
                 self.current_track = Some(t);
-                let mut m = Measure{track_index:t, header_index:h, ..Default::default()};
+                let mut m = Measure{track_index:t, header_index:h, start, ..Default::default()};
                 self.current_measure_number = Some(m.number);
-                if self.version.number < (5,0,0) {self.read_measure(data, seek, &mut m, t);}else {self.read_measure_v5(data, seek, &mut m, t);}
+                if self.version.number < (5,0,0) {self.read_measure(data, &mut m, t);}else {self.read_measure_v5(data, &mut m, t);}
                 self.tracks[t].measures.push(m);
             }
             //println!("read_measures(), start: {} \t numerator: {} \t denominator: {} \t length: {}", start, self.measure_headers[h].time_signature.numerator, self.measure_headers[h].time_signature.denominator.value, self.measure_headers[h].length());
@@ -74,22 +83,23 @@ impl Song {
     }
 
     /// Read measure. The measure is written as number of beats followed by sequence of beats.
-    fn read_measure(&mut self, data: &[u8], seek: &mut usize, measure: &mut Measure, track_index: usize) {
+    fn read_measure(&mut self, data: &mut Input, measure: &mut Measure, track_index: usize) {
         //println!("read_measure()");
         let mut voice = Voice::default();
         self.current_voice_number = Some(1);
-        self.read_voice(data, seek, &mut voice, &mut measure.start, track_index);
+        let mut start = measure.start;
+        self.read_voice(data, &mut voice, &mut start, track_index);
         self.current_voice_number = None;
         measure.voices.push(voice);
         /*
         //read a voice 
-        let beats = read_int(data, seek).to_usize().unwrap();
+        let beats = read_int(data).to_usize().unwrap();
 
         //println!("read_measure() read_voice(), beat count: {}", beats);
         for i in 0..beats {
             self.current_beat_number = Some(i + 1);
             //println!("read_measure() read_voice(), start: {}", measure.start);
-            measure.start += self.read_beat(data, seek, &mut measure.voices[0], measure.start, track_index);
+            measure.start += self.read_beat(data, &mut measure.voices[0], measure.start, track_index);
             //println!("read_measure() read_voice(), start: {}", measure.start);
         }
         self.current_beat_number = None;
@@ -99,35 +109,36 @@ impl Song {
     /// Read measure. Guitar Pro 5 stores twice more measures compared to Guitar Pro 3. One measure consists of two sub-measures for each of two voices.
     /// 
     /// Sub-measures are followed by a  `LineBreak` stored in `byte`.
-    fn read_measure_v5(&mut self, data: &[u8], seek: &mut usize, measure: &mut Measure, track_index: usize) {
+    fn read_measure_v5(&mut self, data: &mut Input, measure: &mut Measure, track_index: usize) {
         //println!("read_measure_v5()");
-        let mut start = measure.start;
         for number in 0..MAX_VOICES {
             self.current_voice_number = Some(number + 1);
             //println!("read_measure_v5() {:?}",self.current_voice_number);
+            //every voice starts at the start of the measure
+            let mut start = measure.start;
             let mut voice = Voice::default();
-            self.read_voice(data, seek, &mut voice, &mut start, track_index);
+            self.read_voice(data, &mut voice, &mut start, track_index);
             measure.voices.push(voice);
         }
         self.current_voice_number = None;
-        if *seek < data.len() {measure.line_break = get_line_break(read_byte(data, seek));} else {measure.line_break = get_line_break(0);}
+        if !data.is_at_end() {measure.line_break = get_line_break(read_byte(data));} else {measure.line_break = get_line_break(0);}
     }
 
-    fn read_voice(&mut self, data: &[u8], seek: &mut usize, voice: &mut Voice, start: &mut i64, track_index: usize) {
-        let beats = read_int(data, seek).to_usize().unwrap();
+    fn read_voice(&mut self, data: &mut Input, voice: &mut Voice, start: &mut i64, track_index: usize) {
+        let beats = read_int(data).to_usize().unwrap();
         for i in 0..beats {
             self.current_beat_number = Some(i + 1);
             //println!("read_measure() read_voice(), start: {}", measure.start);
-            *start += if self.version.number < (5,0,0) {self.read_beat(data, seek, voice, *start, track_index)} else {self.read_beat_v5(data, seek, voice, &mut *start, track_index)};
+            *start += if self.version.number < (5,0,0) {self.read_beat(data, voice, *start, track_index)} else {self.read_beat_v5(data, voice, &mut *start, track_index)};
             //println!("read_measure() read_voice(), start: {}", measure.start);
         }
         self.current_beat_number = None;
     }
 
     pub(crate) fn write_measures(&self, data: &mut Vec<u8>, version: &(u8,u8,u8)) {
-        for i in 0..self.tracks.len() {
-            //self.current_track = Some(i);
-            for m in 0..self.tracks[i].measures.len() {
+        for m in 0..self.measure_headers.len() {
+            for i in 0..self.tracks.len() {
+                //self.current_track = Some(i);
                 //self.current_measure_number = Some(self.tracks[i].measure.number);
                 self.write_measure(data, i, m, version);
             }
@@ -137,9 +148,15 @@ impl Song {
     }
     fn write_measure(&self, data: &mut Vec<u8>, track: usize, measure: usize, version: &(u8,u8,u8)) {
         //self.current_voice_number = Some(1);
-        if version.0 < 5 {self.write_voice(data, track, measure,0, version);}
+        if version.0 < 5 {
+            if self.tracks[track].measures[measure].voices.is_empty() {write_i32(data, 0);}
+            else {self.write_voice(data, track, measure,0, version);}
+        }
         else {
-            for v in 0..self.tracks[track].measures[measure].voices.len() {self.write_voice(data, track, measure,v, version);} //self.current_voice_number = Some(v+1);
+            for v in 0..MAX_VOICES { //self.current_voice_number = Some(v+1);
+                if v < self.tracks[track].measures[measure].voices.len() {self.write_voice(data, track, measure,v, version);}
+                else {write_i32(data, 0);}
+            }
             if version.0 == 5 {write_byte(data, from_line_break(&self.tracks[track].measures[measure].line_break));}
         }
         //self.current_voice_number = None;
@@ -153,4 +170,109 @@ impl Song {
             //self.current_beat_number = None;
         }
     }
+
+    /// Copy a range of measures of all the tracks into a new song. The song has the same tracks, channels and settings, its clipboard
+    /// is set to the copied range so that it can be written as a clipboard file. Repeats that are not complete in the range are removed.
+    /// - `measures`: range of measure indexes (starting at 0)
+    pub fn copy_measures(&self, measures: std::ops::Range<usize>) -> Song {
+        let mut song = Song {measure_headers: self.measure_headers[measures.clone()].to_vec(), tracks: Vec::with_capacity(self.tracks.len()), lyrics: Lyrics::default(), ..self.clone()};
+        for t in &self.tracks {song.tracks.push(Track {measures: t.measures[measures.clone()].to_vec(), ..t.clone()});}
+        for (o, c) in self.repeats() {
+            let (open, close) = (measures.contains(&o), measures.contains(&c));
+            if open && !close {
+                song.measure_headers[o - measures.start].repeat_open = false;
+                for h in o..measures.end {song.measure_headers[h - measures.start].repeat_alternative = 0;}
+            }
+            if close && !open {
+                song.measure_headers[c - measures.start].repeat_close = -1;
+                for h in measures.start..=c {song.measure_headers[h - measures.start].repeat_alternative = 0;}
+            }
+        }
+        song.clipboard = Some(Clipboard {start_measure: (measures.start + 1).to_i32().unwrap(), stop_measure: measures.end.to_i32().unwrap(),
+                                         stop_track: self.tracks.len().max(1).to_i32().unwrap(), ..Default::default()});
+        song.update_measures();
+        song
+    }
+
+    /// Insert the measures of another song (e.g. made by `copy_measures()`) before a measure. The tracks are matched by their index:
+    /// the tracks missing in `source` get empty measures, the extra ones are ignored.
+    /// - `index`: index of the measure before which the measures are inserted, the number of measures to append them
+    pub fn insert_measures(&mut self, index: usize, source: &Song) {
+        let count = source.measure_headers.len();
+        self.measure_headers.splice(index..index, source.measure_headers.iter().cloned());
+        for (t, track) in self.tracks.iter_mut().enumerate() {
+            let measures: Vec<Measure> = match source.tracks.get(t) {
+                Some(s) => s.measures.clone(),
+                None => source.measure_headers.iter().enumerate().map(|(h, header)| Measure::empty(t, index + h, header)).collect(),
+            };
+            track.measures.splice(index..index, measures);
+        }
+        for l in self.lyrics.lines.iter_mut() {
+            if l.1.to_usize().unwrap() > index {l.1 += count.to_u16().unwrap();}
+        }
+        self.update_measures();
+    }
+
+    /// Delete a range of measures of all the tracks. A repeat losing its opening or closing measure is moved to the remaining measures
+    /// next to the range, lyrics starting in the range start after it.
+    /// - `measures`: range of measure indexes (starting at 0)
+    pub fn delete_measures(&mut self, measures: std::ops::Range<usize>) {
+        let count = measures.len();
+        for (o, c) in self.repeats() {
+            let (open, close) = (measures.contains(&o), measures.contains(&c));
+            if open && !close && measures.end < self.measure_headers.len() {self.measure_headers[measures.end].repeat_open = true;}
+            if close && !open && measures.start > 0 {self.measure_headers[measures.start - 1].repeat_close = self.measure_headers[c].repeat_close;}
+        }
+        self.measure_headers.drain(measures.clone());
+        for t in self.tracks.iter_mut() {t.measures.drain(measures.clone());}
+        for l in self.lyrics.lines.iter_mut() {
+            let start = l.1.to_usize().unwrap();
+            if start > measures.end {l.1 -= count.to_u16().unwrap();}
+            else if start > measures.start {l.1 = (measures.start + 1).to_u16().unwrap();}
+        }
+        self.update_measures();
+    }
+
+    /// Cut a range of measures of all the tracks: the measures are deleted and returned as a new song, see `copy_measures()`.
+    pub fn cut_measures(&mut self, measures: std::ops::Range<usize>) -> Song {
+        let song = self.copy_measures(measures.clone());
+        self.delete_measures(measures);
+        song
+    }
+
+    /// Duplicate a range of measures of all the tracks: the copy is inserted after the range.
+    pub fn duplicate_measures(&mut self, measures: std::ops::Range<usize>) {
+        let song = self.copy_measures(measures.clone());
+        self.insert_measures(measures.end, &song);
+    }
+
+    /// Get the repeats as pairs of opening and closing measure indexes. The alternative endings of a repeat close it several times, a
+    /// closing measure without opening repeats from the start.
+    pub(crate) fn repeats(&self) -> Vec<(usize, usize)> {
+        let mut repeats = Vec::new();
+        let mut open = 0;
+        for (i, h) in self.measure_headers.iter().enumerate() {
+            if h.repeat_open {open = i;}
+            if h.repeat_close > 0 {repeats.push((open, i));}
+        }
+        repeats
+    }
+
+    /// Update the numbers and starts of the measure headers, the indexes and starts of the measures and the starts of the beats after an edition.
+    pub(crate) fn update_measures(&mut self) {
+        let mut start = DURATION_QUARTER_TIME;
+        for (i, h) in self.measure_headers.iter_mut().enumerate() {
+            h.number = (i + 1).to_u16().unwrap();
+            h.start = start;
+            start += h.length();
+        }
+        for (t, track) in self.tracks.iter_mut().enumerate() {
+            for (h, m) in track.measures.iter_mut().enumerate() {
+                m.track_index = t;
+                m.header_index = h;
+                if let Some(header) = self.measure_headers.get(h) {m.start = header.start;}
+            }
+        }
+        self.update_beat_starts();
+    }
 }
@@ -0,0 +1,193 @@
+use crate::{gp::*, key_signature::*, enums::*};
+
+/// A played beat of a voice
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MelodyEvent {
+    pub measure: usize,
+    /// Index of the beat in the voice
+    pub beat: usize,
+    /// MIDI pitches of the notes starting on the beat, the highest one last
+    pub pitches: Vec<i16>,
+    pub duration: Duration,
+    /// `true` if the beat is the first played beat of the measure in the voice
+    pub measure_start: bool,
+    /// `true` if the beat is the last played beat of the measure in the voice
+    pub measure_end: bool,
+}
+
+/// The played beats of a voice of a track, rests and tied notes are skipped
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Melody {
+    pub track: usize,
+    pub voice: usize,
+    pub events: Vec<MelodyEvent>,
+}
+
+/// A sequence of notes to search, see `MelodyPattern::parse()`
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MelodyPattern {
+    /// Pitch classes of the notes (0 for C), or intervals in semitones between the highest notes of consecutive beats
+    pub steps: Vec<i16>,
+    pub intervals: bool,
+    /// Match the pitch classes in any transposition
+    pub transpose: bool,
+    /// Required duration of each note, `None` for any duration
+    pub durations: Vec<Option<Duration>>,
+    /// Barlines before each note, and after the last one
+    pub bars: Vec<bool>,
+}
+
+/// A match of a pattern
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MelodyHit {
+    pub track: usize,
+    pub voice: usize,
+    /// Measure and beat (index in the voice) of the first note
+    pub measure: usize,
+    pub beat: usize,
+    /// Measure of the last note
+    pub end_measure: usize,
+    /// Transposition in semitones of the pattern to match the notes, between 0 and 11
+    pub transposition: i16,
+}
+
+/// Melodies of several songs, to search without reading the songs again
+#[derive(Debug,Clone,PartialEq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MelodyIndex {
+    /// Name (e.g. file path) and melodies of each song
+    pub songs: Vec<(String, Vec<Melody>)>,
+}
+
+impl MelodyPattern {
+    /// Parse a pattern, returns `None` if it is not valid:
+    /// * notes: `A` to `G` followed by optional `#` or `b` accidentals, e.g. `DADDC` or `F#GAbC`. Spaces, commas and dashes are ignored.
+    /// * durations: a note can be followed by `:` and a note value with an optional dot, e.g. `D:8A:8D:4.` (eighth, eighth, dotted quarter).
+    /// * barlines: `|` requires a barline, e.g. `|DADDC|` is a whole measure and `|A|E|CC|` 3 consecutive measures.
+    ///   When the pattern has barlines, the notes not separated by a barline must be in the same measure.
+    /// * intervals: a list of semitone intervals between consecutive notes, e.g. `+2 +2 -4`, matches the highest notes of the beats.
+    /// - `transpose`: match the note names in any transposition
+    pub fn parse(text: &str, transpose: bool) -> Option<MelodyPattern> {
+        let text = text.trim();
+        if text.starts_with(['+', '-']) || text.starts_with(|c: char| c.is_ascii_digit()) {
+            let steps: Vec<i16> = text.split([' ', ',']).filter(|s| !s.is_empty()).map(|s| s.trim_start_matches('+').parse::<i16>().ok()).collect::<Option<Vec<i16>>>()?;
+            if steps.is_empty() {return None;}
+            return Some(MelodyPattern {durations: vec![None; steps.len() + 1], bars: vec![false; steps.len() + 2], steps, intervals: true, transpose: false});
+        }
+        let mut p = MelodyPattern {steps: Vec::new(), intervals: false, transpose, durations: Vec::new(), bars: vec![false]};
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                'A'..='G' => {
+                    let mut pitch: i16 = match c {'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, _ => 11};
+                    while let Some(a) = chars.next_if(|a| ['#', '♯', 'b', '♭'].contains(a)) {pitch += if a == '#' || a == '♯' {1} else {-1};}
+                    let mut duration = None;
+                    if chars.next_if_eq(&':').is_some() {
+                        let mut value = String::new();
+                        while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {value.push(d);}
+                        let value = value.parse::<u16>().ok().filter(|v| v.is_power_of_two() && *v <= 64)?;
+                        duration = Some(Duration {value, dotted: chars.next_if_eq(&'.').is_some(), ..Default::default()});
+                    }
+                    p.steps.push(pitch.rem_euclid(12));
+                    p.durations.push(duration);
+                    p.bars.push(false);
+                },
+                '|' => *p.bars.last_mut().unwrap() = true,
+                ' ' | ',' | '-' => {},
+                _ => return None,
+            }
+        }
+        if p.steps.is_empty() {None} else {Some(p)}
+    }
+
+    /// Number of notes matched by the pattern
+    pub fn len(&self) -> usize { if self.intervals {self.steps.len() + 1} else {self.steps.len()} }
+    pub fn is_empty(&self) -> bool { self.steps.is_empty() }
+
+    /// Search the pattern in melodies.
+    pub fn search(&self, melodies: &[Melody]) -> Vec<MelodyHit> {
+        let mut hits = Vec::new();
+        let length = self.len();
+        for m in melodies {
+            if m.events.len() < length {continue;}
+            for start in 0..=m.events.len() - length {
+                if let Some(transposition) = self.matches(&m.events[start..start + length]) {
+                    hits.push(MelodyHit {track: m.track, voice: m.voice, measure: m.events[start].measure, beat: m.events[start].beat,
+                                         end_measure: m.events[start + length - 1].measure, transposition});
+                }
+            }
+        }
+        hits
+    }
+
+    /// Check if events match the pattern, returns the transposition.
+    fn matches(&self, events: &[MelodyEvent]) -> Option<i16> {
+        //rhythm and barlines
+        for (i, e) in events.iter().enumerate() {
+            if let Some(d) = &self.durations[i] {if e.duration.value != d.value || e.duration.dotted != d.dotted {return None;}}
+        }
+        if self.bars.iter().any(|b| *b) {
+            if self.bars[0] && !events[0].measure_start {return None;}
+            if self.bars[events.len()] && !events[events.len() - 1].measure_end {return None;}
+            for i in 1..events.len() {
+                let (a, b) = (&events[i - 1], &events[i]);
+                if self.bars[i] && !(a.measure_end && b.measure_start && b.measure == a.measure + 1) {return None;}
+                if !self.bars[i] && a.measure != b.measure {return None;}
+            }
+        }
+        if self.intervals {
+            let top: Vec<i16> = events.iter().map(|e| *e.pitches.last().unwrap()).collect();
+            return if top.windows(2).zip(&self.steps).all(|(w, s)| w[1] - w[0] == *s) {Some(0)} else {None};
+        }
+        let transpositions = if self.transpose {0..12} else {0..1};
+        transpositions.into_iter().find(|k| events.iter().zip(&self.steps).all(|(e, s)| e.pitches.iter().any(|p| p.rem_euclid(12) == (s + k).rem_euclid(12))))
+    }
+}
+
+impl MelodyIndex {
+    /// Add the melodies of a song (see `Song::melodies()`).
+    pub fn add(&mut self, name: &str, song: &Song, tracks: &[usize]) { self.songs.push((String::from(name), song.melodies(tracks))); }
+
+    /// Search a pattern in all the songs, returns the name of the song of each match.
+    pub fn search(&self, pattern: &MelodyPattern) -> Vec<(String, MelodyHit)> {
+        self.songs.iter().flat_map(|(name, melodies)| pattern.search(melodies).into_iter().map(move |h| (name.clone(), h))).collect()
+    }
+}
+
+impl Song {
+    /// Get the played beats of every voice of some tracks.
+    /// - `tracks`: indexes of the tracks. If empty, all the tracks which are not percussion tracks are used.
+    pub fn melodies(&self, tracks: &[usize]) -> Vec<Melody> {
+        let tracks: Vec<usize> = if tracks.is_empty() {(0..self.tracks.len()).filter(|t| !self.tracks[*t].percussion_track).collect()} else {tracks.to_vec()};
+        let mut melodies = Vec::new();
+        for t in tracks {
+            let track = &self.tracks[t];
+            let voices = track.measures.iter().map(|m| m.voices.len()).max().unwrap_or(0);
+            for v in 0..voices {
+                let mut events: Vec<MelodyEvent> = Vec::new();
+                for (m, measure) in track.measures.iter().enumerate() {
+                    let first = events.len();
+                    for (b, beat) in measure.voices.get(v).map(|v| v.beats.iter().enumerate().collect::<Vec<_>>()).unwrap_or_default() {
+                        if beat.status != BeatStatus::Normal {continue;}
+                        let mut pitches: Vec<i16> = beat.notes.iter().filter(|n| n.kind == NoteType::Normal).filter_map(|n| track.midi_pitch(n)).collect();
+                        if pitches.is_empty() {continue;}
+                        pitches.sort_unstable();
+                        events.push(MelodyEvent {measure: m, beat: b, pitches, duration: beat.duration.clone(), measure_start: events.len() == first, measure_end: false});
+                    }
+                    if events.len() > first {events.last_mut().unwrap().measure_end = true;}
+                }
+                if !events.is_empty() {melodies.push(Melody {track: t, voice: v, events});}
+            }
+        }
+        melodies
+    }
+
+    /// Search a sequence of notes in every voice of some tracks (see `MelodyPattern::parse()`).
+    /// - `tracks`: indexes of the tracks. If empty, all the tracks which are not percussion tracks are used.
+    pub fn find_melody(&self, pattern: &MelodyPattern, tracks: &[usize]) -> Vec<MelodyHit> { pattern.search(&self.melodies(tracks)) }
+}
+
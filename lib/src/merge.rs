@@ -0,0 +1,261 @@
+use crate::{gp::*, track::*, measure::*, beat::*, diff::*};
+
+/// A part of a song changed differently in both edited versions, see `Song::merge_edits()`
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conflict {
+    pub track: Option<usize>,
+    /// Index of the measure in the merged song
+    pub measure: Option<usize>,
+    pub voice: Option<usize>,
+    /// Position of the beat from the start of its measure, in ticks
+    pub position: Option<i64>,
+    /// Name of the conflicting value, e.g. `title`, `track`, `measure header`, `measure`, `voice` or `beat`
+    pub field: String,
+    /// Value in the common ancestor, `None` when it is absent
+    pub base: Option<String>,
+    /// Our value, kept in the merged song. `None` when we removed it.
+    pub ours: Option<String>,
+    /// Their value, `None` when they removed it
+    pub theirs: Option<String>,
+}
+impl Conflict {
+    fn new(field: &str, base: Option<String>, ours: Option<String>, theirs: Option<String>) -> Conflict {
+        Conflict {track: None, measure: None, voice: None, position: None, field: String::from(field), base, ours, theirs}
+    }
+}
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut location = Vec::new();
+        if let Some(m) = self.measure {location.push(format!("measure {}", m + 1));}
+        if let Some(t) = self.track {location.push(format!("track {}", t + 1));}
+        if let Some(v) = self.voice {location.push(format!("voice {}", v + 1));}
+        if let Some(p) = self.position {location.push(format!("beat at tick {}", p));}
+        let location = if location.is_empty() {String::from("song")} else {location.join(", ")};
+        let value = |v: &Option<String>| v.clone().unwrap_or_else(|| String::from("(none)"));
+        write!(f, "{}: {}: base {}, ours {}, theirs {}", location, self.field, value(&self.base), value(&self.ours), value(&self.theirs))
+    }
+}
+
+/// A song merged by `Song::merge_edits()` with the conflicts left in it
+#[derive(Debug,Clone)]
+pub struct MergeResult {
+    pub song: Song,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// Version of a value kept by a merge
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum Choice { Ours, Theirs, Conflict }
+
+/// Keep the value changed by only one version (ours when both made the same change)
+fn choose<T: PartialEq>(base: &T, ours: &T, theirs: &T) -> Choice {
+    if ours == theirs || theirs == base {Choice::Ours} else if ours == base {Choice::Theirs} else {Choice::Conflict}
+}
+
+/// Index of a merged measure in each version
+#[derive(Debug,Clone,Copy)]
+struct Source {
+    base: Option<usize>,
+    ours: Option<usize>,
+    theirs: Option<usize>,
+}
+
+/// Values of the information of a song, with a function copying the value of a song to the merged song
+type InfoField = (&'static str, fn(&Song) -> String, fn(&mut Song, &Song));
+const INFO_FIELDS: [InfoField; 15] = [
+    ("title", |s| s.name.clone(), |m, s| m.name = s.name.clone()),
+    ("subtitle", |s| s.subtitle.clone(), |m, s| m.subtitle = s.subtitle.clone()),
+    ("artist", |s| s.artist.clone(), |m, s| m.artist = s.artist.clone()),
+    ("album", |s| s.album.clone(), |m, s| m.album = s.album.clone()),
+    ("words", |s| s.words.clone(), |m, s| m.words = s.words.clone()),
+    ("music", |s| s.author.clone(), |m, s| m.author = s.author.clone()),
+    ("copyright", |s| s.copyright.clone(), |m, s| m.copyright = s.copyright.clone()),
+    ("writer", |s| s.writer.clone(), |m, s| m.writer = s.writer.clone()),
+    ("transcriber", |s| s.transcriber.clone(), |m, s| m.transcriber = s.transcriber.clone()),
+    ("instructions", |s| s.instructions.clone(), |m, s| m.instructions = s.instructions.clone()),
+    ("comments", |s| s.comments.clone(), |m, s| m.comments = s.comments.clone()),
+    ("notice", |s| s.notice.join("\n"), |m, s| m.notice = s.notice.clone()),
+    ("tempo", |s| s.tempo.to_string(), |m, s| m.tempo = s.tempo),
+    ("key", |s| s.key.to_string(), |m, s| m.key = s.key.clone()),
+    ("lyrics", |s| s.lyrics.to_string(), |m, s| m.lyrics = s.lyrics.clone()),
+];
+
+impl Song {
+    /// Merge two edited versions of the song, the song being their common ancestor. A change made in only one version is kept, the
+    /// changes of both versions are merged field by field for the information, track by track for the track settings (the tracks
+    /// are matched by index), measure by measure (inserted, removed or changed measures, see `align_measures()`), then voice by
+    /// voice and beat by beat in the measures changed by both versions. When both versions changed the same part differently, our
+    /// version is kept and a conflict is reported.
+    pub fn merge_edits(&self, ours: &Song, theirs: &Song) -> MergeResult {
+        let mut song = ours.clone();
+        let mut conflicts = Vec::new();
+        for (field, value, copy) in INFO_FIELDS {
+            let (b, o, t) = (value(self), value(ours), value(theirs));
+            match choose(&b, &o, &t) {
+                Choice::Theirs => copy(&mut song, theirs),
+                Choice::Conflict => conflicts.push(Conflict::new(field, Some(b), Some(o), Some(t))),
+                Choice::Ours => (),
+            }
+        }
+        //settings of the tracks of the 3 versions
+        let common = self.tracks.len().min(ours.tracks.len()).min(theirs.tracks.len());
+        for t in 0..common {
+            let describe = |s: &Song| s.track_fields(&s.tracks[t]).iter().map(|f| format!("{} {}", f.0, f.1)).collect::<Vec<String>>().join(", ");
+            let (b, o, th) = (describe(self), describe(ours), describe(theirs));
+            match choose(&b, &o, &th) {
+                Choice::Theirs => {
+                    let measures = std::mem::take(&mut song.tracks[t].measures);
+                    song.tracks[t] = Track {measures, ..theirs.tracks[t].clone()};
+                    copy_channel(&mut song, theirs, t);
+                },
+                Choice::Conflict => conflicts.push(Conflict {track: Some(t), ..Conflict::new("track", Some(b), Some(o), Some(th))}),
+                Choice::Ours => (),
+            }
+        }
+        //added or removed tracks are taken from the version which changed the number of tracks
+        let names = |s: &Song| s.tracks.iter().map(|t| t.name.clone()).collect::<Vec<String>>().join(", ");
+        let extra_from_theirs = ours.tracks.len() == self.tracks.len() && theirs.tracks.len() != self.tracks.len();
+        if extra_from_theirs {
+            song.tracks.truncate(common);
+            for t in common..theirs.tracks.len() {
+                song.tracks.push(Track {measures: Vec::new(), ..theirs.tracks[t].clone()});
+                copy_channel(&mut song, theirs, t);
+            }
+        } else if ours.tracks.len() != self.tracks.len() && theirs.tracks.len() != self.tracks.len() && names(ours) != names(theirs) {
+            conflicts.push(Conflict::new("tracks", Some(names(self)), Some(names(ours)), Some(names(theirs))));
+        }
+        //measures of the merged song
+        let (fb, fo, ft) = (self.measure_fingerprints(common), ours.measure_fingerprints(common), theirs.measure_fingerprints(common));
+        let count = self.measure_headers.len();
+        let (our_measures, our_insertions) = base_mapping(&self.align_measures(ours), count);
+        let (their_measures, their_insertions) = base_mapping(&self.align_measures(theirs), count);
+        let mut sources: Vec<Source> = Vec::with_capacity(count);
+        for i in 0..=count {
+            let (o, t) = (&our_insertions[i], &their_insertions[i]);
+            if t.is_empty() {sources.extend(o.iter().map(|j| Source {base: None, ours: Some(*j), theirs: None}));}
+            else if o.is_empty() {sources.extend(t.iter().map(|j| Source {base: None, ours: None, theirs: Some(*j)}));}
+            else if o.iter().map(|j| fo[*j]).eq(t.iter().map(|j| ft[*j])) {
+                sources.extend(o.iter().zip(t).map(|(x, y)| Source {base: None, ours: Some(*x), theirs: Some(*y)}));
+            } else {
+                let text = |s: &Song, measures: &[usize]| measures.iter().map(|m| measure_text(s, *m)).collect::<Vec<String>>().join("; ");
+                conflicts.push(Conflict {measure: Some(sources.len()), ..Conflict::new("inserted measures", None, Some(text(ours, o)), Some(text(theirs, t)))});
+                sources.extend(o.iter().map(|j| Source {base: None, ours: Some(*j), theirs: None}));
+            }
+            if i == count {break;}
+            match (our_measures[i], their_measures[i]) {
+                (Some(o), Some(t)) => sources.push(Source {base: Some(i), ours: Some(o), theirs: Some(t)}),
+                (None, None) => (),
+                //removed by a version and unchanged by the other one
+                (None, Some(t)) if ft[t] == fb[i] => (),
+                (Some(o), None) if fo[o] == fb[i] => (),
+                (o, t) => {
+                    //removed by a version and changed by the other one: the changed measure is kept
+                    conflicts.push(Conflict {measure: Some(sources.len()), ..Conflict::new("measure", Some(measure_text(self, i)),
+                        o.map(|o| measure_text(ours, o)), t.map(|t| measure_text(theirs, t)))});
+                    sources.push(Source {base: Some(i), ours: o, theirs: t});
+                },
+            }
+        }
+        song.measure_headers = Vec::with_capacity(sources.len());
+        for (m, s) in sources.iter().enumerate() {
+            let header = match (s.base, s.ours, s.theirs) {
+                (Some(b), Some(o), Some(t)) => {
+                    let (hb, ho, ht) = (&self.measure_headers[b], &ours.measure_headers[o], &theirs.measure_headers[t]);
+                    let (b, o, t) = (header_fields(hb), header_fields(ho), header_fields(ht));
+                    match choose(&b, &o, &t) {
+                        Choice::Ours => ho.clone(),
+                        Choice::Theirs => ht.clone(),
+                        Choice::Conflict => {
+                            //the fields differing between the versions
+                            let fields: Vec<usize> = (0..b.len()).filter(|f| b[*f] != o[*f] || b[*f] != t[*f]).collect();
+                            let text = |h: &[(&str, String)]| fields.iter().map(|f| format!("{} {}", h[*f].0, h[*f].1)).collect::<Vec<String>>().join(", ");
+                            conflicts.push(Conflict {measure: Some(m), ..Conflict::new("measure header", Some(text(&b)), Some(text(&o)), Some(text(&t)))});
+                            ho.clone()
+                        },
+                    }
+                },
+                (_, Some(o), _) => ours.measure_headers[o].clone(),
+                (_, None, Some(t)) => theirs.measure_headers[t].clone(),
+                (b, None, None) => self.measure_headers[b.unwrap_or_default()].clone(),
+            };
+            song.measure_headers.push(header);
+        }
+        for t in 0..song.tracks.len() {
+            let mut measures = Vec::with_capacity(sources.len());
+            for (m, s) in sources.iter().enumerate() {
+                let get = |version: &'_ Song, index: Option<usize>| index.and_then(|i| version.tracks.get(t).and_then(|t| t.measures.get(i))).cloned();
+                let measure = if t >= common {
+                    //track of one version only
+                    if extra_from_theirs {get(theirs, s.theirs)} else {get(ours, s.ours)}
+                } else {
+                    match (get(self, s.base), get(ours, s.ours), get(theirs, s.theirs)) {
+                        (Some(b), Some(o), Some(th)) => Some(merge_measure(&b, o, &th, &Conflict {track: Some(t), measure: Some(m), ..Conflict::new("", None, None, None)}, &mut conflicts)),
+                        (_, Some(o), _) => Some(o),
+                        (_, None, th) => th,
+                    }
+                };
+                measures.push(measure.unwrap_or_else(|| Measure::empty(t, m, &song.measure_headers[m])));
+            }
+            song.tracks[t].measures = measures;
+        }
+        song.update_measures();
+        MergeResult {song, conflicts}
+    }
+}
+
+/// Get the index in the edited version of each measure of the ancestor (`None` if removed), and the measures of the edited
+/// version inserted before each measure of the ancestor (the last list is after the last measure)
+fn base_mapping(pairs: &[(Option<usize>, Option<usize>)], count: usize) -> (Vec<Option<usize>>, Vec<Vec<usize>>) {
+    let mut measures = vec![None; count];
+    let mut insertions = vec![Vec::new(); count + 1];
+    let mut next = 0;
+    for p in pairs {
+        match p {
+            (Some(b), m) => {measures[*b] = *m; next = b + 1;},
+            (None, Some(m)) => insertions[next].push(*m),
+            (None, None) => (),
+        }
+    }
+    (measures, insertions)
+}
+
+/// Copy the MIDI channel of a track of another version to the merged song
+fn copy_channel(song: &mut Song, version: &Song, track: usize) {
+    let index = version.tracks[track].channel_index;
+    if let (Some(c), Some(m)) = (version.channels.get(index), song.channels.get_mut(index)) {*m = *c;}
+}
+
+/// Merge a measure changed by both versions: voice by voice, then beat by beat when the 3 versions of a voice have the same rhythm.
+/// - `location`: track and measure of the conflicts
+fn merge_measure(base: &Measure, ours: Measure, theirs: &Measure, location: &Conflict, conflicts: &mut Vec<Conflict>) -> Measure {
+    let mut measure = ours;
+    let voices = base.voices.len().max(measure.voices.len()).max(theirs.voices.len());
+    if measure.voices.len() < voices {measure.voices.resize(voices, Voice::default());}
+    for v in 0..voices {
+        let (b, o, t) = (beat_positions(base.voices.get(v)), beat_positions(measure.voices.get(v)), beat_positions(theirs.voices.get(v)));
+        let describe = |beats: &[(usize, i64, &Beat)]| beats.iter().map(|b| (b.1, beat_fingerprint(b.2))).collect::<Vec<(i64, String)>>();
+        match choose(&describe(&b), &describe(&o), &describe(&t)) {
+            Choice::Ours => continue,
+            Choice::Theirs => {measure.voices[v] = theirs.voices.get(v).cloned().unwrap_or_default(); continue;},
+            Choice::Conflict => (),
+        }
+        let rhythm = |beats: &[(usize, i64, &Beat)]| beats.iter().map(|b| (b.1, b.2.duration.clone())).collect::<Vec<_>>();
+        if rhythm(&b) != rhythm(&o) || rhythm(&o) != rhythm(&t) {
+            let text = |beats: &[(usize, i64, &Beat)]| beats.iter().map(|b| beat_text(b.2)).collect::<Vec<String>>().join(" | ");
+            conflicts.push(Conflict {voice: Some(v), field: String::from("voice"), base: Some(text(&b)), ours: Some(text(&o)), theirs: Some(text(&t)), ..location.clone()});
+            continue;
+        }
+        let mut replaced = Vec::new();
+        for ((x, y), z) in b.iter().zip(&o).zip(&t) {
+            match choose(&beat_fingerprint(x.2), &beat_fingerprint(y.2), &beat_fingerprint(z.2)) {
+                Choice::Ours => (),
+                Choice::Theirs => replaced.push((y.0, Beat {start: y.2.start, ..z.2.clone()})),
+                Choice::Conflict => conflicts.push(Conflict {voice: Some(v), position: Some(y.1), field: String::from("beat"),
+                    base: Some(beat_text(x.2)), ours: Some(beat_text(y.2)), theirs: Some(beat_text(z.2)), ..location.clone()}),
+            }
+        }
+        for (i, beat) in replaced {measure.voices[v].beats[i] = beat;}
+    }
+    measure
+}
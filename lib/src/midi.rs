@@ -40,8 +40,16 @@ pub const CHANNEL_DEFAULT_NAMES: [&str; 128] = ["Piano", "Bright Piano", "Electr
                                             "Seashore", "Bird", "Telephone", "Helicopter", "Applause", "Gunshot"];
 
 pub const DEFAULT_PERCUSSION_CHANNEL: u8 = 9;
+/// Number of MIDI channels of a song: 4 ports of 16 channels
+pub const CHANNEL_COUNT: usize = 64;
+
+/// First channel which is neither used nor a percussion channel, looking in every port.
+pub(crate) fn free_channel(used: &[usize]) -> Option<usize> {
+    (0..CHANNEL_COUNT).find(|c| c % 16 != usize::from(DEFAULT_PERCUSSION_CHANNEL) && !used.contains(c))
+}
 /// A MIDI channel describes playing data for a track.
 #[derive(Debug,Copy,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MidiChannel {
     pub channel: u8,
     pub effect_channel: u8,
@@ -61,18 +69,22 @@ impl MidiChannel {
     pub(crate) fn is_percussion_channel(self) -> bool {
         (self.channel % 16) == DEFAULT_PERCUSSION_CHANNEL
     }
-    pub(crate) fn set_instrument(mut self, instrument: i32) {
+    pub(crate) fn set_instrument(&mut self, instrument: i32) {
         if instrument == -1 && self.is_percussion_channel() { self.instrument = 0; }
         else {self.instrument = instrument;}
     }
 
     pub(crate) fn _get_instrument(self) -> i32 {self.instrument}
-    pub(crate) fn get_instrument_name(&self) -> String {String::from(CHANNEL_DEFAULT_NAMES[self.instrument.to_usize().unwrap()])} //TODO: FIXME: does not seems OK
+    /// Copy the channel settings to other channel numbers
+    pub(crate) fn with_channels(self, channel: usize, effect_channel: usize) -> MidiChannel {
+        MidiChannel {channel: channel.to_u8().unwrap(), effect_channel: effect_channel.to_u8().unwrap(), ..self}
+    }
+    pub fn get_instrument_name(&self) -> String {String::from(CHANNEL_DEFAULT_NAMES[self.instrument.to_usize().unwrap()])} //TODO: FIXME: does not seems OK
 }
 
 impl Song{
     /// Read all the MIDI channels
-    pub(crate) fn read_midi_channels(&mut self, data: &[u8], seek: &mut usize) { for i in 0u8..64u8 { self.channels.push(self.read_midi_channel(data, seek, i)); } }
+    pub(crate) fn read_midi_channels(&mut self, data: &mut Input) { for i in 0u8..64u8 { self.channels.push(self.read_midi_channel(data, i)); } }
     /// Read MIDI channels. Guitar Pro format provides 64 channels (4 MIDI ports by 16 hannels), the channels are stored in this order:
     ///`port1/channel1`, `port1/channel2`, ..., `port1/channel16`, `port2/channel1`, ..., `port4/channel16`.
     ///
@@ -87,21 +99,23 @@ impl Song{
     /// * **Tremolo**: `byte`
     /// * **blank1**: `byte` => Backward compatibility with version 3.0
     /// * **blank2**: `byte` => Backward compatibility with version 3.0
-    pub(crate) fn read_midi_channel(&self, data: &[u8], seek: &mut usize, channel: u8) -> MidiChannel {
-        let instrument = read_int(data, seek);
+    pub(crate) fn read_midi_channel(&self, data: &mut Input, channel: u8) -> MidiChannel {
+        let instrument = read_int(data);
         let mut c = MidiChannel{channel, effect_channel: channel, ..Default::default()};
-        c.volume = read_signed_byte(data, seek); c.balance = read_signed_byte(data, seek);
-        c.chorus = read_signed_byte(data, seek); c.reverb = read_signed_byte(data, seek); c.phaser = read_signed_byte(data, seek); c.tremolo = read_signed_byte(data, seek);
+        c.volume = read_signed_byte(data); c.balance = read_signed_byte(data);
+        c.chorus = read_signed_byte(data); c.reverb = read_signed_byte(data); c.phaser = read_signed_byte(data); c.tremolo = read_signed_byte(data);
         c.set_instrument(instrument);
         //println!("Channel: {}\t Volume: {}\tBalance: {}\tInstrument={}, {}, {}", c.channel, c.volume, c.balance, instrument, c.get_instrument(), c.get_instrument_name());
-        *seek += 2; //Backward compatibility with version 3.0
+        data.skip(2); //Backward compatibility with version 3.0
         c
     }
 
     /// Read MIDI channel. MIDI channel in Guitar Pro is represented by two integers. First is zero-based number of channel, second is zero-based number of channel used for effects.
-    pub(crate) fn read_channel(&mut self, data: &[u8], seek: &mut usize) -> usize { //TODO: fixme for writing
-        let index          = read_int(data, seek) - 1;
-        let effect_channel = read_int(data, seek) - 1;
+    /// The channels are numbered in the MIDI `port` of the track, the returned index is the one in the 64 channels of the song.
+    pub(crate) fn read_channel(&mut self, data: &mut Input, port: u8) -> usize {
+        let offset = if (1..=4).contains(&port) {i32::from(port - 1) * 16} else {0};
+        let index          = offset + read_int(data) - 1;
+        let effect_channel = offset + read_int(data) - 1;
         if 0 <= index && index < self.channels.len().to_i32().unwrap() {
             if  self.channels[index.to_usize().unwrap()].instrument < 0 {self.channels[index.to_usize().unwrap()].instrument = 0;}
             if !self.channels[index.to_usize().unwrap()].is_percussion_channel() {self.channels[index.to_usize().unwrap()].effect_channel =  effect_channel.to_u8().unwrap();}
@@ -111,18 +125,15 @@ impl Song{
 
     pub(crate) fn write_midi_channels(&self, data: &mut Vec<u8>) {
         for i in 0..self.channels.len() {
-            println!("writing channel: {:?}", self.channels[i]);
-            if self.channels[i].is_percussion_channel() && self.channels[i].instrument == 0 {write_i32(data, -1);}
-            else                                                                            {write_i32(data, self.channels[i].instrument);}
-            write_signed_byte(data, Self::from_channel_short(self.channels[i].volume));
-            write_signed_byte(data, Self::from_channel_short(self.channels[i].balance));
-            write_signed_byte(data, Self::from_channel_short(self.channels[i].chorus));
-            write_signed_byte(data, Self::from_channel_short(self.channels[i].reverb));
-            write_signed_byte(data, Self::from_channel_short(self.channels[i].phaser));
-            write_signed_byte(data, Self::from_channel_short(self.channels[i].tremolo));
+            //println!("writing channel: {:?}", self.channels[i]);
+            write_i32(data, self.channels[i].instrument);
+            write_signed_byte(data, self.channels[i].volume);
+            write_signed_byte(data, self.channels[i].balance);
+            write_signed_byte(data, self.channels[i].chorus);
+            write_signed_byte(data, self.channels[i].reverb);
+            write_signed_byte(data, self.channels[i].phaser);
+            write_signed_byte(data, self.channels[i].tremolo);
             write_placeholder_default(data, 2); //Backward compatibility with version 3.0
         }
     }
-
-    fn from_channel_short(data: i8) -> i8 { ((data >> 3) - 1).clamp(-128, 127) + 1 }
 }
\ No newline at end of file
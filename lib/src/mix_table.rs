@@ -6,6 +6,7 @@ use crate::gp::*;
 
 /// A mix table item describes a mix parameter, e.g. volume or reverb
 #[derive(Debug,Clone,PartialEq,Eq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MixTableItem {
     pub value: u8,
     pub duration: u8,
@@ -13,9 +14,11 @@ pub struct MixTableItem {
 }
 //impl Default for MixTableItem { fn default() -> Self { MixTableItem { value: 0, duration: 0, all_tracks: false }}}
 
+#[allow(dead_code)]
 const WAH_EFFECT_OFF:  i8 = -2;
 const WAH_EFFECT_NONE: i8 = -1;
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WahEffect {
     value: i8,
     display: bool,
@@ -25,13 +28,14 @@ impl WahEffect {
     pub(crate) fn _check_value(value: i8) {
         if !(WAH_EFFECT_OFF..=100).contains(&value) {panic!("Value for a wah effect must be in range from -2 to 100")}
     }
-    pub(crate) fn _is_on(&self) -> bool {self.value <= 0 && self.value <= 100}
+    pub(crate) fn _is_on(&self) -> bool {self.value >= 0 && self.value <= 100}
     pub(crate) fn _is_off(&self) -> bool {self.value == WAH_EFFECT_OFF}
     pub(crate) fn _is_none(&self) -> bool {self.value == WAH_EFFECT_NONE}
 }
 
 /// A MixTableChange describes a change in mix parameters
 #[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MixTableChange {
     pub instrument: Option<MixTableItem>,
     pub rse: RseInstrument,
@@ -52,7 +56,7 @@ impl Default for MixTableChange { fn default() -> Self { MixTableChange { instru
 }}}
 impl MixTableChange {
     pub(crate) fn is_just_wah(&self) -> bool {
-        self.instrument.is_none() &&  self.volume.is_none() && self.balance.is_none() && self.chorus.is_none() && self.reverb.is_none() && self.phaser.is_none() && self.tremolo.is_none() && self.tempo.is_none() && self.wah.is_none()
+        self.instrument.is_none() &&  self.volume.is_none() && self.balance.is_none() && self.chorus.is_none() && self.reverb.is_none() && self.phaser.is_none() && self.tremolo.is_none() && self.tempo.is_none() && self.wah.is_some()
     }
 }
 
@@ -67,16 +71,16 @@ impl Song {
     /// Mix table change was modified to support RSE instruments. It is read as in Guitar Pro 3 and is followed by:
     /// - Wah effect. See :meth:`read_wah_effect()`.
     /// - RSE instrument effect. See :meth:`read_rse_instrument_effect()`.
-    pub(crate) fn read_mix_table_change(&mut self, data: &[u8], seek: &mut usize) -> MixTableChange {
+    pub(crate) fn read_mix_table_change(&mut self, data: &mut Input) -> MixTableChange {
         let mut tc = MixTableChange::default();
-        self.read_mix_table_change_values(data, seek, &mut tc);
-        self.read_mix_table_change_durations(data, seek, &mut tc);
+        self.read_mix_table_change_values(data, &mut tc);
+        self.read_mix_table_change_durations(data, &mut tc);
         //println!("read_mix_table_change()");
         if self.version.number >= (4,0,0) {
-            let flags = self.read_mix_table_change_flags(data, seek, &mut tc);
+            let flags = self.read_mix_table_change_flags(data, &mut tc);
             if self.version.number >= (5,0,0) {
-                tc.wah = Some(self.read_wah_effect(data, seek, flags));
-                self.read_rse_instrument_effect(data, seek, &mut tc.rse);
+                tc.wah = Some(self.read_wah_effect(data, flags));
+                self.read_rse_instrument_effect(data, &mut tc.rse);
             }
         }
         tc
@@ -94,52 +98,52 @@ impl Song {
     /// - tempo
     /// 
     /// If signed byte is *-1* then corresponding parameter hasn't changed.
-    fn read_mix_table_change_values(&mut self, data: &[u8], seek: &mut usize, mtc: &mut MixTableChange) {
+    fn read_mix_table_change_values(&mut self, data: &mut Input, mtc: &mut MixTableChange) {
         //instrument
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.instrument = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //RSE instrument GP5
-        if self.version.number.0 == 5 {mtc.rse = self.read_rse_instrument(data, seek);}
-        if self.version.number == (5,0,0)  { *seek += 1; }
+        if self.version.number.0 == 5 {mtc.rse = self.read_rse_instrument(data);}
+        if self.version.number == (5,0,0)  { data.skip(1); }
         //volume
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.volume = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //balance
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.balance = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //chorus
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.chorus = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //reverb
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.reverb = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //phaser
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.phaser = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //tremolo
-        let b = read_signed_byte(data, seek);
+        let b = read_signed_byte(data);
         if b >= 0 {mtc.tremolo = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
         //tempo
-        if self.version.number >= (5,0,0) {mtc.tempo_name = read_int_byte_size_string(data, seek);}
-        let b = read_int(data, seek);
+        if self.version.number >= (5,0,0) {mtc.tempo_name = read_int_byte_size_string(data);}
+        let b = read_int(data);
         if b >= 0 {mtc.tempo = Some(MixTableItem{value: b.to_u8().unwrap(), ..Default::default()});}
     }
     /// Read mix table change durations. Durations are read for each non-null `MixTableItem`. Durations are encoded in `signed-byte`.
     /// 
     /// If tempo did change, then one :ref:`bool` is read. If it's true, then tempo change won't be displayed on the score.
-    fn read_mix_table_change_durations(&self, data: &[u8], seek: &mut usize, mtc: &mut MixTableChange) {
-        if mtc.volume.is_some()  {mtc.volume.take().unwrap().duration  = read_signed_byte(data, seek).to_u8().unwrap();}
-        if mtc.balance.is_some() {mtc.balance.take().unwrap().duration = read_signed_byte(data, seek).to_u8().unwrap();}
-        if mtc.chorus.is_some()  {mtc.chorus.take().unwrap().duration  = read_signed_byte(data, seek).to_u8().unwrap();}
-        if mtc.reverb.is_some()  {mtc.reverb.take().unwrap().duration  = read_signed_byte(data, seek).to_u8().unwrap();}
-        if mtc.phaser.is_some()  {mtc.phaser.take().unwrap().duration  = read_signed_byte(data, seek).to_u8().unwrap();}
-        if mtc.tremolo.is_some() {mtc.tremolo.take().unwrap().duration = read_signed_byte(data, seek).to_u8().unwrap();}
+    fn read_mix_table_change_durations(&self, data: &mut Input, mtc: &mut MixTableChange) {
+        if let Some(e) = mtc.volume.as_mut() {e.duration = read_signed_byte(data).to_u8().unwrap();}
+        if let Some(e) = mtc.balance.as_mut() {e.duration = read_signed_byte(data).to_u8().unwrap();}
+        if let Some(e) = mtc.chorus.as_mut() {e.duration = read_signed_byte(data).to_u8().unwrap();}
+        if let Some(e) = mtc.reverb.as_mut() {e.duration = read_signed_byte(data).to_u8().unwrap();}
+        if let Some(e) = mtc.phaser.as_mut() {e.duration = read_signed_byte(data).to_u8().unwrap();}
+        if let Some(e) = mtc.tremolo.as_mut() {e.duration = read_signed_byte(data).to_u8().unwrap();}
         if mtc.tempo.is_some()   {
             let mut t = mtc.tempo.take().unwrap();
-            t.duration = read_signed_byte(data, seek).to_u8().unwrap();
+            t.duration = read_signed_byte(data).to_u8().unwrap();
             mtc.tempo = Some(t);
             mtc.hide_tempo = false;
-            if self.version.number >= (5,0,0) {mtc.hide_tempo = read_bool(data, seek);}
+            if self.version.number >= (5,0,0) {mtc.hide_tempo = read_bool(data);}
         }
     }
 
@@ -154,8 +158,8 @@ impl Song {
     /// In GP5, there is one additional flag:
     /// - *0x40*: use RSE
     /// - *0x80*: show wah-wah
-    fn read_mix_table_change_flags(&self, data: &[u8], seek: &mut usize, mtc: &mut MixTableChange) -> i8 {
-        let flags = read_signed_byte(data, seek);
+    fn read_mix_table_change_flags(&self, data: &mut Input, mtc: &mut MixTableChange) -> i8 {
+        let flags = read_signed_byte(data);
         //println!("read_mix_table_change_flags(), flags:  {}", flags);
         if mtc.volume.is_some() {
             let mut e = mtc.volume.take().unwrap();
@@ -164,27 +168,27 @@ impl Song {
         }
         if mtc.balance.is_some() {
             let mut e = mtc.balance.take().unwrap();
-            e.all_tracks = (flags & 0x01) == 0x01;
+            e.all_tracks = (flags & 0x02) == 0x02;
             mtc.balance = Some(e);
         }
         if mtc.chorus.is_some() {
             let mut e = mtc.chorus.take().unwrap();
-            e.all_tracks = (flags & 0x01) == 0x01;
+            e.all_tracks = (flags & 0x04) == 0x04;
             mtc.chorus = Some(e);
         }
         if mtc.reverb.is_some() {
             let mut e = mtc.reverb.take().unwrap();
-            e.all_tracks = (flags & 0x01) == 0x01;
+            e.all_tracks = (flags & 0x08) == 0x08;
             mtc.reverb = Some(e);
         }
         if mtc.phaser.is_some() {
             let mut e = mtc.phaser.take().unwrap();
-            e.all_tracks = (flags & 0x01) == 0x01;
+            e.all_tracks = (flags & 0x10) == 0x10;
             mtc.phaser = Some(e);
         }
         if mtc.tremolo.is_some() {
             let mut e = mtc.tremolo.take().unwrap();
-            e.all_tracks = (flags & 0x01) == 0x01;
+            e.all_tracks = (flags & 0x20) == 0x20;
             mtc.tremolo = Some(e);
         }
         if self.version.number >= (5,0,0) {mtc.use_rse = (flags & 0x40) == 0x40;}
@@ -193,7 +197,7 @@ impl Song {
 
     /// Read wah-wah.
     /// - Wah value: :ref:`signed-byte`. See `WahEffect` for value mapping.
-    fn read_wah_effect(&self, data: &[u8], seek: &mut usize, flags: i8) -> WahEffect {WahEffect{value: read_signed_byte(data, seek), display: (flags & -0x80) == -0x80 /*(flags & 0x80) == 0x80*/}}
+    fn read_wah_effect(&self, data: &mut Input, flags: i8) -> WahEffect {WahEffect{value: read_signed_byte(data), display: (flags & -0x80) == -0x80 /*(flags & 0x80) == 0x80*/}}
 
     pub(crate) fn write_mix_table_change(&self, data: &mut Vec<u8>, mix_table_change: &Option<MixTableChange>, version: &(u8,u8,u8)) {
         if let Some(mtc) = mix_table_change {
@@ -232,38 +236,28 @@ impl Song {
         if let Some(i) = &mix_table_change.tremolo {write_signed_byte(data, i.value.to_i8().unwrap());}
         else {write_signed_byte(data, -1);}
         //tempo
-        if let Some(i) = &mix_table_change.tempo {write_signed_byte(data, i.value.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
-        if version.0 >= 5 {
-            write_int_byte_size_string(data, &mix_table_change.tempo_name);
-            if let Some(t) = &mix_table_change.tempo {write_i32(data, t.value.to_i32().unwrap());}
-            else {write_i32(data, -1);}
-        }
+        if version.0 >= 5 {write_int_byte_size_string(data, &mix_table_change.tempo_name, self.encoding);}
+        if let Some(t) = &mix_table_change.tempo {write_i32(data, t.value.to_i32().unwrap());}
+        else {write_i32(data, -1);}
     }
     fn write_mix_table_change_durations(&self, data: &mut Vec<u8>, mix_table_change: &MixTableChange, version: &(u8,u8,u8)) {
         //volume
         if let Some(i) = &mix_table_change.volume {write_signed_byte(data, i.duration.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
         //balance
         if let Some(i) = &mix_table_change.balance {write_signed_byte(data, i.duration.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
         //chorus
         if let Some(i) = &mix_table_change.chorus {write_signed_byte(data, i.duration.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
         //reverb
         if let Some(i) = &mix_table_change.reverb {write_signed_byte(data, i.duration.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
         //phaser
         if let Some(i) = &mix_table_change.phaser {write_signed_byte(data, i.duration.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
         //tremolo
         if let Some(i) = &mix_table_change.tremolo {write_signed_byte(data, i.duration.to_i8().unwrap());}
-        else {write_signed_byte(data, -1);}
         //tempo
         if let Some(i) = &mix_table_change.tempo {
             write_signed_byte(data, i.duration.to_i8().unwrap());
-            if version > &(5,0,0) {write_bool(data, mix_table_change.hide_tempo);}
-        } else {write_signed_byte(data, -1);}
+            if version.0 >= 5 {write_bool(data, mix_table_change.hide_tempo);}
+        }
     }
     fn write_mix_table_change_flags_v4(&self, data: &mut Vec<u8>, mix_table_change: &MixTableChange) {
         let mut flags = 0i8;
@@ -287,4 +281,15 @@ impl Song {
         if let Some(w) = &mix_table_change.wah {if w.display {flags |= 0x80;}}
         write_byte(data, flags);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use crate::mix_table::*;
+    #[test]
+    fn test_wah_effect_state() {
+        let wah = |value: i8| WahEffect {value, display: false};
+        assert!(wah(0)._is_on() && wah(100)._is_on());
+        assert!(!wah(WAH_EFFECT_OFF)._is_on() && !wah(WAH_EFFECT_NONE)._is_on());
+        assert!(wah(WAH_EFFECT_OFF)._is_off() && wah(WAH_EFFECT_NONE)._is_none());
+    }
+}
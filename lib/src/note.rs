@@ -3,6 +3,7 @@ use fraction::ToPrimitive;
 use crate::{effects::*, enums::*, io::*, gp::*, beat::*, key_signature::*};
 
 #[derive(Debug,Clone, PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Note {
     pub value: i16,
     pub velocity: i16,
@@ -11,7 +12,7 @@ pub struct Note {
     pub duration_percent: f32,
     pub swap_accidentals: bool,
     pub kind: NoteType,
-    duration: Option<i8>,
+    pub(crate) duration: Option<i8>,
     tuplet: Option<i8,>
 }
 impl Default for Note {fn default() -> Self {Note {
@@ -25,14 +26,19 @@ impl Default for Note {fn default() -> Self {Note {
     duration: None, tuplet: None,
 }}}
 impl Note {
+    /// Get the MIDI pitch of the fretted note, without capo nor harmonic (see `Track::midi_pitch()`). Notes that are not bound to a
+    /// string (e.g. imported from MIDI) carry their MIDI pitch in `value`.
     pub(crate) fn real_value(&self, strings: &[(i8,i8)]) -> i8 {
-        if self.string > 0 {return self.value.to_i8().unwrap() + strings[self.string.to_usize().unwrap() -1].1;}
-        panic!("Cannot get real value for the note.");
+        match self.string.to_usize().filter(|s| *s > 0).and_then(|s| strings.get(s - 1)) {
+            Some(s) => self.value.to_i8().unwrap() + s.1,
+            None => self.value.to_i8().unwrap(),
+        }
     }
 }
 
 /// Contains all effects which can be applied to one note.
 #[derive(Debug,Clone, PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NoteEffect {
     pub accentuated_note: bool,
     pub bend: Option<BendEffect>,
@@ -106,14 +112,14 @@ impl Song {
     /// - *0x20*: 2th string
     /// - *0x40*: 1th string
     /// - *0x80*: *blank*
-    pub(crate) fn read_notes(&mut self, data: &[u8], seek: &mut usize, track_index: usize, beat: &mut Beat, duration: &Duration, note_effect: NoteEffect) {
-        let flags = read_byte(data, seek);
+    pub(crate) fn read_notes(&mut self, data: &mut Input, track_index: usize, beat: &mut Beat, duration: &Duration, note_effect: NoteEffect) {
+        let flags = read_byte(data);
         //println!("read_notes(), flags: {}", flags);
         for i in 0..self.tracks[track_index].strings.len() {
             if (flags & 1 << (7 - self.tracks[track_index].strings[i].0)) > 0 {
                 let mut note = Note{effect: note_effect.clone(), ..Default::default()};
-                if self.version.number < (5,0,0) {self.read_note(data, seek, &mut note, self.tracks[track_index].strings[i], track_index);}
-                else {self.read_note_v5(data, seek, &mut note, self.tracks[track_index].strings[i], track_index);}
+                if self.version.number < (5,0,0) {self.read_note(data, &mut note, self.tracks[track_index].strings[i], track_index);}
+                else {self.read_note_v5(data, &mut note, self.tracks[track_index].strings[i], track_index);}
                 beat.notes.push(note);
             }
             beat.duration = duration.clone();
@@ -137,36 +143,38 @@ impl Song {
     /// - Fret number: `signed-byte`. If flag at *0x20* is set then read fret number.
     /// - Fingering: 2 `SignedBytes <signed-byte>`. See `Fingering`.
     /// - Note effects. See `read_note_effects()`.
-    fn read_note(&mut self, data: &[u8], seek: &mut usize, note: &mut Note, guitar_string: (i8,i8), track_index: usize) {
-        let flags = read_byte(data, seek);
+    fn read_note(&mut self, data: &mut Input, note: &mut Note, guitar_string: (i8,i8), track_index: usize) {
+        let flags = read_byte(data);
         note.string = guitar_string.0;
+        note.effect.heavy_accentuated_note = (flags & 0x02) == 0x02;
         note.effect.ghost_note = (flags & 0x04) == 0x04;
+        note.effect.accentuated_note = self.version.number.0 > 3 && (flags & 0x40) == 0x40;
         //println!("read_note(), flags: {} \t string: {} \t ghost note: {}", flags, guitar_string.0, note.effect.ghost_note);
-        if (flags & 0x20) == 0x20 {note.kind = get_note_type(read_byte(data, seek)); }
+        if (flags & 0x20) == 0x20 {note.kind = get_note_type(read_byte(data)); }
         if (flags & 0x01) == 0x01 {
             //println!("read_note(), duration: {} \t tuplet: {}",duration, tuplet);
-            note.duration = Some(read_signed_byte(data, seek));
-            note.tuplet = Some(read_signed_byte(data, seek));
+            note.duration = Some(read_signed_byte(data));
+            note.tuplet = Some(read_signed_byte(data));
         }
         if (flags & 0x10) == 0x10 {
-            let v = read_signed_byte(data, seek);
+            let v = read_signed_byte(data);
             //println!("read_note(), v: {}", v);
             note.velocity = crate::effects::unpack_velocity(v.to_i16().unwrap());
             //println!("read_note(), velocity: {}", note.velocity);
         }
         if (flags & 0x20) == 0x20 {
-            let fret = read_signed_byte(data, seek);
+            let fret = read_signed_byte(data);
             let value = if note.kind == NoteType::Tie { self.get_tied_note_value(guitar_string.0, track_index)} else {fret.to_i16().unwrap()};
             note.value = value.clamp(0, 99);
             //println!("read_note(), value: {}", note.value);
         }
         if (flags & 0x80) == 0x80 {
-            note.effect.left_hand_finger = get_fingering(read_signed_byte(data, seek));
-            note.effect.right_hand_finger= get_fingering(read_signed_byte(data, seek));
+            note.effect.left_hand_finger = get_fingering(read_signed_byte(data));
+            note.effect.right_hand_finger= get_fingering(read_signed_byte(data));
         }
         if (flags & 0x08) == 0x08 {
-            if      self.version.number == (3,0,0) {self.read_note_effects_v3(data, seek, note);}
-            else if self.version.number.0 == 4 {self.read_note_effects_v4(data, seek, note);}
+            if      self.version.number == (3,0,0) {self.read_note_effects_v3(data, note);}
+            else if self.version.number.0 == 4 {self.read_note_effects_v4(data, note);}
             if note.effect.is_harmonic() && note.effect.harmonic.is_some() {
                 let mut h = note.effect.harmonic.take().unwrap();
                 if h.kind == HarmonicType::Tapped {h.fret = Some(note.value.to_i8().unwrap() + 12);}
@@ -193,33 +201,33 @@ impl Song {
     /// - Second set of flags: `byte`.
     /// - *0x02*: swap accidentals.
     /// - Note effects. See `read_note_effects()`.
-    fn read_note_v5(&mut self, data: &[u8], seek: &mut usize, note: &mut Note, guitar_string: (i8,i8), track_index: usize) {
-        let flags = read_byte(data, seek);
+    fn read_note_v5(&mut self, data: &mut Input, note: &mut Note, guitar_string: (i8,i8), track_index: usize) {
+        let flags = read_byte(data);
         //println!("read_note_v5(), flags: {}", flags);
         note.string = guitar_string.0;
         note.effect.heavy_accentuated_note = (flags &0x02) == 0x02;
         note.effect.ghost_note = (flags &0x04) == 0x04;
         note.effect.accentuated_note = (flags &0x40) == 0x40;
-        if (flags &0x20) == 0x20 {note.kind = get_note_type(read_byte(data, seek));}
+        if (flags &0x20) == 0x20 {note.kind = get_note_type(read_byte(data));}
         if (flags &0x10) == 0x10 {
-            let v = read_signed_byte(data, seek);
+            let v = read_signed_byte(data);
             //println!("read_note(), v: {}", v);
             note.velocity = crate::effects::unpack_velocity(v.to_i16().unwrap());
             //println!("read_note(), velocity: {}", note.velocity);
         }
         if (flags &0x20) == 0x20 {
-            let fret = read_signed_byte(data, seek);
+            let fret = read_signed_byte(data);
             let value = if note.kind == NoteType::Tie { self.get_tied_note_value(guitar_string.0, track_index)} else {fret.to_i16().unwrap()};
             note.value = value.clamp(0, 99);
             //println!("read_note(), value: {}", note.value);
         }
         if (flags &0x80) == 0x80 {
-            note.effect.left_hand_finger = get_fingering(read_signed_byte(data, seek));
-            note.effect.right_hand_finger= get_fingering(read_signed_byte(data, seek));
+            note.effect.left_hand_finger = get_fingering(read_signed_byte(data));
+            note.effect.right_hand_finger= get_fingering(read_signed_byte(data));
         }
-        if (flags & 0x01) == 0x01 {note.duration_percent = read_double(data, seek).to_f32().unwrap();}
-        note.swap_accidentals = (read_byte(data, seek) & 0x02) == 0x02;
-        if (flags & 0x08) == 0x08 {self.read_note_effects_v4(data, seek, note);}
+        if (flags & 0x01) == 0x01 {note.duration_percent = read_double(data).to_f32().unwrap();}
+        note.swap_accidentals = (read_byte(data) & 0x02) == 0x02;
+        if (flags & 0x08) == 0x08 {self.read_note_effects_v4(data, note);}
     }
 
     /// Read note effects. First byte is note effects flags:
@@ -232,13 +240,13 @@ impl Song {
     /// Flags are followed by:
     /// - Bend. See `readBend`.
     /// - Grace note. See `readGrace`.
-    fn read_note_effects_v3(&self, data: &[u8], seek: &mut usize, note: &mut Note) {
-        let flags = read_byte(data, seek);
+    fn read_note_effects_v3(&self, data: &mut Input, note: &mut Note) {
+        let flags = read_byte(data);
         //println!("read_effect(), flags: {}", flags);
         note.effect.hammer = (flags & 0x02) == 0x02;
         note.effect.let_ring = (flags & 0x08) == 0x08;
-        if (flags & 0x01) == 0x01 {note.effect.bend = self.read_bend_effect(data, seek);}
-        if (flags & 0x10) == 0x10 {note.effect.grace = Some(self.read_grace_effect(data, seek));}
+        if (flags & 0x01) == 0x01 {note.effect.bend = self.read_bend_effect(data);}
+        if (flags & 0x10) == 0x10 {note.effect.grace = Some(self.read_grace_effect(data));}
         if (flags & 0x04) == 0x04 {note.effect.slides.push(SlideType::ShiftSlideTo);}
         //println!("read_note_effects(): {:?}", note);
     }
@@ -269,29 +277,29 @@ impl Song {
     /// - Slide. See `read_slides()`.
     /// - Harmonic. See `read_harmonic()`.
     /// - Trill. See `read_trill()`.
-    fn read_note_effects_v4(&mut self, data: &[u8], seek: &mut usize, note: &mut Note) {
-        let flags1 = read_signed_byte(data, seek);
-        let flags2 = read_signed_byte(data, seek);
+    fn read_note_effects_v4(&mut self, data: &mut Input, note: &mut Note) {
+        let flags1 = read_signed_byte(data);
+        let flags2 = read_signed_byte(data);
         note.effect.hammer = (flags1 & 0x02) == 0x02;
         note.effect.let_ring = (flags1 & 0x08) == 0x08;
         note.effect.staccato = (flags2 & 0x01) == 0x01;
         note.effect.palm_mute = (flags2 & 0x02) == 0x02;
         note.effect.vibrato = (flags2 & 0x40) == 0x40 || note.effect.vibrato;
-        if (flags1 & 0x01) == 0x01 {note.effect.bend = self.read_bend_effect(data, seek);}
+        if (flags1 & 0x01) == 0x01 {note.effect.bend = self.read_bend_effect(data);}
         if (flags1 & 0x10) == 0x10 {
-            if self.version.number >= (5,0,0) {note.effect.grace = Some(self.read_grace_effect_v5(data,seek));}
-            else                              {note.effect.grace = Some(self.read_grace_effect(data, seek));}
+            if self.version.number >= (5,0,0) {note.effect.grace = Some(self.read_grace_effect_v5(data));}
+            else                              {note.effect.grace = Some(self.read_grace_effect(data));}
         }
-        if (flags2 & 0x04) == 0x04 {note.effect.tremolo_picking = Some(self.read_tremolo_picking(data, seek));}
+        if (flags2 & 0x04) == 0x04 {note.effect.tremolo_picking = Some(self.read_tremolo_picking(data));}
         if (flags2 & 0x08) == 0x08 {
-            if self.version.number >= (5,0,0) {note.effect.slides.extend(self.read_slides_v5(data, seek));}
-            else                              {note.effect.slides.push(get_slide_type(read_signed_byte(data, seek)));}
+            if self.version.number >= (5,0,0) {note.effect.slides.extend(self.read_slides_v5(data));}
+            else                              {note.effect.slides.push(get_slide_type(read_signed_byte(data)));}
         }
         if (flags2 & 0x10) == 0x10 {
-            if self.version.number >= (5,0,0) {note.effect.harmonic = Some(self.read_harmonic_v5(data, seek));}
-            else                              {note.effect.harmonic = Some(self.read_harmonic(data, seek, note));}
+            if self.version.number >= (5,0,0) {note.effect.harmonic = Some(self.read_harmonic_v5(data));}
+            else                              {note.effect.harmonic = Some(self.read_harmonic(data, note));}
         }
-        if (flags2 & 0x20) == 0x20 {note.effect.trill = Some(self.read_trill(data, seek));}
+        if (flags2 & 0x20) == 0x20 {note.effect.trill = Some(self.read_trill(data));}
     }
 
     /// Get note value of tied note
@@ -381,10 +389,10 @@ impl Song {
     }
     fn pack_note_flags(&self, note: &Note, version: &(u8,u8,u8)) -> u8 {
         let mut flags: u8 = 0u8;
-        if note.duration.is_some() && note.tuplet.is_some() {flags |= 0x01;}
+        if version.0 < 5 && note.duration.is_some() && note.tuplet.is_some() {flags |= 0x01;}
         if note.effect.heavy_accentuated_note {flags |= 0x02;}
         if note.effect.ghost_note {flags |= 0x04;}
-        if note.effect.is_default() {flags |= 0x08;}
+        if !note.effect.is_default() {flags |= 0x08;}
         if note.velocity != DEFAULT_VELOCITY {flags |= 0x10;}
         flags |= 0x20;
         if version.0 > 3 {
@@ -415,12 +423,12 @@ impl Song {
 
         let mut flags2 = 0i8;
         if note.effect.staccato              {flags2 |= 0x01;}
-        if note.effect.palm_mute             {flags2 |= 0x01;}
-        if note.effect.is_tremollo_picking() {flags2 |= 0x01;}
-        if !note.effect.slides.is_empty()    {flags2 |= 0x01;}
-        if note.effect.is_harmonic()         {flags2 |= 0x01;}
-        if note.effect.is_trill()            {flags2 |= 0x01;}
-        if note.effect.vibrato               {flags2 |= 0x01;}
+        if note.effect.palm_mute             {flags2 |= 0x02;}
+        if note.effect.is_tremollo_picking() {flags2 |= 0x04;}
+        if !note.effect.slides.is_empty()    {flags2 |= 0x08;}
+        if note.effect.is_harmonic()         {flags2 |= 0x10;}
+        if note.effect.is_trill()            {flags2 |= 0x20;}
+        if note.effect.vibrato               {flags2 |= 0x40;}
         write_signed_byte(data, flags2);
 
         if (flags1 & 0x01) == 0x01 {self.write_bend(data, &note.effect.bend);}
@@ -4,6 +4,7 @@ use crate::{gp::*, io::*};
 
 ///A padding construct
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Padding {
     pub right: u16,
     pub top: u16,
@@ -13,6 +14,7 @@ pub struct Padding {
 
 /// A point construct using integer coordinates
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Point { pub x: u16, pub y: u16, }
 
 // An enumeration of the elements which can be shown in the header and footer of a rendered song sheet.
@@ -45,6 +47,7 @@ pub const HEADER_FOOTER_ALL: u16 = 0x1ff;
 /// - ``%N%``: will be replaced with the current page number (if supported by layout)
 /// - ``%P%``: will be replaced with the number of pages (if supported by layout)
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PageSetup {
     pub page_size: Point,
     pub page_margin: Padding,
@@ -84,27 +87,27 @@ impl Song {
     ///   * copyright1, e.g. *"Copyright %copyright%"*
     ///   * copyright2, e.g. *"All Rights Reserved - International Copyright Secured"*
     ///   * pageNumber
-    pub(crate) fn read_page_setup(&mut self, data: &[u8], seek: &mut usize) {
-        self.page_setup.page_size.x = read_int(data, seek).to_u16().unwrap();
-        self.page_setup.page_size.y = read_int(data, seek).to_u16().unwrap();
-        self.page_setup.page_margin.left   = read_int(data, seek).to_u16().unwrap();
-        self.page_setup.page_margin.right  = read_int(data, seek).to_u16().unwrap();
-        self.page_setup.page_margin.top    = read_int(data, seek).to_u16().unwrap();
-        self.page_setup.page_margin.bottom = read_int(data, seek).to_u16().unwrap();
-        self.page_setup.score_size_proportion = read_int(data, seek).to_f32().unwrap() / 100.0;
-        self.page_setup.header_and_footer = read_short(data, seek).to_u16().unwrap();
-        self.page_setup.title =          read_int_size_string(data, seek);
-        self.page_setup.subtitle =       read_int_size_string(data, seek);
-        self.page_setup.artist =         read_int_size_string(data, seek);
-        self.page_setup.album =          read_int_size_string(data, seek);
-        self.page_setup.words =          read_int_size_string(data, seek);
-        self.page_setup.music =          read_int_size_string(data, seek);
-        self.page_setup.word_and_music = read_int_size_string(data, seek);
-        let mut c = read_int_size_string(data, seek);
+    pub(crate) fn read_page_setup(&mut self, data: &mut Input) {
+        self.page_setup.page_size.x = read_int(data).to_u16().unwrap();
+        self.page_setup.page_size.y = read_int(data).to_u16().unwrap();
+        self.page_setup.page_margin.left   = read_int(data).to_u16().unwrap();
+        self.page_setup.page_margin.right  = read_int(data).to_u16().unwrap();
+        self.page_setup.page_margin.top    = read_int(data).to_u16().unwrap();
+        self.page_setup.page_margin.bottom = read_int(data).to_u16().unwrap();
+        self.page_setup.score_size_proportion = read_int(data).to_f32().unwrap() / 100.0;
+        self.page_setup.header_and_footer = read_short(data).to_u16().unwrap();
+        self.page_setup.title =          read_int_byte_size_string(data);
+        self.page_setup.subtitle =       read_int_byte_size_string(data);
+        self.page_setup.artist =         read_int_byte_size_string(data);
+        self.page_setup.album =          read_int_byte_size_string(data);
+        self.page_setup.words =          read_int_byte_size_string(data);
+        self.page_setup.music =          read_int_byte_size_string(data);
+        self.page_setup.word_and_music = read_int_byte_size_string(data);
+        let mut c = read_int_byte_size_string(data);
         c.push('\n');
-        c.push_str(&read_int_size_string(data, seek));
+        c.push_str(&read_int_byte_size_string(data));
         self.page_setup.copyright = c;
-        self.page_setup.page_number = read_int_size_string(data, seek);
+        self.page_setup.page_number = read_int_byte_size_string(data);
     }
 
     pub(crate) fn write_page_setup(&self, data: &mut Vec<u8>) {
@@ -115,23 +118,23 @@ impl Song {
         write_i32(data, self.page_setup.page_margin.right.to_i32().unwrap());
         write_i32(data, self.page_setup.page_margin.top.to_i32().unwrap());
         write_i32(data, self.page_setup.page_margin.bottom.to_i32().unwrap());
-        write_i32(data, (self.page_setup.score_size_proportion * 100f32).ceil().to_i32().unwrap());
+        write_i32(data, (self.page_setup.score_size_proportion * 100f32).round().to_i32().unwrap());
 
         write_byte(data, (self.page_setup.header_and_footer & 0xff).to_u8().unwrap());
 
         let mut flags2 = 0u8;
-        if self.page_setup.header_and_footer != 0 && (self.page_setup.header_and_footer & HEADER_FOOTER_PAGE_NUMBER) != 0 {flags2 |= 0x01;} //TODO: check
+        if (self.page_setup.header_and_footer & HEADER_FOOTER_PAGE_NUMBER) != 0 {flags2 |= 0x01;}
         write_byte(data, flags2);
-        write_int_byte_size_string(data, &self.page_setup.title);
-        write_int_byte_size_string(data, &self.page_setup.subtitle);
-        write_int_byte_size_string(data, &self.page_setup.artist);
-        write_int_byte_size_string(data, &self.page_setup.album);
-        write_int_byte_size_string(data, &self.page_setup.word_and_music);
-        write_int_byte_size_string(data, &self.page_setup.music);
-        write_int_byte_size_string(data, &self.page_setup.word_and_music);
-        let c: Vec<&str> = self.page_setup.copyright.split('\n').collect();
-        write_int_byte_size_string(data, c[0]);
-        write_int_byte_size_string(data, c[1]);
-        write_int_byte_size_string(data, &self.page_setup.page_number);
+        write_int_byte_size_string(data, &self.page_setup.title, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.subtitle, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.artist, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.album, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.words, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.music, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.word_and_music, self.encoding);
+        let c = self.page_setup.copyright.split_once('\n').unwrap_or((&self.page_setup.copyright, ""));
+        write_int_byte_size_string(data, c.0, self.encoding);
+        write_int_byte_size_string(data, c.1, self.encoding);
+        write_int_byte_size_string(data, &self.page_setup.page_number, self.encoding);
     }
 }
@@ -0,0 +1,127 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, beat::*, note::*, track::*, key_signature::*, enums::*};
+
+/// Letters of the natural notes and their pitch class
+const LETTERS: [(char, i16); 7] = [('C', 0), ('D', 2), ('E', 4), ('F', 5), ('G', 7), ('A', 9), ('B', 11)];
+/// Indexes in `LETTERS` of the notes altered by the key signatures, in the order of the sharps (the flats are in the reverse order)
+const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+/// A note spelled with a letter, an accidental and an octave
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pitch {
+    /// Sounding MIDI pitch (60 is the middle C)
+    pub midi: i16,
+    /// Written MIDI pitch: the sounding pitch moved by the ottava sign of the beat
+    pub written: i16,
+    /// Letter of the note, from `A` to `G`
+    pub letter: char,
+    /// Alteration of the letter in semitones: `1` for a sharp, `-1` for a flat, `2` and `-2` for double accidentals
+    pub accidental: i8,
+    /// Octave of the written note in scientific pitch notation: B♯3 and C4 are the same pitch
+    pub octave: i16,
+}
+impl Pitch {
+    /// Spell a written MIDI pitch in a key signature (see `KeySignature::key`):
+    /// * the notes of the key get the letter and the accidental of the key, e.g. `Bb` in F major,
+    /// * the other notes are written as naturals when possible, otherwise raised in sharp keys and C major and lowered in flat keys.
+    ///
+    /// The sounding pitch is the written one.
+    pub fn spell(written: i16, key: &KeySignature) -> Pitch {
+        let pc = written.rem_euclid(12);
+        let mut alterations = [0i16; 7];
+        let count = key.key.unsigned_abs().min(7).to_usize().unwrap();
+        if key.key > 0 {for i in &SHARP_ORDER[..count] {alterations[*i] = 1;}}
+        else {for i in SHARP_ORDER.iter().rev().take(count) {alterations[*i] = -1;}}
+        let letter = (0..7).find(|l| (LETTERS[*l].1 + alterations[*l]).rem_euclid(12) == pc)
+            .or_else(|| (0..7).find(|l| LETTERS[*l].1 == pc))
+            .unwrap_or_else(|| if key.key < 0 {LETTERS.iter().position(|l| l.1 == (pc + 1) % 12).unwrap()} else {LETTERS.iter().position(|l| l.1 == (pc + 11) % 12).unwrap()});
+        Pitch::with_letter(written, written, letter)
+    }
+
+    /// Build the pitch written with the letter of index `letter` in `LETTERS`.
+    fn with_letter(midi: i16, written: i16, letter: usize) -> Pitch {
+        let accidental = ((written - LETTERS[letter].1 + 6).rem_euclid(12) - 6).to_i8().unwrap();
+        Pitch {midi, written, letter: LETTERS[letter].0, accidental, octave: (written - i16::from(accidental)).div_euclid(12) - 1}
+    }
+
+    /// Get the enharmonic spelling of the note with the other accidental: a sharp becomes the flat of the letter above (`F#` becomes
+    /// `Gb`), a flat becomes the sharp of the letter below. Natural notes are unchanged.
+    pub fn swapped(&self) -> Pitch {
+        let index = LETTERS.iter().position(|l| l.0 == self.letter).unwrap();
+        match self.accidental.signum() {
+            1 => Pitch::with_letter(self.midi, self.written, (index + 1) % 7),
+            -1 => Pitch::with_letter(self.midi, self.written, (index + 6) % 7),
+            _ => self.clone(),
+        }
+    }
+
+    /// Get the name of the note without octave, e.g. `F#` or `Bb`
+    pub fn pitch_class_name(&self) -> String {
+        let accidental = match self.accidental {2 => "##", 1 => "#", -1 => "b", -2 => "bb", _ => ""};
+        format!("{}{}", self.letter, accidental)
+    }
+
+    /// Get the name of the note in scientific pitch notation, e.g. `F#3`
+    pub fn name(&self) -> String { format!("{}{}", self.pitch_class_name(), self.octave) }
+}
+impl std::fmt::Display for Pitch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "{}", self.name()) }
+}
+
+impl Track {
+    /// Get the sounding MIDI pitch of a note played on this track: capo included, and the harmonics sound at their harmonic pitch.
+    /// Returns `None` for rests and for the notes of percussion tracks.
+    pub fn midi_pitch(&self, note: &Note) -> Option<i16> {
+        if self.percussion_track || note.kind == NoteType::Rest {return None;}
+        let fretted = self.fingering_pitch(note)?;
+        let h = match &note.effect.harmonic {Some(h) => h, None => return Some(fretted)};
+        match h.kind {
+            //the string is touched at the fret of the note, relative to the capo
+            HarmonicType::Natural => Some(fretted - note.value + natural_harmonic_interval(note.value)),
+            HarmonicType::Artificial => {
+                let shift = match h.octave {Some(Octave::Quindicesima) => 24, _ => 12};
+                //the pitch class is stored without the capo
+                let interval = h.pitch.as_ref().map(|p| (i16::from(p.value) - (fretted - self.offset.to_i16().unwrap())).rem_euclid(12)).unwrap_or(0);
+                Some(fretted + shift + interval)
+            },
+            HarmonicType::Tapped => Some(fretted + h.fret.map(|f| natural_harmonic_interval(i16::from(f) - note.value)).unwrap_or(0)),
+            HarmonicType::Pinch | HarmonicType::Semi => Some(fretted),
+        }
+    }
+
+    /// Get the pitch of a note of a beat of this track, spelled in the key signature of the measure and following
+    /// `Note::swap_accidentals`. Returns `None` for rests and for the notes of percussion tracks.
+    pub fn pitch(&self, beat: &Beat, note: &Note, key: &KeySignature) -> Option<Pitch> {
+        let midi = self.midi_pitch(note)?;
+        let written = midi - match beat.octave {Octave::Ottava => 12, Octave::Quindicesima => 24, Octave::OttavaBassa => -12, Octave::QuindicesimaBassa => -24, Octave::None => 0};
+        let mut pitch = Pitch::spell(written, key);
+        pitch.midi = midi;
+        if note.swap_accidentals {pitch = pitch.swapped();}
+        Some(pitch)
+    }
+}
+
+impl Song {
+    /// Get the pitch of a note spelled in the key signature of its measure (see `Track::pitch()`).
+    /// Returns `None` if the note does not exist, is a rest or belongs to a percussion track.
+    pub fn note_pitch(&self, track: usize, measure: usize, voice: usize, beat: usize, note: usize) -> Option<Pitch> {
+        let t = self.tracks.get(track)?;
+        let b = t.measures.get(measure)?.voices.get(voice)?.beats.get(beat)?;
+        let key = self.measure_headers.get(measure).map(|h| h.key_signature.clone()).unwrap_or_default();
+        t.pitch(b, b.notes.get(note)?, &key)
+    }
+}
+
+/// Interval in semitones between the open string and the natural harmonic touched at a fret
+fn natural_harmonic_interval(fret: i16) -> i16 {
+    match fret {
+        12 => 12,
+        7 | 19 => 19,
+        5 | 24 => 24,
+        4 | 9 | 16 => 28,
+        3 => 31,
+        _ => 12,
+    }
+}
@@ -0,0 +1,300 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, chord::*, chord_detection::*, key_signature::*};
+
+const MAJOR_NUMERALS: [&str; 12] = ["I", "bII", "II", "bIII", "III", "IV", "bV", "V", "bVI", "VI", "bVII", "VII"];
+const MINOR_NUMERALS: [&str; 12] = ["I", "bII", "II", "III", "#III", "IV", "bV", "V", "VI", "#VI", "VII", "#VII"];
+/// Number of measures written on each line of the ChordPro export
+const CHORDPRO_MEASURES_PER_LINE: usize = 4;
+
+/// A chord of a progression
+#[derive(Debug,Clone,PartialEq)]
+pub struct ProgressionChord {
+    /// Index of the measure
+    pub measure: usize,
+    /// Start in ticks
+    pub start: i64,
+    /// Length in ticks, until the next chord or the end of the song
+    pub length: i64,
+    /// Position in the measure, in beats of the time signature starting at 1
+    pub beat: f64,
+    pub name: String,
+    /// Roman numeral relative to the key of the song, empty if the name cannot be parsed
+    pub numeral: String,
+    /// `true` if the chord is written in the file, `false` if it was detected from the notes
+    pub explicit: bool,
+}
+
+/// Split a chord name in root, suffix and bass, e.g. *F#m7/E* gives `(6, "m7", Some(4))`.
+pub(crate) fn parse_chord_name(name: &str) -> Option<(i8, String, Option<i8>)> {
+    let pitch = |s: &str| -> Option<(i8, usize)> {
+        let mut chars = s.chars();
+        let mut value = match chars.next()? {'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11, _ => return None};
+        let mut length = 1;
+        match chars.next() {
+            Some('#') | Some('♯') => {value += 1; length += 1;},
+            Some('b') | Some('♭') => {value += 11; length += 1;},
+            _ => {},
+        }
+        Some((value % 12, s.char_indices().nth(length).map(|c| c.0).unwrap_or(s.len())))
+    };
+    let name = name.trim();
+    let (root, end) = pitch(name)?;
+    let rest = &name[end..];
+    let (suffix, bass) = match rest.rfind('/') {
+        Some(i) => match pitch(&rest[i + 1..]) {
+            Some((b, _)) => (&rest[..i], Some(b)),
+            None => (rest, None),
+        },
+        None => (rest, None),
+    };
+    Some((root, suffix.to_string(), bass))
+}
+
+/// Get the tonic of a key signature (0 is C).
+pub(crate) fn key_tonic(key: &KeySignature) -> i8 {
+    let major = (key.key * 7).rem_euclid(12);
+    if key.is_minor {(major + 9) % 12} else {major}
+}
+
+/// Convert a chord name to a Roman numeral relative to a key: minor and diminished chords are written in lower case,
+/// diminished chords with *°*, half-diminished chords with *ø* and augmented chords with *+*. The bass of inverted chords is written as a degree.
+/// Returns `None` if the name cannot be parsed.
+pub fn roman_numeral(name: &str, key: &KeySignature) -> Option<String> {
+    let (root, suffix, bass) = parse_chord_name(name)?;
+    let numerals = if key.is_minor {&MINOR_NUMERALS} else {&MAJOR_NUMERALS};
+    let tonic = key_tonic(key);
+    let degree = |p: i8| numerals[(p - tonic).rem_euclid(12).to_usize().unwrap()];
+    let mut numeral = String::from(degree(root));
+    let mut suffix = suffix;
+    if suffix.starts_with("m7b5") {
+        numeral = numeral.to_lowercase();
+        suffix = suffix.replacen("m7b5", "ø7", 1);
+    } else if suffix.starts_with("dim") {
+        numeral = numeral.to_lowercase();
+        suffix = suffix.replacen("dim", "°", 1);
+    } else if suffix.starts_with("aug") {
+        suffix = suffix.replacen("aug", "+", 1);
+    } else if suffix.starts_with('m') && !suffix.starts_with("maj") {
+        numeral = numeral.to_lowercase();
+        suffix.remove(0);
+    }
+    numeral.push_str(&suffix);
+    if let Some(b) = bass {
+        numeral.push('/');
+        numeral.push_str(degree(b));
+    }
+    Some(numeral)
+}
+
+/// Escape a string for JSON, the quotes included.
+pub fn json_string(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            c if (c as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", c as u32)),
+            c => r.push(c),
+        }
+    }
+    r.push('"');
+    r
+}
+
+impl Song {
+    /// Get the chord progression of the song. The chords written in the given tracks are used; measures without written chord get the
+    /// chords detected from the notes of these tracks (see `Song::chord_progression()`). Repeated chords are merged
+    /// within a measure, and every measure starts with the chord sounding at its start.
+    /// - `tracks`: indexes of the tracks to use. If empty, all the tracks which are not percussion tracks are used.
+    pub fn progression(&self, tracks: &[usize]) -> Vec<ProgressionChord> {
+        let tracks: Vec<usize> = if tracks.is_empty() {(0..self.tracks.len()).filter(|t| !self.tracks[*t].percussion_track).collect()} else {tracks.to_vec()};
+        let mut chords: Vec<(usize, i64, String, bool)> = Vec::new();
+        let detected = self.chord_progression(&tracks);
+        for m in 0..self.measure_headers.len() {
+            let mut explicit: Vec<(usize, i64, String, bool)> = tracks.iter().filter(|t| m < self.tracks[**t].measures.len())
+                .flat_map(|t| self.tracks[*t].measures[m].voices.iter().flat_map(|v| v.beats.iter()))
+                .filter_map(|b| b.effect.chord.as_ref().zip(b.start).map(|(c, s)| (m, s, chord_name(c), true)))
+                .filter(|c| !c.2.is_empty()).collect();
+            explicit.sort_by_key(|c| c.1);
+            explicit.dedup_by_key(|c| c.1);
+            if explicit.is_empty() {explicit.extend(detected.iter().filter(|c| c.measure == m).map(|c| (m, c.start, c.chord.name.clone(), false)));}
+            //a chord held over a barline is repeated at the start of the measure
+            let start = self.measure_headers[m].start;
+            if explicit.first().map(|c| c.1 > start).unwrap_or(true) {
+                if let Some(held) = chords.last().map(|c| (m, start, c.2.clone(), c.3)) {explicit.insert(0, held);}
+            }
+            chords.extend(explicit);
+        }
+        chords.dedup_by(|b, a| a.0 == b.0 && a.2 == b.2);
+        let end = self.measure_headers.last().map(|h| h.start + h.length()).unwrap_or(0);
+        let mut progression: Vec<ProgressionChord> = Vec::with_capacity(chords.len());
+        for (i, c) in chords.iter().enumerate() {
+            let header = &self.measure_headers[c.0];
+            let next = chords.get(i + 1).map(|n| n.1).unwrap_or(end);
+            progression.push(ProgressionChord {measure: c.0, start: c.1, length: next - c.1,
+                                               beat: 1.0 + (c.1 - header.start).to_f64().unwrap() / f64::from(header.time_signature.denominator.time()),
+                                               name: c.2.clone(), numeral: roman_numeral(&c.2, &self.key).unwrap_or_default(), explicit: c.3});
+        }
+        progression
+    }
+
+    /// Export a progression as JSON. The chords are grouped by measure:
+    /// `{"title": ..., "key": ..., "measures": [{"measure": 1, "chords": [{"name": "C", "numeral": "I", "start": 960, "beat": 1.0, "length": 3840, "explicit": true}]}]}`
+    pub fn progression_json(&self, progression: &[ProgressionChord]) -> String {
+        let mut s = format!("{{\"title\":{},\"key\":{},\"measures\":[", json_string(&self.name), json_string(&self.key.to_string()));
+        let mut measure: Option<usize> = None;
+        for c in progression {
+            if measure != Some(c.measure) {
+                if measure.is_some() {s.push_str("]},");}
+                s.push_str(&format!("{{\"measure\":{},\"chords\":[", c.measure + 1));
+                measure = Some(c.measure);
+            } else {s.push(',');}
+            s.push_str(&format!("{{\"name\":{},\"numeral\":{},\"start\":{},\"beat\":{:?},\"length\":{},\"explicit\":{}}}",
+                                json_string(&c.name), json_string(&c.numeral), c.start, c.beat, c.length, c.explicit));
+        }
+        if measure.is_some() {s.push_str("]}");}
+        s.push_str("]}");
+        s
+    }
+
+    /// Export a progression as ChordPro text. Chords are written inline between brackets before the syllable sung on the same beat,
+    /// using the first lyric line with text. Markers start a new section with a `{comment: ...}` directive, and a new line is started
+    /// every 4 measures.
+    pub fn progression_chordpro(&self, progression: &[ProgressionChord]) -> String {
+        let mut s = String::new();
+        if !self.name.is_empty() {s.push_str(&format!("{{title: {}}}\n", self.name));}
+        if !self.artist.is_empty() {s.push_str(&format!("{{artist: {}}}\n", self.artist));}
+        let tonic = key_tonic(&self.key);
+        s.push_str(&format!("{{key: {}{}}}\n", if self.key.key < 0 {FLAT_NOTES[tonic as usize]} else {SHARP_NOTES[tonic as usize]}, if self.key.is_minor {"m"} else {""}));
+        let syllables = self.lyrics.lines.iter().position(|l| !l.2.trim().is_empty()).map(|l| self.lyric_syllables(l)).unwrap_or_default();
+        let mut line = String::new();
+        let mut count = 0;
+        for (m, header) in self.measure_headers.iter().enumerate() {
+            if let Some(marker) = &header.marker {
+                if !line.trim().is_empty() {s.push_str(line.trim_end()); s.push('\n');}
+                line.clear();
+                count = 0;
+                s.push_str(&format!("\n{{comment: {}}}\n", marker.title));
+            }
+            //chords and syllables of the measure, sorted by time
+            let mut events: Vec<(i64, u8, String)> = progression.iter().filter(|c| c.measure == m).map(|c| (c.start, 0, format!("[{}]", c.name))).collect();
+            events.extend(syllables.iter().filter(|l| l.measure == m).map(|l| (l.start, 1, l.text.clone())));
+            events.sort_by_key(|e| (e.0, e.1));
+            for e in events {
+                //a hyphen ends a syllable in the middle of a word
+                if line.ends_with('-') {line.pop();}
+                else if e.1 == 0 && !line.is_empty() && !line.ends_with(' ') {line.push(' ');}
+                line.push_str(&e.2);
+                if e.1 == 1 && !e.2.ends_with('-') {line.push(' ');}
+            }
+            count += 1;
+            if count == CHORDPRO_MEASURES_PER_LINE {
+                if !line.trim().is_empty() {s.push_str(line.trim_end()); s.push('\n');}
+                line.clear();
+                count = 0;
+            }
+        }
+        if !line.trim().is_empty() {s.push_str(line.trim_end()); s.push('\n');}
+        s
+    }
+}
+
+/// Get the name of a chord annotation, built from its root and type when the name is empty.
+fn chord_name(chord: &Chord) -> String {
+    if !chord.name.trim().is_empty() {return chord.name.trim().to_string();}
+    match &chord.root {
+        Some(r) => format!("{}{}", r, chord.kind.as_ref().map(chord_type_suffix).unwrap_or("")),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{progression::*, headers::*, track::*, measure::*, beat::*, note::*, lyric::*, enums::*};
+
+    #[test]
+    fn test_roman_numerals() {
+        let c = KeySignature::default();
+        assert_eq!(roman_numeral("C", &c).unwrap(), "I");
+        assert_eq!(roman_numeral("Am7", &c).unwrap(), "vi7");
+        assert_eq!(roman_numeral("G7/B", &c).unwrap(), "V7/VII");
+        assert_eq!(roman_numeral("Bdim", &c).unwrap(), "vii°");
+        assert_eq!(roman_numeral("Bb", &c).unwrap(), "bVII");
+        let e_minor = KeySignature {key: 1, is_minor: true};
+        assert_eq!(roman_numeral("Em", &e_minor).unwrap(), "i");
+        assert_eq!(roman_numeral("G", &e_minor).unwrap(), "III");
+        assert_eq!(roman_numeral("F#m7b5", &e_minor).unwrap(), "iiø7");
+        assert!(roman_numeral("N.C.", &c).is_none());
+    }
+
+    #[test]
+    fn test_held_chords() {
+        use crate::builder::*;
+        let whole = Duration {value: 1, ..Default::default()};
+        let c_major = [(5, 3), (4, 2), (3, 0), (2, 1), (1, 0)];
+        let song = SongBuilder::new().title("Held")
+            .track(TrackBuilder::new("Guitar").measure(MeasureBuilder::new().notes(&c_major, whole.clone()))
+                .measure(MeasureBuilder::new().notes(&c_major, whole.clone())).measure(MeasureBuilder::new().notes(&c_major, whole)))
+            .build().unwrap();
+        let p = song.progression(&[]);
+        assert_eq!(p.iter().map(|c| (c.measure, c.name.as_str(), c.start, c.beat, c.length)).collect::<Vec<_>>(),
+                   vec![(0, "C", 960, 1.0, 3840), (1, "C", 4800, 1.0, 3840), (2, "C", 8640, 1.0, 3840)]);
+        assert_eq!(song.progression_chordpro(&p).matches("[C]").count(), 3);
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("Rock \"n\" Roll\\\n\u{1}é"), "\"Rock \\\"n\\\" Roll\\\\\\n\\u0001é\"");
+    }
+
+    #[test]
+    fn test_syllables() {
+        assert_eq!(Lyrics::syllables("Hel-lo [hidden] world\nit's+me"), vec!["Hel-", "lo", "world", "it's me"]);
+    }
+
+    /// Two measures of whole notes: C major then a written G chord
+    fn song() -> Song {
+        let mut song = Song {name: String::from("Test"), ..Default::default()};
+        let mut track = Track::default();
+        for (i, frets) in [[0, 1, 0, 2, 3, -1], [3, 0, 0, 0, 2, 3]].iter().enumerate() {
+            song.measure_headers.push(MeasureHeader {number: (i + 1) as u16, start: 960 + 3840 * i as i64, ..Default::default()});
+            let mut beat = Beat {start: Some(960 + 3840 * i as i64), duration: Duration {value: 1, ..Default::default()}, ..Default::default()};
+            for (s, f) in frets.iter().enumerate().filter(|f| *f.1 >= 0) {
+                let mut note = Note::default();
+                note.string = (s + 1) as i8;
+                note.value = *f;
+                note.kind = NoteType::Normal;
+                beat.notes.push(note);
+            }
+            if i == 1 {beat.effect.chord = Some(Chord {name: String::from("G"), ..Default::default()});}
+            track.measures.push(Measure {voices: vec![Voice {beats: vec![beat], ..Default::default()}], ..Default::default()});
+        }
+        song.measure_headers[1].marker = Some(Marker {title: String::from("Chorus"), ..Default::default()});
+        song.tracks.push(track);
+        song.lyrics = Lyrics {track_choice: 1, lines: vec![(0, 1, String::from("Hel-lo you")), (1, 1, String::new())]};
+        song
+    }
+
+    #[test]
+    fn test_progression() {
+        let song = song();
+        let p = song.progression(&[]);
+        assert_eq!(p.len(), 2);
+        assert_eq!((p[0].name.as_str(), p[0].numeral.as_str(), p[0].explicit, p[0].length), ("C", "I", false, 3840));
+        assert_eq!((p[1].name.as_str(), p[1].numeral.as_str(), p[1].explicit, p[1].beat), ("G", "V", true, 1.0));
+        assert_eq!(song.progression_json(&p), "{\"title\":\"Test\",\"key\":\"C major\",\"measures\":[\
+            {\"measure\":1,\"chords\":[{\"name\":\"C\",\"numeral\":\"I\",\"start\":960,\"beat\":1.0,\"length\":3840,\"explicit\":false}]},\
+            {\"measure\":2,\"chords\":[{\"name\":\"G\",\"numeral\":\"V\",\"start\":4800,\"beat\":1.0,\"length\":3840,\"explicit\":true}]}]}");
+        assert_eq!(song.progression_chordpro(&p), "{title: Test}\n{key: C}\n[C]Hel-\n\n{comment: Chorus}\n[G]lo\n");
+        //the same chord in the next measure is kept
+        let mut song = song;
+        song.tracks[0].measures[1].voices[0].beats[0].effect.chord = Some(Chord {name: String::from("C"), ..Default::default()});
+        let p = song.progression(&[]);
+        assert_eq!(p.iter().map(|c| (c.measure, c.name.as_str(), c.length)).collect::<Vec<_>>(), vec![(0, "C", 3840), (1, "C", 3840)]);
+    }
+}
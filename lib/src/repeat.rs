@@ -0,0 +1,167 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, beat::*};
+
+/// Maximum number of measures played when repeats are expanded, relative to the number of measures, to stop on inconsistent repeats
+const MAX_PLAYBACK_FACTOR: usize = 256;
+/// Maximum number of times a part can be played with a repeat sign
+const MAX_REPEAT_COUNT: usize = 128;
+
+/// Measures played several times in a row, that can be written with repeat signs
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RepeatedGroup {
+    /// Index of the first measure
+    pub start: usize,
+    /// Number of measures of the repeated part
+    pub length: usize,
+    /// Number of times the part is played
+    pub count: usize,
+    /// Number of measures of the two alternative endings following each repetition of the part, `0` without alternative endings
+    pub ending: usize,
+}
+impl RepeatedGroup {
+    /// Number of measures written when the repeats are expanded
+    pub fn expanded_length(&self) -> usize { if self.ending > 0 {2 * (self.length + self.ending)} else {self.length * self.count} }
+    /// Number of measures saved by writing the group with repeat signs
+    pub fn saved_measures(&self) -> usize { if self.ending > 0 {self.length} else {self.length * (self.count - 1)} }
+}
+
+impl Song {
+    /// Check if 2 measures are the same in all the tracks: same beats (their start excepted), time signature, key signature, triplet
+    /// feel and tempo. The measures must not have repeat signs, and the second one must not have a marker or a direction so that it
+    /// can be replaced by a repeat of the first one.
+    pub fn same_measures(&self, first: usize, second: usize) -> bool {
+        let (a, b) = (&self.measure_headers[first], &self.measure_headers[second]);
+        if a.time_signature != b.time_signature || a.key_signature != b.key_signature || a.triplet_feel != b.triplet_feel || a.tempo != b.tempo {return false;}
+        if b.marker.is_some() || b.direction.is_some() {return false;}
+        if [a, b].iter().any(|h| h.repeat_open || h.repeat_close > -1 || h.repeat_alternative > 0) {return false;}
+        self.tracks.iter().all(|t| {
+            let (a, b) = (&t.measures[first], &t.measures[second]);
+            a.voices.len() == b.voices.len() && a.voices.iter().zip(&b.voices).all(|(x, y)| x.beats.len() == y.beats.len() && x.beats.iter().zip(&y.beats).all(|(p, q)| same_beats(p, q)))
+        })
+    }
+
+    /// Find the measures played several times in a row in all the tracks. At each measure, the repetition saving the most measures is
+    /// chosen (the shortest one if several save as many measures), then the search goes on after it:
+    /// * a part repeated several times, e.g. `A A A` (written `|: A :|` played 3 times),
+    /// * a part repeated with 2 alternative endings of the same length, e.g. `A B A C` (written `|: A [1. B :| [2. C`).
+    ///
+    /// Measures already having repeat signs are not part of the groups.
+    /// - `max_length`: maximum number of measures of the repeated part and of the endings
+    pub fn find_repeated_groups(&self, max_length: usize) -> Vec<RepeatedGroup> {
+        let count = self.measure_headers.len();
+        let same = |a: usize, b: usize, length: usize| -> bool { (0..length).all(|i| self.same_measures(a + i, b + i)) };
+        let mut groups = Vec::new();
+        let mut i = 0;
+        while i < count {
+            let mut best: Option<RepeatedGroup> = None;
+            for length in 1..=max_length.min(count - i) {
+                let mut repeats = 1;
+                while repeats < MAX_REPEAT_COUNT && i + (repeats + 1) * length <= count && same(i, i + repeats * length, length) {repeats += 1;}
+                let mut candidates = vec![RepeatedGroup {start: i, length, count: repeats, ending: 0}];
+                for ending in 1..=max_length {
+                    if i + 2 * (length + ending) > count || same(i + length, i + 2 * length + ending, ending) {continue;}
+                    if same(i, i + length + ending, length) && self.without_repeats(i + length..i + 2 * (length + ending)) {
+                        candidates.push(RepeatedGroup {start: i, length, count: 2, ending});
+                    }
+                }
+                for c in candidates.into_iter().filter(|c| c.count > 1) {
+                    if best.as_ref().map(|b| c.saved_measures() > b.saved_measures()).unwrap_or(true) {best = Some(c);}
+                }
+            }
+            match best {
+                Some(b) => {i += b.expanded_length(); groups.push(b);},
+                None => i += 1,
+            }
+        }
+        groups
+    }
+
+    /// Replace the measures played several times in a row by repeat signs and alternative endings (see `find_repeated_groups()`).
+    /// Returns the number of repeats added.
+    pub fn compress_repeats(&mut self, max_length: usize) -> usize {
+        let groups = self.find_repeated_groups(max_length);
+        for g in groups.iter().rev() {
+            let end = g.start + g.length - 1;
+            self.measure_headers[g.start].repeat_open = true;
+            if g.ending == 0 {
+                self.measure_headers[end].repeat_close = (g.count - 1).to_i8().unwrap();
+                self.delete_measures(g.start + g.length..g.start + g.expanded_length());
+            } else {
+                //the second occurrence of the part is removed, the endings follow the part
+                for e in 0..g.ending {
+                    self.measure_headers[end + 1 + e].repeat_alternative = 1;
+                    self.measure_headers[end + 1 + g.length + g.ending + e].repeat_alternative = 2;
+                }
+                self.measure_headers[end + g.ending].repeat_close = 1;
+                self.delete_measures(end + 1 + g.ending..end + 1 + g.ending + g.length);
+            }
+        }
+        groups.len()
+    }
+
+    /// Get the indexes of the measures in the order they are played, following the repeats and the alternative endings.
+    /// A closing repeat without opening repeat goes back to the start of the song. Directions (coda, segno, ...) are not followed.
+    pub fn playback_order(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.measure_headers.len());
+        let (mut index, mut last_index) = (0usize, 0usize);
+        let (mut repeat_start, mut repeat_number, mut alternative) = (0usize, 0u8, 0u8);
+        let mut repeat_open = true;
+        while index < self.measure_headers.len() && order.len() < self.measure_headers.len() * MAX_PLAYBACK_FACTOR {
+            let h = &self.measure_headers[index];
+            if h.repeat_open {
+                repeat_start = index;
+                repeat_open = true;
+                if index > last_index {repeat_number = 0; alternative = 0;}
+            } else {
+                if alternative == 0 {alternative = h.repeat_alternative;}
+                //alternative ending of another pass
+                if repeat_open && alternative > 0 && u32::from(alternative) & 1u32.checked_shl(u32::from(repeat_number)).unwrap_or(0) == 0 {
+                    if h.repeat_close > 0 {alternative = 0;}
+                    index += 1;
+                    continue;
+                }
+            }
+            order.push(index);
+            last_index = last_index.max(index);
+            if repeat_open && h.repeat_close > 0 {
+                if repeat_number < h.repeat_close.to_u8().unwrap() || alternative > 0 {
+                    index = repeat_start;
+                    repeat_number += 1;
+                    alternative = 0;
+                    continue;
+                }
+                repeat_number = 0;
+                repeat_open = false;
+                alternative = 0;
+            }
+            index += 1;
+        }
+        order
+    }
+
+    /// Write the measures in the order they are played (see `playback_order()`) and remove the repeat signs and alternative endings.
+    pub fn expand_repeats(&mut self) {
+        let order = self.playback_order();
+        self.measure_headers = order.iter().map(|i| {
+            let mut h = self.measure_headers[*i].clone();
+            h.repeat_open = false;
+            h.repeat_close = -1;
+            h.repeat_alternative = 0;
+            h
+        }).collect();
+        for t in self.tracks.iter_mut() {t.measures = order.iter().map(|i| t.measures[*i].clone()).collect();}
+        self.update_measures();
+    }
+
+    /// Check that the measures of a range have no repeat signs.
+    fn without_repeats(&self, measures: std::ops::Range<usize>) -> bool {
+        self.measure_headers[measures].iter().all(|h| !h.repeat_open && h.repeat_close < 0 && h.repeat_alternative == 0)
+    }
+}
+
+/// Compare 2 beats without their start
+fn same_beats(a: &Beat, b: &Beat) -> bool {
+    a.notes == b.notes && a.duration == b.duration && a.text == b.text && a.effect == b.effect && a.octave == b.octave && a.display == b.display && a.status == b.status
+}
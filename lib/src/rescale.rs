@@ -0,0 +1,164 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, key_signature::*, enums::*};
+
+/// Shortest note value that can be written (sixty-fourth note)
+const MAX_DURATION_VALUE: u16 = 64;
+/// Values of the time-independent durations of the notes: from `-2` (whole note) to `4` (sixty-fourth note)
+const NOTE_DURATIONS: std::ops::RangeInclusive<i8> = -2..=4;
+
+/// How the note values and the time signatures are rewritten by `Song::rescale_time()`
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeScale {
+    /// Note values are doubled, a quarter note becomes a half note: 4/4 becomes 4/2 and the tempo is doubled.
+    Double,
+    /// Note values are halved, a quarter note becomes an eighth note: 4/4 becomes 4/8 and the tempo is halved.
+    Half,
+    /// Time signatures get twice as many beats of half the value (4/4 becomes 8/8), the notes are unchanged.
+    SplitBeats,
+    /// Time signatures get half as many beats of twice the value (4/4 becomes 2/2), the notes are unchanged.
+    MergeBeats,
+}
+
+/// Reason why a song cannot be rescaled by `Song::rescale_time()`. Measures and tracks are counted from 1.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum RescaleError {
+    /// The value of the time signature of the measure cannot be doubled or halved
+    TimeSignature {measure: usize},
+    /// The number of beats of the time signature of the measure cannot be doubled or halved
+    Beats {measure: usize, numerator: i8},
+    /// The beams of the measure cannot be doubled
+    Beams {measure: usize},
+    /// A beat or a note has a duration which cannot be doubled or halved
+    Duration {track: usize, measure: usize},
+    /// A tempo change or its transition cannot be scaled
+    Tempo {track: usize, measure: usize, tempo: u8},
+}
+impl std::fmt::Display for RescaleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RescaleError::TimeSignature {measure} => write!(f, "the value of the time signature of measure {} cannot be scaled", measure),
+            RescaleError::Beats {measure, numerator} => write!(f, "the {} beats of measure {} cannot be scaled", numerator, measure),
+            RescaleError::Beams {measure} => write!(f, "the beams of measure {} cannot be doubled", measure),
+            RescaleError::Duration {track, measure} => write!(f, "a duration cannot be scaled (track {}, measure {})", track, measure),
+            RescaleError::Tempo {track, measure, tempo} => write!(f, "the tempo change {} cannot be scaled (track {}, measure {})", tempo, track, measure),
+        }
+    }
+}
+impl std::error::Error for RescaleError {}
+
+impl Song {
+    /// Rewrite the song with other note values or time signatures so that it sounds the same: durations of the beats and the notes,
+    /// time signatures (with their beams), tempos of the song, the measure headers and the mix tables, and tempo transitions are scaled.
+    /// When the note values change, the triplet feels follow them (an eighth triplet feel becomes a sixteenth one when halving,
+    /// and is removed when it cannot be written). The speeds of tremolo picking, trills and grace notes are kept.
+    ///
+    /// Returns an error if a duration, a time signature or a tempo cannot be scaled (e.g. doubling a whole note or merging the beats
+    /// of a 3/4 measure). The song is not changed in this case.
+    pub fn rescale_time(&mut self, scale: TimeScale) -> Result<(), RescaleError> {
+        self.check_rescale(scale)?;
+        let (numerator, denominator): (i64, i64) = match scale {TimeScale::Double => (2, 1), TimeScale::Half => (1, 2), _ => (1, 1)};
+        let scale_value = |value: u16| -> u16 {
+            match scale {
+                TimeScale::Double | TimeScale::MergeBeats => value / 2,
+                TimeScale::Half | TimeScale::SplitBeats => value * 2,
+            }
+        };
+        //tempos are expressed in quarter notes per minute
+        let scale_tempo = |tempo: i64| -> i64 { (tempo * numerator + denominator / 2) / denominator };
+        if scale == TimeScale::Double || scale == TimeScale::Half {
+            self.tempo = scale_tempo(i64::from(self.tempo)).to_i16().unwrap();
+            self.triplet_feel = scale_triplet_feel(&self.triplet_feel, scale);
+        }
+        for h in self.measure_headers.iter_mut() {
+            let ts = &mut h.time_signature;
+            ts.denominator.value = scale_value(ts.denominator.value);
+            match scale {
+                TimeScale::Double => for b in ts.beams.iter_mut() {*b *= 2;},
+                TimeScale::Half => for b in ts.beams.iter_mut() {*b = b.div_ceil(2);},
+                TimeScale::SplitBeats => ts.numerator *= 2,
+                TimeScale::MergeBeats => ts.numerator /= 2,
+            }
+            if scale == TimeScale::Double || scale == TimeScale::Half {
+                if h.tempo > 0 {h.tempo = scale_tempo(i64::from(h.tempo)).to_i32().unwrap();}
+                h.triplet_feel = scale_triplet_feel(&h.triplet_feel, scale);
+            }
+        }
+        for track in self.tracks.iter_mut() {
+            for (m, measure) in track.measures.iter_mut().enumerate() {
+                if let Some(h) = self.measure_headers.get(m) {measure.time_signature = h.time_signature.clone();}
+                for voice in measure.voices.iter_mut() {
+                    for beat in voice.beats.iter_mut() {
+                        if scale == TimeScale::Double || scale == TimeScale::Half {
+                            beat.duration.value = scale_value(beat.duration.value);
+                            beat.start = beat.start.map(|s| DURATION_QUARTER_TIME + (s - DURATION_QUARTER_TIME) * numerator / denominator);
+                            for n in beat.notes.iter_mut() {
+                                n.duration = n.duration.map(|d| if scale == TimeScale::Double {d - 1} else {d + 1});
+                            }
+                        }
+                        if let Some(t) = beat.effect.mix_table_change.as_mut().and_then(|mtc| mtc.tempo.as_mut()) {
+                            match scale {
+                                TimeScale::Double | TimeScale::Half => t.value = scale_tempo(i64::from(t.value)).to_u8().unwrap(),
+                                //transitions last a number of beats of the time signature
+                                TimeScale::SplitBeats => t.duration *= 2,
+                                TimeScale::MergeBeats => t.duration = t.duration.div_ceil(2),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.update_measures();
+        Ok(())
+    }
+
+    /// Check that the song can be rescaled before changing it.
+    fn check_rescale(&self, scale: TimeScale) -> Result<(), RescaleError> {
+        for h in &self.measure_headers {
+            let ts = &h.time_signature;
+            match scale {
+                TimeScale::Double | TimeScale::MergeBeats if ts.denominator.value < 2 => return Err(RescaleError::TimeSignature {measure: h.number.into()}),
+                TimeScale::Half | TimeScale::SplitBeats if ts.denominator.value >= MAX_DURATION_VALUE => return Err(RescaleError::TimeSignature {measure: h.number.into()}),
+                TimeScale::SplitBeats if ts.numerator > i8::MAX / 2 => return Err(RescaleError::Beats {measure: h.number.into(), numerator: ts.numerator}),
+                TimeScale::MergeBeats if ts.numerator % 2 != 0 => return Err(RescaleError::Beats {measure: h.number.into(), numerator: ts.numerator}),
+                TimeScale::Double if ts.beams.iter().any(|b| *b > u8::MAX / 2) => return Err(RescaleError::Beams {measure: h.number.into()}),
+                _ => {},
+            }
+        }
+        for (t, track) in self.tracks.iter().enumerate() {
+            for (m, measure) in track.measures.iter().enumerate() {
+                for beat in measure.voices.iter().flat_map(|v| v.beats.iter()) {
+                    let d = &beat.duration;
+                    match scale {
+                        TimeScale::Double if d.value < 2 => return Err(RescaleError::Duration {track: t + 1, measure: m + 1}),
+                        TimeScale::Half if d.value >= MAX_DURATION_VALUE => return Err(RescaleError::Duration {track: t + 1, measure: m + 1}),
+                        _ => {},
+                    }
+                    for n in beat.notes.iter().filter_map(|n| n.duration) {
+                        if (scale == TimeScale::Double && !NOTE_DURATIONS.contains(&(n - 1))) || (scale == TimeScale::Half && !NOTE_DURATIONS.contains(&(n + 1))) {
+                            return Err(RescaleError::Duration {track: t + 1, measure: m + 1});
+                        }
+                    }
+                    if let Some(i) = beat.effect.mix_table_change.as_ref().and_then(|mtc| mtc.tempo.as_ref()) {
+                        match scale {
+                            TimeScale::Double if i.value > u8::MAX / 2 => return Err(RescaleError::Tempo {track: t + 1, measure: m + 1, tempo: i.value}),
+                            TimeScale::SplitBeats if i.duration > u8::MAX / 2 => return Err(RescaleError::Tempo {track: t + 1, measure: m + 1, tempo: i.value}),
+                            _ => {},
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Triplet feel of the scaled notes: an eighth triplet feel applies to sixteenth notes once the note values are halved.
+fn scale_triplet_feel(feel: &TripletFeel, scale: TimeScale) -> TripletFeel {
+    match (feel, scale) {
+        (TripletFeel::Eighth, TimeScale::Half) => TripletFeel::Sixteenth,
+        (TripletFeel::Sixteenth, TimeScale::Double) => TripletFeel::Eighth,
+        _ => TripletFeel::None,
+    }
+}
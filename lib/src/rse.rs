@@ -7,6 +7,7 @@ use crate::{io::*, gp::*, enums::*, track::*};
 /// Attribute :attr:`RSEEqualizer.knobs` is a list of values in range from -6.0 to 5.9. Master effect has 10 knobs, track effect has 3
 /// knobs. Gain is a value in range from -6.0 to 5.9 which can be found in both master and track effects and is named as "PRE" in Guitar Pro 5.
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RseEqualizer {
     pub knobs: Vec<f32>,
     pub gain: f32,
@@ -15,6 +16,7 @@ impl Default for RseEqualizer {fn default() -> Self { RseEqualizer { knobs: Vec:
 
 /// Master effect as seen in "Score information"
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RseMasterEffect {
     pub volume: f32,
     pub reverb: f32,
@@ -23,6 +25,7 @@ pub struct RseMasterEffect {
 impl Default for RseMasterEffect { fn default() -> Self { RseMasterEffect {volume:0.0, reverb:0.0, equalizer:RseEqualizer{knobs:vec![0.0;10], ..Default::default()} }}}
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RseInstrument {
     pub instrument: i16,
     pub unknown: i16,
@@ -34,6 +37,7 @@ pub struct RseInstrument {
 impl Default for RseInstrument { fn default() -> Self { RseInstrument { instrument:-1, unknown:-1, sound_bank:-1, effect_number:-1, effect_category:String::new(), effect:String::new()}}}
 
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackRse {
     pub instrument: RseInstrument,
     pub equalizer: RseEqualizer,
@@ -46,25 +50,26 @@ impl Song {
     /// Read RSE master effect. Persistence of RSE master effect was introduced in Guitar Pro 5.1. It is read as:
     /// - Master volume: `int`. Values are in range from 0 to 200.
     /// - 10-band equalizer. See `read_equalizer()`.
-    pub(crate) fn read_rse_master_effect(&self, data: &[u8], seek: &mut usize) -> RseMasterEffect {
+    pub(crate) fn read_rse_master_effect(&self, data: &mut Input) -> RseMasterEffect {
         let mut me = RseMasterEffect::default();
         if self.version.number > (5,0,0) {
-            me.volume = read_int(data, seek).to_f32().unwrap();
-            read_int(data, seek); //???
-            me.equalizer = self.read_rse_equalizer(data, seek, 11);
+            me.volume = read_int(data).to_f32().unwrap();
+            read_int(data); //???
+            me.equalizer = self.read_rse_equalizer(data, 11);
             //println!("read_rse_master_effect(): {:?}", me);
         }
         me
     }
     /// Read equalizer values. Equalizers are used in RSE master effect and Track RSE. They consist of *n* `SignedBytes <signed-byte>` for each *n* bands and one `signed-byte` for gain (PRE) fader.
     /// Volume values are stored as opposite to actual value. See `unpack_volume_value()`.
-    fn read_rse_equalizer(&self, data: &[u8], seek: &mut usize, knobs: u8) -> RseEqualizer {
+    fn read_rse_equalizer(&self, data: &mut Input, knobs: u8) -> RseEqualizer {
         let mut e = RseEqualizer::default();
-        for _ in 0..knobs {e.knobs.push(self.unpack_volume_value(read_signed_byte(data, seek)));} //knobs = list(map(self.unpackVolumeValue, self.readSignedByte(count=knobsNumber)))
-        e                                                                                         //return gp.RSEEqualizer(knobs=knobs[:-1], gain=knobs[-1])
+        for _ in 0..knobs {e.knobs.push(self.unpack_volume_value(read_signed_byte(data)));} //knobs = list(map(self.unpackVolumeValue, self.readSignedByte(count=knobsNumber)))
+        e.gain = e.knobs.pop().unwrap_or(0.0);                                                    //return gp.RSEEqualizer(knobs=knobs[:-1], gain=knobs[-1])
+        e
     }
     /// Unpack equalizer volume value. Equalizer volumes are float but stored as `SignedBytes <signed-byte>`.
-    fn unpack_volume_value(&self, value: i8) -> f32 { -value.to_f32().unwrap() / 10.0 }
+    fn unpack_volume_value(&self, value: i8) -> f32 { (-i16::from(value)).to_f32().unwrap() / 10.0 }
 
     /// Read track RSE. In GuitarPro 5.1 track RSE is read as follows:
     /// - Humanize: :`byte`.
@@ -72,15 +77,15 @@ impl Song {
     /// - RSE instrument. See `readRSEInstrument`.
     /// - 3-band track equalizer. See `read_equalizer()`.
     /// - RSE instrument effect. See `read_rse_instrument_effect()`.
-    pub(crate) fn read_track_rse(&mut self, data: &[u8], seek: &mut usize, track: &mut Track) {
-        track.rse.humanize = read_byte(data, seek);
-        //println!("read_track_rse(), humanize: {} \t\t seek: {}", track.rse.humanize, *seek);
-        *seek += 12; //read_int(data, seek); read_int(data, seek); read_int(data, seek);  //??? 4 bytes*3 //*seek += 12;
-        *seek += 12; //???
-        track.rse.instrument = self.read_rse_instrument(data, seek);
+    pub(crate) fn read_track_rse(&mut self, data: &mut Input, track: &mut Track) {
+        track.rse.humanize = read_byte(data);
+        //println!("read_track_rse(), humanize: {} \t\t position: {}", track.rse.humanize, data._position());
+        data.skip(12); //read_int(data); read_int(data); read_int(data);  //??? 4 bytes*3 //data.skip(12);
+        data.skip(12); //???
+        track.rse.instrument = self.read_rse_instrument(data);
         if self.version.number > (5,0,0) {
-            track.rse.equalizer = self.read_rse_equalizer(data, seek, 4);
-            self.read_rse_instrument_effect(data, seek, &mut track.rse.instrument);
+            track.rse.equalizer = self.read_rse_equalizer(data, 4);
+            self.read_rse_instrument_effect(data, &mut track.rse.instrument);
         }
     }
     /// Read RSE instrument.
@@ -88,25 +93,25 @@ impl Song {
     /// - Unknown `int`.
     /// - Sound bank: `int`.
     /// - Effect number: `int`. Vestige of Guitar Pro 5.0 format.
-    pub(crate) fn read_rse_instrument(&mut self, data: &[u8], seek: &mut usize) -> RseInstrument {
-        let mut instrument = RseInstrument{instrument: read_int(data, seek).to_i16().unwrap(), ..Default::default()};
-        instrument.unknown    = read_int(data, seek).to_i16().unwrap(); //??? mostly 1
-        instrument.sound_bank = read_int(data, seek).to_i16().unwrap();
-        //println!("read_rse_instrument(), instrument: {} {} {} \t\t seek: {}", instrument.instrument, instrument.unknown, instrument.sound_bank, *seek);
+    pub(crate) fn read_rse_instrument(&mut self, data: &mut Input) -> RseInstrument {
+        let mut instrument = RseInstrument{instrument: read_int(data).to_i16().unwrap(), ..Default::default()};
+        instrument.unknown    = read_int(data).to_i16().unwrap(); //??? mostly 1
+        instrument.sound_bank = read_int(data).to_i16().unwrap();
+        //println!("read_rse_instrument(), instrument: {} {} {} \t\t position: {}", instrument.instrument, instrument.unknown, instrument.sound_bank, data._position());
         if self.version.number == (5,0,0) {
-            instrument.effect_number = read_short(data, seek);
-            *seek += 1;
-        } else {instrument.effect_number = read_int(data, seek).to_i16().unwrap();}
-        //println!("read_rse_instrument(), instrument.effect_number: {} \t\t seek: {}", instrument.effect_number, *seek);
+            instrument.effect_number = read_short(data);
+            data.skip(1);
+        } else {instrument.effect_number = read_int(data).to_i16().unwrap();}
+        //println!("read_rse_instrument(), instrument.effect_number: {} \t\t position: {}", instrument.effect_number, data._position());
         instrument
     }
     /// Read RSE instrument effect name. This feature was introduced in Guitar Pro 5.1.
     /// - Effect name: `int-byte-size-string`.
     /// - Effect category: `int-byte-size-string`.
-    pub(crate) fn read_rse_instrument_effect(&mut self, data: &[u8], seek: &mut usize, instrument: &mut RseInstrument) {
+    pub(crate) fn read_rse_instrument_effect(&mut self, data: &mut Input, instrument: &mut RseInstrument) {
         if self.version.number > (5,0,0) {
-            instrument.effect =          read_int_byte_size_string(data, seek);
-            instrument.effect_category = read_int_byte_size_string(data, seek);
+            instrument.effect =          read_int_byte_size_string(data);
+            instrument.effect_category = read_int_byte_size_string(data);
         }
     }
 
@@ -147,7 +152,7 @@ impl Song {
         } else {write_i32(data, instrument.effect_number.to_i32().unwrap());}
     }
     pub(crate) fn write_rse_instrument_effect(&self, data: &mut Vec<u8>, instrument: &RseInstrument) { //version>5.0.0
-        write_int_byte_size_string(data, &instrument.effect);
-        write_int_byte_size_string(data, &instrument.effect_category);
+        write_int_byte_size_string(data, &instrument.effect, self.encoding);
+        write_int_byte_size_string(data, &instrument.effect_category, self.encoding);
     }
 }
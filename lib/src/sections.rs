@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+
+use fraction::ToPrimitive;
+
+use crate::{gp::*, headers::*, enums::*};
+
+/// Weights of the pitches, the rhythm and the chords in the similarity of 2 measures
+const PITCH_WEIGHT: f64 = 0.5;
+const RHYTHM_WEIGHT: f64 = 0.3;
+const CHORD_WEIGHT: f64 = 0.2;
+/// Colors of the markers written for the sections, by label
+const SECTION_COLORS: [i32; 6] = [0xff0000, 0x0000ff, 0x00a000, 0xff8000, 0x8000ff, 0x008080];
+
+/// A part of the song, labelled by its similarity with the other parts
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Section {
+    /// Index of the first measure
+    pub start: usize,
+    /// Number of measures
+    pub length: usize,
+    /// Label shared by the similar sections: `A`, `B`, ...
+    pub label: String,
+    /// Similarity (from 0 to 1) with the first section having the same label, `1.0` for the first one
+    pub similarity: f64,
+}
+
+/// What is played in a measure by the analysed tracks
+#[derive(Debug,Clone,Default)]
+struct MeasureFeatures {
+    /// Notes as (track, onset in ticks from the start of the measure, MIDI pitch)
+    notes: BTreeSet<(usize, i64, i16)>,
+    /// Onsets of the beats as (track, onset in ticks from the start of the measure)
+    onsets: BTreeSet<(usize, i64)>,
+    /// Names of the chords sounding in the measure
+    chords: BTreeSet<String>,
+}
+
+impl Song {
+    /// Get the similarity (from 0 to 1) of 2 groups of `length` measures, comparing the pitches and the onsets of the notes of each track
+    /// and the chords played by the tracks together. Measures beyond the end of the song are empty.
+    /// - `tracks`: indexes of the tracks to compare. If empty, all the tracks which are not percussion tracks are used.
+    pub fn section_similarity(&self, first: usize, second: usize, length: usize, tracks: &[usize]) -> f64 {
+        let features = self.measure_features(tracks);
+        segment_similarity(&features, first, second, length)
+    }
+
+    /// Split the song in groups of `length` measures and label them: a group gets the label of the first labelled group whose similarity
+    /// is at least `threshold` (the most similar one if there are several), a new label otherwise. Consecutive groups with the same
+    /// label are merged into one section, keeping the lowest similarity.
+    /// - `tracks`: indexes of the tracks to analyse. If empty, all the tracks which are not percussion tracks are used.
+    pub fn detect_sections(&self, length: usize, threshold: f64, tracks: &[usize]) -> Vec<Section> {
+        let features = self.measure_features(tracks);
+        let length = length.max(1);
+        //first group of each label
+        let mut references: Vec<usize> = Vec::new();
+        let mut sections: Vec<Section> = Vec::new();
+        for start in (0..features.len()).step_by(length) {
+            let size = length.min(features.len() - start);
+            let best = references.iter().enumerate().map(|(l, r)| (l, segment_similarity(&features, *r, start, size)))
+                                 .filter(|s| s.1 >= threshold).max_by(|a, b| a.1.total_cmp(&b.1));
+            let (label, similarity) = match best {
+                Some(b) => (section_label(b.0), b.1),
+                None => {references.push(start); (section_label(references.len() - 1), 1.0)},
+            };
+            match sections.last_mut() {
+                Some(s) if s.label == label => {s.length += size; s.similarity = s.similarity.min(similarity);},
+                _ => sections.push(Section {start, length: size, label, similarity}),
+            }
+        }
+        sections
+    }
+
+    /// Write the sections as markers of their first measure, titled by their label. The sections with the same label get the same color.
+    /// Existing markers are kept. Returns the number of markers added.
+    pub fn mark_sections(&mut self, sections: &[Section]) -> usize {
+        let mut count = 0;
+        let mut labels: Vec<&str> = Vec::new();
+        for s in sections {if !labels.contains(&s.label.as_str()) {labels.push(&s.label);}}
+        for s in sections {
+            if let Some(h) = self.measure_headers.get_mut(s.start) {
+                if h.marker.is_some() {continue;}
+                let index = labels.iter().position(|l| *l == s.label).unwrap();
+                h.marker = Some(Marker {title: s.label.clone(), color: SECTION_COLORS[index % SECTION_COLORS.len()]});
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn measure_features(&self, tracks: &[usize]) -> Vec<MeasureFeatures> {
+        let tracks: Vec<usize> = if tracks.is_empty() {(0..self.tracks.len()).filter(|t| !self.tracks[*t].percussion_track).collect()} else {tracks.to_vec()};
+        let mut features = vec![MeasureFeatures::default(); self.measure_headers.len()];
+        for t in &tracks {
+            let track = &self.tracks[*t];
+            for (m, measure) in track.measures.iter().enumerate().take(features.len()) {
+                for voice in &measure.voices {
+                    let mut onset = 0i64;
+                    for beat in &voice.beats {
+                        if beat.status == BeatStatus::Normal {
+                            features[m].onsets.insert((*t, onset));
+                            for n in beat.notes.iter().filter(|n| n.kind == NoteType::Normal || n.kind == NoteType::Tie) {
+                                if let Some(p) = track.fingering_pitch(n) {features[m].notes.insert((*t, onset, p));}
+                            }
+                        }
+                        onset += i64::from(beat.duration.time());
+                    }
+                }
+            }
+        }
+        //the chord sounding at the start of a measure comes from the last change before it
+        let mut last: Option<String> = None;
+        let changes = self.chord_progression(&tracks);
+        let mut changes = changes.iter().peekable();
+        for (m, f) in features.iter_mut().enumerate() {
+            if let Some(c) = &last {f.chords.insert(c.clone());}
+            while let Some(c) = changes.next_if(|c| c.measure == m) {
+                f.chords.insert(c.chord.name.clone());
+                last = Some(c.chord.name.clone());
+            }
+        }
+        features
+    }
+}
+
+fn segment_similarity(features: &[MeasureFeatures], first: usize, second: usize, length: usize) -> f64 {
+    if length == 0 {return 1.0;}
+    let empty = MeasureFeatures::default();
+    let total: f64 = (0..length).map(|i| measure_similarity(features.get(first + i).unwrap_or(&empty), features.get(second + i).unwrap_or(&empty))).sum();
+    total / length.to_f64().unwrap()
+}
+
+fn measure_similarity(a: &MeasureFeatures, b: &MeasureFeatures) -> f64 {
+    PITCH_WEIGHT * jaccard(&a.notes, &b.notes) + RHYTHM_WEIGHT * jaccard(&a.onsets, &b.onsets) + CHORD_WEIGHT * jaccard(&a.chords, &b.chords)
+}
+
+/// Size of the intersection divided by the size of the union, 2 empty sets are the same
+fn jaccard<T: Ord>(a: &BTreeSet<T>, b: &BTreeSet<T>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {return 1.0;}
+    a.intersection(b).count().to_f64().unwrap() / union.to_f64().unwrap()
+}
+
+/// Label of a section: `A` to `Z`, then `AA`, `AB`, ...
+fn section_label(index: usize) -> String {
+    let letter = |i: usize| char::from(b'A' + (i % 26).to_u8().unwrap());
+    if index < 26 {letter(index).to_string()} else {format!("{}{}", section_label(index / 26 - 1), letter(index))}
+}
@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+
+use fraction::ToPrimitive;
+
+use crate::{gp::*, headers::*, track::*, measure::*, beat::*, note::*, effects::*, key_signature::*, midi::*, mix_table::*, enums::*, lyric::*};
+
+/// Pitch bend range of the MIDI files, in semitones.
+const PITCH_BEND_RANGE: f32 = 2.0;
+/// Onsets are snapped to one of these grids (in Guitar Pro ticks), chosen for every quarter note. The second value is added to the
+/// quantisation error of every onset so that the simplest grid wins when several fit.
+const QUANTIZATION_GRIDS: [(i64, i64); 3] = [(240, 0), (160, 10), (120, 15)];
+/// Standard tuning of a 4 strings bass guitar.
+const BASS_TUNING: [(i8, i8); 4] = [(1, 43), (2, 38), (3, 33), (4, 28)];
+
+/// A note read from a MIDI track. Times are in Guitar Pro ticks from the start of the song.
+#[derive(Debug,Clone)]
+struct MidiNote {
+    pitch: i16,
+    velocity: i16,
+    start: i64,
+    end: i64,
+    /// Pitch bend changes as `(tick, offset from the center)`, including the bend in effect when the note starts.
+    bends: Vec<(i64, i16)>,
+}
+
+/// The notes played on one channel of one MIDI track, which will become one track of the song.
+#[derive(Debug,Clone,Default)]
+struct MidiPart {
+    name: String,
+    channel: u8,
+    program: Option<u8>,
+    notes: Vec<MidiNote>,
+}
+
+/// Everything read from a Standard MIDI File, with times converted to Guitar Pro ticks.
+#[derive(Debug,Clone,Default)]
+struct MidiEvents {
+    /// `(tick, micro-seconds per quarter note)`
+    tempos: Vec<(i64, u32)>,
+    /// `(tick, numerator, denominator)`
+    time_signatures: Vec<(i64, i8, u16)>,
+    /// `(tick, sharps or flats, is minor)`
+    keys: Vec<(i64, i8, bool)>,
+    parts: Vec<MidiPart>,
+    /// Name of the first track of a type-1 file (the conductor track)
+    title: String,
+    end: i64,
+}
+
+/// Read a byte of a MIDI file. The MIDI files are read from a slice as their chunks are found with their lengths.
+fn next_byte(data: &[u8], seek: &mut usize) -> u8 {
+    let b = *data.get(*seek).unwrap_or_else(|| panic!("End of file reached"));
+    *seek += 1;
+    b
+}
+
+/// Read an integer stored in `count` big-endian bytes.
+fn read_big_endian(data: &[u8], seek: &mut usize, count: usize) -> u32 {
+    if data.len() < *seek + count {panic!("End of file reached");}
+    let mut n = 0u32;
+    for _ in 0..count {n = (n << 8) | u32::from(next_byte(data, seek));}
+    n
+}
+
+/// Read a variable-length quantity: 7 bits per byte, the highest bit is set on every byte but the last.
+fn read_variable_length(data: &[u8], seek: &mut usize) -> u32 {
+    let mut n = 0u32;
+    loop {
+        let b = next_byte(data, seek);
+        n = (n << 7) | u32::from(b & 0x7f);
+        if (b & 0x80) == 0 {return n;}
+    }
+}
+
+impl Song {
+    /// Read a Standard MIDI File (type 0 or 1). A track is created for every channel of every MIDI track:
+    /// - the channel 10 is read as a percussion track,
+    /// - program changes set the instrument of the MIDI channel of the track,
+    /// - tempo, time signature and key signature meta-events are mapped onto the measure headers,
+    /// - note onsets and durations are quantised to `Duration` values, notes crossing a beat or a bar are tied. Every quarter note is
+    ///   snapped to sixteenths, eighth triplets or thirty-seconds (see `QUANTIZATION_GRIDS`): quintuplets and the other tuplets are
+    ///   approximated by the closest of these grids,
+    /// - the drum hits of a beat are written on the 6 strings of the first voice, then of the second voice: at most 12 hits are kept,
+    /// - pitch bends are converted to bend effects,
+    /// - strings and frets are assigned to the notes of fretted instruments (see `Track::assign_fingering()`).
+    pub fn read_midi(&mut self, data: &[u8]) {
+        let events = read_midi_events(data);
+        self.version = Version {data: String::from("FICHIER GUITAR PRO v5.10"), number: (5,1,0), clipboard: false};
+        if !events.title.is_empty() {self.name = events.title.clone();}
+        self.lyrics = Lyrics::default();
+        for i in 0..5u8 {self.lyrics.lines.push((i, 1, String::new()));}
+        self.channels.clear();
+        for i in 0..64u8 {
+            let mut channel = MidiChannel::default();
+            channel.channel = i;
+            channel.effect_channel = i;
+            self.channels.push(channel);
+        }
+        self.tempo = events.tempos.first().filter(|t| t.0 == 0).map(|t| bpm(t.1)).unwrap_or(120);
+        if let Some(k) = events.keys.first() {self.key = KeySignature {key: k.1, is_minor: k.2};}
+        self.midi_measure_headers(&events);
+        for (i, part) in events.parts.iter().enumerate() {
+            let track = self.midi_track(part, i);
+            self.tracks.push(track);
+        }
+        self.midi_tempo_changes(&events);
+    }
+
+    /// Create the measure headers from the time signature and key signature changes, until the end of the last track.
+    fn midi_measure_headers(&mut self, events: &MidiEvents) {
+        let mut tick = 0i64;
+        let mut time_signature = TimeSignature::default();
+        let mut key_signature = self.key.clone();
+        let mut tempo = self.tempo.to_i32().unwrap();
+        loop {
+            for ts in events.time_signatures.iter().filter(|ts| ts.0 <= tick) {
+                time_signature.numerator = ts.1;
+                time_signature.denominator = Duration {value: ts.2, ..Default::default()};
+            }
+            for k in events.keys.iter().filter(|k| k.0 <= tick) {key_signature = KeySignature {key: k.1, is_minor: k.2};}
+            let mut header = MeasureHeader {number: (self.measure_headers.len() + 1).to_u16().unwrap(), start: DURATION_QUARTER_TIME + tick,
+                                            time_signature: time_signature.clone(), key_signature: key_signature.clone(), ..Default::default()};
+            if let Some(t) = events.tempos.iter().rev().find(|t| t.0 <= tick) {
+                if bpm(t.1).to_i32().unwrap() != tempo || self.measure_headers.is_empty() {
+                    tempo = bpm(t.1).to_i32().unwrap();
+                    header.tempo = tempo;
+                }
+            }
+            let length = header.length();
+            self.measure_headers.push(header);
+            tick += length;
+            if tick >= events.end {break;}
+        }
+    }
+
+    /// Add a tempo mix table change to the first track for every tempo change after the start of the song.
+    fn midi_tempo_changes(&mut self, events: &MidiEvents) {
+        if self.tracks.is_empty() {return;}
+        for t in events.tempos.iter().filter(|t| t.0 > 0) {
+            let start = DURATION_QUARTER_TIME + t.0;
+            let beat = self.tracks[0].measures.iter_mut().flat_map(|m| m.voices[0].beats.iter_mut()).filter(|b| b.start.unwrap_or(0) <= start).last();
+            if let Some(b) = beat {
+                let mut mtc = b.effect.mix_table_change.take().unwrap_or_default();
+                mtc.tempo = Some(MixTableItem {value: bpm(t.1).clamp(1, 255).to_u8().unwrap(), ..Default::default()});
+                mtc.hide_tempo = false;
+                b.effect.mix_table_change = Some(mtc);
+            }
+        }
+    }
+
+    /// Build a track from the notes of a MIDI part.
+    fn midi_track(&mut self, part: &MidiPart, number: usize) -> Track {
+        let mut track = Track {number: number.to_i32().unwrap(), channel_index: part.channel.to_usize().unwrap(), ..Default::default()};
+        track.percussion_track = part.channel == DEFAULT_PERCUSSION_CHANNEL;
+        let channel = &mut self.channels[track.channel_index];
+        if let Some(p) = part.program {channel.set_instrument(p.to_i32().unwrap());}
+        if track.percussion_track {
+            channel.set_instrument(0);
+            track.strings = (1..=6).map(|s| (s, 0)).collect();
+        } else if (32..40).contains(&part.program.unwrap_or(0)) {
+            track.strings = BASS_TUNING.to_vec();
+        }
+        track.name = if !part.name.is_empty() {part.name.clone()} else if track.percussion_track {String::from("Drums")} else {channel.get_instrument_name()};
+        let lowest = track.strings.iter().map(|s| i16::from(s.1)).min().unwrap_or(0);
+        let highest = track.strings.iter().map(|s| i16::from(s.1)).max().unwrap_or(0) + i16::from(track.fret_count);
+
+        let mut notes = part.notes.clone();
+        if !track.percussion_track {
+            //fold the notes outside of the range of the instrument by octaves
+            for n in notes.iter_mut() {
+                while n.pitch < lowest {n.pitch += 12;}
+                while n.pitch > highest {n.pitch -= 12;}
+            }
+        }
+        let chords = quantize(&notes);
+        for h in 0..self.measure_headers.len() {
+            let mut measure = Measure {number: h + 1, start: self.measure_headers[h].start, track_index: number, header_index: h,
+                                       time_signature: self.measure_headers[h].time_signature.clone(), key_signature: self.measure_headers[h].key_signature.clone(), ..Default::default()};
+            measure.voices.push(Voice {measure_index: h.to_i16().unwrap(), beats: self.midi_beats(&chords, h), ..Default::default()});
+            measure.voices.push(Voice {measure_index: h.to_i16().unwrap(), ..Default::default()});
+            track.measures.push(measure);
+        }
+        if track.percussion_track {
+            for m in track.measures.iter_mut() {
+                //the hits after the sixth one of a beat are moved to the second voice, rests fill its other beats
+                if m.voices[0].beats.iter().any(|b| b.notes.len() > 6) {
+                    m.voices[1].beats = m.voices[0].beats.iter().map(|b| {
+                        let mut beat = b.clone();
+                        beat.notes = b.notes.iter().skip(6).take(6).cloned().collect();
+                        if beat.notes.is_empty() {beat.status = BeatStatus::Rest;}
+                        beat
+                    }).collect();
+                }
+                for v in m.voices.iter_mut() {
+                    for b in v.beats.iter_mut() {
+                        b.notes.truncate(6);
+                        for (i, n) in b.notes.iter_mut().enumerate() {n.string = (i + 1).to_i8().unwrap();}
+                    }
+                }
+            }
+        } else {
+            track.assign_fingering();
+            //more simultaneous notes than strings
+            for b in track.measures.iter_mut().flat_map(|m| m.voices[0].beats.iter_mut()) {b.notes.retain(|n| n.string > 0);}
+        }
+        track
+    }
+
+    /// Create the beats of a measure from the quantised chords: each chord lasts until the next one or until its notes end,
+    /// then rests fill the gaps. Spans that cannot be written with a single duration are split into tied beats.
+    fn midi_beats(&self, chords: &BTreeMap<i64, Vec<MidiNote>>, header: usize) -> Vec<Beat> {
+        let start = self.measure_headers[header].start - DURATION_QUARTER_TIME;
+        let end = start + self.measure_headers[header].length();
+        //chords sounding at the start of the measure (started in a previous one) and chords starting in the measure
+        let mut spans: Vec<(i64, i64, Vec<MidiNote>, bool)> = Vec::new();
+        if let Some((&s, c)) = chords.range(..start).next_back() {
+            let next = chords.range(start..).next().map(|c| *c.0).unwrap_or(i64::MAX);
+            let stop = c.iter().map(|n| n.end).max().unwrap_or(s).min(next);
+            if stop > start {spans.push((start, stop.min(end), c.clone(), true));}
+        }
+        let starts: Vec<i64> = chords.range(start..end).map(|c| *c.0).collect();
+        for (i, s) in starts.iter().enumerate() {
+            let next = if i + 1 < starts.len() {starts[i + 1]} else {chords.range(end..).next().map(|c| *c.0).unwrap_or(i64::MAX)};
+            let stop = chords[s].iter().map(|n| n.end).max().unwrap_or(*s).min(next).min(end);
+            spans.push((*s, stop, chords[s].clone(), false));
+        }
+        let mut beats: Vec<Beat> = Vec::new();
+        let mut position = start;
+        for (s, e, notes, tied) in spans {
+            if s > position {beats.extend(rest_beats(self.measure_headers[header].start, position - start, s - start));}
+            let mut beat_start = s;
+            for (i, d) in durations_for(e - s).into_iter().enumerate() {
+                let length = i64::from(d.time());
+                let mut beat = Beat {start: Some(DURATION_QUARTER_TIME + beat_start), duration: d, ..Default::default()};
+                beat_start += length;
+                for n in &notes {
+                    let mut note = Note::default();
+                    note.value = n.pitch;
+                    note.velocity = n.velocity.max(MIN_VELOCITY);
+                    note.kind = if tied || i > 0 {NoteType::Tie} else {NoteType::Normal};
+                    if i == 0 && !tied {note.effect.bend = bend_effect(n);}
+                    beat.notes.push(note);
+                }
+                beats.push(beat);
+            }
+            position = e;
+        }
+        if position < end {beats.extend(rest_beats(self.measure_headers[header].start, position - start, end - start));}
+        beats
+    }
+}
+
+/// Snap the notes on the grid that fits best every quarter note and group them by onset.
+fn quantize(notes: &[MidiNote]) -> BTreeMap<i64, Vec<MidiNote>> {
+    let quarter = DURATION_QUARTER_TIME;
+    let mut grids: BTreeMap<i64, i64> = BTreeMap::new();
+    let last = notes.iter().map(|n| n.end).max().unwrap_or(0);
+    let mut window = 0i64;
+    while window <= last {
+        let onsets: Vec<i64> = notes.iter().flat_map(|n| [n.start, n.end]).filter(|t| *t >= window && *t < window + quarter).collect();
+        let grid = QUANTIZATION_GRIDS.iter().min_by_key(|g| onsets.iter().map(|t| {
+            let r = (t - window) % g.0;
+            r.min(g.0 - r) + g.1
+        }).sum::<i64>()).unwrap().0;
+        grids.insert(window, grid);
+        window += quarter;
+    }
+    let snap = |t: i64| -> i64 {
+        let window = t - t % quarter;
+        let grid = grids.get(&window).copied().unwrap_or(QUANTIZATION_GRIDS[0].0);
+        window + ((t - window) as f64 / grid as f64).round() as i64 * grid
+    };
+    let mut chords: BTreeMap<i64, Vec<MidiNote>> = BTreeMap::new();
+    for n in notes {
+        let start = snap(n.start);
+        let end = snap(n.end).max(start + grids.get(&(start - start % quarter)).copied().unwrap_or(QUANTIZATION_GRIDS[0].0));
+        let chord = chords.entry(start).or_default();
+        if !chord.iter().any(|c| c.pitch == n.pitch) {chord.push(MidiNote {start, end, ..n.clone()});}
+    }
+    chords
+}
+
+/// Convert the pitch bends played during a note to a bend effect. Bend values are in quarter tones.
+fn bend_effect(note: &MidiNote) -> Option<BendEffect> {
+    if note.bends.iter().all(|b| b.1 == 0) {return None;}
+    let length = (note.end - note.start).max(1);
+    let mut be = BendEffect {kind: BendType::Bend, ..Default::default()};
+    for b in &note.bends {
+        let position = ((b.0 - note.start).max(0) * i64::from(BEND_EFFECT_MAX_POSITION) / length).min(i64::from(BEND_EFFECT_MAX_POSITION)).to_u8().unwrap();
+        let value = (f32::from(b.1) / 8192.0 * PITCH_BEND_RANGE * 2.0).round().to_i8().unwrap();
+        if let Some(p) = be.points.last_mut() {if p.position == position {p.value = value; continue;}}
+        be.points.push(BendPoint {position, value, vibrato: false});
+    }
+    if be.points.first().map(|p| p.position) != Some(0) {be.points.insert(0, BendPoint::default());}
+    be.value = be.points.iter().map(|p| i16::from(p.value).abs()).max().unwrap_or(0) * GP_BEND_SEMITONE.to_i16().unwrap();
+    Some(be)
+}
+
+/// Convert micro-seconds per quarter note to beats per minute.
+fn bpm(micro_seconds: u32) -> i16 { (60_000_000f64 / f64::from(micro_seconds.max(1))).round().clamp(1.0, 999.0).to_i16().unwrap() }
+
+/// Read the chunks of a Standard MIDI File:
+/// - Header chunk: `MThd`, length (6), format (0, 1 or 2), number of tracks, division (ticks per quarter note). SMPTE divisions are not supported.
+/// - Track chunks: `MTrk`, length and events. Each event is preceded by its delta-time stored in a variable-length quantity.
+fn read_midi_events(data: &[u8]) -> MidiEvents {
+    let mut seek = 0usize;
+    if data.len() < 14 || &data[0..4] != b"MThd" {panic!("Not a Standard MIDI File");}
+    seek += 4;
+    let length = read_big_endian(data, &mut seek, 4).to_usize().unwrap();
+    let format = read_big_endian(data, &mut seek, 2);
+    let track_count = read_big_endian(data, &mut seek, 2);
+    let division = read_big_endian(data, &mut seek, 2);
+    if format > 1 {panic!("Only type 0 and type 1 MIDI files are supported");}
+    if (division & 0x8000) != 0 || division == 0 {panic!("SMPTE time division is not supported");}
+    seek = 8 + length;
+    let mut events = MidiEvents::default();
+    for t in 0..track_count {
+        while seek + 8 <= data.len() && &data[seek..seek + 4] != b"MTrk" { //skip unknown chunks
+            seek += 4;
+            let length = read_big_endian(data, &mut seek, 4).to_usize().unwrap();
+            seek += length;
+        }
+        if seek + 8 > data.len() {break;}
+        seek += 4;
+        let length = read_big_endian(data, &mut seek, 4).to_usize().unwrap();
+        let end = (seek + length).min(data.len());
+        read_midi_track(&data[..end], &mut seek, t.to_usize().unwrap(), i64::from(division), &mut events);
+        seek = end;
+    }
+    //the conductor track of a type-1 file has no notes: its name is the title of the song
+    if format == 1 {
+        if let Some(p) = events.parts.first() {if p.notes.is_empty() {events.title = p.name.clone();}}
+    }
+    events.parts.retain(|p| !p.notes.is_empty());
+    events.tempos.sort_by_key(|t| t.0);
+    events.time_signatures.sort_by_key(|t| t.0);
+    events.keys.sort_by_key(|k| k.0);
+    events
+}
+
+/// Read the events of a track chunk. Running status is supported. Notes are split by channel.
+fn read_midi_track(data: &[u8], seek: &mut usize, track: usize, division: i64, events: &mut MidiEvents) {
+    let to_ticks = |t: i64| -> i64 {t * DURATION_QUARTER_TIME / division};
+    let mut tick = 0i64;
+    let mut status = 0u8;
+    let mut name = String::new();
+    let mut parts: BTreeMap<u8, MidiPart> = BTreeMap::new();
+    let mut pending: BTreeMap<(u8, u8), Vec<(i64, i16)>> = BTreeMap::new();
+    let mut bends: [Vec<(i64, i16)>; 16] = Default::default();
+    while *seek < data.len() {
+        tick += i64::from(read_variable_length(data, seek));
+        let time = to_ticks(tick);
+        if data[*seek] & 0x80 != 0 {
+            let b = next_byte(data, seek);
+            if b == 0xff {
+                let kind = next_byte(data, seek);
+                let length = read_variable_length(data, seek).to_usize().unwrap();
+                let value = &data[*seek..(*seek + length).min(data.len())];
+                match kind {
+                    0x03 if name.is_empty() => name = String::from_utf8_lossy(value).trim().to_string(),
+                    0x51 if length == 3 => events.tempos.push((time, (u32::from(value[0]) << 16) | (u32::from(value[1]) << 8) | u32::from(value[2]))),
+                    0x58 if length >= 2 => events.time_signatures.push((time, value[0].to_i8().unwrap(), 1u16 << value[1].min(6))),
+                    0x59 if length == 2 => events.keys.push((time, value[0] as i8, value[1] != 0)),
+                    0x2f => {*seek += length; break;},
+                    _ => {},
+                }
+                *seek += length;
+                continue;
+            }
+            if b == 0xf0 || b == 0xf7 {
+                let length = read_variable_length(data, seek).to_usize().unwrap();
+                *seek += length;
+                continue;
+            }
+            status = b;
+        }
+        if status < 0x80 {panic!("Invalid MIDI event");}
+        let channel = status & 0x0f;
+        match status & 0xf0 {
+            0x80 | 0x90 => {
+                let pitch = next_byte(data, seek);
+                let velocity = next_byte(data, seek);
+                if (status & 0xf0) == 0x90 && velocity > 0 {
+                    pending.entry((channel, pitch)).or_default().push((time, i16::from(velocity)));
+                } else if let Some(p) = pending.get_mut(&(channel, pitch)) {
+                    if !p.is_empty() {
+                        let (start, velocity) = p.remove(0);
+                        let part = parts.entry(channel).or_insert_with(|| MidiPart {channel, ..Default::default()});
+                        part.notes.push(MidiNote {pitch: i16::from(pitch), velocity, start, end: time, bends: note_bends(&bends[channel as usize], start, time)});
+                    }
+                }
+            },
+            0xa0 | 0xb0 => {*seek += 2;},
+            0xc0 => {
+                let program = next_byte(data, seek);
+                parts.entry(channel).or_insert_with(|| MidiPart {channel, ..Default::default()}).program.get_or_insert(program);
+            },
+            0xd0 => {*seek += 1;},
+            0xe0 => {
+                let lsb = i16::from(next_byte(data, seek));
+                let msb = i16::from(next_byte(data, seek));
+                bends[channel as usize].push((time, ((msb << 7) | lsb) - 8192));
+            },
+            _ => panic!("Invalid MIDI event"),
+        }
+        events.end = events.end.max(time);
+    }
+    if parts.is_empty() && track == 0 {parts.insert(0, MidiPart::default());}
+    for (_, mut part) in parts {
+        if !name.is_empty() {part.name = name.clone();}
+        part.notes.sort_by_key(|n| (n.start, n.pitch));
+        events.parts.push(part);
+    }
+}
+
+/// Pitch bend values of a channel during a note, including the one in effect when the note starts.
+fn note_bends(bends: &[(i64, i16)], start: i64, end: i64) -> Vec<(i64, i16)> {
+    let mut result: Vec<(i64, i16)> = Vec::new();
+    if let Some(b) = bends.iter().rev().find(|b| b.0 <= start) {result.push((start, b.1));}
+    result.extend(bends.iter().filter(|b| b.0 > start && b.0 < end));
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{gp::*, enums::*};
+
+    /// Encode a variable-length quantity
+    fn vlq(mut n: u32) -> Vec<u8> {
+        let mut v = vec![(n & 0x7f) as u8];
+        n >>= 7;
+        while n > 0 {v.insert(0, ((n & 0x7f) as u8) | 0x80); n >>= 7;}
+        v
+    }
+    fn track_chunk(events: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for e in events {body.extend(vlq(e.0)); body.extend(&e.1);}
+        body.extend([0x00, 0xff, 0x2f, 0x00]);
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend((body.len() as u32).to_be_bytes());
+        chunk.extend(body);
+        chunk
+    }
+    fn midi_file(format: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"MThd".to_vec();
+        data.extend(6u32.to_be_bytes());
+        data.extend(format.to_be_bytes());
+        data.extend((tracks.len() as u16).to_be_bytes());
+        data.extend(480u16.to_be_bytes());
+        for t in tracks {data.extend(t);}
+        data
+    }
+
+    #[test]
+    fn test_midi_type_1() {
+        let conductor = track_chunk(&[(0, vec![0xff, 0x03, 4, b'S', b'o', b'n', b'g']),
+                                      (0, vec![0xff, 0x51, 3, 0x07, 0xa1, 0x20]), //120 bpm
+                                      (0, vec![0xff, 0x58, 4, 3, 2, 24, 8]),      //3/4
+                                      (1440, vec![0xff, 0x51, 3, 0x0f, 0x42, 0x40])]); //60 bpm on the second measure
+        //a quarter E2, a quarter rest, an eighth triplet, then a bent half note on the second measure
+        let guitar = track_chunk(&[(0, vec![0xc0, 29]),
+                                   (0, vec![0x90, 40, 100]), (480, vec![0x80, 40, 0]),
+                                   (480, vec![0x90, 52, 90]), (160, vec![0x80, 52, 0]),
+                                   (0, vec![0x90, 53, 90]), (160, vec![0x80, 53, 0]),
+                                   (0, vec![0x90, 55, 90]), (160, vec![0x80, 55, 0]),
+                                   (0, vec![0x90, 57, 90]), (0, vec![0xe0, 0x00, 0x60]), (960, vec![0x80, 57, 0]), (0, vec![0xe0, 0x00, 0x40])]);
+        let drums = track_chunk(&[(0, vec![0x99, 36, 100]), (0, vec![42, 100]), (240, vec![0x89, 36, 0]), (0, vec![0x89, 42, 0])]);
+        let mut song = Song::default();
+        song.read_midi(&midi_file(1, &[conductor, guitar, drums]));
+        assert_eq!(song.name, "Song");
+        assert_eq!(song.tempo, 120);
+        assert_eq!(song.tracks.len(), 2);
+        assert_eq!(song.measure_headers.len(), 2);
+        assert_eq!(song.measure_headers[0].time_signature.numerator, 3);
+        assert_eq!(song.measure_headers[1].tempo, 60);
+        assert_eq!(song.channels[0].get_instrument_name(), "Overdrive Guitar");
+
+        let beats = &song.tracks[0].measures[0].voices[0].beats;
+        assert_eq!(beats[0].notes[0].kind, NoteType::Normal);
+        assert_eq!((beats[0].notes[0].string, beats[0].notes[0].value), (6, 0));
+        assert_eq!(beats[1].status, BeatStatus::Rest);
+        assert_eq!((beats[2].duration.value, beats[2].duration.tuplet_enters, beats[2].duration.tuplet_times), (8, 3, 2));
+        assert_eq!(beats.len(), 5);
+        let starts: Vec<i64> = beats.iter().map(|b| b.start.unwrap()).collect();
+        assert_eq!(starts, vec![960, 1920, 2880, 3200, 3520]);
+        assert!(starts.windows(2).all(|w| w[0] < w[1]));
+        let next = &song.tracks[0].measures[1].voices[0].beats;
+        assert_eq!(next[0].duration.value, 2);
+        assert!(next[0].notes[0].effect.bend.is_some());
+        assert_eq!(next[0].notes[0].effect.bend.as_ref().unwrap().points[0].value, 2);
+        assert!(next[0].effect.mix_table_change.is_some());
+
+        assert!(song.tracks[1].percussion_track);
+        assert_eq!(song.tracks[1].measures[0].voices[0].beats[0].notes.len(), 2);
+        assert_eq!(song.tracks[1].measures[0].voices[0].beats[0].notes[0].value, 36);
+    }
+
+    #[test]
+    fn test_midi_drum_hits() {
+        //8 hits on the first quarter, then one hit on the second quarter
+        let mut events: Vec<(u32, Vec<u8>)> = (35..43).map(|p| (0, vec![0x99, p, 100])).collect();
+        events.extend((35..43).map(|p| (if p == 35 {480} else {0}, vec![0x89, p, 0])));
+        events.push((0, vec![0x99, 49, 100]));
+        events.push((480, vec![0x89, 49, 0]));
+        let mut song = Song::default();
+        song.read_midi(&midi_file(0, &[track_chunk(&events)]));
+        let measure = &song.tracks[0].measures[0];
+        assert_eq!(measure.voices[0].beats[0].notes.iter().map(|n| n.value).collect::<Vec<i16>>(), vec![35, 36, 37, 38, 39, 40]);
+        assert_eq!(measure.voices[1].beats[0].notes.iter().map(|n| (n.string, n.value)).collect::<Vec<(i8, i16)>>(), vec![(1, 41), (2, 42)]);
+        //the second voice has the rhythm of the first one
+        assert_eq!(measure.voices[1].beats.len(), measure.voices[0].beats.len());
+        assert!(measure.voices[1].beats[1..].iter().all(|b| b.status == BeatStatus::Rest && b.notes.is_empty()));
+        assert!(song.validate_rhythm().is_empty());
+    }
+
+    #[test]
+    fn test_midi_type_0_split_by_channel() {
+        let track = track_chunk(&[(0, vec![0x90, 60, 100]), (0, vec![0x91, 36, 100]),
+                                  (2400, vec![0x80, 60, 0]), (0, vec![0x81, 36, 0])]);
+        let mut song = Song::default();
+        song.read_midi(&midi_file(0, &[track]));
+        assert_eq!(song.tracks.len(), 2);
+        assert_eq!(song.measure_headers.len(), 2);
+        assert_eq!(song.tracks[0].measures[0].voices[0].beats[0].duration.value, 1);
+        let next = &song.tracks[0].measures[1].voices[0].beats;
+        assert_eq!(next[0].duration.value, 4);
+        assert_eq!(next[0].notes[0].kind, NoteType::Tie);
+        assert_eq!(next[1].status, BeatStatus::Rest);
+    }
+}
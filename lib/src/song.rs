@@ -10,10 +10,16 @@ use crate::key_signature::*;
 use crate::lyric::*;
 use crate::midi::*;
 use crate::rse::*;
+use crate::encoding::*;
+
+/// Maximum size of a Guitar Pro file in bytes (16 MB). The readers panic when more data is read, so that a stream or a corrupted
+/// length cannot make them read or allocate without limit.
+pub const GUITAR_FILE_MAX_SIZE: usize = 16777216;
 
 
 // Struct utility to read file: https://stackoverflow.com/questions/55555538/what-is-the-correct-way-to-read-a-binary-file-in-chunks-of-a-fixed-size-and-stor
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Song {
     pub version: Version,
     pub clipboard: Option<Clipboard>,
@@ -46,11 +52,19 @@ pub struct Song {
     pub master_effect: RseMasterEffect,
 
     pub page_setup: PageSetup,
+    /// Encoding of the strings of the file. Set it before reading to force an encoding, otherwise it is detected from the strings.
+    /// The strings are written with it, so a song read then written keeps its encoding.
+    #[cfg_attr(feature="serde", serde(default))]
+    pub encoding: TextEncoding,
 
     //Used to read the file
+    #[cfg_attr(feature="serde", serde(skip))]
     pub current_measure_number: Option<usize>,
+    #[cfg_attr(feature="serde", serde(skip))]
     pub current_track: Option<usize>,
+    #[cfg_attr(feature="serde", serde(skip))]
     pub current_voice_number: Option<usize>,
+    #[cfg_attr(feature="serde", serde(skip))]
     pub current_beat_number: Option<usize>,
 }
 
@@ -73,6 +87,7 @@ impl Default for Song {
         current_measure_number: None, current_track: None, current_voice_number: None, current_beat_number: None,
 
         page_setup: PageSetup::default(),
+        encoding: TextEncoding::Auto,
 
         master_effect: RseMasterEffect::default(),
 	}}
@@ -91,23 +106,28 @@ impl Song {
     /// - Tracks. See `read_tracks()`.
     /// - Measures. See `read_measures()`.
     pub fn read_gp3(&mut self, data: &[u8]) {
-        let mut seek: usize = 0;
-        self.version = read_version_string(data, &mut seek);
-        self.read_info(data, &mut seek);
-        self.triplet_feel = if read_bool(data, &mut seek) {TripletFeel::Eighth} else {TripletFeel::None};
+        let mut input = Input::new(data, self.encoding);
+        self.version = read_version_string(&mut input);
+        self.read_song_v3(&mut input, true);
+        self.encoding = input.encoding();
+    }
+    /// Read the song after the version string (see `read_gp3()`), the measures are skipped if `measures` is `false`.
+    fn read_song_v3(&mut self, data: &mut Input, measures: bool) {
+        self.read_info(data);
+        self.triplet_feel = if read_bool(data) {TripletFeel::Eighth} else {TripletFeel::None};
         //println!("Triplet feel: {}", self.triplet_feel);
-        self.tempo = read_int(data, &mut seek).to_i16().unwrap();
-        self.key.key = read_int(data, &mut seek).to_i8().unwrap();
+        self.tempo = read_int(data).to_i16().unwrap();
+        self.key.key = read_int(data).to_i8().unwrap();
         //println!("Tempo: {} bpm\t\tKey: {}", self.tempo, self.key.to_string());
-        self.read_midi_channels(data, &mut seek);
-        let measure_count = read_int(data, &mut seek).to_usize().unwrap();
-        let track_count = read_int(data, &mut seek).to_usize().unwrap();
+        self.read_midi_channels(data);
+        let measure_count = read_int(data).to_usize().unwrap();
+        let track_count = read_int(data).to_usize().unwrap();
         //println!("Measures count: {}\tTrack count: {}", measure_count, track_count);
         // Read measure headers. The *measures* are written one after another, their number have been specified previously.
-        self.read_measure_headers(data, &mut seek, measure_count);
+        self.read_measure_headers(data, measure_count);
         self.current_measure_number = Some(0);
-        self.read_tracks(data, &mut seek, track_count);
-        self.read_measures(data, &mut seek);
+        self.read_tracks(data, track_count);
+        if measures {self.read_measures(data);}
     }
     /// Read the song. A song consists of score information, triplet feel, tempo, song key, MIDI channels, measure and track count, measure headers, tracks, measures.
     /// - Version: `byte-size-string` of size 30.
@@ -124,68 +144,103 @@ impl Song {
     /// - Tracks. See `read_tracks()`.
     /// - Measures. See `read_measures()`.
     pub fn read_gp4(&mut self, data: &[u8]) {
-        let mut seek: usize = 0;
-        self.version = read_version_string(data, &mut seek);
-        self.read_clipboard(data, &mut seek);
-        self.read_info(data, &mut seek);
-        self.triplet_feel = if read_bool(data, &mut seek) {TripletFeel::Eighth} else {TripletFeel::None};
+        let mut input = Input::new(data, self.encoding);
+        self.version = read_version_string(&mut input);
+        self.read_song_v4(&mut input, true);
+        self.encoding = input.encoding();
+    }
+    /// Read the song after the version string (see `read_gp4()`), the measures are skipped if `measures` is `false`.
+    fn read_song_v4(&mut self, data: &mut Input, measures: bool) {
+        self.clipboard = self.read_clipboard(data);
+        self.read_info(data);
+        self.triplet_feel = if read_bool(data) {TripletFeel::Eighth} else {TripletFeel::None};
         //println!("Triplet feel: {}", self.triplet_feel);
-        self.lyrics = self.read_lyrics(data, &mut seek); //read lyrics
-        self.tempo = read_int(data, &mut seek).to_i16().unwrap();
-        self.key.key = read_int(data, &mut seek).to_i8().unwrap();
+        self.lyrics = self.read_lyrics(data); //read lyrics
+        self.tempo = read_int(data).to_i16().unwrap();
+        self.key.key = read_int(data).to_i8().unwrap();
         //println!("Tempo: {} bpm\t\tKey: {}", self.tempo, self.key.to_string());
-        read_signed_byte(data, &mut seek); //octave
-        self.read_midi_channels(data, &mut seek);
-        let measure_count = read_int(data, &mut seek).to_usize().unwrap();
-        let track_count = read_int(data, &mut seek).to_usize().unwrap();
+        read_signed_byte(data); //octave
+        self.read_midi_channels(data);
+        let measure_count = read_int(data).to_usize().unwrap();
+        let track_count = read_int(data).to_usize().unwrap();
         //println!("Measures count: {}\tTrack count: {}", measure_count, track_count);
         // Read measure headers. The *measures* are written one after another, their number have been specified previously.
-        self.read_measure_headers(data, &mut seek, measure_count);
+        self.read_measure_headers(data, measure_count);
         //self.current_measure_number = Some(0);
-        self.read_tracks(data, &mut seek, track_count);
-        self.read_measures(data, &mut seek);
+        self.read_tracks(data, track_count);
+        if measures {self.read_measures(data);}
     }
     pub fn read_gp5(&mut self, data: &[u8]) {
-        let mut seek: usize = 0;
-        self.version = read_version_string(data, &mut seek);
-        self.read_clipboard(data, &mut seek);
-        self.read_info(data, &mut seek);
-        self.lyrics = self.read_lyrics(data, &mut seek); //read lyrics
-        self.master_effect = self.read_rse_master_effect(data, &mut seek);
-        self.read_page_setup(data, &mut seek);
-        self.tempo_name = read_int_size_string(data, &mut seek);
-        self.tempo = read_int(data, &mut seek).to_i16().unwrap();
-        self.hide_tempo = if self.version.number > (5,0,0) {read_bool(data, &mut seek)} else {false};
-        self.key.key = read_signed_byte(data, &mut seek);
-        read_int(data, &mut seek); //octave
-        self.read_midi_channels(data, &mut seek);
-        let directions = self.read_directions(data, &mut seek);
-        self.master_effect.reverb = read_int(data, &mut seek).to_f32().unwrap();
-        let measure_count = read_int(data, &mut seek).to_usize().unwrap();
-        let track_count = read_int(data, &mut seek).to_usize().unwrap();
+        let mut input = Input::new(data, self.encoding);
+        self.version = read_version_string(&mut input);
+        self.read_song_v5(&mut input, true);
+        self.encoding = input.encoding();
+    }
+    /// Read the song after the version string (see `read_gp5()`), the measures are skipped if `measures` is `false`.
+    fn read_song_v5(&mut self, data: &mut Input, measures: bool) {
+        self.clipboard = self.read_clipboard(data);
+        self.read_info(data);
+        self.lyrics = self.read_lyrics(data); //read lyrics
+        self.master_effect = self.read_rse_master_effect(data);
+        self.read_page_setup(data);
+        self.tempo_name = read_int_byte_size_string(data);
+        self.tempo = read_int(data).to_i16().unwrap();
+        self.hide_tempo = if self.version.number > (5,0,0) {read_bool(data)} else {false};
+        self.key.key = read_signed_byte(data);
+        read_int(data); //octave
+        self.read_midi_channels(data);
+        let directions = self.read_directions(data);
+        self.master_effect.reverb = read_int(data).to_f32().unwrap();
+        let measure_count = read_int(data).to_usize().unwrap();
+        let track_count = read_int(data).to_usize().unwrap();
         //println!("{} {} {} {:?}", self.tempo_name, self.tempo, self.hide_tempo, self.key.key); //OK
-        println!("Track count: {} \t Measure count: {}", track_count, measure_count); //OK
-        self.read_measure_headers_v5(data, &mut seek, measure_count, &directions);
-        self.read_tracks_v5(data, &mut seek, track_count);
-        println!("read_gp5(), after tracks   \t seek: {}", seek);
-        self.read_measures(data, &mut seek);
-        println!("read_gp5(), after measures \t seek: {}", seek);
+        //println!("Track count: {} \t Measure count: {}", track_count, measure_count); //OK
+        self.read_measure_headers_v5(data, measure_count, &directions);
+        self.read_tracks_v5(data, track_count);
+        //println!("read_gp5(), after tracks   \t position: {}", data._position());
+        if measures {self.read_measures(data);}
+        //println!("read_gp5(), after measures \t position: {}", data._position());
+    }
+
+    /// Read a Guitar Pro 3, 4 or 5 file from a stream (e.g. a pipe, an uploaded file or an entry of an archive) without loading it in
+    /// memory first: the format is detected from the version string at the start of the file. At most `GUITAR_FILE_MAX_SIZE` bytes
+    /// are read, so an untrusted stream (e.g. the body of a request of a server) can be read directly.
+    ///
+    /// Panics if the version is not supported, the file is corrupted or bigger than `GUITAR_FILE_MAX_SIZE`.
+    pub fn read_from<R: std::io::Read>(&mut self, reader: R) { self.read_stream(reader, true); }
+
+    /// Read the information, the MIDI channels, the measure headers and the tracks of a Guitar Pro 3, 4 or 5 file from a stream and
+    /// stop before the measures: the tracks have no measures. It is much faster than `read_from()` to index many files (see `summary()`).
+    ///
+    /// Panics if the version is not supported, the file is corrupted or bigger than `GUITAR_FILE_MAX_SIZE`.
+    pub fn read_header_only<R: std::io::Read>(&mut self, reader: R) { self.read_stream(reader, false); }
+
+    fn read_stream<R: std::io::Read>(&mut self, reader: R, measures: bool) {
+        let mut input = Input::new(std::io::BufReader::new(reader), self.encoding);
+        self.version = read_version_string(&mut input);
+        if !VERSIONS.iter().any(|v| v.2 == self.version.data) {panic!("Unsupported file version: {}", self.version.data);}
+        match self.version.number.0 {
+            3 => self.read_song_v3(&mut input, measures),
+            4 => self.read_song_v4(&mut input, measures),
+            _ => self.read_song_v5(&mut input, measures),
+        }
+        self.encoding = input.encoding();
     }
 
     /// Read information (name, artist, ...)
-    fn read_info(&mut self, data: &[u8], seek: &mut usize) {
-        self.name        = read_int_byte_size_string(data, seek);//.replace("\r", " ").replace("\n", " ").trim().to_owned();
-        self.subtitle    = read_int_byte_size_string(data, seek);
-        self.artist      = read_int_byte_size_string(data, seek);
-        self.album       = read_int_byte_size_string(data, seek);
-        self.words       = read_int_byte_size_string(data, seek); //music
-        self.author      = if self.version.number.0 < 5 {self.words.clone()} else {read_int_byte_size_string(data, seek)};
-        self.copyright   = read_int_byte_size_string(data, seek);
-        self.writer      = read_int_byte_size_string(data, seek); //tabbed by
-        self.instructions= read_int_byte_size_string(data, seek); //instructions
+    fn read_info(&mut self, data: &mut Input) {
+        self.name        = read_int_byte_size_string(data);//.replace("\r", " ").replace("\n", " ").trim().to_owned();
+        self.subtitle    = read_int_byte_size_string(data);
+        self.artist      = read_int_byte_size_string(data);
+        self.album       = read_int_byte_size_string(data);
+        self.words       = read_int_byte_size_string(data); //music
+        self.author      = if self.version.number.0 < 5 {self.words.clone()} else {read_int_byte_size_string(data)};
+        self.copyright   = read_int_byte_size_string(data);
+        self.writer      = read_int_byte_size_string(data); //tabbed by
+        self.instructions= read_int_byte_size_string(data); //instructions
         //notices
-        let nc = read_int(data, seek).to_usize().unwrap(); //notes count
-        if nc > 0 { for i in 0..nc { self.notice.push(read_int_byte_size_string(data, seek)); println!("  {}\t\t{}",i, self.notice[self.notice.len()-1]); }}
+        let nc = read_int(data).to_usize().unwrap(); //notes count
+        if nc > 0 { for _i in 0..nc { self.notice.push(read_int_byte_size_string(data)); /*println!("  {}\t\t{}",_i, self.notice[self.notice.len()-1]);*/  }}
     }
 
     /*pub const _MAX_STRINGS: i32 = 25;
@@ -196,21 +251,26 @@ impl Song {
     /// Write data to a Vec<u8>, you are free to use the encoded data to write it in a file or in a database or do something else.
     pub fn write(&self, version: (u8,u8,u8), clipboard: Option<bool>) ->Vec<u8> {
         let mut data: Vec<u8> = Vec::with_capacity(8388608); //capacity of 8MB, should be sufficient
-        write_version(&mut data, version);
-        if clipboard.is_some() && clipboard.unwrap() && version.0 >= 4 {self.write_clipboard(&mut data, &version);}
+        let clipboard = clipboard == Some(true) && version.0 >= 4;
+        write_version(&mut data, version, clipboard);
+        if clipboard {self.write_clipboard(&mut data, &version);}
         self.write_info(&mut data, version);
         if version.0 < 5 {write_bool(&mut data, self.triplet_feel != TripletFeel::None);}
         if version.0 >= 4 {self.write_lyrics(&mut data);}
         if version > (5,0,0) {self.write_rse_master_effect(&mut data);}
         if version.0 >= 5 {
             self.write_page_setup(&mut data);
-            write_int_byte_size_string(&mut data, &self.tempo_name);
+            write_int_byte_size_string(&mut data, &self.tempo_name, self.encoding);
         }
         write_i32(&mut data, self.tempo.to_i32().unwrap());
         if version > (5,0,0) {write_bool(&mut data, self.hide_tempo);}
-        write_i32(&mut data, self.key.key.to_i32().unwrap());
-
-        if version.0 >= 4 {write_signed_byte(&mut data, 0);} //octave
+        if version.0 < 5 {
+            write_i32(&mut data, self.key.key.to_i32().unwrap());
+            if version.0 == 4 {write_signed_byte(&mut data, 0);} //octave
+        } else {
+            write_signed_byte(&mut data, self.key.key);
+            write_i32(&mut data, 0); //octave
+        }
         self.write_midi_channels(&mut data); //TODO: fixme for writing
         //return data;
 
@@ -219,7 +279,7 @@ impl Song {
             self.write_master_reverb(&mut data);
         }
 
-        write_i32(&mut data, self.tracks[0].measures.len().to_i32().unwrap());
+        write_i32(&mut data, self.measure_headers.len().to_i32().unwrap());
         write_i32(&mut data, self.tracks.len().to_i32().unwrap());
         self.write_measure_headers(&mut data, &version);
         self.write_tracks(&mut data, &version);
@@ -228,20 +288,20 @@ impl Song {
         data
     }
     fn write_info(&self, data: &mut Vec<u8>, version: (u8,u8,u8)) {
-        write_int_byte_size_string(data, &self.name);
-        write_int_byte_size_string(data, &self.subtitle);
-        write_int_byte_size_string(data, &self.artist);
-        write_int_byte_size_string(data, &self.album);
-        if version.0 < 5 {write_int_byte_size_string(data, &self.pack_author());}
+        write_int_byte_size_string(data, &self.name, self.encoding);
+        write_int_byte_size_string(data, &self.subtitle, self.encoding);
+        write_int_byte_size_string(data, &self.artist, self.encoding);
+        write_int_byte_size_string(data, &self.album, self.encoding);
+        if version.0 < 5 {write_int_byte_size_string(data, &self.pack_author(), self.encoding);}
         else {
-            write_int_byte_size_string(data, &self.words);
-            write_int_byte_size_string(data, &self.author);
+            write_int_byte_size_string(data, &self.words, self.encoding);
+            write_int_byte_size_string(data, &self.author, self.encoding);
         }
-        write_int_byte_size_string(data, &self.copyright);
-        write_int_byte_size_string(data, &self.writer);
-        write_int_byte_size_string(data, &self.instructions);
+        write_int_byte_size_string(data, &self.copyright, self.encoding);
+        write_int_byte_size_string(data, &self.writer, self.encoding);
+        write_int_byte_size_string(data, &self.instructions, self.encoding);
         write_i32(data, self.notice.len().to_i32().unwrap());
-        for i in 0..self.notice.len() {write_int_byte_size_string(data, &self.notice[i]);}
+        for i in 0..self.notice.len() {write_int_byte_size_string(data, &self.notice[i], self.encoding);}
     }
     fn pack_author(&self) -> String {
         if !self.words.is_empty() && !self.author.is_empty() {
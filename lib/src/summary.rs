@@ -0,0 +1,62 @@
+use crate::{gp::*, key_signature::*};
+
+/// The main settings of a track, see `SongSummary`
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackSummary {
+    pub name: String,
+    /// Name of the MIDI instrument of the channel of the track
+    pub instrument: String,
+    pub percussion_track: bool,
+    /// String numbers and MIDI pitches of the open strings, from the first (highest) string
+    pub strings: Vec<(i8, i8)>,
+    /// Capo fret
+    pub offset: i32,
+    pub fret_count: u8,
+    pub color: i32,
+}
+
+/// The information needed to index a song without its measures, see `Song::read_header_only()`
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SongSummary {
+    /// Version string of the file
+    pub version: String,
+    pub name: String,
+    pub subtitle: String,
+    pub artist: String,
+    pub album: String,
+    pub words: String,
+    pub author: String,
+    pub copyright: String,
+    pub writer: String,
+    pub instructions: String,
+    pub notice: Vec<String>,
+    pub tempo: i16,
+    pub key: KeySignature,
+    pub measure_count: usize,
+    pub tracks: Vec<TrackSummary>,
+}
+impl SongSummary {
+    /// Read the summary of a Guitar Pro 3, 4 or 5 file from a stream, skipping its measures.
+    ///
+    /// Panics if the version is not supported or the file is corrupted.
+    pub fn read<R: std::io::Read>(reader: R) -> SongSummary {
+        let mut song = Song::default();
+        song.read_header_only(reader);
+        song.summary()
+    }
+}
+
+impl Song {
+    /// Get the summary of the song, it can be read with `read_header_only()`.
+    pub fn summary(&self) -> SongSummary {
+        let tracks = self.tracks.iter().map(|t| TrackSummary {name: t.name.clone(), percussion_track: t.percussion_track,
+            instrument: self.channels.get(t.channel_index).map(|c| c.get_instrument_name()).unwrap_or_default(),
+            strings: t.strings.clone(), offset: t.offset, fret_count: t.fret_count, color: t.color}).collect();
+        SongSummary {version: self.version.data.clone(), name: self.name.clone(), subtitle: self.subtitle.clone(), artist: self.artist.clone(),
+                     album: self.album.clone(), words: self.words.clone(), author: self.author.clone(), copyright: self.copyright.clone(),
+                     writer: self.writer.clone(), instructions: self.instructions.clone(), notice: self.notice.clone(), tempo: self.tempo,
+                     key: self.key.clone(), measure_count: self.measure_headers.len(), tracks}
+    }
+}
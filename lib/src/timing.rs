@@ -0,0 +1,201 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, enums::*, key_signature::*};
+
+/// A change of tempo
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TempoChange {
+    /// Absolute tick of the change, the song starts at `960`
+    pub tick: i64,
+    /// Tempo reached at the end of the transition, in quarter notes per minute
+    pub tempo: f64,
+    /// Length in ticks of the progressive change from the previous tempo, `0` for an immediate change
+    pub transition: i64,
+}
+
+/// Tempo changes of a song, sorted by tick. The first change is the initial tempo of the song.
+#[derive(Debug,Clone,PartialEq,Default)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TempoMap {
+    pub changes: Vec<TempoChange>,
+}
+impl TempoMap {
+    /// Get the time in seconds elapsed between the start of the song and an absolute `tick`.
+    /// Transitions change the tempo linearly over their length.
+    pub fn seconds(&self, tick: i64) -> f64 {
+        let mut seconds = 0.0;
+        let mut previous = match self.changes.first() {Some(c) => c.tempo, None => return 0.0};
+        for (i, c) in self.changes.iter().enumerate() {
+            if tick <= c.tick {break;}
+            let end = self.changes.get(i + 1).map(|n| n.tick).unwrap_or(i64::MAX).min(tick);
+            let (s, tempo) = segment(previous, c, end);
+            seconds += s;
+            previous = tempo;
+        }
+        seconds
+    }
+
+    /// Get the tempo at an absolute `tick`, in quarter notes per minute.
+    pub fn tempo_at(&self, tick: i64) -> f64 {
+        let mut previous = match self.changes.first() {Some(c) => c.tempo, None => return 0.0};
+        for (i, c) in self.changes.iter().enumerate() {
+            if tick < c.tick {break;}
+            let end = self.changes.get(i + 1).map(|n| n.tick).unwrap_or(i64::MAX).min(tick);
+            previous = segment(previous, c, end).1;
+        }
+        previous
+    }
+}
+
+/// Seconds elapsed from the tick of a change to `end` and tempo reached at `end`, the tempo going from `from` to the value of the change.
+fn segment(from: f64, change: &TempoChange, end: i64) -> (f64, f64) {
+    //a tick lasts 60 / (960 * tempo) seconds
+    let per_tick = |tempo: f64| 60.0 / (DURATION_QUARTER_TIME.to_f64().unwrap() * tempo);
+    let length = end - change.tick;
+    let ramp = length.min(change.transition);
+    let mut seconds = 0.0;
+    let mut tempo = change.tempo;
+    if ramp > 0 {
+        let delta = change.tempo - from;
+        let t = change.transition.to_f64().unwrap();
+        tempo = from + delta * ramp.to_f64().unwrap() / t;
+        //integral of the duration of a tick while the tempo changes linearly
+        seconds += if delta.abs() < 1e-9 {per_tick(from) * ramp.to_f64().unwrap()} else {per_tick(1.0) * t / delta * (tempo / from).ln()};
+    }
+    if length > ramp {seconds += per_tick(change.tempo) * (length - ramp).to_f64().unwrap();}
+    (seconds, tempo)
+}
+
+/// Timing of a note, extended by the following tied notes of the same string
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoteTiming {
+    /// Index of the note in the beat
+    pub note: usize,
+    /// Absolute start in ticks
+    pub tick: i64,
+    /// Length in ticks, including the tied notes and the duration percent of the note
+    pub duration: i64,
+    /// Start and end in seconds from the start of the song
+    pub seconds: f64,
+    pub end_seconds: f64,
+}
+
+/// Timing of a beat
+#[derive(Debug,Clone,PartialEq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BeatTiming {
+    pub track: usize,
+    pub measure: usize,
+    pub voice: usize,
+    /// Index of the beat in the voice
+    pub beat: usize,
+    /// Absolute start in ticks, the song starts at `960`
+    pub tick: i64,
+    /// Length in ticks
+    pub duration: i64,
+    /// Start and end in seconds from the start of the song
+    pub seconds: f64,
+    pub end_seconds: f64,
+    /// Timing of the played notes of the beat. Tied notes are included in the note they continue.
+    pub notes: Vec<NoteTiming>,
+}
+
+impl Song {
+    /// Get the tempo map of the song, built from the tempo of the song, the tempo of the measure headers and the tempo changes of
+    /// the mix tables of every track. The `duration` of a mix table tempo change is a number of beats of the time signature.
+    pub fn tempo_map(&self) -> TempoMap {
+        let start = self.measure_headers.first().map(|h| h.start).unwrap_or(DURATION_QUARTER_TIME);
+        let mut changes = vec![TempoChange {tick: start, tempo: f64::from(self.tempo), transition: 0}];
+        for h in self.measure_headers.iter().filter(|h| h.tempo > 0) {changes.push(TempoChange {tick: h.start, tempo: f64::from(h.tempo), transition: 0});}
+        for t in 0..self.tracks.len() {
+            for (m, v, b, tick) in self.beat_ticks(t) {
+                let beat = &self.tracks[t].measures[m].voices[v].beats[b];
+                if let Some(mtc) = &beat.effect.mix_table_change {
+                    if let Some(item) = &mtc.tempo {
+                        let transition = i64::from(item.duration) * i64::from(self.measure_headers[m].time_signature.denominator.time());
+                        changes.push(TempoChange {tick, tempo: f64::from(item.value), transition});
+                    }
+                }
+            }
+        }
+        //a later change at the same tick replaces the previous one
+        changes.sort_by_key(|c| c.tick);
+        let mut map = TempoMap {changes: Vec::with_capacity(changes.len())};
+        for c in changes {
+            if map.changes.last().map(|l| l.tick == c.tick).unwrap_or(false) {map.changes.pop();}
+            map.changes.push(c);
+        }
+        map
+    }
+
+    /// Compute the timing of every beat and note of the song: absolute ticks are recomputed from the measure headers and the durations
+    /// of the beats of each voice (the `start` stored in the beats is ignored), then mapped to seconds with the tempo map.
+    /// Repeats are not expanded. Beats are sorted by track, measure, voice and beat.
+    pub fn timing(&self) -> Vec<BeatTiming> {
+        let map = self.tempo_map();
+        let mut timings = Vec::new();
+        for t in 0..self.tracks.len() {
+            let ticks = self.beat_ticks(t);
+            for (i, (m, v, b, tick)) in ticks.iter().enumerate() {
+                let beat = &self.tracks[t].measures[*m].voices[*v].beats[*b];
+                let duration = i64::from(beat.duration.time());
+                let mut notes = Vec::new();
+                if beat.status == BeatStatus::Normal {
+                    for (n, note) in beat.notes.iter().enumerate().filter(|n| n.1.kind != NoteType::Rest && n.1.kind != NoteType::Tie) {
+                        let mut length = (duration.to_f64().unwrap() * f64::from(note.duration_percent)).round().to_i64().unwrap();
+                        //following beats of the voice continuing the note
+                        for (m2, v2, b2, tick2) in ticks[i + 1..].iter().filter(|x| x.1 == *v) {
+                            let next = &self.tracks[t].measures[*m2].voices[*v2].beats[*b2];
+                            if next.status == BeatStatus::Empty {continue;}
+                            match next.notes.iter().find(|x| x.string == note.string && x.kind == NoteType::Tie) {
+                                Some(_) => length = tick2 - tick + i64::from(next.duration.time()),
+                                None => break,
+                            }
+                        }
+                        notes.push(NoteTiming {note: n, tick: *tick, duration: length, seconds: map.seconds(*tick), end_seconds: map.seconds(tick + length)});
+                    }
+                }
+                timings.push(BeatTiming {track: t, measure: *m, voice: *v, beat: *b, tick: *tick, duration,
+                                         seconds: map.seconds(*tick), end_seconds: map.seconds(tick + duration), notes});
+            }
+        }
+        timings
+    }
+
+    /// Set the `start` of every beat to the absolute tick computed like `Song::timing()`. The readers of `Beat::start` (chord detection,
+    /// lyrics, progression) skip the beats without start, so call it after building or editing beats by hand.
+    pub fn update_beat_starts(&mut self) {
+        for t in 0..self.tracks.len() {
+            for (m, v, b, tick) in self.beat_ticks(t) {self.tracks[t].measures[m].voices[v].beats[b].start = Some(tick);}
+        }
+    }
+
+    /// Get the time in seconds of an absolute `tick` (see `Song::tempo_map()`).
+    pub fn tick_to_seconds(&self, tick: i64) -> f64 { self.tempo_map().seconds(tick) }
+
+    /// Get the length of the song in seconds, from the start of the first measure to the end of the last one. Repeats are not expanded.
+    pub fn length_seconds(&self) -> f64 {
+        match self.measure_headers.last() {
+            Some(h) => self.tick_to_seconds(h.start + h.length()),
+            None => 0.0,
+        }
+    }
+
+    /// Absolute ticks of the beats of a track: `(measure, voice, beat, tick)` sorted by measure, voice and beat.
+    fn beat_ticks(&self, track: usize) -> Vec<(usize, usize, usize, i64)> {
+        let mut ticks = Vec::new();
+        for (m, measure) in self.tracks[track].measures.iter().enumerate() {
+            let start = self.measure_headers.get(m).map(|h| h.start).unwrap_or(measure.start);
+            for (v, voice) in measure.voices.iter().enumerate() {
+                let mut tick = start;
+                for (b, beat) in voice.beats.iter().enumerate() {
+                    ticks.push((m, v, b, tick));
+                    tick += i64::from(beat.duration.time());
+                }
+            }
+        }
+        ticks
+    }
+}
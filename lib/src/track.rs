@@ -1,9 +1,31 @@
+use std::collections::HashMap;
+
 use fraction::ToPrimitive;
 
-use crate::{io::*, gp::*, enums::*, rse::*, measure::*};
+use crate::{io::*, gp::*, enums::*, rse::*, measure::*, midi::*, lyric::*};
+
+/// Reason why the tracks of a song cannot be merged into another one, see `Song::merge_tracks()`
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum MergeError {
+    /// A measure (counted from 1) has different time signatures in the two songs, as (numerator, denominator)
+    TimeSignature {measure: usize, first: (i8, u16), second: (i8, u16)},
+    /// Every MIDI channel is used, none is left for the track
+    NoFreeChannel {track: String},
+}
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MergeError::TimeSignature {measure, first, second} => write!(f, "measure {} is {}/{} in the first song and {}/{} in the second one",
+                                                                            measure, first.0, first.1, second.0, second.1),
+            MergeError::NoFreeChannel {track} => write!(f, "no free MIDI channel left for the track {}", track),
+        }
+    }
+}
+impl std::error::Error for MergeError {}
 
 /// Settings of the track.
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackSettings {
     pub tablature: bool,
     pub notation: bool,
@@ -33,6 +55,7 @@ impl Default for TrackSettings { fn default() -> Self { TrackSettings {
 
 
 #[derive(Debug,Clone)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Track {
     pub number: i32,
 	pub offset: i32,
@@ -76,15 +99,15 @@ impl Default for Track {
 impl Song {
     /// Read tracks. The tracks are written one after another, their number having been specified previously in :meth:`GP3File.readSong`.
     /// - `track_count`: number of tracks to expect.
-    pub(crate) fn read_tracks(&mut self, data: &[u8], seek: &mut usize, track_count: usize) {
+    pub(crate) fn read_tracks(&mut self, data: &mut Input, track_count: usize) {
         //println!("read_tracks()");
-        for i in 0..track_count {self.read_track(data, seek, i);}
+        for i in 0..track_count {self.read_track(data, i);}
     }
 
-    pub(crate) fn read_tracks_v5(&mut self, data: &[u8], seek: &mut usize, track_count: usize) {
+    pub(crate) fn read_tracks_v5(&mut self, data: &mut Input, track_count: usize) {
         //println!("read_tracks_v5(): {:?} {}", self.version.number, self.version.number == (5,1,0));
-        for i in 0..track_count { self.read_track_v5(data, seek, i); }
-        *seek += if self.version.number == (5,0,0) {2} else {1};
+        for i in 0..track_count { self.read_track_v5(data, i); }
+        data.skip(if self.version.number == (5,0,0) {2} else {1});
     }
 
     /// Read a  track. The first byte is the track's flags. It presides the track's attributes:
@@ -104,30 +127,31 @@ impl Song {
     /// * **Number of frets**: `integer`. The number of frets of the instrument.
     /// * **Height of the capo**: `integer`. The number of the fret on which a capo is present. If no capo is used, the value is `0x00000000`.
     /// * **Track's color**: `color`. The track's displayed color in Guitar Pro.
-    fn read_track(&mut self, data: &[u8], seek: &mut usize, number: usize) {
+    fn read_track(&mut self, data: &mut Input, number: usize) {
         let mut track = Track{number: number.to_i32().unwrap(), ..Default::default()};
         //read the flag
-        let flags = read_byte(data, seek);
+        let flags = read_byte(data);
         //println!("read_track(), flags: {}", flags);
         track.percussion_track = (flags & 0x01) == 0x01; //Drums track
         track.twelve_stringed_guitar_track = (flags & 0x02) == 0x02; //12 stringed guitar track
         track.banjo_track = (flags & 0x04) == 0x04; //Banjo track
 
-        track.name = read_byte_size_string(data, seek, 40);
-        let string_count = read_int(data, seek).to_u8().unwrap();
+        track.name = read_byte_size_string(data, 40);
+        let string_count = read_int(data).to_u8().unwrap();
         track.strings.clear();
         for i in 0..7i8 {
-            let i_tuning = read_int(data, seek).to_i8().unwrap();
+            let i_tuning = read_int(data).to_i8().unwrap();
             if string_count.to_i8().unwrap() > i { track.strings.push((i + 1, i_tuning)); }
         }
         //println!("tuning: {:?}", track.strings);
-        track.port = read_int(data, seek).to_u8().unwrap();
-        let index = self.read_channel(data, seek);
+        track.port = read_int(data).to_u8().unwrap();
+        let index = self.read_channel(data, track.port);
+        track.channel_index = index;
         if self.channels[index].channel == 9 {track.percussion_track = true;}
-        track.fret_count = read_int(data, seek).to_u8().unwrap();
-        track.offset = read_int(data, seek);
-        track.color = read_color(data, seek);
-        println!("\tInstrument: {} \t Strings: {}/{} ({:?})", self.channels[index].get_instrument_name(), string_count, track.strings.len(), track.strings);
+        track.fret_count = read_int(data).to_u8().unwrap();
+        track.offset = read_int(data);
+        track.color = read_color(data);
+        //println!("\tInstrument: {} \t Strings: {}/{} ({:?})", self.channels[index].get_instrument_name(), string_count, track.strings.len(), track.strings);
         self.tracks.push(track);
     }
 
@@ -168,36 +192,37 @@ impl Song {
     /// - Auto accentuation: :ref:`byte`. See :class:`guitarpro.models.Accentuation`.
     /// - MIDI bank: :ref:`byte`.
     /// - Track RSE. See `readTrackRSE`.
-    fn read_track_v5(&mut self, data: &[u8], seek: &mut usize, number: usize) {
+    fn read_track_v5(&mut self, data: &mut Input, number: usize) {
         let mut track = Track{number: number.to_i32().unwrap(), ..Default::default()};
-        if number == 0 || self.version.number == (5,0,0) {*seek += 1;} //always 0 //missing 3 skips?
-        let flags1 = read_byte(data, seek);
-        //println!("read_track_v5(), flags1: {} \t seek: {}", flags1, *seek);
+        if number == 0 || self.version.number == (5,0,0) {data.skip(1);} //always 0 //missing 3 skips?
+        let flags1 = read_byte(data);
+        //println!("read_track_v5(), flags1: {} \t position: {}", flags1, data._position());
         track.percussion_track  = (flags1 & 0x01) == 0x01;
-        track.banjo_track       = (flags1 & 0x02) == 0x02;
-        track.visible           = (flags1 & 0x04) == 0x04;
+        track.twelve_stringed_guitar_track = (flags1 & 0x02) == 0x02;
+        track.banjo_track       = (flags1 & 0x04) == 0x04;
+        track.visible           = (flags1 & 0x08) == 0x08;
         track.solo              = (flags1 & 0x10) == 0x10;
         track.mute              = (flags1 & 0x20) == 0x20;
         track.use_rse           = (flags1 & 0x40) == 0x40;
         track.indicate_tuning   = (flags1 & 0x80) == 0x80;
-        track.name              = read_byte_size_string(data, seek, 40);
-        //let string_count = read_int(data, seek).to_u8().unwrap();
-        let sc = read_int(data, seek);
+        track.name              = read_byte_size_string(data, 40);
+        //let string_count = read_int(data).to_u8().unwrap();
+        let sc = read_int(data);
         //println!("read_track_v5(), track:name: \"{}\", string count: {}", track.name, sc);
         let string_count = sc.to_u8().unwrap();
         track.strings.clear();
         for i in 0i8..7i8 {
-            let i_tuning = read_int(data, seek).to_i8().unwrap();
+            let i_tuning = read_int(data).to_i8().unwrap();
             if string_count.to_i8().unwrap() > i { track.strings.push((i + 1, i_tuning)); }
         }
-        track.port = read_int(data, seek).to_u8().unwrap();
-        self.read_channel(data, seek);
-        if self.channels[number].channel == 9 {track.percussion_track = true;}
-        track.fret_count    = read_int(data, seek).to_u8().unwrap();
-        track.offset        = read_int(data, seek);
-        track.color         = read_color(data, seek);
+        track.port = read_int(data).to_u8().unwrap();
+        track.channel_index = self.read_channel(data, track.port);
+        if self.channels[track.channel_index].channel == 9 {track.percussion_track = true;}
+        track.fret_count    = read_int(data).to_u8().unwrap();
+        track.offset        = read_int(data);
+        track.color         = read_color(data);
 
-        let flags2 = read_short(data, seek);
+        let flags2 = read_short(data);
         //println!("read_track_v5(), flags2: {}", flags2);
         track.settings.tablature            = (flags2 & 0x0001) == 0x0001;
         track.settings.notation             = (flags2 & 0x0002) == 0x0002;
@@ -212,9 +237,9 @@ impl Song {
         track.settings.auto_brush           = (flags2 & 0x0400) == 0x0400;
         track.settings.extend_rythmic       = (flags2 & 0x0800) == 0x0800;
 
-        track.rse.auto_accentuation = get_accentuation(read_byte(data, seek));
-        self.channels[number].bank = read_byte(data, seek);
-        self.read_track_rse(data, seek, &mut track);
+        track.rse.auto_accentuation = get_accentuation(read_byte(data));
+        self.channels[track.channel_index].bank = read_byte(data);
+        self.read_track_rse(data, &mut track);
         self.tracks.push(track);
     }
 
@@ -233,25 +258,24 @@ impl Song {
         if self.tracks[number].twelve_stringed_guitar_track {flags |= 0x02;}
         if self.tracks[number].banjo_track {flags |= 0x04;}
         write_byte(data, flags);
-        write_byte_size_string(data, &self.tracks[number].name);
-        write_placeholder_default(data, 30 - self.tracks[number].name.len());
+        write_padded_byte_size_string(data, &self.tracks[number].name, 40, self.encoding);
         write_i32(data, self.tracks[number].strings.len().to_i32().unwrap());
         for i in 0..7usize {
-            let mut tuning = 0i8;
+            let mut tuning = -1i8;
             if i < self.tracks[number].strings.len() { tuning = self.tracks[number].strings[i].1;}
             write_i32(data, tuning.to_i32().unwrap());
         }
         write_i32(data, self.tracks[number].port.to_i32().unwrap());
         //write channel
-        write_i32(data, self.channels[self.tracks[number].channel_index].channel.to_i32().unwrap() + 1);
-        write_i32(data, self.channels[self.tracks[number].channel_index].effect_channel.to_i32().unwrap() + 1);
+        write_i32(data, (self.channels[self.tracks[number].channel_index].channel % 16).to_i32().unwrap() + 1);
+        write_i32(data, (self.channels[self.tracks[number].channel_index].effect_channel % 16).to_i32().unwrap() + 1);
         //end write channel
         write_i32(data, self.tracks[number].fret_count.to_i32().unwrap());
         write_i32(data, self.tracks[number].offset);
         write_color(data, self.tracks[number].color);
     }
     fn write_track_v5(&self, data: &mut Vec<u8>, number: usize, version: &(u8,u8,u8)) {
-        if number == 1 || version == &(5,0,0) {write_placeholder_default(data, 1);}
+        if number == 0 || version == &(5,0,0) {write_placeholder_default(data, 1);}
         let mut flags1 = 0u8;
         if self.tracks[number].percussion_track             {flags1 |= 0x01;}
         if self.tracks[number].twelve_stringed_guitar_track {flags1 |= 0x02;}
@@ -263,19 +287,18 @@ impl Song {
         if self.tracks[number].indicate_tuning              {flags1 |= 0x80;}
         write_byte(data, flags1);
 
-        write_byte_size_string(data, &self.tracks[number].name);
-        write_placeholder_default(data, 40 - self.tracks[number].name.len());
+        write_padded_byte_size_string(data, &self.tracks[number].name, 40, self.encoding);
 
         write_i32(data, self.tracks[number].strings.len().to_i32().unwrap());
         for i in 0..7usize {
-            let mut tuning = 0i8;
+            let mut tuning = -1i8;
             if i < self.tracks[number].strings.len() { tuning = self.tracks[number].strings[i].1;}
             write_i32(data, tuning.to_i32().unwrap());
         }
         write_i32(data, self.tracks[number].port.to_i32().unwrap());
         //write channel
-        write_i32(data, self.channels[self.tracks[number].channel_index].channel.to_i32().unwrap() + 1);
-        write_i32(data, self.channels[self.tracks[number].channel_index].effect_channel.to_i32().unwrap() + 1);
+        write_i32(data, (self.channels[self.tracks[number].channel_index].channel % 16).to_i32().unwrap() + 1);
+        write_i32(data, (self.channels[self.tracks[number].channel_index].effect_channel % 16).to_i32().unwrap() + 1);
         //end write channel
         write_i32(data, self.tracks[number].fret_count.to_i32().unwrap());
         write_i32(data, self.tracks[number].offset);
@@ -299,4 +322,105 @@ impl Song {
         write_byte(data, self.channels[self.tracks[number].channel_index].bank);
         self.write_track_rse(data, &self.tracks[number].rse, version);
     }
+
+    /// Create a new song with a subset of the tracks, in the given order. Measure headers and MIDI channels are kept, the lyrics are
+    /// kept if they are bound to one of the extracted tracks.
+    /// - `tracks`: indexes of the tracks to keep
+    pub fn extract_tracks(&self, tracks: &[usize]) -> Song {
+        let mut song = Song {tracks: Vec::with_capacity(tracks.len()), ..self.clone()};
+        for (i, &t) in tracks.iter().enumerate() {
+            let mut track = self.tracks[t].clone();
+            track.number = i.to_i32().unwrap();
+            for m in track.measures.iter_mut() {m.track_index = i;}
+            song.tracks.push(track);
+        }
+        song.lyrics = self.lyrics_for(tracks, 0);
+        song
+    }
+
+    /// Append tracks of another song. The measure headers of the current song are kept: the time signatures of the measures that both
+    /// songs have must be the same, extra measures of the other song are appended and the tracks are filled with empty measures.
+    /// The tracks get free MIDI channels of the current song in any port (channels shared by several tracks stay shared), percussion
+    /// tracks use the percussion channel. The lyrics of the other song are used if the current song has no lyrics.
+    /// The song is not changed if the tracks cannot be merged.
+    /// - `other`: the song to take the tracks from
+    /// - `tracks`: indexes of the tracks of `other` to append
+    pub fn merge_tracks(&mut self, other: &Song, tracks: &[usize]) -> Result<(), MergeError> {
+        if !self.tracks.is_empty() {
+            for (i, (a, b)) in self.measure_headers.iter().zip(other.measure_headers.iter()).enumerate() {
+                if a.time_signature != b.time_signature {
+                    return Err(MergeError::TimeSignature {measure: i + 1, first: (a.time_signature.numerator, a.time_signature.denominator.value),
+                                                          second: (b.time_signature.numerator, b.time_signature.denominator.value)});
+                }
+            }
+        }
+        //allocate the MIDI channels before changing the song
+        let mut used: Vec<usize> = Vec::with_capacity(32);
+        for t in &self.tracks {used.push(t.channel_index); used.push(self.channels[t.channel_index].effect_channel.to_usize().unwrap());}
+        let mut allocated: HashMap<usize, usize> = HashMap::with_capacity(tracks.len());
+        let mut channels: Vec<(usize, MidiChannel)> = Vec::with_capacity(tracks.len());
+        let mut indexes: Vec<usize> = Vec::with_capacity(tracks.len());
+        for &t in tracks {
+            let source = other.channels[other.tracks[t].channel_index];
+            let index = match allocated.get(&other.tracks[t].channel_index) {
+                Some(i) => *i,
+                None if other.tracks[t].percussion_track || source.is_percussion_channel() => {
+                    let p = DEFAULT_PERCUSSION_CHANNEL.to_usize().unwrap();
+                    if !used.contains(&p) {channels.push((p, source.with_channels(p, p)));}
+                    p
+                },
+                None => {
+                    let main = free_channel(&used).ok_or_else(|| MergeError::NoFreeChannel {track: other.tracks[t].name.clone()})?;
+                    used.push(main);
+                    let effect = if source.effect_channel != source.channel {free_channel(&used).unwrap_or(main)} else {main};
+                    used.push(effect);
+                    channels.push((main, source.with_channels(main, effect)));
+                    main
+                },
+            };
+            used.push(index);
+            allocated.insert(other.tracks[t].channel_index, index);
+            indexes.push(index);
+        }
+        if self.tracks.is_empty() {self.measure_headers = other.measure_headers.clone();}
+        for h in self.measure_headers.len()..other.measure_headers.len() {
+            let mut header = other.measure_headers[h].clone();
+            if let Some(p) = self.measure_headers.last() {header.start = p.start + p.length();}
+            header.number = (h + 1).to_u16().unwrap();
+            self.measure_headers.push(header);
+        }
+        for t in 0..self.tracks.len() {
+            for h in self.tracks[t].measures.len()..self.measure_headers.len() {
+                let m = Measure::empty(t, h, &self.measure_headers[h]);
+                self.tracks[t].measures.push(m);
+            }
+        }
+        for i in self.channels.len()..CHANNEL_COUNT {self.channels.push(MidiChannel::default().with_channels(i, i));}
+        for (i, c) in channels {self.channels[i] = c;}
+        let first = self.tracks.len();
+        for (&t, index) in tracks.iter().zip(indexes) {
+            let number = self.tracks.len();
+            let mut track = other.tracks[t].clone();
+            track.number = number.to_i32().unwrap();
+            track.channel_index = index;
+            track.port = (index / 16 + 1).to_u8().unwrap();
+            track.measures.truncate(self.measure_headers.len());
+            for h in track.measures.len()..self.measure_headers.len() {track.measures.push(Measure::empty(number, h, &self.measure_headers[h]));}
+            for (h, m) in track.measures.iter_mut().enumerate() {m.track_index = number; m.header_index = h;}
+            self.tracks.push(track);
+        }
+        if self.lyrics.lines.iter().all(|l| l.2.trim().is_empty()) {
+            let lyrics = other.lyrics_for(tracks, first);
+            if lyrics.lines.iter().any(|l| !l.2.trim().is_empty()) {self.lyrics = lyrics;}
+        }
+        Ok(())
+    }
+
+    /// Get the lyrics if they are bound to one of the tracks, with the track number they will have once moved at `offset` in a song.
+    fn lyrics_for(&self, tracks: &[usize], offset: usize) -> Lyrics {
+        match tracks.iter().position(|t| *t == self.lyrics_track()) {
+            Some(p) if self.lyrics.track_choice > 0 => Lyrics {track_choice: (offset + p + 1).to_u8().unwrap(), ..self.lyrics.clone()},
+            _ => Lyrics::default(),
+        }
+    }
 }
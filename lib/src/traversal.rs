@@ -0,0 +1,110 @@
+use crate::{gp::*, headers::*, track::*, measure::*, beat::*, note::*, pitch::*};
+
+/// A beat of a song with the parts containing it
+#[derive(Debug,Clone,Copy)]
+pub struct BeatContext<'a> {
+    /// Index of the track in the song, taken from `Measure::track_index`
+    pub track_index: usize,
+    pub measure_index: usize,
+    pub voice_index: usize,
+    pub beat_index: usize,
+    /// Absolute tick of the beat (the song starts at `960`), from the start of its measure and the durations of the previous beats of the voice
+    pub tick: i64,
+    pub track: &'a Track,
+    /// Header of the measure, found with `Measure::header_index`
+    pub header: &'a MeasureHeader,
+    pub measure: &'a Measure,
+    pub voice: &'a Voice,
+    pub beat: &'a Beat,
+}
+
+/// A note of a song with the parts containing it
+#[derive(Debug,Clone,Copy)]
+pub struct NoteContext<'a> {
+    pub beat: BeatContext<'a>,
+    pub note_index: usize,
+    pub note: &'a Note,
+}
+impl NoteContext<'_> {
+    /// Get the pitch of the note spelled in the key signature of its measure (see `Track::pitch()`).
+    pub fn pitch(&self) -> Option<Pitch> { self.beat.track.pitch(self.beat.beat, self.note, &self.beat.header.key_signature) }
+}
+
+/// Position of a part of a song given to a `VisitorMut`. The indexes of the parts below the visited one are `0`.
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+pub struct Location {
+    pub track: usize,
+    pub measure: usize,
+    pub voice: usize,
+    pub beat: usize,
+    pub note: usize,
+    /// Absolute tick of the measure or the beat
+    pub tick: i64,
+}
+
+/// Change the parts of a song with `Song::visit_mut()`. The methods do nothing by default.
+pub trait VisitorMut {
+    /// Called for every measure header before the tracks.
+    fn visit_header(&mut self, _header: &mut MeasureHeader, _index: usize) {}
+    /// Called for every track before its measures.
+    fn visit_track(&mut self, _track: &mut Track, _index: usize) {}
+    fn visit_measure(&mut self, _measure: &mut Measure, _header: &MeasureHeader, _location: &Location) {}
+    fn visit_voice(&mut self, _voice: &mut Voice, _location: &Location) {}
+    /// Called for every beat before its notes. The tick of the next beat is computed with the duration of the visited beat.
+    fn visit_beat(&mut self, _beat: &mut Beat, _location: &Location) {}
+    fn visit_note(&mut self, _note: &mut Note, _location: &Location) {}
+}
+
+impl Track {
+    /// Iterate over the beats of every voice of the track, measure after measure.
+    /// - `headers`: measure headers of the song, indexed by `Measure::header_index`
+    pub fn beats_with_context<'a>(&'a self, headers: &'a [MeasureHeader]) -> impl Iterator<Item = BeatContext<'a>> + 'a {
+        self.measures.iter().enumerate().flat_map(move |(m, measure)| {
+            let header = &headers[measure.header_index];
+            measure.voices.iter().enumerate().flat_map(move |(v, voice)| {
+                voice.beats.iter().enumerate().scan(header.start, move |tick, (b, beat)| {
+                    let context = BeatContext {track_index: measure.track_index, measure_index: m, voice_index: v, beat_index: b, tick: *tick,
+                                               track: self, header, measure, voice, beat};
+                    *tick += i64::from(beat.duration.time());
+                    Some(context)
+                })
+            })
+        })
+    }
+
+    /// Iterate over the notes (rests included) of the beats of the track (see `beats_with_context()`).
+    pub fn notes_with_context<'a>(&'a self, headers: &'a [MeasureHeader]) -> impl Iterator<Item = NoteContext<'a>> + 'a {
+        self.beats_with_context(headers).flat_map(|beat| beat.beat.notes.iter().enumerate().map(move |(n, note)| NoteContext {beat, note_index: n, note}))
+    }
+}
+
+impl Song {
+    /// Iterate over the beats of every track, track after track (see `Track::beats_with_context()`).
+    pub fn beats(&self) -> impl Iterator<Item = BeatContext<'_>> { self.tracks.iter().flat_map(|t| t.beats_with_context(&self.measure_headers)) }
+
+    /// Iterate over the notes (rests included) of every track, track after track.
+    pub fn notes(&self) -> impl Iterator<Item = NoteContext<'_>> { self.tracks.iter().flat_map(|t| t.notes_with_context(&self.measure_headers)) }
+
+    /// Visit the measure headers, then every track with its measures, voices, beats and notes in this order.
+    pub fn visit_mut<V: VisitorMut>(&mut self, visitor: &mut V) {
+        for (h, header) in self.measure_headers.iter_mut().enumerate() {visitor.visit_header(header, h);}
+        for (t, track) in self.tracks.iter_mut().enumerate() {
+            visitor.visit_track(track, t);
+            for (m, measure) in track.measures.iter_mut().enumerate() {
+                let header = &self.measure_headers[measure.header_index];
+                let mut location = Location {track: t, measure: m, tick: header.start, ..Default::default()};
+                visitor.visit_measure(measure, header, &location);
+                for (v, voice) in measure.voices.iter_mut().enumerate() {
+                    location = Location {voice: v, beat: 0, note: 0, tick: header.start, ..location};
+                    visitor.visit_voice(voice, &location);
+                    for (b, beat) in voice.beats.iter_mut().enumerate() {
+                        location = Location {beat: b, note: 0, ..location};
+                        visitor.visit_beat(beat, &location);
+                        for (n, note) in beat.notes.iter_mut().enumerate() {visitor.visit_note(note, &Location {note: n, ..location});}
+                        location.tick += i64::from(beat.duration.time());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use fraction::ToPrimitive;
+
+use crate::{gp::*, beat::*, enums::*};
+
+/// A kind of rhythm or notation error
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RhythmIssueKind {
+    /// The beats of the voice last longer than the measure (`length` and `expected` in ticks)
+    Overfull {length: i64, expected: i64},
+    /// The beats of the voice do not fill the measure (`length` and `expected` in ticks)
+    Underfull {length: i64, expected: i64},
+    /// The tuplet of the beat cannot be written in a file
+    UnsupportedTuplet {enters: u8, times: u8},
+    /// The tied note does not continue a note of the same string
+    TieToNothing,
+    /// The note is on a string that the track does not have
+    StringOutOfRange {string: i8, strings: usize},
+    /// The fret of the note is higher than the number of frets of the track
+    FretOutOfRange {fret: i16, fret_count: u8},
+}
+
+/// An error found by `Song::validate_rhythm()`
+#[derive(Debug,Clone,PartialEq,Eq)]
+#[cfg_attr(feature="serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RhythmIssue {
+    pub track: usize,
+    pub measure: usize,
+    pub voice: usize,
+    /// Index of the beat in the voice, `None` for the issues of the whole voice
+    pub beat: Option<usize>,
+    /// Index of the note in the beat, `None` for the issues of a beat or a voice
+    pub note: Option<usize>,
+    pub kind: RhythmIssueKind,
+}
+impl std::fmt::Display for RhythmIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "track {}, measure {}, voice {}", self.track + 1, self.measure + 1, self.voice + 1)?;
+        if let Some(b) = self.beat {write!(f, ", beat {}", b + 1)?;}
+        if let Some(n) = self.note {write!(f, ", note {}", n + 1)?;}
+        match &self.kind {
+            RhythmIssueKind::Overfull {length, expected} => write!(f, ": overfull measure ({} ticks instead of {})", length, expected),
+            RhythmIssueKind::Underfull {length, expected} => write!(f, ": underfull measure ({} ticks instead of {})", length, expected),
+            RhythmIssueKind::UnsupportedTuplet {enters, times} => write!(f, ": unsupported {}:{} tuplet", enters, times),
+            RhythmIssueKind::TieToNothing => write!(f, ": tied note without a previous note on the same string"),
+            RhythmIssueKind::StringOutOfRange {string, strings} => write!(f, ": string {} on a track of {} strings", string, strings),
+            RhythmIssueKind::FretOutOfRange {fret, fret_count} => write!(f, ": fret {} on a track of {} frets", fret, fret_count),
+        }
+    }
+}
+
+impl Song {
+    /// Check the rhythm and the notes of every voice of the song:
+    /// * the sum of the durations of the beats (dots and tuplets included) must be the length of the measure,
+    /// * the tuplets must be supported by the file format,
+    /// * a tied note must continue a note of the same string in the previous beat of the voice,
+    /// * the notes must be on the strings of the track and below its number of frets (percussion tracks are not checked).
+    ///
+    /// Voices without beats or with only empty beats (e.g. the unused second voice of Guitar Pro 5 files) are ignored.
+    pub fn validate_rhythm(&self) -> Vec<RhythmIssue> {
+        let mut issues = Vec::new();
+        for (t, track) in self.tracks.iter().enumerate() {
+            //strings played by the previous beat of each voice
+            let mut previous: Vec<Vec<i8>> = Vec::new();
+            for (m, measure) in track.measures.iter().enumerate() {
+                let expected = self.measure_headers.get(m).map(|h| h.length()).unwrap_or(0);
+                for (v, voice) in measure.voices.iter().enumerate() {
+                    if previous.len() <= v {previous.resize(v + 1, Vec::new());}
+                    if voice.beats.iter().all(|b| b.status == BeatStatus::Empty) {continue;}
+                    let (length, tolerance) = voice_length(voice);
+                    let issue = |beat: Option<usize>, note: Option<usize>, kind: RhythmIssueKind| RhythmIssue {track: t, measure: m, voice: v, beat, note, kind};
+                    if length > expected {issues.push(issue(None, None, RhythmIssueKind::Overfull {length, expected}));}
+                    else if length + tolerance < expected {issues.push(issue(None, None, RhythmIssueKind::Underfull {length, expected}));}
+                    for (b, beat) in voice.beats.iter().enumerate() {
+                        if !beat.duration.is_supported() {
+                            issues.push(issue(Some(b), None, RhythmIssueKind::UnsupportedTuplet {enters: beat.duration.tuplet_enters, times: beat.duration.tuplet_times}));
+                        }
+                        if beat.status == BeatStatus::Empty {continue;}
+                        for (n, note) in beat.notes.iter().enumerate().filter(|n| n.1.kind != NoteType::Rest) {
+                            if note.kind == NoteType::Tie && !previous[v].contains(&note.string) {issues.push(issue(Some(b), Some(n), RhythmIssueKind::TieToNothing));}
+                            if note.string < 1 || note.string.to_usize().unwrap() > track.strings.len() {
+                                issues.push(issue(Some(b), Some(n), RhythmIssueKind::StringOutOfRange {string: note.string, strings: track.strings.len()}));
+                            }
+                            if !track.percussion_track && note.value > i16::from(track.fret_count) {
+                                issues.push(issue(Some(b), Some(n), RhythmIssueKind::FretOutOfRange {fret: note.value, fret_count: track.fret_count}));
+                            }
+                        }
+                        previous[v] = beat.notes.iter().filter(|n| n.kind == NoteType::Normal || n.kind == NoteType::Tie).map(|n| n.string).collect();
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    /// Fill the underfull voices with rests so that they last as long as their measure. Overfull voices are not changed.
+    /// Returns the number of voices fixed.
+    pub fn fix_rhythm(&mut self) -> usize {
+        let mut count = 0;
+        for track in self.tracks.iter_mut() {
+            for (m, measure) in track.measures.iter_mut().enumerate() {
+                let (start, expected) = match self.measure_headers.get(m) {Some(h) => (h.start, h.length()), None => continue};
+                for voice in measure.voices.iter_mut() {
+                    if voice.beats.iter().all(|b| b.status == BeatStatus::Empty) {continue;}
+                    let (length, tolerance) = voice_length(voice);
+                    if length + tolerance >= expected {continue;}
+                    voice.beats.extend(rest_beats(start, length, expected));
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+/// Length of the beats of a voice in ticks, and the rounding error of its tuplets: the length of a tuplet beat is truncated, e.g. a
+/// seventh of a quarter note lasts 137 ticks instead of 137.14.
+fn voice_length(voice: &Voice) -> (i64, i64) {
+    let length = voice.beats.iter().map(|b| i64::from(b.duration.time())).sum();
+    (length, voice.beats.iter().filter(|b| !b.duration.is_default_tuplet()).count().to_i64().unwrap())
+}